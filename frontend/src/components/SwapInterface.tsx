@@ -279,7 +279,7 @@ export default function SwapInterface() {
 
         const hashlock = [253, 127, 201, 127, 180, 15, 64, 246, 188, 16, 43, 118, 249, 252, 107, 120, 162, 193, 72, 123, 17, 190, 186, 158, 105, 232, 145, 51, 52, 104, 201, 102]
 
-        await webapp?.withdraw_src(secret, hashlock).then((e) => {
+        await webapp?.withdraw_src(secret, hashlock, [], []).then((e) => {
             console.log('Withdraw successful:', e);
             alert('Withdraw successful!');
         }).catch((error) => {
@@ -33,8 +33,20 @@ const createEscrow = async (webapp: ActorSubclass<Record<string, ActorMethod<unk
             deployed_at: 0,
             withdrawal: 3600,
             public_withdrawal: 7200,
-            cancellation: 86400
-        }
+            cancellation: 86400,
+            public_cancellation: [],
+            dst: []
+        },
+        maker_min_amount: [],
+        min_received: [],
+        safety_deposit_funding: [],
+        chain_id: [],
+        icp_token: [],
+        hash_algorithm: [],
+        merkle_root: [],
+        parts_count: [],
+        auction: [],
+        metadata: []
     };
 
     if (!webapp) {
@@ -43,14 +55,14 @@ const createEscrow = async (webapp: ActorSubclass<Record<string, ActorMethod<unk
     }
 
     if (type === 'src') {
-        await webapp.create_src_escrow(args).then((e) => {
+        await webapp.create_src_escrow(args, [], []).then((e) => {
             console.log('Swap successful:', e);
         }).catch((error) => {
             console.error('Swap failed:', error);
             return false;
         });
     } else if (type === 'dst') {
-        await webapp.create_dst_escrow(args).then((e) => {
+        await webapp.create_dst_escrow(args, [], []).then((e) => {
             console.log('Swap successful:', e);
         }).catch((error) => {
             console.error('Swap failed:', error);
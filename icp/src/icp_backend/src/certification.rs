@@ -0,0 +1,292 @@
+use std::cell::RefCell;
+
+use candid::CandidType;
+use ic_certification::{AsHashTree, HashTree, NestedTree};
+use serde::Serialize;
+
+use crate::utils::sha256;
+
+/// Label of the event log subtree: ["events", seq] -> SHA256(candid(EventRecord))
+const EVENTS_LABEL: &[u8] = b"events";
+
+/// Label of the escrow state subtree: ["escrows", hashlock] -> SHA256(candid(ICPEscrow))
+const ESCROWS_LABEL: &[u8] = b"escrows";
+
+/// Label of the settlement receipts subtree: ["receipts", hashlock] -> SHA256(candid(EscrowReceipt))
+const RECEIPTS_LABEL: &[u8] = b"receipts";
+
+/// ICRC-3 tip labels, kept at the root of the tree as the standard requires:
+/// last_block_index -> leb128(index), last_block_hash -> block hash
+const LAST_BLOCK_INDEX_LABEL: &[u8] = b"last_block_index";
+const LAST_BLOCK_HASH_LABEL: &[u8] = b"last_block_hash";
+
+thread_local! {
+    /// Certified state tree; its root hash is published as the canister's certified data
+    static CERTIFIED_TREE: RefCell<Option<NestedTree<Vec<u8>, Vec<u8>>>> = const { RefCell::new(None) };
+}
+
+/// Initialize the certified tree and publish its (empty) root
+pub fn init_certification() {
+    CERTIFIED_TREE.with_borrow_mut(|tree| {
+        tree.get_or_insert_with(NestedTree::default);
+    });
+    publish_root();
+}
+
+/// Hash of a value's Candid encoding, as stored in certified leaves
+pub fn leaf_hash<T: CandidType>(value: &T) -> Vec<u8> {
+    sha256(&candid::encode_one(value).expect("candid encoding cannot fail"))
+}
+
+/// Path of an event leaf in the certified tree
+fn event_path(seq: u64) -> [Vec<u8>; 2] {
+    [EVENTS_LABEL.to_vec(), seq.to_be_bytes().to_vec()]
+}
+
+/// Record an event hash under its sequence number and re-certify
+pub fn certify_event(seq: u64, event_hash: Vec<u8>) {
+    CERTIFIED_TREE.with_borrow_mut(|tree| {
+        if let Some(tree) = tree.as_mut() {
+            tree.insert(&event_path(seq), event_hash);
+        }
+    });
+    publish_root();
+}
+
+/// Whether the event under `seq` is still committed in the certified tree
+pub fn is_event_certified(seq: u64) -> bool {
+    CERTIFIED_TREE.with_borrow(|tree| {
+        tree.as_ref()
+            .map(|tree| tree.contains_leaf(&event_path(seq)))
+            .unwrap_or(false)
+    })
+}
+
+/// Drop pruned events from the certified tree and re-certify
+pub fn uncertify_events(seqs: &[u64]) {
+    if seqs.is_empty() {
+        return;
+    }
+    CERTIFIED_TREE.with_borrow_mut(|tree| {
+        if let Some(tree) = tree.as_mut() {
+            for seq in seqs {
+                tree.delete(&event_path(*seq));
+            }
+        }
+    });
+    publish_root();
+}
+
+/// Path of an escrow leaf in the certified tree
+fn escrow_path(hashlock: &[u8]) -> [Vec<u8>; 2] {
+    [ESCROWS_LABEL.to_vec(), hashlock.to_vec()]
+}
+
+/// Record the current hash of an escrow record and re-certify
+pub fn certify_escrow(hashlock: &[u8], escrow_hash: Vec<u8>) {
+    CERTIFIED_TREE.with_borrow_mut(|tree| {
+        if let Some(tree) = tree.as_mut() {
+            tree.insert(&escrow_path(hashlock), escrow_hash);
+        }
+    });
+    publish_root();
+}
+
+/// Witness for the escrow stored under `hashlock`
+pub fn escrow_witness(hashlock: &[u8]) -> HashTree {
+    CERTIFIED_TREE.with_borrow(|tree| {
+        tree.as_ref()
+            .map(|tree| tree.witness(&escrow_path(hashlock)))
+            .unwrap_or_else(ic_certification::empty)
+    })
+}
+
+/// Path of a receipt leaf in the certified tree
+fn receipt_path(hashlock: &[u8]) -> [Vec<u8>; 2] {
+    [RECEIPTS_LABEL.to_vec(), hashlock.to_vec()]
+}
+
+/// Record the receipt hash of a settled escrow and re-certify
+pub fn certify_receipt(hashlock: &[u8], receipt_hash: Vec<u8>) {
+    CERTIFIED_TREE.with_borrow_mut(|tree| {
+        if let Some(tree) = tree.as_mut() {
+            tree.insert(&receipt_path(hashlock), receipt_hash);
+        }
+    });
+    publish_root();
+}
+
+/// Witness for the receipt stored under `hashlock`
+pub fn receipt_witness(hashlock: &[u8]) -> HashTree {
+    CERTIFIED_TREE.with_borrow(|tree| {
+        tree.as_ref()
+            .map(|tree| tree.witness(&receipt_path(hashlock)))
+            .unwrap_or_else(ic_certification::empty)
+    })
+}
+
+/// Record the newest block of the ICRC-3 log and re-certify
+pub fn certify_tip(last_block_index: Vec<u8>, last_block_hash: Vec<u8>) {
+    CERTIFIED_TREE.with_borrow_mut(|tree| {
+        if let Some(tree) = tree.as_mut() {
+            tree.insert(&[LAST_BLOCK_INDEX_LABEL.to_vec()], last_block_index);
+            tree.insert(&[LAST_BLOCK_HASH_LABEL.to_vec()], last_block_hash);
+        }
+    });
+    publish_root();
+}
+
+/// Witness revealing both tip labels. They sort next to each other among the root labels,
+/// so one value range covers exactly the two.
+fn tip_witness_in(tree: &NestedTree<Vec<u8>, Vec<u8>>) -> HashTree {
+    match tree {
+        NestedTree::Nested(root) => root.value_range(LAST_BLOCK_HASH_LABEL, LAST_BLOCK_INDEX_LABEL),
+        NestedTree::Leaf(_) => ic_certification::empty(),
+    }
+}
+
+/// Witness for the ICRC-3 tip certificate
+pub fn tip_witness() -> HashTree {
+    CERTIFIED_TREE.with_borrow(|tree| {
+        tree.as_ref()
+            .map(tip_witness_in)
+            .unwrap_or_else(ic_certification::empty)
+    })
+}
+
+/// Root hash of the escrow state subtree (commitment over the full escrow map)
+pub fn escrows_root() -> Vec<u8> {
+    CERTIFIED_TREE.with_borrow(|tree| match tree.as_ref() {
+        Some(NestedTree::Nested(tree)) => tree.get(ESCROWS_LABEL)
+            .map(|subtree| subtree.root_hash().to_vec())
+            .unwrap_or_else(|| ic_certification::empty().digest().to_vec()),
+        _ => Vec::new(),
+    })
+}
+
+/// Current root hash of the certified tree
+pub fn root_hash() -> Vec<u8> {
+    CERTIFIED_TREE.with_borrow(|tree| {
+        tree.as_ref()
+            .map(|tree| tree.root_hash().to_vec())
+            .unwrap_or_default()
+    })
+}
+
+/// Witness for the event stored under `seq`
+pub fn event_witness(seq: u64) -> HashTree {
+    CERTIFIED_TREE.with_borrow(|tree| {
+        tree.as_ref()
+            .map(|tree| tree.witness(&event_path(seq)))
+            .unwrap_or_else(ic_certification::empty)
+    })
+}
+
+/// Publish the current tree root via certified data (update context only)
+fn publish_root() {
+    let root = CERTIFIED_TREE.with_borrow(|tree| tree.as_ref().map(|tree| tree.root_hash()));
+    if let Some(root) = root {
+        ic_cdk::api::certified_data_set(root);
+    }
+}
+
+/// Current data certificate (only available in query calls)
+pub fn data_certificate() -> Option<Vec<u8>> {
+    ic_cdk::api::data_certificate()
+}
+
+/// Encode a witness as self-describing CBOR, as expected by IC agents
+pub fn encode_witness(witness: &HashTree) -> Vec<u8> {
+    let mut serializer = serde_cbor::Serializer::new(Vec::new());
+    serializer.self_describe().expect("CBOR tag write cannot fail");
+    witness.serialize(&mut serializer).expect("hash tree serialization cannot fail");
+    serializer.into_inner()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_certification::LookupResult;
+
+    #[test]
+    fn test_event_witness_reconstructs_root() {
+        let mut tree: NestedTree<Vec<u8>, Vec<u8>> = NestedTree::default();
+        for seq in 0..5u64 {
+            tree.insert(&event_path(seq), vec![seq as u8; 32]);
+        }
+
+        let witness = tree.witness(&event_path(3));
+        assert_eq!(witness.digest(), tree.root_hash());
+
+        match witness.lookup_path(event_path(3)) {
+            LookupResult::Found(value) => assert_eq!(value, &[3u8; 32][..]),
+            _ => panic!("event leaf should be present in witness"),
+        }
+    }
+
+    #[test]
+    fn test_escrow_witness_hides_events() {
+        let mut tree: NestedTree<Vec<u8>, Vec<u8>> = NestedTree::default();
+        tree.insert(&event_path(0), vec![1u8; 32]);
+        tree.insert(&escrow_path(&[7u8; 32]), vec![2u8; 32]);
+
+        let witness = tree.witness(&escrow_path(&[7u8; 32]));
+        assert_eq!(witness.digest(), tree.root_hash());
+        assert!(matches!(witness.lookup_path(escrow_path(&[7u8; 32])), LookupResult::Found(_)));
+        assert!(!matches!(witness.lookup_path(event_path(0)), LookupResult::Found(_)));
+    }
+
+    #[test]
+    fn test_escrow_witness_proves_absence() {
+        let mut tree: NestedTree<Vec<u8>, Vec<u8>> = NestedTree::default();
+        tree.insert(&escrow_path(&[3u8; 32]), vec![1u8; 32]);
+        tree.insert(&escrow_path(&[9u8; 32]), vec![2u8; 32]);
+
+        let witness = tree.witness(&escrow_path(&[5u8; 32]));
+        assert_eq!(witness.digest(), tree.root_hash());
+        assert!(matches!(witness.lookup_path(escrow_path(&[5u8; 32])), LookupResult::Absent));
+    }
+
+    #[test]
+    fn test_receipt_and_escrow_leaves_are_separate() {
+        let hashlock = [7u8; 32];
+        let mut tree: NestedTree<Vec<u8>, Vec<u8>> = NestedTree::default();
+        tree.insert(&escrow_path(&hashlock), vec![1u8; 32]);
+        tree.insert(&receipt_path(&hashlock), vec![2u8; 32]);
+
+        let witness = tree.witness(&receipt_path(&hashlock));
+        assert_eq!(witness.digest(), tree.root_hash());
+        match witness.lookup_path(receipt_path(&hashlock)) {
+            LookupResult::Found(value) => assert_eq!(value, &[2u8; 32][..]),
+            _ => panic!("receipt leaf should be present in witness"),
+        }
+    }
+
+    #[test]
+    fn test_tip_witness_reveals_both_labels() {
+        let mut tree: NestedTree<Vec<u8>, Vec<u8>> = NestedTree::default();
+        tree.insert(&event_path(0), vec![1u8; 32]);
+        tree.insert(&escrow_path(&[7u8; 32]), vec![2u8; 32]);
+        tree.insert(&[LAST_BLOCK_INDEX_LABEL.to_vec()], vec![0]);
+        tree.insert(&[LAST_BLOCK_HASH_LABEL.to_vec()], vec![3u8; 32]);
+
+        let witness = tip_witness_in(&tree);
+        assert_eq!(witness.digest(), tree.root_hash());
+        match witness.lookup_path([LAST_BLOCK_INDEX_LABEL]) {
+            LookupResult::Found(value) => assert_eq!(value, &[0u8][..]),
+            _ => panic!("last_block_index should be present in witness"),
+        }
+        match witness.lookup_path([LAST_BLOCK_HASH_LABEL]) {
+            LookupResult::Found(value) => assert_eq!(value, &[3u8; 32][..]),
+            _ => panic!("last_block_hash should be present in witness"),
+        }
+        assert!(!matches!(witness.lookup_path(event_path(0)), LookupResult::Found(_)));
+    }
+
+    #[test]
+    fn test_encode_witness_is_self_describing_cbor() {
+        let encoded = encode_witness(&ic_certification::empty());
+        // CBOR self-describe tag 55799
+        assert_eq!(&encoded[..3], &[0xd9, 0xd9, 0xf7]);
+    }
+}
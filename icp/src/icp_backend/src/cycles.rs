@@ -0,0 +1,148 @@
+use candid::Principal;
+
+use crate::storage;
+use crate::types::{CyclesStatus, EscrowConfig, EscrowError, EscrowEvent, Result};
+
+// Approximate cycle prices on a 13-node application subnet; attribution is for calibrating
+// the fee schedule, not for billing, so round figures are good enough
+
+/// Execution of one update message, before instructions
+pub const UPDATE_EXECUTION_FEE: u64 = 5_000_000;
+
+/// Cycles charged per 10 executed instructions
+const CYCLES_PER_10_INSTRUCTIONS: u64 = 4;
+
+/// One inter-canister call, including a few hundred bytes of arguments and reply
+pub const INTER_CANISTER_CALL_FEE: u64 = 260_000 + 1_000 * 400;
+
+/// One threshold ECDSA signature or vetKD key derivation with the production key
+pub const THRESHOLD_KEY_FEE: u64 = 26_153_846_153;
+
+/// Cycles for `instructions` executed instructions
+pub fn instructions_cost(instructions: u64) -> u64 {
+    instructions.saturating_mul(CYCLES_PER_10_INSTRUCTIONS) / 10
+}
+
+/// Cycles of the running message so far, plus `calls` inter-canister calls it made
+pub fn message_cost(calls: u64) -> u64 {
+    let instructions = ic_cdk::api::call_context_instruction_counter();
+    UPDATE_EXECUTION_FEE + instructions_cost(instructions) + calls * INTER_CANISTER_CALL_FEE
+}
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// Span of balance samples the burn rate is estimated over
+const BURN_RATE_WINDOW: u64 = NANOS_PER_DAY;
+
+/// Cycles burned per day across balance samples `(timestamp, balance)`, oldest first. A rise
+/// between two samples is a top-up and is skipped rather than netted against the burn.
+/// None until the samples span some time.
+pub fn burn_per_day(samples: &[(u64, u128)]) -> Option<u128> {
+    let (first, last) = (samples.first()?, samples.last()?);
+    let elapsed = last.0.saturating_sub(first.0);
+    if elapsed == 0 {
+        return None;
+    }
+    let burned: u128 = samples.windows(2).map(|pair| pair[0].1.saturating_sub(pair[1].1)).sum();
+    Some(burned.saturating_mul(u128::from(NANOS_PER_DAY)) / u128::from(elapsed))
+}
+
+/// Whole days until `liquid_balance` is spent at `burn_per_day`; None while nothing burns
+pub fn days_to_freeze(liquid_balance: u128, burn_per_day: Option<u128>) -> Option<u64> {
+    let burn = burn_per_day.filter(|&burn| burn > 0)?;
+    Some(u64::try_from(liquid_balance / burn).unwrap_or(u64::MAX))
+}
+
+/// Balance, burn rate over the last day of hourly samples, and runway
+pub fn status(config: &EscrowConfig, now: u64) -> CyclesStatus {
+    let balance = ic_cdk::api::canister_cycle_balance();
+    let liquid_balance = ic_cdk::api::canister_liquid_cycle_balance();
+    let mut samples: Vec<(u64, u128)> = storage::cycles_samples(now.saturating_sub(BURN_RATE_WINDOW))
+        .into_iter()
+        .map(|(timestamp, balance)| (timestamp, u128::from(balance)))
+        .collect();
+    samples.push((now, balance));
+    let burn_per_day = burn_per_day(&samples);
+
+    CyclesStatus {
+        balance,
+        liquid_balance,
+        burn_per_day,
+        days_to_freeze: days_to_freeze(liquid_balance, burn_per_day),
+        min_cycles_for_creation: config.min_cycles_for_creation,
+        creation_blocked: balance < u128::from(config.min_cycles_for_creation),
+    }
+}
+
+/// Refuse new escrows once cycles are critically low. Withdrawals, cancellations and rescues
+/// do not check this, so the cycles left go to returning funds already escrowed.
+pub fn check_creation_allowed(config: &EscrowConfig) -> Result<()> {
+    let balance = ic_cdk::api::canister_cycle_balance();
+    if balance < u128::from(config.min_cycles_for_creation) {
+        return Err(EscrowError::CyclesLow { balance, minimum: config.min_cycles_for_creation });
+    }
+    Ok(())
+}
+
+/// Accept every cycle attached to the running call as a top-up from `depositor`, logging it
+/// and adding it to the metrics. Returns the cycles accepted.
+pub fn accept_deposit(depositor: Principal, now: u64) -> Result<u128> {
+    if ic_cdk::api::msg_cycles_available() == 0 {
+        return Err(EscrowError::InvalidAmount);
+    }
+    let amount = ic_cdk::api::msg_cycles_accept(u128::MAX);
+
+    storage::update_metrics(|metrics| metrics.record_cycles_deposit(amount));
+    storage::add_event(EscrowEvent::CyclesDeposited {
+        depositor,
+        amount,
+        balance: ic_cdk::api::canister_cycle_balance(),
+        timestamp: now,
+    });
+    Ok(amount)
+}
+
+/// Sample the cycles balance into the metrics history, emitting `CyclesLow` when it first
+/// falls within the alert margin of the creation minimum
+pub fn record_sample(now: u64) {
+    let config = storage::get_config();
+    let balance = u64::try_from(ic_cdk::api::canister_cycle_balance()).unwrap_or(u64::MAX);
+    let previous = storage::latest_cycles_balance();
+    storage::snapshot_metrics(now, balance);
+
+    let alert_level = config.min_cycles_for_creation.saturating_add(config.cycles_alert_margin);
+    if balance < alert_level && previous.is_none_or(|previous| previous >= alert_level) {
+        storage::add_event(EscrowEvent::CyclesLow {
+            balance,
+            minimum: config.min_cycles_for_creation,
+            timestamp: now,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instructions_cost_rounds_down_and_saturates() {
+        assert_eq!(instructions_cost(0), 0);
+        assert_eq!(instructions_cost(9), 3);
+        assert_eq!(instructions_cost(1_000_000_000), 400_000_000);
+        assert_eq!(instructions_cost(u64::MAX), u64::MAX / 10);
+    }
+
+    #[test]
+    fn test_burn_rate_skips_top_ups() {
+        let hour = NANOS_PER_DAY / 24;
+        assert_eq!(burn_per_day(&[(0, 1_000)]), None);
+
+        // 100 burned per hour, with a top-up of 500 between the second and third samples
+        let samples = [(0, 1_000), (hour, 900), (2 * hour, 1_300), (3 * hour, 1_200)];
+        assert_eq!(burn_per_day(&samples), Some(200 * 24 / 3));
+
+        assert_eq!(days_to_freeze(4_800, Some(1_600)), Some(3));
+        assert_eq!(days_to_freeze(4_800, Some(0)), None);
+        assert_eq!(days_to_freeze(4_800, None), None);
+    }
+}
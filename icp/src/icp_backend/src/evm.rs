@@ -0,0 +1,426 @@
+use candid::{CandidType, Nat, Principal, Reserved};
+use ic_cdk::call::Call;
+use ic_cdk::management_canister::{
+    ecdsa_public_key, sign_with_ecdsa, EcdsaPublicKeyArgs, SignWithEcdsaArgs,
+};
+use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+use serde_json::{json, Value};
+
+use crate::cycles;
+use crate::migration;
+use crate::types::{ChainConfig, EscrowError, EvmEscrowStatus, EvmImmutables, EvmWithdrawal, Result};
+use crate::utils;
+
+/// Official EVM RPC canister, which relays JSON-RPC requests over HTTPS outcalls
+const EVM_RPC_CANISTER: &str = "7hfb6-caaaa-aaaar-qadga-cai";
+
+/// Largest JSON-RPC response accepted; escrows are minimal proxies, so their code is tiny
+const MAX_RESPONSE_BYTES: u64 = 4_096;
+
+/// Cycles attached to each relayed request; the EVM RPC canister refunds what it does not use
+const REQUEST_CYCLES: u128 = 2_000_000_000;
+
+/// Derivation path of the canister's EVM signing key, apart from migration signatures
+const EVM_SIGNER_CONTEXT: &[u8] = b"avginch/evm-signer";
+
+/// Gas limit of a withdrawal; the escrow's checks and its two transfers stay well below it
+const WITHDRAWAL_GAS_LIMIT: u64 = 250_000;
+
+/// EIP-2718 type byte of EIP-1559 transactions
+const EIP1559_TX_TYPE: u8 = 0x02;
+
+/// Provider as the EVM RPC canister's `RpcApi`; its optional headers are left out
+#[derive(CandidType)]
+struct RpcApi {
+    url: String,
+}
+
+/// The one `RpcService` variant used: a provider from the chain config
+#[derive(CandidType)]
+enum RpcService {
+    Custom(RpcApi),
+}
+
+/// JSON-RPC body of a single request
+fn rpc_payload(method: &str, params: Value) -> String {
+    json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }).to_string()
+}
+
+/// The `result` string of a JSON-RPC response; error responses are rejected
+fn rpc_result(body: &str) -> Result<String> {
+    let response: Value = serde_json::from_str(body).map_err(|_| EscrowError::EvmRpcFailed)?;
+    response
+        .get("result")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or(EscrowError::EvmRpcFailed)
+}
+
+/// Decode a JSON-RPC hex quantity such as a block number
+fn parse_quantity(quantity: &str) -> Result<u64> {
+    let digits = quantity.strip_prefix("0x").ok_or(EscrowError::EvmRpcFailed)?;
+    u64::from_str_radix(digits, 16).map_err(|_| EscrowError::EvmRpcFailed)
+}
+
+/// Whether an `eth_getCode` result holds contract code; accounts without code return "0x"
+fn has_code(code: &str) -> bool {
+    code.strip_prefix("0x").is_some_and(|bytes| !bytes.is_empty())
+}
+
+/// Relay one JSON-RPC request to a provider. Returns its result and the cycles it consumed.
+async fn request(url: &str, method: &str, params: Value) -> Result<(String, u64)> {
+    let canister = Principal::from_text(EVM_RPC_CANISTER).expect("EVM RPC canister id is valid");
+    let args = (RpcService::Custom(RpcApi { url: url.to_string() }), rpc_payload(method, params), MAX_RESPONSE_BYTES);
+
+    let response = Call::unbounded_wait(canister, "request")
+        .with_args(&args)
+        .with_cycles(REQUEST_CYCLES)
+        .await
+        .map_err(|e| {
+            ic_cdk::api::debug_print(format!("EVM RPC call error: {:?}", e));
+            EscrowError::CanisterCallError
+        })?;
+    let spent = REQUEST_CYCLES.saturating_sub(ic_cdk::api::msg_cycles_refunded()) as u64;
+
+    match response.candid::<std::result::Result<String, Reserved>>() {
+        Ok(Ok(body)) => Ok((rpc_result(&body)?, spent)),
+        Ok(Err(_)) | Err(_) => Err(EscrowError::EvmRpcFailed),
+    }
+}
+
+/// Check for contract code at `address` with every provider configured for the chain, each at
+/// the block `confirmations` below its tip. The providers must all agree. Returns the status
+/// and the cycles spent on the requests.
+pub async fn escrow_status(chain: &ChainConfig, address: &str, now: u64) -> Result<(EvmEscrowStatus, u64)> {
+    let mut deployed = None;
+    let mut block_number = u64::MAX;
+    let mut spent = 0;
+
+    for url in &chain.rpc_providers {
+        let (tip, cost) = request(url, "eth_blockNumber", json!([])).await?;
+        spent += cost;
+        let confirmed = parse_quantity(&tip)?.saturating_sub(chain.confirmations as u64);
+
+        let (code, cost) = request(url, "eth_getCode", json!([address, format!("{:#x}", confirmed)])).await?;
+        spent += cost;
+        let found = has_code(&code);
+        if deployed.is_some_and(|agreed| agreed != found) {
+            return Err(EscrowError::EvmVerificationFailed);
+        }
+        deployed = Some(found);
+        block_number = block_number.min(confirmed);
+    }
+
+    let status = EvmEscrowStatus {
+        chain_id: chain.chain_id,
+        address: address.to_string(),
+        deployed: deployed.ok_or(EscrowError::ChainNotConfigured)?,
+        block_number,
+        providers: chain.rpc_providers.len() as u32,
+        checked_at: now,
+    };
+    Ok((status, spent))
+}
+
+/// Minimal big-endian bytes of an integer, as RLP encodes integers
+fn trim_leading_zeros(bytes: &[u8]) -> &[u8] {
+    let first = bytes.iter().position(|byte| *byte != 0).unwrap_or(bytes.len());
+    &bytes[first..]
+}
+
+/// RLP prefix of a string (`offset` 0x80) or list (`offset` 0xc0) payload of `len` bytes
+fn rlp_prefix(len: usize, offset: u8) -> Vec<u8> {
+    if len < 56 {
+        return vec![offset + len as u8];
+    }
+    let len_bytes = (len as u64).to_be_bytes();
+    let len_bytes = trim_leading_zeros(&len_bytes);
+    [&[offset + 55 + len_bytes.len() as u8][..], len_bytes].concat()
+}
+
+fn rlp_bytes(bytes: &[u8]) -> Vec<u8> {
+    if bytes.len() == 1 && bytes[0] < 0x80 {
+        return bytes.to_vec();
+    }
+    [rlp_prefix(bytes.len(), 0x80), bytes.to_vec()].concat()
+}
+
+fn rlp_uint(value: u64) -> Vec<u8> {
+    rlp_bytes(trim_leading_zeros(&value.to_be_bytes()))
+}
+
+fn rlp_list(items: &[Vec<u8>]) -> Vec<u8> {
+    let payload = items.concat();
+    [rlp_prefix(payload.len(), 0xc0), payload].concat()
+}
+
+/// 20 bytes of a 0x-prefixed EVM address
+fn address_bytes(address: &str) -> Result<[u8; 20]> {
+    if !utils::validate_evm_address(address) {
+        return Err(EscrowError::InvalidAddress);
+    }
+    let mut bytes = [0u8; 20];
+    hex::decode_to_slice(&address[2..], &mut bytes).map_err(|_| EscrowError::InvalidAddress)?;
+    Ok(bytes)
+}
+
+/// Left-pad to one 32-byte ABI word; longer values do not fit a uint256
+fn abi_word(bytes: &[u8]) -> Result<[u8; 32]> {
+    let bytes = trim_leading_zeros(bytes);
+    if bytes.len() > 32 {
+        return Err(EscrowError::InvalidAmount);
+    }
+    let mut word = [0u8; 32];
+    word[32 - bytes.len()..].copy_from_slice(bytes);
+    Ok(word)
+}
+
+fn nat_word(value: &Nat) -> Result<[u8; 32]> {
+    abi_word(&value.0.to_bytes_be())
+}
+
+/// Calldata of `withdraw` or `publicWithdraw(bytes32 secret, Immutables immutables)`.
+/// `Address` and `Timelocks` are uint256 value types, so the struct is all static words
+/// encoded in place after the secret.
+fn withdrawal_calldata(public: bool, secret: &[u8], immutables: &EvmImmutables) -> Result<Vec<u8>> {
+    let function = if public { "publicWithdraw" } else { "withdraw" };
+    let signature = format!("{}(bytes32,(bytes32,bytes32,uint256,uint256,uint256,uint256,uint256,uint256))", function);
+    let words = [
+        abi_word(secret)?,
+        abi_word(&immutables.order_hash)?,
+        abi_word(&immutables.hashlock)?,
+        abi_word(&address_bytes(&immutables.maker)?)?,
+        abi_word(&address_bytes(&immutables.taker)?)?,
+        abi_word(&address_bytes(&immutables.token)?)?,
+        nat_word(&immutables.amount)?,
+        nat_word(&immutables.safety_deposit)?,
+        abi_word(&immutables.timelocks)?,
+    ];
+    let mut calldata = utils::keccak256(signature.as_bytes())[..4].to_vec();
+    words.iter().for_each(|word| calldata.extend_from_slice(word));
+    Ok(calldata)
+}
+
+/// EIP-1559 contract call carrying no value and an empty access list
+struct Eip1559Transaction {
+    chain_id: u64,
+    nonce: u64,
+    max_priority_fee_per_gas: u64,
+    max_fee_per_gas: u64,
+    gas_limit: u64,
+    to: [u8; 20],
+    data: Vec<u8>,
+}
+
+impl Eip1559Transaction {
+    fn fields(&self) -> Vec<Vec<u8>> {
+        vec![
+            rlp_uint(self.chain_id),
+            rlp_uint(self.nonce),
+            rlp_uint(self.max_priority_fee_per_gas),
+            rlp_uint(self.max_fee_per_gas),
+            rlp_uint(self.gas_limit),
+            rlp_bytes(&self.to),
+            rlp_uint(0),
+            rlp_bytes(&self.data),
+            rlp_list(&[]),
+        ]
+    }
+
+    /// Digest the sender signs: keccak256(0x02 || rlp(fields))
+    fn signing_hash(&self) -> Vec<u8> {
+        utils::keccak256(&[&[EIP1559_TX_TYPE][..], &rlp_list(&self.fields())].concat())
+    }
+
+    /// Raw transaction for `eth_sendRawTransaction`: 0x02 || rlp(fields ++ [y_parity, r, s])
+    fn encode_signed(&self, y_parity: u8, signature: &Signature) -> Vec<u8> {
+        let (r, s) = (signature.r().to_bytes(), signature.s().to_bytes());
+        let mut fields = self.fields();
+        fields.push(rlp_uint(y_parity as u64));
+        fields.push(rlp_bytes(trim_leading_zeros(&r)));
+        fields.push(rlp_bytes(trim_leading_zeros(&s)));
+        [&[EIP1559_TX_TYPE][..], &rlp_list(&fields)].concat()
+    }
+}
+
+/// EVM address of a secp256k1 public key: the last 20 bytes of keccak256 of its uncompressed point
+fn evm_address(public_key: &VerifyingKey) -> String {
+    let point = public_key.to_encoded_point(false);
+    format!("0x{}", hex::encode(&utils::keccak256(&point.as_bytes()[1..])[12..]))
+}
+
+/// Recovery id that makes `signature` over `hash` recover to `public_key`
+fn y_parity(hash: &[u8], signature: &Signature, public_key: &VerifyingKey) -> Result<u8> {
+    (0..2)
+        .find(|byte| {
+            RecoveryId::from_byte(*byte)
+                .and_then(|id| VerifyingKey::recover_from_prehash(hash, signature, id).ok())
+                .is_some_and(|recovered| recovered == *public_key)
+        })
+        .ok_or(EscrowError::EvmRpcFailed)
+}
+
+async fn signer_key(key_name: &str) -> Result<VerifyingKey> {
+    let args = EcdsaPublicKeyArgs {
+        canister_id: None,
+        derivation_path: vec![EVM_SIGNER_CONTEXT.to_vec()],
+        key_id: migration::key_id(key_name),
+    };
+    let public_key = ecdsa_public_key(&args)
+        .await
+        .map_err(|_| EscrowError::CanisterCallError)?
+        .public_key;
+    VerifyingKey::from_sec1_bytes(&public_key).map_err(|_| EscrowError::CanisterCallError)
+}
+
+/// EVM address controlled by the canister's threshold ECDSA key
+pub async fn canister_address(key_name: &str) -> Result<String> {
+    Ok(evm_address(&signer_key(key_name).await?))
+}
+
+/// Sign a withdrawal of the EVM escrow at `escrow_address` with the canister's threshold ECDSA
+/// key and broadcast it to every provider configured for the chain. `withdraw` is called when
+/// the canister's address is the maker or taker there, `publicWithdraw` otherwise. Fees are
+/// capped at the chain's `max_gas_price`. Returns the submission and the cycles spent.
+pub async fn submit_withdrawal(
+    chain: &ChainConfig,
+    key_name: &str,
+    escrow_address: &str,
+    secret: &[u8],
+    immutables: &EvmImmutables,
+    now: u64,
+) -> Result<(EvmWithdrawal, u64)> {
+    let provider = chain.rpc_providers.first().ok_or(EscrowError::ChainNotConfigured)?;
+    let signer = signer_key(key_name).await?;
+    let from = evm_address(&signer);
+    let public = !from.eq_ignore_ascii_case(&immutables.maker) && !from.eq_ignore_ascii_case(&immutables.taker);
+
+    let (nonce, nonce_cost) = request(provider, "eth_getTransactionCount", json!([from, "pending"])).await?;
+    let (priority_fee, fee_cost) = request(provider, "eth_maxPriorityFeePerGas", json!([])).await?;
+    let mut spent = nonce_cost + fee_cost + cycles::THRESHOLD_KEY_FEE;
+
+    let transaction = Eip1559Transaction {
+        chain_id: chain.chain_id,
+        nonce: parse_quantity(&nonce)?,
+        max_priority_fee_per_gas: parse_quantity(&priority_fee)?.min(chain.max_gas_price),
+        max_fee_per_gas: chain.max_gas_price,
+        gas_limit: WITHDRAWAL_GAS_LIMIT,
+        to: address_bytes(escrow_address)?,
+        data: withdrawal_calldata(public, secret, immutables)?,
+    };
+    let hash = transaction.signing_hash();
+    let args = SignWithEcdsaArgs {
+        message_hash: hash.clone(),
+        derivation_path: vec![EVM_SIGNER_CONTEXT.to_vec()],
+        key_id: migration::key_id(key_name),
+    };
+    let signature = sign_with_ecdsa(&args).await.map_err(|_| EscrowError::CanisterCallError)?.signature;
+    let signature = Signature::from_slice(&signature).map_err(|_| EscrowError::CanisterCallError)?;
+    let signature = signature.normalize_s().unwrap_or(signature);
+    let raw = transaction.encode_signed(y_parity(&hash, &signature, &signer)?, &signature);
+
+    // Any provider accepting the transaction is enough; the others may already know it
+    let raw_hex = format!("0x{}", hex::encode(&raw));
+    let mut accepted = false;
+    for url in &chain.rpc_providers {
+        if let Ok((_, cost)) = request(url, "eth_sendRawTransaction", json!([raw_hex])).await {
+            accepted = true;
+            spent += cost;
+        }
+    }
+    if !accepted {
+        return Err(EscrowError::EvmRpcFailed);
+    }
+
+    let withdrawal = EvmWithdrawal {
+        tx_hash: format!("0x{}", hex::encode(utils::keccak256(&raw))),
+        from,
+        nonce: transaction.nonce,
+        public,
+        submitted_at: now,
+    };
+    Ok((withdrawal, spent))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_rpc_encoding_and_parsing() {
+        let payload: Value = serde_json::from_str(&rpc_payload("eth_getCode", json!(["0xabc", "0x10"]))).unwrap();
+        assert_eq!(payload["method"], "eth_getCode");
+        assert_eq!(payload["params"][1], "0x10");
+
+        assert_eq!(rpc_result(r#"{"jsonrpc":"2.0","id":1,"result":"0x1b4"}"#).unwrap(), "0x1b4");
+        assert!(rpc_result(r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32000,"message":"bad"}}"#).is_err());
+        assert!(rpc_result("not json").is_err());
+
+        assert_eq!(parse_quantity("0x1b4").unwrap(), 436);
+        assert!(parse_quantity("1b4").is_err());
+        assert!(parse_quantity("0xzz").is_err());
+
+        assert!(has_code("0x363d3d373d3d3d363d73"));
+        assert!(!has_code("0x"));
+        assert!(!has_code(""));
+    }
+
+    #[test]
+    fn test_rlp_encoding() {
+        // Examples from the Ethereum RLP specification
+        assert_eq!(rlp_bytes(b"dog"), vec![0x83, b'd', b'o', b'g']);
+        assert_eq!(rlp_list(&[rlp_bytes(b"cat"), rlp_bytes(b"dog")]), [&[0xc8, 0x83][..], b"cat", &[0x83], b"dog"].concat());
+        assert_eq!(rlp_bytes(&[]), vec![0x80]);
+        assert_eq!(rlp_list(&[]), vec![0xc0]);
+        assert_eq!(rlp_uint(0), vec![0x80]);
+        assert_eq!(rlp_uint(15), vec![0x0f]);
+        assert_eq!(rlp_uint(1024), vec![0x82, 0x04, 0x00]);
+        let long = [b'a'; 56];
+        assert_eq!(rlp_bytes(&long)[..2], [0xb8, 56]);
+    }
+
+    #[test]
+    fn test_withdrawal_calldata_layout() {
+        let immutables = EvmImmutables {
+            order_hash: vec![1; 32],
+            hashlock: vec![2; 32],
+            maker: "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed".to_string(),
+            taker: "0x0000000000000000000000000000000000000001".to_string(),
+            token: "0x0000000000000000000000000000000000000000".to_string(),
+            amount: Nat::from(1_000u64),
+            safety_deposit: Nat::from(5u64),
+            timelocks: vec![3; 32],
+        };
+        let calldata = withdrawal_calldata(false, &[9; 32], &immutables).unwrap();
+        assert_eq!(calldata.len(), 4 + 9 * 32);
+        assert_eq!(calldata[4..36], [9; 32]);
+        assert_eq!(calldata[4 + 3 * 32 + 12..4 + 4 * 32], address_bytes(&immutables.maker).unwrap());
+        assert_eq!(calldata[4 + 7 * 32 - 2..4 + 7 * 32], [0x03, 0xe8]);
+
+        let public = withdrawal_calldata(true, &[9; 32], &immutables).unwrap();
+        assert_ne!(calldata[..4], public[..4]);
+        assert_eq!(calldata[4..], public[4..]);
+    }
+
+    #[test]
+    fn test_signed_transaction_recovers_to_signer() {
+        let signing_key = k256::ecdsa::SigningKey::from_slice(&[7; 32]).unwrap();
+        let transaction = Eip1559Transaction {
+            chain_id: 1,
+            nonce: 3,
+            max_priority_fee_per_gas: 1_000_000_000,
+            max_fee_per_gas: 50_000_000_000,
+            gas_limit: WITHDRAWAL_GAS_LIMIT,
+            to: [0x11; 20],
+            data: vec![0xde, 0xad],
+        };
+        let hash = transaction.signing_hash();
+        let (signature, _) = signing_key.sign_prehash_recoverable(&hash).unwrap();
+        let parity = y_parity(&hash, &signature, signing_key.verifying_key()).unwrap();
+
+        let raw = transaction.encode_signed(parity, &signature);
+        assert_eq!(raw[0], EIP1559_TX_TYPE);
+        assert!(raw.len() > 100);
+        assert_eq!(evm_address(signing_key.verifying_key()).len(), 42);
+    }
+}
@@ -0,0 +1,363 @@
+use crate::ledger;
+use crate::storage;
+use crate::types::{EscrowError, EscrowImmutables, EvmImmutables, Result, MAX_CREATION_FEE};
+use crate::utils;
+
+/// Length of a hashlock or order hash
+pub const HASH_LEN: usize = 32;
+
+/// Longest timelock offset accepted (100 years in seconds); keeps stage starts far from overflow
+pub const MAX_TIMELOCK_OFFSET: u64 = 100 * 365 * 24 * 60 * 60;
+
+/// Deepest Merkle proof accepted for a partial fill; covers trees of 2^32 secrets
+pub const MAX_MERKLE_PROOF_LEN: usize = 32;
+
+/// Settlement transfers of one escrow whose fees must fit alongside its amounts
+const MAX_TRANSFER_LEGS: u32 = 4;
+
+/// Exclusive hold on one escrow while an operation awaits ledger calls between reading and
+/// writing its state. Other operations on the hashlock are rejected until it is dropped, which
+/// also happens when the call traps after an await, as the CDK drops the pending future.
+pub struct EscrowLock {
+    hashlock: Vec<u8>,
+}
+
+impl EscrowLock {
+    pub fn acquire(hashlock: &[u8]) -> Result<Self> {
+        if !storage::lock_escrow(hashlock) {
+            return Err(EscrowError::OperationInProgress);
+        }
+        Ok(Self { hashlock: hashlock.to_vec() })
+    }
+}
+
+impl Drop for EscrowLock {
+    fn drop(&mut self) {
+        storage::unlock_escrow(&self.hashlock);
+    }
+}
+
+fn malformed(field: &str, reason: &str) -> EscrowError {
+    EscrowError::MalformedInput(format!("{} {}", field, reason))
+}
+
+/// A hashlock or order hash must be exactly 32 bytes
+pub fn check_hash(field: &str, bytes: &[u8]) -> Result<()> {
+    if bytes.len() != HASH_LEN {
+        return Err(malformed(field, &format!("must be {} bytes, got {}", HASH_LEN, bytes.len())));
+    }
+    Ok(())
+}
+
+/// A secret must be present and no longer than any hashlock preimage we accept
+pub fn check_secret(secret: &[u8]) -> Result<()> {
+    if secret.is_empty() {
+        return Err(malformed("secret", "must not be empty"));
+    }
+    utils::check_length("secret", secret.len(), utils::MAX_SECRET_LEN)
+}
+
+/// Structural checks on a withdrawal's secret, hashlock and optional order hash
+pub fn check_withdrawal(secret: &[u8], hashlock: &[u8], order_hash: Option<&[u8]>) -> Result<()> {
+    check_secret(secret)?;
+    check_hash("hashlock", hashlock)?;
+    if let Some(order_hash) = order_hash {
+        check_hash("order_hash", order_hash)?;
+    }
+    Ok(())
+}
+
+/// A partial fill's Merkle proof must be a bounded list of 32-byte sibling hashes
+pub fn check_merkle_proof(proof: &[Vec<u8>]) -> Result<()> {
+    if proof.len() > MAX_MERKLE_PROOF_LEN {
+        return Err(malformed("proof", &format!("must have at most {} hashes, got {}", MAX_MERKLE_PROOF_LEN, proof.len())));
+    }
+    proof.iter().try_for_each(|hash| check_hash("proof", hash))
+}
+
+/// A party string must be printable ASCII without whitespace and parse as a principal or an
+/// EVM address, so a malformed principal cannot hide behind padding or lookalike characters
+pub fn check_party(field: &str, party: &str) -> Result<()> {
+    utils::check_length(field, party.len(), utils::MAX_ADDRESS_LEN)?;
+    if party.is_empty() {
+        return Err(malformed(field, "must not be empty"));
+    }
+    if !party.chars().all(|c| c.is_ascii_graphic()) {
+        return Err(malformed(field, "must be printable ASCII without whitespace"));
+    }
+    if !utils::validate_evm_address(party) && utils::validate_principal(party).is_err() {
+        return Err(malformed(field, "is neither a principal nor an EVM address"));
+    }
+    Ok(())
+}
+
+/// An amount must leave room for the deposit, creation fee and every ledger fee of the escrow,
+/// so no sum or fee deduction along its lifecycle can overflow
+pub fn check_amounts(amount: u128, safety_deposit: u128) -> Result<()> {
+    let headroom = u128::from(MAX_CREATION_FEE + ledger::calculate_total_fees(MAX_TRANSFER_LEGS));
+    amount
+        .checked_add(safety_deposit)
+        .and_then(|total| total.checked_add(headroom))
+        .map(|_| ())
+        .ok_or_else(|| malformed("amount", "with safety_deposit overflows"))
+}
+
+/// Structural checks on escrow immutables, run before templates, storage or the ledger see them
+pub fn check_immutables(immutables: &EscrowImmutables) -> Result<()> {
+    check_hash("hashlock", &immutables.hashlock)?;
+    check_hash("order_hash", &immutables.order_hash)?;
+    if let Some(merkle_root) = &immutables.merkle_root {
+        check_hash("merkle_root", merkle_root)?;
+    }
+    if let Some(metadata) = &immutables.metadata {
+        utils::check_length("metadata", metadata.len(), utils::MAX_METADATA_LEN)?;
+    }
+    check_party("maker", &immutables.maker)?;
+    check_party("taker", &immutables.taker)?;
+    utils::check_length("token", immutables.token.len(), utils::MAX_ADDRESS_LEN)?;
+    check_amounts(immutables.amount, immutables.safety_deposit)?;
+
+    let timelocks = &immutables.timelocks;
+    let dst = timelocks.dst.as_ref();
+    for (field, offset) in [
+        ("timelocks.withdrawal", timelocks.withdrawal),
+        ("timelocks.public_withdrawal", timelocks.public_withdrawal),
+        ("timelocks.cancellation", timelocks.cancellation),
+        ("timelocks.public_cancellation", timelocks.public_cancellation.unwrap_or(0)),
+        ("timelocks.dst.withdrawal", dst.map_or(0, |dst| dst.withdrawal)),
+        ("timelocks.dst.public_withdrawal", dst.map_or(0, |dst| dst.public_withdrawal)),
+        ("timelocks.dst.cancellation", dst.map_or(0, |dst| dst.cancellation)),
+    ] {
+        if offset > MAX_TIMELOCK_OFFSET {
+            return Err(malformed(field, &format!("exceeds {} seconds", MAX_TIMELOCK_OFFSET)));
+        }
+    }
+    Ok(())
+}
+
+/// Structural checks on an EVM escrow's immutables: 32-byte words, EVM addresses and amounts
+/// that fit a uint256
+pub fn check_evm_immutables(immutables: &EvmImmutables) -> Result<()> {
+    check_hash("hashlock", &immutables.hashlock)?;
+    check_hash("order_hash", &immutables.order_hash)?;
+    check_hash("timelocks", &immutables.timelocks)?;
+    for (field, address) in [("maker", &immutables.maker), ("taker", &immutables.taker), ("token", &immutables.token)] {
+        if !utils::validate_evm_address(address) {
+            return Err(malformed(field, "must be an EVM address"));
+        }
+    }
+    for (field, amount) in [("amount", &immutables.amount), ("safety_deposit", &immutables.safety_deposit)] {
+        if amount.0.bits() > 256 {
+            return Err(malformed(field, "does not fit a uint256"));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Timelocks;
+
+    /// Deterministic xorshift generator, so failures reproduce without a fuzzing dependency
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        /// Values clustered around the edges where overflow and length bugs live
+        fn edgy_u64(&mut self) -> u64 {
+            match self.next() % 4 {
+                0 => u64::MAX - self.next() % 1_000_000,
+                1 => self.next() % 1_000,
+                2 => 1u64 << (self.next() % 64),
+                _ => self.next(),
+            }
+        }
+
+        /// Plausible values half the time, so well-formed inputs are generated too
+        fn plausible_u64(&mut self) -> u64 {
+            if self.next().is_multiple_of(2) {
+                self.next() % 10_000_000
+            } else {
+                self.edgy_u64()
+            }
+        }
+
+        fn bytes(&mut self, max_len: usize) -> Vec<u8> {
+            let len = (self.next() % (max_len as u64 + 1)) as usize;
+            (0..len).map(|_| self.next() as u8).collect()
+        }
+
+        /// Hashes of the right length half the time, anything up to `max_len` otherwise
+        fn hash(&mut self, max_len: usize) -> Vec<u8> {
+            if self.next().is_multiple_of(2) {
+                (0..HASH_LEN).map(|_| self.next() as u8).collect()
+            } else {
+                self.bytes(max_len)
+            }
+        }
+
+        fn text(&mut self, max_len: usize) -> String {
+            self.bytes(max_len).into_iter().map(|b| char::from(b % 128)).collect()
+        }
+
+        /// Well-formed parties, sometimes with one byte mutated, padded or truncated
+        fn party(&mut self) -> String {
+            const PARTIES: [&str; 3] = [
+                "rrkah-fqaaa-aaaaa-aaaaq-cai",
+                "ryjl3-tyaaa-aaaaa-aaaba-cai",
+                "0x742d35Cc6634C0532925a3b844Bc454e4438f44e",
+            ];
+            let mut party = PARTIES[(self.next() % 3) as usize].as_bytes().to_vec();
+            match self.next() % 5 {
+                0 => {
+                    let at = (self.next() % party.len() as u64) as usize;
+                    party[at] = self.next() as u8 % 128;
+                }
+                1 => party.insert(0, b' '),
+                2 => party.truncate((self.next() % party.len() as u64) as usize),
+                3 => return self.text(160),
+                _ => {}
+            }
+            String::from_utf8_lossy(&party).into_owned()
+        }
+    }
+
+    fn immutables(rng: &mut Rng) -> EscrowImmutables {
+        EscrowImmutables {
+            order_hash: rng.hash(64),
+            hashlock: rng.hash(64),
+            maker: rng.party(),
+            taker: rng.party(),
+            token: rng.text(160),
+            amount: rng.plausible_u64().into(),
+            safety_deposit: rng.plausible_u64().into(),
+            timelocks: Timelocks {
+                withdrawal: rng.plausible_u64(),
+                public_withdrawal: rng.plausible_u64(),
+                cancellation: rng.plausible_u64(),
+                public_cancellation: None,
+                deployed_at: 0,
+                dst: None,
+            },
+            maker_min_amount: None,
+            min_received: None,
+            safety_deposit_funding: None,
+            chain_id: None,
+            icp_token: None,
+            hash_algorithm: None,
+            merkle_root: None,
+            parts_count: None,
+            auction: None,
+            metadata: None,
+        }
+    }
+
+    #[test]
+    fn test_accepted_immutables_never_overflow() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        let mut accepted = 0;
+        for _ in 0..10_000 {
+            let immutables = immutables(&mut rng);
+            if check_immutables(&immutables).is_err() {
+                continue;
+            }
+            accepted += 1;
+            assert_eq!(immutables.hashlock.len(), HASH_LEN);
+            assert!(utils::validate_principal(&immutables.maker).is_ok() || utils::validate_evm_address(&immutables.maker));
+            let total = immutables.amount + immutables.safety_deposit;
+            assert!(total.checked_add(u128::from(MAX_CREATION_FEE + ledger::calculate_total_fees(MAX_TRANSFER_LEGS))).is_some());
+
+            // Stage starts of an escrow deployed now must stay representable
+            let now = 2_000_000_000 * 1_000_000_000;
+            let timelocks = Timelocks { deployed_at: now, ..immutables.timelocks.clone() };
+            assert!(timelocks.cancellation.checked_mul(1_000_000_000).and_then(|ns| ns.checked_add(now)).is_some());
+            let _ = timelocks.cancellation_start();
+        }
+        assert!(accepted > 0, "generator never produced a well-formed input");
+    }
+
+    #[test]
+    fn test_guards_reject_hostile_inputs() {
+        let valid = EscrowImmutables {
+            order_hash: vec![0; 32],
+            hashlock: vec![1; 32],
+            maker: "rrkah-fqaaa-aaaaa-aaaaq-cai".to_string(),
+            taker: "ryjl3-tyaaa-aaaaa-aaaba-cai".to_string(),
+            token: "0x0000000000000000000000000000000000000000".to_string(),
+            amount: 1_000_000,
+            safety_deposit: 100_000,
+            timelocks: Timelocks { withdrawal: 10, public_withdrawal: 20, cancellation: 30, public_cancellation: None, deployed_at: 0, dst: None },
+            maker_min_amount: None,
+            min_received: None,
+            safety_deposit_funding: None,
+            chain_id: None,
+            icp_token: None,
+            hash_algorithm: None,
+            merkle_root: None,
+            parts_count: None,
+            auction: None,
+            metadata: None,
+        };
+        assert!(check_immutables(&valid).is_ok());
+
+        let rejected = |immutables: EscrowImmutables| check_immutables(&immutables).is_err();
+        assert!(rejected(EscrowImmutables { hashlock: vec![1; 1 << 16], ..valid.clone() }));
+        assert!(rejected(EscrowImmutables { amount: u128::MAX, ..valid.clone() }));
+        assert!(rejected(EscrowImmutables { safety_deposit: u128::MAX - 1, ..valid.clone() }));
+        // 18-decimal amounts beyond u64 are representable
+        assert!(!rejected(EscrowImmutables { amount: 1_000 * 10u128.pow(18), ..valid.clone() }));
+        assert!(rejected(EscrowImmutables { maker: format!(" {} ", valid.maker), ..valid.clone() }));
+        assert!(rejected(EscrowImmutables { taker: "ryjl3-tyaaa-aaaaa-aaaba-cai\0".to_string(), ..valid.clone() }));
+        assert!(rejected(EscrowImmutables { taker: "ryjl3-tyaaa-aaaaa-aaaba-caj".to_string(), ..valid.clone() }));
+        let mut far_future = valid.clone();
+        far_future.timelocks.cancellation = u64::MAX / 2;
+        assert!(rejected(far_future));
+        assert!(rejected(EscrowImmutables { merkle_root: Some(vec![5; 31]), ..valid.clone() }));
+        assert!(rejected(EscrowImmutables { metadata: Some(vec![0; utils::MAX_METADATA_LEN + 1]), ..valid.clone() }));
+        assert!(!rejected(EscrowImmutables { metadata: Some(vec![0; utils::MAX_METADATA_LEN]), ..valid.clone() }));
+
+        assert!(check_secret(&[]).is_err());
+        assert!(check_secret(&[7; utils::MAX_SECRET_LEN + 1]).is_err());
+        assert!(check_secret(&[7; 32]).is_ok());
+
+        assert!(check_merkle_proof(&[vec![1; 32], vec![2; 32]]).is_ok());
+        assert!(check_merkle_proof(&[vec![1; 33]]).is_err());
+        assert!(check_merkle_proof(&vec![vec![1; 32]; MAX_MERKLE_PROOF_LEN + 1]).is_err());
+    }
+    #[test]
+    fn test_evm_immutables_checks() {
+        let valid = EvmImmutables {
+            order_hash: vec![0; 32],
+            hashlock: vec![1; 32],
+            maker: "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed".to_string(),
+            taker: "0x0000000000000000000000000000000000000001".to_string(),
+            token: "0x0000000000000000000000000000000000000000".to_string(),
+            amount: candid::Nat::from(1_000_000u64),
+            safety_deposit: candid::Nat::from(100_000u64),
+            timelocks: vec![2; 32],
+        };
+        assert!(check_evm_immutables(&valid).is_ok());
+        assert!(check_evm_immutables(&EvmImmutables { timelocks: vec![2; 31], ..valid.clone() }).is_err());
+        assert!(check_evm_immutables(&EvmImmutables { taker: "rrkah-fqaaa-aaaaa-aaaaq-cai".to_string(), ..valid.clone() }).is_err());
+        // 2^256
+        let too_large: candid::Nat = "115792089237316195423570985008687907853269984665640564039457584007913129639936".parse().unwrap();
+        assert!(check_evm_immutables(&EvmImmutables { amount: too_large, ..valid }).is_err());
+    }
+
+    #[test]
+    fn test_escrow_lock_rejects_concurrent_operations() {
+        let lock = EscrowLock::acquire(&[1; 32]).unwrap();
+        assert!(matches!(EscrowLock::acquire(&[1; 32]), Err(EscrowError::OperationInProgress)));
+        // Other escrows are unaffected
+        assert!(EscrowLock::acquire(&[2; 32]).is_ok());
+        drop(lock);
+        assert!(EscrowLock::acquire(&[1; 32]).is_ok());
+    }
+}
@@ -0,0 +1,159 @@
+use candid::{define_function, CandidType, Deserialize};
+use serde_bytes::ByteBuf;
+
+use crate::storage;
+use crate::types::EventRecord;
+
+/// Events per response chunk; keeps each message well under the response size limit
+pub const EVENTS_PER_CHUNK: usize = 500;
+
+pub type HeaderField = (String, String);
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<HeaderField>,
+    pub body: ByteBuf,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<HeaderField>,
+    pub body: ByteBuf,
+    pub streaming_strategy: Option<StreamingStrategy>,
+}
+
+/// Position of the next chunk of an `/events` stream
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct StreamingCallbackToken {
+    pub next_seq: u64,
+    pub end_seq: u64, // Log length when the stream started, so the stream terminates
+}
+
+define_function!(pub StreamingCallback : (StreamingCallbackToken) -> (StreamingCallbackHttpResponse) query);
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum StreamingStrategy {
+    Callback {
+        callback: StreamingCallback,
+        token: StreamingCallbackToken,
+    },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct StreamingCallbackHttpResponse {
+    pub body: ByteBuf,
+    pub token: Option<StreamingCallbackToken>,
+}
+
+/// Serve `GET /events?after_seq=N` as newline-delimited JSON event records.
+/// The first chunk is returned inline; the rest is streamed via the callback.
+pub fn handle_request(request: &HttpRequest) -> HttpResponse {
+    let (path, query) = request.url.split_once('?').unwrap_or((&request.url, ""));
+
+    if request.method != "GET" || path != "/events" {
+        return text_response(404, "not found");
+    }
+
+    let start_seq = match parse_after_seq(query) {
+        Ok(after_seq) => after_seq.map_or(0, |seq| seq.saturating_add(1)),
+        Err(message) => return text_response(400, message),
+    };
+
+    let (body, token) = next_chunk(StreamingCallbackToken {
+        next_seq: start_seq,
+        end_seq: storage::next_event_seq(),
+    });
+
+    HttpResponse {
+        status_code: 200,
+        headers: vec![("Content-Type".to_string(), "application/x-ndjson".to_string())],
+        body,
+        streaming_strategy: token.map(|token| StreamingStrategy::Callback {
+            callback: StreamingCallback::new(ic_cdk::api::canister_self(), "http_request_streaming_callback".to_string()),
+            token,
+        }),
+    }
+}
+
+/// Next chunk of an `/events` stream
+pub fn handle_streaming_callback(token: StreamingCallbackToken) -> StreamingCallbackHttpResponse {
+    let (body, token) = next_chunk(token);
+    StreamingCallbackHttpResponse { body, token }
+}
+
+fn next_chunk(token: StreamingCallbackToken) -> (ByteBuf, Option<StreamingCallbackToken>) {
+    let mut records = storage::get_events_range(token.next_seq, token.end_seq, EVENTS_PER_CHUNK);
+    let now = crate::utils::current_time();
+    for record in &mut records {
+        storage::redact_public_event(&mut record.event, now);
+    }
+    let next_seq = records.last().map_or(token.end_seq, |record| record.seq + 1);
+    let next = (next_seq < token.end_seq).then_some(StreamingCallbackToken { next_seq, ..token });
+    (ByteBuf::from(encode_ndjson(&records)), next)
+}
+
+/// Value of the `after_seq` query parameter, if present
+fn parse_after_seq(query: &str) -> Result<Option<u64>, &'static str> {
+    match query.split('&').find_map(|pair| pair.strip_prefix("after_seq=")) {
+        Some(value) => value.parse().map(Some).map_err(|_| "after_seq must be an unsigned integer"),
+        None => Ok(None),
+    }
+}
+
+/// One JSON-encoded event record per line
+fn encode_ndjson(records: &[EventRecord]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for record in records {
+        serde_json::to_writer(&mut body, record).expect("event records serialize to JSON");
+        body.push(b'\n');
+    }
+    body
+}
+
+fn text_response(status_code: u16, message: &str) -> HttpResponse {
+    HttpResponse {
+        status_code,
+        headers: vec![("Content-Type".to_string(), "text/plain".to_string())],
+        body: ByteBuf::from(message.as_bytes().to_vec()),
+        streaming_strategy: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{EscrowEvent, EscrowType};
+
+    #[test]
+    fn test_parse_after_seq() {
+        assert_eq!(parse_after_seq(""), Ok(None));
+        assert_eq!(parse_after_seq("after_seq=42"), Ok(Some(42)));
+        assert_eq!(parse_after_seq("format=json&after_seq=7"), Ok(Some(7)));
+        assert!(parse_after_seq("after_seq=-1").is_err());
+    }
+
+    #[test]
+    fn test_ndjson_has_one_record_per_line() {
+        let record = |seq| EventRecord {
+            seq,
+            event: EscrowEvent::EscrowCreated {
+                hashlock: vec![1, 2],
+                escrow_type: EscrowType::Source,
+                maker: "maker".to_string(),
+                taker: "taker".to_string(),
+                amount: 100,
+                metadata: None,
+                timestamp: 5,
+            },
+        };
+        let body = String::from_utf8(encode_ndjson(&[record(0), record(1)])).unwrap();
+        let lines: Vec<&str> = body.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let decoded: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(decoded["seq"], 1);
+        assert_eq!(decoded["event"]["EscrowCreated"]["amount"], 100);
+    }
+}
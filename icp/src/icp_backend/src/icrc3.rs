@@ -0,0 +1,261 @@
+use candid::{define_function, CandidType, Deserialize, Int, Nat, Principal};
+use num_traits::ToPrimitive;
+use serde_bytes::ByteBuf;
+use std::collections::BTreeMap;
+
+use crate::certification;
+use crate::storage;
+use crate::types::EventRecord;
+use crate::utils::sha256;
+
+/// Block type of every block in the log: one escrow event
+pub const EVENT_BLOCK_TYPE: &str = "avginch_event";
+
+/// Most blocks returned by one `icrc3_get_blocks` call, across all requested ranges
+pub const MAX_BLOCKS_PER_RESPONSE: usize = 500;
+
+/// ICRC-3 generic value, the representation blocks are served and hashed in
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum ICRC3Value {
+    Blob(ByteBuf),
+    Text(String),
+    Nat(Nat),
+    Int(Int),
+    Array(Vec<ICRC3Value>),
+    Map(BTreeMap<String, ICRC3Value>),
+}
+
+impl ICRC3Value {
+    /// Representation-independent hash, as specified by ICRC-3
+    pub fn hash(&self) -> [u8; 32] {
+        let digest = match self {
+            ICRC3Value::Blob(bytes) => sha256(bytes),
+            ICRC3Value::Text(text) => sha256(text.as_bytes()),
+            ICRC3Value::Nat(nat) => {
+                let mut leb128 = Vec::new();
+                nat.encode(&mut leb128).expect("writing to a Vec cannot fail");
+                sha256(&leb128)
+            }
+            ICRC3Value::Int(int) => {
+                let mut sleb128 = Vec::new();
+                int.encode(&mut sleb128).expect("writing to a Vec cannot fail");
+                sha256(&sleb128)
+            }
+            ICRC3Value::Array(items) => sha256(&items.iter().flat_map(|item| item.hash()).collect::<Vec<u8>>()),
+            ICRC3Value::Map(entries) => {
+                let mut pairs: Vec<Vec<u8>> = entries
+                    .iter()
+                    .map(|(key, value)| [sha256(key.as_bytes()), value.hash().to_vec()].concat())
+                    .collect();
+                pairs.sort();
+                sha256(&pairs.concat())
+            }
+        };
+        digest.try_into().expect("SHA-256 digests are 32 bytes")
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct GetBlocksArgs {
+    pub start: Nat,
+    pub length: Nat,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct BlockWithId {
+    pub id: Nat,
+    pub block: ICRC3Value,
+}
+
+define_function!(pub GetBlocksCallback : (Vec<GetBlocksArgs>) -> (GetBlocksResult) query);
+
+/// Blocks held by an archive canister; always empty, every block stays in this canister
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ArchivedBlocks {
+    pub args: Vec<GetBlocksArgs>,
+    pub callback: GetBlocksCallback,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct GetBlocksResult {
+    pub log_length: Nat,
+    pub blocks: Vec<BlockWithId>,
+    pub archived_blocks: Vec<ArchivedBlocks>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ICRC3DataCertificate {
+    pub certificate: ByteBuf,
+    pub hash_tree: ByteBuf, // CBOR witness for last_block_index and last_block_hash
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct GetArchivesArgs {
+    pub from: Option<Principal>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ICRC3ArchiveInfo {
+    pub canister_id: Principal,
+    pub start: Nat,
+    pub end: Nat,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SupportedBlockType {
+    pub block_type: String,
+    pub url: String,
+}
+
+/// Name of the event variant, e.g. "EscrowCreated"
+fn event_op(record: &EventRecord) -> String {
+    // Read off the JSON text: a serde_json::Value cannot hold amounts above u64::MAX
+    let json = serde_json::to_string(&record.event).unwrap_or_default();
+    json.strip_prefix("{\"")
+        .and_then(|tagged| tagged.split('"').next())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// The block for an event. It carries the event's certified leaf hash rather than the event
+/// itself, so settled secrets never enter the hash chain; `get_event_proof(id)` serves the rest.
+pub fn event_block(record: &EventRecord, phash: Option<[u8; 32]>) -> ICRC3Value {
+    let mut tx = BTreeMap::from([
+        ("op".to_string(), ICRC3Value::Text(event_op(record))),
+        ("event_hash".to_string(), ICRC3Value::Blob(ByteBuf::from(storage::event_hash(record)))),
+    ]);
+    if let Some(hashlock) = record.event.hashlock() {
+        tx.insert("hashlock".to_string(), ICRC3Value::Blob(ByteBuf::from(hashlock.to_vec())));
+    }
+
+    let mut block = BTreeMap::from([
+        ("btype".to_string(), ICRC3Value::Text(EVENT_BLOCK_TYPE.to_string())),
+        ("ts".to_string(), ICRC3Value::Nat(Nat::from(record.event.timestamp()))),
+        ("tx".to_string(), ICRC3Value::Map(tx)),
+    ]);
+    if let Some(phash) = phash {
+        block.insert("phash".to_string(), ICRC3Value::Blob(ByteBuf::from(phash.to_vec())));
+    }
+    ICRC3Value::Map(block)
+}
+
+/// Block `index` of the log, which is the event with that sequence number
+pub fn get_block(index: u64) -> Option<ICRC3Value> {
+    let record = storage::get_event(index)?;
+    Some(event_block(&record, index.checked_sub(1).and_then(storage::block_hash)))
+}
+
+/// Blocks in the requested ranges, in request order, up to `MAX_BLOCKS_PER_RESPONSE` in total
+pub fn get_blocks(args: &[GetBlocksArgs]) -> GetBlocksResult {
+    let log_length = storage::next_event_seq();
+    let mut blocks = Vec::new();
+    for range in args {
+        let start = range.start.0.to_u64().unwrap_or(u64::MAX);
+        let end = start.saturating_add(range.length.0.to_u64().unwrap_or(u64::MAX)).min(log_length);
+        let room = MAX_BLOCKS_PER_RESPONSE - blocks.len();
+        blocks.extend((start..end).take(room).filter_map(|index| {
+            get_block(index).map(|block| BlockWithId { id: Nat::from(index), block })
+        }));
+    }
+    GetBlocksResult { log_length: Nat::from(log_length), blocks, archived_blocks: Vec::new() }
+}
+
+/// Certificate over the newest block; None while the log is empty or outside a query call
+pub fn tip_certificate() -> Option<ICRC3DataCertificate> {
+    storage::next_event_seq().checked_sub(1)?;
+    let certificate = certification::data_certificate()?;
+    Some(ICRC3DataCertificate {
+        certificate: ByteBuf::from(certificate),
+        hash_tree: ByteBuf::from(certification::encode_witness(&certification::tip_witness())),
+    })
+}
+
+/// Unsigned LEB128 encoding, used for the certified `last_block_index`
+pub fn leb128(mut value: u64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::EscrowEvent;
+
+    #[test]
+    fn test_value_hash_matches_icrc3_examples() {
+        // Test vectors from the ICRC-3 specification
+        assert_eq!(
+            hex::encode(ICRC3Value::Nat(Nat::from(42u64)).hash()),
+            "684888c0ebb17f374298b65ee2807526c066094c701bcc7ebbe1c1095f494fc1"
+        );
+        assert_eq!(
+            hex::encode(ICRC3Value::Text("Hello, World!".to_string()).hash()),
+            "dffd6021bb2bd5b0af676290809ec3a53191dd81c7f70a4b28688a362182986f"
+        );
+        assert_eq!(
+            hex::encode(ICRC3Value::Blob(ByteBuf::from(vec![0x01, 0x02, 0x03, 0x04])).hash()),
+            "9f64a747e1b97f131fabb6b447296c9b6f0201e79fb3c5356e6c77e89b6a806a"
+        );
+        let array = ICRC3Value::Array(vec![
+            ICRC3Value::Nat(Nat::from(3u64)),
+            ICRC3Value::Text("foo".to_string()),
+            ICRC3Value::Blob(ByteBuf::from(vec![0x05, 0x06])),
+        ]);
+        assert_eq!(hex::encode(array.hash()), "514a04011caa503990d446b7dec5d79e19c221ae607fb08b2848c67734d468d6");
+    }
+
+    #[test]
+    fn test_event_block_chains_and_hides_event_body() {
+        let record = EventRecord {
+            seq: 4,
+            event: EscrowEvent::EscrowExpired { hashlock: vec![7; 32], timestamp: 9 },
+        };
+        let first = event_block(&record, None);
+        let chained = event_block(&record, Some(first.hash()));
+        assert_ne!(first.hash(), chained.hash());
+
+        let ICRC3Value::Map(block) = chained else { panic!("blocks are maps") };
+        assert_eq!(block["btype"], ICRC3Value::Text(EVENT_BLOCK_TYPE.to_string()));
+        assert_eq!(block["phash"], ICRC3Value::Blob(ByteBuf::from(first.hash().to_vec())));
+        let ICRC3Value::Map(tx) = &block["tx"] else { panic!("tx is a map") };
+        assert_eq!(tx["op"], ICRC3Value::Text("EscrowExpired".to_string()));
+        assert_eq!(tx["event_hash"], ICRC3Value::Blob(ByteBuf::from(certification::leaf_hash(&record))));
+    }
+
+    #[test]
+    fn test_blocks_chain_through_the_log() {
+        storage::clear_all_storage();
+        let first = storage::next_event_seq();
+        for timestamp in 0..3 {
+            storage::add_event(EscrowEvent::EscrowExpired { hashlock: vec![timestamp as u8; 32], timestamp });
+        }
+
+        let result = get_blocks(&[GetBlocksArgs { start: Nat::from(first), length: Nat::from(10u64) }]);
+        assert_eq!(result.log_length, Nat::from(first + 3));
+        assert_eq!(result.blocks.len(), 3);
+        for pair in result.blocks.windows(2) {
+            let ICRC3Value::Map(block) = &pair[1].block else { panic!("blocks are maps") };
+            assert_eq!(block["phash"], ICRC3Value::Blob(ByteBuf::from(pair[0].block.hash().to_vec())));
+        }
+        assert_eq!(storage::block_hash(first + 2), Some(result.blocks[2].block.hash()));
+
+        // Ranges past the tip are cut short rather than rejected
+        let past = get_blocks(&[GetBlocksArgs { start: Nat::from(first + 2), length: Nat::from(u64::MAX) }]);
+        assert_eq!(past.blocks.len(), 1);
+    }
+
+    #[test]
+    fn test_leb128() {
+        assert_eq!(leb128(0), vec![0]);
+        assert_eq!(leb128(127), vec![0x7f]);
+        assert_eq!(leb128(624_485), vec![0xe5, 0x8e, 0x26]);
+    }
+}
@@ -0,0 +1,323 @@
+use candid::{Nat, Principal};
+use ic_cdk::call::Call;
+use icrc_ledger_types::icrc1::account::{Account, Subaccount};
+use icrc_ledger_types::icrc1::transfer::{Memo, TransferArg, TransferError};
+use icrc_ledger_types::icrc2::transfer_from::{TransferFromArgs, TransferFromError};
+use icrc_ledger_types::icrc3::transactions::{GetTransactionsRequest, GetTransactionsResponse, Transaction};
+use num_traits::ToPrimitive;
+
+use crate::types::{EscrowError, LedgerBlockEvidence, Result};
+
+/// Transactions fetched per page when scanning for a settlement transfer
+const SCAN_PAGE: u64 = 1_000;
+
+/// Most pages scanned back from the ledger tip before giving up
+const MAX_SCAN_PAGES: usize = 10;
+
+/// Default account of `owner`
+pub fn account(owner: Principal) -> Account {
+    Account { owner, subaccount: None }
+}
+
+/// One of this canister's accounts; `None` is its default account
+pub fn own_account(subaccount: Option<Subaccount>) -> Account {
+    Account { owner: ic_cdk::api::canister_self(), subaccount }
+}
+
+fn to_u64(value: &Nat) -> Result<u64> {
+    value.0.to_u64().ok_or_else(|| EscrowError::TransferFailed { reason: format!("{} does not fit in 64 bits", value) })
+}
+
+fn to_u128(value: &Nat) -> Result<u128> {
+    value.0.to_u128().ok_or_else(|| EscrowError::TransferFailed { reason: format!("{} does not fit in 128 bits", value) })
+}
+
+fn created_in_future(ledger_time: u64) -> EscrowError {
+    EscrowError::TransferFailed { reason: format!("transfer created ahead of the ledger's clock ({}); retry shortly", ledger_time) }
+}
+
+/// Transfer `amount` of the token from one of this canister's subaccounts to `to`, created at
+/// `stamp` if given. The ledger charges its current fee on top, like a native ICP transfer.
+/// A duplicate of a transfer already on the ledger reports the original block.
+pub async fn transfer(
+    ledger: Principal,
+    from_subaccount: Option<Subaccount>,
+    to: Account,
+    amount: u128,
+    memo: &[u8],
+    stamp: Option<u64>,
+) -> Result<u64> {
+    let args = TransferArg {
+        from_subaccount,
+        to,
+        fee: None,
+        created_at_time: Some(crate::ledger::created_at_time(stamp)),
+        memo: Some(Memo::from(memo.to_vec())),
+        amount: Nat::from(amount),
+    };
+
+    let response = Call::unbounded_wait(ledger, "icrc1_transfer")
+        .with_arg(&args)
+        .await
+        .map_err(|e| {
+            ic_cdk::api::debug_print(format!("Canister call error: {:?}", e));
+            EscrowError::CanisterCallError
+        })?;
+
+    match response.candid::<std::result::Result<Nat, TransferError>>() {
+        Ok(Ok(block_index)) | Ok(Err(TransferError::Duplicate { duplicate_of: block_index })) => to_u64(&block_index),
+        Ok(Err(TransferError::CreatedInFuture { ledger_time })) => Err(created_in_future(ledger_time)),
+        Ok(Err(e)) => {
+            ic_cdk::api::debug_print(format!("ICRC-1 transfer error: {:?}", e));
+            Err(EscrowError::ledger_rejected("icrc1_transfer", &e))
+        }
+        Err(_) => Err(EscrowError::CanisterCallError),
+    }
+}
+
+/// Pull `amount` of the token from `from` into one of this canister's subaccounts.
+/// ICRC-1 has no pull transfer, so `from` must first approve this canister (ICRC-2)
+/// for `amount` plus the ledger fee.
+pub async fn transfer_from(
+    ledger: Principal,
+    from: Principal,
+    to_subaccount: Option<Subaccount>,
+    amount: u128,
+    memo: &[u8],
+) -> Result<u64> {
+    let args = TransferFromArgs {
+        spender_subaccount: None,
+        from: account(from),
+        to: own_account(to_subaccount),
+        amount: Nat::from(amount),
+        fee: None,
+        memo: Some(Memo::from(memo.to_vec())),
+        created_at_time: Some(crate::ledger::created_at_time(None)),
+    };
+
+    let response = Call::unbounded_wait(ledger, "icrc2_transfer_from")
+        .with_arg(&args)
+        .await
+        .map_err(|e| {
+            ic_cdk::api::debug_print(format!("Canister call error: {:?}", e));
+            EscrowError::CanisterCallError
+        })?;
+
+    match response.candid::<std::result::Result<Nat, TransferFromError>>() {
+        Ok(Ok(block_index)) | Ok(Err(TransferFromError::Duplicate { duplicate_of: block_index })) => to_u64(&block_index),
+        Ok(Err(TransferFromError::CreatedInFuture { ledger_time })) => Err(created_in_future(ledger_time)),
+        // The allowance must also cover the ledger fee, which is not known here
+        Ok(Err(TransferFromError::InsufficientAllowance { allowance })) => Err(EscrowError::InsufficientBalance {
+            required: amount,
+            available: allowance.0.to_u128().unwrap_or(u128::MAX),
+        }),
+        Ok(Err(e)) => {
+            ic_cdk::api::debug_print(format!("ICRC-2 transfer_from error: {:?}", e));
+            Err(EscrowError::ledger_rejected("icrc2_transfer_from", &e))
+        }
+        Err(_) => Err(EscrowError::CanisterCallError),
+    }
+}
+
+/// Current transfer fee of the token's ledger
+pub async fn fee(ledger: Principal) -> Result<u128> {
+    let response = Call::bounded_wait(ledger, "icrc1_fee")
+        .await
+        .map_err(|_| EscrowError::CanisterCallError)?;
+    let fee: Nat = response.candid().map_err(|_| EscrowError::CanisterCallError)?;
+    to_u128(&fee)
+}
+
+/// Token balance of one of this canister's subaccounts
+pub async fn get_balance(ledger: Principal, subaccount: Option<Subaccount>) -> Result<u128> {
+    let response = Call::bounded_wait(ledger, "icrc1_balance_of")
+        .with_arg(own_account(subaccount))
+        .await
+        .map_err(|_| EscrowError::CanisterCallError)?;
+    let balance: Nat = response.candid().map_err(|_| EscrowError::CanisterCallError)?;
+    to_u128(&balance)
+}
+
+async fn get_transactions(ledger: Principal, start: u64, length: u64) -> Result<GetTransactionsResponse> {
+    let args = GetTransactionsRequest { start: Nat::from(start), length: Nat::from(length) };
+    let response = Call::bounded_wait(ledger, "get_transactions")
+        .with_arg(&args)
+        .await
+        .map_err(|_| EscrowError::CanisterCallError)?;
+    response.candid().map_err(|_| EscrowError::CanisterCallError)
+}
+
+/// Whether a recorded memo is `memo`. Transfers made before memos carried the full
+/// hashlock wrote only its first 8 bytes, which still match.
+fn memo_matches(recorded: &Memo, memo: &[u8]) -> bool {
+    let recorded = recorded.0.as_slice();
+    recorded == memo || (memo.len() > 8 && recorded == &memo[..8])
+}
+
+/// Whether `transaction` is a transfer out of `from` carrying `memo`
+fn is_transfer_from(transaction: &Transaction, from: &Account, memo: &[u8]) -> bool {
+    transaction.transfer.as_ref().is_some_and(|transfer| {
+        transfer.from == *from && transfer.memo.as_ref().is_some_and(|recorded| memo_matches(recorded, memo))
+    })
+}
+
+/// Find a transfer out of one of this canister's subaccounts with `memo` made at or after
+/// `since`, scanning back from the ledger tip. Returns its transaction index if found.
+pub async fn find_recent_transfer(
+    ledger: Principal,
+    from_subaccount: Option<Subaccount>,
+    memo: &[u8],
+    since: u64,
+) -> Result<Option<u64>> {
+    let from = own_account(from_subaccount);
+    let tip = to_u64(&get_transactions(ledger, 0, 0).await?.log_length)?;
+
+    let mut end = tip;
+    for _ in 0..MAX_SCAN_PAGES {
+        if end == 0 {
+            break;
+        }
+        let start = end.saturating_sub(SCAN_PAGE);
+        let response = get_transactions(ledger, start, end - start).await?;
+        let first_index = to_u64(&response.first_index)?;
+
+        for (offset, transaction) in response.transactions.iter().enumerate().rev() {
+            if transaction.timestamp < since {
+                return Ok(None);
+            }
+            if is_transfer_from(transaction, &from, memo) {
+                return Ok(Some(first_index + offset as u64));
+            }
+        }
+
+        // Anything older has been archived, long before a settlement could be stuck
+        if first_index > start {
+            break;
+        }
+        end = start;
+    }
+    Ok(None)
+}
+
+/// Fetch a ledger transaction and flatten it for evidence export.
+/// Archived transactions are reported as not found.
+pub async fn fetch_transaction_evidence(ledger: Principal, index: u64) -> Result<LedgerBlockEvidence> {
+    let response = get_transactions(ledger, index, 1).await?;
+    let transaction = match to_u64(&response.first_index)? == index {
+        true => response.transactions.into_iter().next(),
+        false => None,
+    };
+    Ok(transaction_evidence(index, transaction))
+}
+
+/// Flatten a ledger transaction into its evidence representation
+fn transaction_evidence(index: u64, transaction: Option<Transaction>) -> LedgerBlockEvidence {
+    let mut evidence = LedgerBlockEvidence {
+        index,
+        found: transaction.is_some(),
+        operation: String::new(),
+        memo: 0,
+        from: None,
+        to: None,
+        amount: 0,
+        fee: 0,
+        timestamp: 0,
+        parent_hash: None,
+    };
+    let Some(transaction) = transaction else { return evidence };
+
+    evidence.timestamp = transaction.timestamp;
+    evidence.operation = match transaction.kind.as_str() {
+        "transfer" => "Transfer".to_string(),
+        "mint" => "Mint".to_string(),
+        "burn" => "Burn".to_string(),
+        "approve" => "Approve".to_string(),
+        other => other.to_string(),
+    };
+    if let Some(transfer) = transaction.transfer {
+        evidence.memo = transfer.memo.as_ref().map(memo_value).unwrap_or(0);
+        evidence.from = Some(transfer.from.to_string());
+        evidence.to = Some(transfer.to.to_string());
+        evidence.amount = transfer.amount.0.to_u128().unwrap_or(u128::MAX);
+        evidence.fee = transfer.fee.and_then(|fee| fee.0.to_u128()).unwrap_or(0);
+    }
+    evidence
+}
+
+/// Read back a memo written by `transfer`; other memos yield their first 8 bytes
+fn memo_value(memo: &Memo) -> u64 {
+    let mut bytes = [0u8; 8];
+    let len = memo.0.len().min(8);
+    bytes[..len].copy_from_slice(&memo.0[..len]);
+    u64::from_be_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decoded from Candid text as a ledger reply would be, so fields a newer
+    /// icrc-ledger-types adds to `Transaction` leave the fixture intact
+    fn transfer_transaction(from: Principal, memo: &[u8]) -> Transaction {
+        let memo: String = memo.iter().map(|byte| format!("\\{:02x}", byte)).collect();
+        let text = format!(
+            r#"(record {{
+                kind = "transfer";
+                timestamp = 2 : nat64;
+                transfer = opt record {{
+                    amount = 1_000 : nat;
+                    from = record {{ owner = principal "{}" }};
+                    to = record {{ owner = principal "{}" }};
+                    memo = opt blob "{}";
+                    fee = opt (10 : nat);
+                }};
+            }})"#,
+            from,
+            Principal::from_slice(&[2]),
+            memo,
+        );
+        let args = candid_parser::parse_idl_args(&text).expect("fixture must parse");
+        candid::decode_one(&args.to_bytes().expect("fixture must encode")).expect("fixture must decode")
+    }
+
+    #[test]
+    fn test_transfer_matching_and_evidence() {
+        let canister = Principal::from_slice(&[1]);
+        let memo = 42u64.to_be_bytes();
+        let transaction = transfer_transaction(canister, &memo);
+        assert!(is_transfer_from(&transaction, &account(canister), &memo));
+        assert!(!is_transfer_from(&transaction, &account(canister), &43u64.to_be_bytes()));
+        assert!(!is_transfer_from(&transaction, &account(Principal::from_slice(&[3])), &memo));
+
+        // Transfers out of another subaccount of the same canister do not match
+        let escrow_account = Account { owner: canister, subaccount: Some([7; 32]) };
+        assert!(!is_transfer_from(&transaction, &escrow_account, &memo));
+
+        let evidence = transaction_evidence(7, Some(transaction));
+        assert!(evidence.found);
+        assert_eq!(evidence.operation, "Transfer");
+        assert_eq!(evidence.memo, 42);
+        assert_eq!(evidence.amount, 1_000);
+        assert_eq!(evidence.fee, 10);
+        assert_eq!(evidence.timestamp, 2);
+
+        assert!(!transaction_evidence(8, None).found);
+    }
+
+    #[test]
+    fn test_full_memo_matches_legacy_prefix() {
+        let canister = Principal::from_slice(&[1]);
+        let full: Vec<u8> = [0x02].into_iter().chain([9u8; 32]).collect();
+
+        let current = transfer_transaction(canister, &full);
+        assert!(is_transfer_from(&current, &account(canister), &full));
+
+        // A transfer written with the 8-byte memo before the upgrade is still found
+        let legacy = transfer_transaction(canister, &full[..8]);
+        assert!(is_transfer_from(&legacy, &account(canister), &full));
+
+        let other_escrow: Vec<u8> = full[..8].iter().copied().chain([1u8; 25]).collect();
+        assert!(!is_transfer_from(&current, &account(canister), &other_escrow));
+        assert_eq!(transaction_evidence(3, Some(current)).memo, u64::from_be_bytes(full[..8].try_into().unwrap()));
+    }
+}
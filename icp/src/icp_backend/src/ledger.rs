@@ -2,12 +2,13 @@ use candid::{CandidType, Deserialize, Principal, Nat};
 use ic_cdk::{call, id};
 use num_traits::ToPrimitive;
 
-use crate::types::{EscrowError, Result};
+use crate::icrc_ledger;
+use crate::utils::icp_amount;
+use crate::types::{EscrowError, EscrowEvent, LedgerBlockEvidence, LedgerFeePayer, Result};
 
 use ic_cdk_macros::*;
 use ic_ledger_types::{
-    AccountIdentifier, BlockIndex, Memo, Subaccount, Tokens, DEFAULT_SUBACCOUNT,
-    MAINNET_LEDGER_CANISTER_ID
+    AccountIdentifier, BlockIndex, Memo, Subaccount, Timestamp, Tokens, DEFAULT_SUBACCOUNT,
 };
 
 
@@ -27,9 +28,9 @@ struct AccountBalanceArgs {
     account: String,
 }
 
-/// ICP Ledger canister ID (mainnet)
+/// ICP ledger canister ID, as configured (the mainnet ledger unless deployed against another)
 fn get_icp_ledger_canister_id() -> Principal {
-    Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap() // ICP Ledger canister ID
+    crate::storage::get_config().icp_ledger
 }
 
 /// Standard ICP transfer fee (0.0001 ICP)
@@ -38,6 +39,49 @@ pub const TRANSFER_FEE: u64 = 10_000;
 /// Minimum transferable amount (must be greater than fee)
 pub const MIN_TRANSFER_AMOUNT: u64 = TRANSFER_FEE + 1;
 
+/// Age past which a transfer's creation time is no longer reused. The ledgers deduplicate
+/// transfers created within the last 24 hours and reject older ones.
+pub const TRANSFER_STAMP_TTL: u64 = 23 * 60 * 60 * 1_000_000_000;
+
+/// Creation time to send a transfer with: its stamp, or the replica's own clock (never the
+/// simulated one, which the ledger would see as in the future)
+pub fn created_at_time(stamp: Option<u64>) -> u64 {
+    stamp.unwrap_or_else(ic_cdk::api::time)
+}
+
+/// Block index of an ICP ledger transfer. A duplicate of a transfer already on the ledger (same
+/// arguments and creation time) reports the original block, so a retried transfer is not paid twice.
+fn icp_transfer_result(result: std::result::Result<BlockIndex, ic_ledger_types::TransferError>) -> Result<u64> {
+    match result {
+        Ok(block_index) => Ok(block_index),
+        Err(ic_ledger_types::TransferError::TxDuplicate { duplicate_of }) => Ok(duplicate_of),
+        Err(ic_ledger_types::TransferError::TxCreatedInFuture) => Err(EscrowError::TransferFailed {
+            reason: "transfer created ahead of the ledger's clock; retry shortly".to_string(),
+        }),
+        Err(e) => Err(EscrowError::ledger_rejected("transfer", &e)),
+    }
+}
+
+/// Send a transfer on the ICP ledger
+async fn send_icp(args: &ic_ledger_types::TransferArgs) -> Result<u64> {
+    match ic_ledger_types::transfer(get_icp_ledger_canister_id(), args).await {
+        Ok(result) => {
+            match &result {
+                Err(ic_ledger_types::TransferError::TxDuplicate { duplicate_of }) => {
+                    ic_cdk::api::debug_print(format!("Transfer already on the ledger at block {}", duplicate_of));
+                }
+                Err(e) => ic_cdk::api::debug_print(format!("Canister call error: {:?}", e)),
+                Ok(_) => {}
+            }
+            icp_transfer_result(result)
+        }
+        Err(e) => {
+            ic_cdk::api::debug_print(format!("Canister call error: {:?}", e));
+            Err(EscrowError::CanisterCallError)
+        },
+    }
+}
+
 /// Create a simple account representation for the principal
 fn get_account_string(principal: &Principal) -> String {
     hex::encode(principal.as_slice())
@@ -45,31 +89,39 @@ fn get_account_string(principal: &Principal) -> String {
 
 /// Transfer ICP from the caller to this canister
 pub async fn transfer_from_caller(amount: u64, memo: u64) -> Result<u64> {
+    transfer_from_caller_into(DEFAULT_SUBACCOUNT, amount, memo).await
+}
+
+/// Transfer ICP from the caller into one of this canister's subaccounts
+pub async fn transfer_from_caller_into(to_subaccount: Subaccount, amount: u64, memo: u64) -> Result<u64> {
     let canister_id = ic_cdk::api::canister_self();
-    let to_subaccount = DEFAULT_SUBACCOUNT;
     let transfer_args = ic_ledger_types::TransferArgs {
         memo: Memo(memo),
         amount: Tokens::from_e8s(amount),
         fee: Tokens::from_e8s(TRANSFER_FEE),
         from_subaccount: None,
         to: AccountIdentifier::new(&canister_id, &to_subaccount),
-        created_at_time: None,
+        created_at_time: Some(Timestamp { timestamp_nanos: created_at_time(None) }),
     };
 
-    match ic_ledger_types::transfer(get_icp_ledger_canister_id(), &transfer_args).await {
-        Ok(result) => result.map_err(|e| {
-            ic_cdk::api::debug_print(format!("Canister call error: {:?}", e));
-            EscrowError::CanisterCallSuccLedgerError
-        }),
-        Err(e) => {
-            ic_cdk::api::debug_print(format!("Canister call error: {:?}", e));
-            Err(EscrowError::CanisterCallError)
-        },
-    }
+    send_icp(&transfer_args).await
 }
 
-/// Transfer ICP from this canister to a recipient
-pub async fn transfer_to(recipient: Principal, amount: u64, memo: u64) -> Result<u64> {
+/// Transfer ICP from this canister to a recipient, created at `stamp` if given
+pub async fn transfer_to(recipient: Principal, amount: u64, memo: u64, stamp: Option<u64>) -> Result<u64> {
+    // Never dip into the operational reserve kept for refund/cancellation fees
+    let config = crate::storage::get_config();
+    let mut reserve_alert = None;
+    if config.operational_reserve > 0 {
+        let balance = get_balance().await?;
+        let remaining = check_reserve(balance, amount, config.operational_reserve)?;
+        
+        let alert_level = config.operational_reserve.saturating_add(config.reserve_alert_margin);
+        if balance >= alert_level && remaining < alert_level {
+            reserve_alert = Some(remaining);
+        }
+    }
+
     let to_subaccount = DEFAULT_SUBACCOUNT;
     let transfer_args = ic_ledger_types::TransferArgs {
         memo: Memo(memo),
@@ -77,46 +129,373 @@ pub async fn transfer_to(recipient: Principal, amount: u64, memo: u64) -> Result
         fee: Tokens::from_e8s(TRANSFER_FEE),
         from_subaccount: None,
         to: AccountIdentifier::new(&recipient, &to_subaccount),
-        created_at_time: None,
+        created_at_time: Some(Timestamp { timestamp_nanos: created_at_time(stamp) }),
     };
 
-    match ic_ledger_types::transfer(get_icp_ledger_canister_id(), &transfer_args).await {
-        Ok(result) => result.map_err(|e| {
-            ic_cdk::api::debug_print(format!("Canister call error: {:?}", e));
-            EscrowError::CanisterCallSuccLedgerError
-        }),
-        Err(e) => {
-            ic_cdk::api::debug_print(format!("Canister call error: {:?}", e));
-            Err(EscrowError::CanisterCallError)
-        },
+    let block_index = send_icp(&transfer_args).await?;
+
+    // Alert once when this transfer takes the balance into the reserve's alert margin
+    if let Some(balance) = reserve_alert {
+        crate::storage::add_event(EscrowEvent::ReserveLow {
+            balance,
+            reserve: config.operational_reserve,
+            timestamp: crate::utils::current_time(),
+        });
+    }
+
+    Ok(block_index)
+}
+
+/// Subaccount isolating one escrow's funds, derived from its hashlock
+pub fn escrow_subaccount(hashlock: &[u8]) -> Subaccount {
+    let mut bytes = [0u8; 32];
+    bytes.copy_from_slice(&crate::utils::sha256(&[b"avginch/escrow".as_slice(), hashlock].concat()));
+    Subaccount(bytes)
+}
+
+/// Subaccount staging a batch creation's aggregate deposit until it is split across its
+/// escrows, derived from the batch's first hashlock
+pub fn batch_subaccount(hashlock: &[u8]) -> Subaccount {
+    let mut bytes = [0u8; 32];
+    bytes.copy_from_slice(&crate::utils::sha256(&[b"avginch/batch".as_slice(), hashlock].concat()));
+    Subaccount(bytes)
+}
+
+/// The same subaccount as ICRC-1 expresses it (the default subaccount is `None`)
+fn icrc_subaccount(subaccount: Subaccount) -> Option<[u8; 32]> {
+    (subaccount != DEFAULT_SUBACCOUNT).then_some(subaccount.0)
+}
+
+/// Pull an escrow's token from the caller into `to_subaccount`: native ICP when `token` is None,
+/// otherwise an ICRC-2 `transfer_from` on the token's ledger (the caller must have approved this canister)
+pub async fn transfer_token_from_caller(
+    token: Option<Principal>,
+    to_subaccount: Subaccount,
+    amount: u128,
+    memo: &TransferMemo,
+) -> Result<u64> {
+    match token {
+        None => transfer_from_caller_into(to_subaccount, icp_amount(amount)?, memo.value).await,
+        Some(ledger) => {
+            let caller = ic_cdk::api::msg_caller();
+            icrc_ledger::transfer_from(ledger, caller, icrc_subaccount(to_subaccount), amount, &memo.icrc_blob()).await
+        }
+    }
+}
+
+/// Send an escrow's token from `from_subaccount` to `recipient`: native ICP when `token` is None,
+/// otherwise an `icrc1_transfer` on the token's ledger. Only ICP leaving the default account
+/// is checked against the operational reserve; escrow subaccounts hold nothing but their own funds.
+pub async fn transfer_token_to(
+    token: Option<Principal>,
+    from_subaccount: Subaccount,
+    recipient: Principal,
+    amount: u128,
+    memo: &TransferMemo,
+) -> Result<u64> {
+    match token {
+        None if from_subaccount == DEFAULT_SUBACCOUNT => transfer_to(recipient, icp_amount(amount)?, memo.value, memo.created_at_time).await,
+        None => transfer_from_subaccount(from_subaccount, recipient, DEFAULT_SUBACCOUNT, icp_amount(amount)?, memo.value, memo.created_at_time).await,
+        Some(ledger) => {
+            let to = icrc_ledger::account(recipient);
+            icrc_ledger::transfer(ledger, icrc_subaccount(from_subaccount), to, amount, &memo.icrc_blob(), memo.created_at_time).await
+        }
+    }
+}
+
+/// Move `token` from the canister's default account into an escrow subaccount, covering
+/// ledger fees the escrow's own funds do not
+pub async fn top_up_subaccount(token: Option<Principal>, subaccount: Subaccount, amount: u128, memo: &TransferMemo) -> Result<u64> {
+    let canister = ic_cdk::api::canister_self();
+    match token {
+        None => transfer_from_subaccount(DEFAULT_SUBACCOUNT, canister, subaccount, icp_amount(amount)?, memo.value, memo.created_at_time).await,
+        Some(ledger) => {
+            let to = icrc_ledger::own_account(icrc_subaccount(subaccount));
+            icrc_ledger::transfer(ledger, None, to, amount, &memo.icrc_blob(), memo.created_at_time).await
+        }
+    }
+}
+
+/// Move `token` between two of this canister's subaccounts; the ledger fee is paid from `from_subaccount`
+pub async fn move_token(
+    token: Option<Principal>,
+    from_subaccount: Subaccount,
+    to_subaccount: Subaccount,
+    amount: u128,
+    memo: &TransferMemo,
+) -> Result<u64> {
+    match token {
+        None => {
+            let canister = ic_cdk::api::canister_self();
+            transfer_from_subaccount(from_subaccount, canister, to_subaccount, icp_amount(amount)?, memo.value, memo.created_at_time).await
+        }
+        Some(ledger) => {
+            let to = icrc_ledger::own_account(icrc_subaccount(to_subaccount));
+            icrc_ledger::transfer(ledger, icrc_subaccount(from_subaccount), to, amount, &memo.icrc_blob(), memo.created_at_time).await
+        }
+    }
+}
+
+/// Ledger fee of one transfer of `token`
+pub async fn token_transfer_fee(token: Option<Principal>) -> Result<u128> {
+    match token {
+        None => Ok(u128::from(TRANSFER_FEE)),
+        Some(ledger) => icrc_ledger::fee(ledger).await,
+    }
+}
+
+/// Balance of `token` held in one of this canister's subaccounts
+pub async fn get_token_balance(token: Option<Principal>, subaccount: Subaccount) -> Result<u128> {
+    match token {
+        None => get_subaccount_balance(subaccount).await.map(u128::from),
+        Some(ledger) => icrc_ledger::get_balance(ledger, icrc_subaccount(subaccount)).await,
+    }
+}
+
+/// Look for a transfer of `token` out of `from_subaccount` with `memo`, made at or after `since`
+pub async fn find_recent_token_transfer(
+    token: Option<Principal>,
+    from_subaccount: Subaccount,
+    memo: &TransferMemo,
+    since: u64,
+) -> Result<Option<u64>> {
+    match token {
+        None => find_recent_transfer(from_subaccount, memo.value, since).await,
+        Some(ledger) => icrc_ledger::find_recent_transfer(ledger, icrc_subaccount(from_subaccount), &memo.icrc_blob(), since).await,
+    }
+}
+
+/// Evidence for block `index` of `token`'s ledger
+pub async fn fetch_token_block_evidence(token: Option<Principal>, index: u64) -> Result<LedgerBlockEvidence> {
+    match token {
+        None => fetch_block_evidence(index).await,
+        Some(ledger) => icrc_ledger::fetch_transaction_evidence(ledger, index).await,
+    }
+}
+
+/// Account a deposit into one of this canister's subaccounts credits, as block evidence
+/// of `token`'s ledger renders it
+fn deposit_account_text(token: Option<Principal>, subaccount: Subaccount) -> String {
+    match token {
+        None => AccountIdentifier::new(&ic_cdk::api::canister_self(), &subaccount).to_string(),
+        Some(_) => icrc_ledger::own_account(icrc_subaccount(subaccount)).to_string(),
     }
+}
+
+/// Amount a block transferred to `account`; anything else is not a deposit there
+fn deposit_amount(evidence: &LedgerBlockEvidence, account: &str) -> Result<u128> {
+    let is_deposit = evidence.found && evidence.operation == "Transfer" && evidence.to.as_deref() == Some(account);
+    is_deposit.then_some(evidence.amount).ok_or(EscrowError::DepositNotFound)
+}
+
+/// Check that ledger block `index` of `token` is a transfer into one of this canister's
+/// subaccounts, returning the amount it deposited
+pub async fn verify_token_deposit(token: Option<Principal>, subaccount: Subaccount, index: u64) -> Result<u128> {
+    let evidence = fetch_token_block_evidence(token, index).await?;
+    deposit_amount(&evidence, &deposit_account_text(token, subaccount))
+}
+
+/// Canister subaccount holding treasury-supplied ICP for treasury swaps, kept apart from escrowed funds
+pub const TREASURY_SWAP_SUBACCOUNT: Subaccount = Subaccount(*b"avginch/treasury-swaps\0\0\0\0\0\0\0\0\0\0");
+
+/// Account the treasury tops up to fund treasury swaps
+pub fn treasury_swap_account() -> AccountIdentifier {
+    AccountIdentifier::new(&ic_cdk::api::canister_self(), &TREASURY_SWAP_SUBACCOUNT)
+}
+
+/// Move ICP from the treasury swap subaccount into an escrow's subaccount to fund a treasury swap
+pub async fn fund_from_treasury_swaps(escrow_subaccount: Subaccount, amount: u64, memo: u64) -> Result<u64> {
+    transfer_from_subaccount(TREASURY_SWAP_SUBACCOUNT, ic_cdk::api::canister_self(), escrow_subaccount, amount, memo, None).await
+}
+
+/// Return ICP from an escrow's subaccount to the treasury swap subaccount
+pub async fn return_to_treasury_swaps(escrow_subaccount: Subaccount, amount: u64, memo: &TransferMemo) -> Result<u64> {
+    let canister = ic_cdk::api::canister_self();
+    transfer_from_subaccount(escrow_subaccount, canister, TREASURY_SWAP_SUBACCOUNT, amount, memo.value, memo.created_at_time).await
+}
+
+/// Send unused treasury swap funds back to `recipient`, never touching the escrow account
+pub async fn sweep_treasury_swaps(recipient: Principal, amount: u64, memo: u64) -> Result<u64> {
+    transfer_from_subaccount(TREASURY_SWAP_SUBACCOUNT, recipient, DEFAULT_SUBACCOUNT, amount, memo, None).await
+}
+
+/// Canister subaccount collecting creation fees, so fee accounting never touches escrowed funds
+pub const FEE_SUBACCOUNT: Subaccount = Subaccount(*b"avginch/protocol-fees\0\0\0\0\0\0\0\0\0\0\0");
+
+/// Account creation fees are collected into
+pub fn fee_account() -> AccountIdentifier {
+    AccountIdentifier::new(&ic_cdk::api::canister_self(), &FEE_SUBACCOUNT)
+}
+
+/// Collect a creation fee from the caller into the fee subaccount
+pub async fn collect_fee(amount: u64, memo: u64) -> Result<u64> {
+    transfer_from_caller_into(FEE_SUBACCOUNT, amount, memo).await
+}
 
+/// Send `amount` of collected fees to `recipient`; the ledger fee is paid from the fee subaccount too
+pub async fn withdraw_fees(recipient: Principal, amount: u64, memo: u64) -> Result<u64> {
+    transfer_from_subaccount(FEE_SUBACCOUNT, recipient, DEFAULT_SUBACCOUNT, amount, memo, None).await
 }
 
-/// Get ICP balance of this canister
+async fn transfer_from_subaccount(
+    from_subaccount: Subaccount,
+    owner: Principal,
+    to_subaccount: Subaccount,
+    amount: u64,
+    memo: u64,
+    stamp: Option<u64>,
+) -> Result<u64> {
+    let transfer_args = ic_ledger_types::TransferArgs {
+        memo: Memo(memo),
+        amount: Tokens::from_e8s(amount),
+        fee: Tokens::from_e8s(TRANSFER_FEE),
+        from_subaccount: Some(from_subaccount),
+        to: AccountIdentifier::new(&owner, &to_subaccount),
+        created_at_time: Some(Timestamp { timestamp_nanos: created_at_time(stamp) }),
+    };
+
+    send_icp(&transfer_args).await
+}
+
+/// Check that sending `amount` plus the ledger fee leaves at least `reserve` behind.
+/// Returns the balance remaining after the transfer.
+pub fn check_reserve(balance: u64, amount: u64, reserve: u64) -> Result<u64> {
+    balance
+        .checked_sub(amount.saturating_add(TRANSFER_FEE))
+        .filter(|remaining| *remaining >= reserve)
+        .ok_or(EscrowError::ReserveBreached)
+}
+
+/// Get ICP balance of this canister's default account
 pub async fn get_balance() -> Result<u64> {
-    let canister_id = id();
-    let account_string = get_account_string(&canister_id);
+    get_subaccount_balance(DEFAULT_SUBACCOUNT).await
+}
 
-    let args = AccountBalanceArgs {
-        account: account_string,
+/// Get ICP balance of one of this canister's subaccounts
+pub async fn get_subaccount_balance(subaccount: Subaccount) -> Result<u64> {
+    let args = ic_ledger_types::AccountBalanceArgs {
+        account: AccountIdentifier::new(&id(), &subaccount),
     };
 
-    let result: std::result::Result<(Nat,), (ic_cdk::api::call::RejectionCode, String)> = call(
-        get_icp_ledger_canister_id(),
-        "account_balance",
-        (args,)
-    ).await;
+    match ic_ledger_types::account_balance(get_icp_ledger_canister_id(), &args).await {
+        Ok(balance) => Ok(balance.e8s()),
+        Err(e) => Err(EscrowError::TransferFailed { reason: format!("account_balance call failed: {:?}", e) }),
+    }
+}
 
-    match result {
-        Ok((balance,)) => {
-            match balance.0.to_u64() {
-                Some(bal) => Ok(bal),
-                None => Err(EscrowError::TransferFailed),
+/// Fetch a ledger block (following archive callbacks) and flatten it for evidence export
+pub async fn fetch_block_evidence(index: u64) -> Result<LedgerBlockEvidence> {
+    let args = ic_ledger_types::GetBlocksArgs { start: index, length: 1 };
+    let response = ic_ledger_types::query_blocks(get_icp_ledger_canister_id(), &args)
+        .await
+        .map_err(|_| EscrowError::CanisterCallError)?;
+
+    let mut block = response.blocks.into_iter().next();
+    if block.is_none() {
+        if let Some(range) = response.archived_blocks.iter().find(|range| {
+            index >= range.start && index < range.start + range.length
+        }) {
+            let archived = ic_ledger_types::query_archived_blocks(&range.callback, &args)
+                .await
+                .map_err(|_| EscrowError::CanisterCallError)?;
+            block = archived.ok().and_then(|range| range.blocks.into_iter().next());
+        }
+    }
+
+    Ok(block_evidence(index, block))
+}
+
+/// Ledger blocks fetched per page when scanning for a settlement transfer
+const SCAN_PAGE: u64 = 1_000;
+
+/// Most pages scanned back from the ledger tip before giving up
+const MAX_SCAN_PAGES: usize = 10;
+
+/// Find a transfer out of one of this canister's subaccounts with `memo` made at or after
+/// `since`, scanning back from the ledger tip. Returns its block index if found.
+pub async fn find_recent_transfer(from_subaccount: Subaccount, memo: u64, since: u64) -> Result<Option<u64>> {
+    let ledger = get_icp_ledger_canister_id();
+    let canister_account = AccountIdentifier::new(&ic_cdk::api::canister_self(), &from_subaccount);
+    let tip = ic_ledger_types::query_blocks(ledger, &ic_ledger_types::GetBlocksArgs { start: 0, length: 0 })
+        .await
+        .map_err(|_| EscrowError::CanisterCallError)?
+        .chain_length;
+
+    let mut end = tip;
+    for _ in 0..MAX_SCAN_PAGES {
+        if end == 0 {
+            break;
+        }
+        let start = end.saturating_sub(SCAN_PAGE);
+        let args = ic_ledger_types::GetBlocksArgs { start, length: end - start };
+        let response = ic_ledger_types::query_blocks(ledger, &args)
+            .await
+            .map_err(|_| EscrowError::CanisterCallError)?;
+
+        for (offset, block) in response.blocks.iter().enumerate().rev() {
+            if block.timestamp.timestamp_nanos < since {
+                return Ok(None);
+            }
+            let from_canister = matches!(
+                &block.transaction.operation,
+                Some(ic_ledger_types::Operation::Transfer { from, .. }) if *from == canister_account
+            );
+            if from_canister && block.transaction.memo.0 == memo {
+                return Ok(Some(response.first_block_index + offset as u64));
             }
         }
-        Err(_) => Err(EscrowError::TransferFailed),
+
+        // Anything older has been archived, long before a settlement could be stuck
+        if response.first_block_index > start {
+            break;
+        }
+        end = start;
     }
+    Ok(None)
+}
+
+/// Flatten a ledger block into its evidence representation
+fn block_evidence(index: u64, block: Option<ic_ledger_types::Block>) -> LedgerBlockEvidence {
+    let mut evidence = LedgerBlockEvidence {
+        index,
+        found: block.is_some(),
+        operation: String::new(),
+        memo: 0,
+        from: None,
+        to: None,
+        amount: 0,
+        fee: 0,
+        timestamp: 0,
+        parent_hash: None,
+    };
+    let Some(block) = block else { return evidence };
+
+    evidence.memo = block.transaction.memo.0;
+    evidence.timestamp = block.timestamp.timestamp_nanos;
+    evidence.parent_hash = block.parent_hash.map(|hash| hash.to_vec());
+
+    match block.transaction.operation {
+        Some(ic_ledger_types::Operation::Transfer { from, to, amount, fee }) => {
+            evidence.operation = "Transfer".to_string();
+            evidence.from = Some(from.to_string());
+            evidence.to = Some(to.to_string());
+            evidence.amount = u128::from(amount.e8s());
+            evidence.fee = u128::from(fee.e8s());
+        }
+        Some(ic_ledger_types::Operation::Mint { to, amount }) => {
+            evidence.operation = "Mint".to_string();
+            evidence.to = Some(to.to_string());
+            evidence.amount = u128::from(amount.e8s());
+        }
+        Some(ic_ledger_types::Operation::Burn { from, amount }) => {
+            evidence.operation = "Burn".to_string();
+            evidence.from = Some(from.to_string());
+            evidence.amount = u128::from(amount.e8s());
+        }
+        Some(other) => evidence.operation = format!("{:?}", other),
+        None => {}
+    }
+    evidence
 }
 
 /// Get ICP balance of a specific principal
@@ -137,10 +516,10 @@ pub async fn get_balance_of(principal: Principal) -> Result<u64> {
         Ok((balance,)) => {
             match balance.0.to_u64() {
                 Some(bal) => Ok(bal),
-                None => Err(EscrowError::TransferFailed),
+                None => Err(EscrowError::TransferFailed { reason: format!("balance {} does not fit in 64 bits", balance) }),
             }
         }
-        Err(_) => Err(EscrowError::TransferFailed),
+        Err((code, message)) => Err(EscrowError::TransferFailed { reason: format!("account_balance rejected ({:?}): {}", code, message) }),
     }
 }
 
@@ -152,7 +531,7 @@ pub async fn transfer_between(
     memo: u64,
 ) -> Result<u64> {
     if amount < MIN_TRANSFER_AMOUNT {
-        return Err(EscrowError::InvalidAmount);
+        return Err(EscrowError::AmountBelowMinimum { minimum: MIN_TRANSFER_AMOUNT.into(), actual: amount.into() });
     }
 
     let account_string = get_account_string(&to);
@@ -177,11 +556,15 @@ pub async fn transfer_between(
         Ok((Ok(block_index),)) => {
             match block_index.0.to_u64() {
                 Some(idx) => Ok(idx),
-                None => Err(EscrowError::TransferFailed),
+                None => Err(EscrowError::TransferFailed { reason: format!("block index {} does not fit in 64 bits", block_index) }),
             }
         }
-        Ok((Err(_),)) => Err(EscrowError::TransferFailed),
-        Err(_) => Err(EscrowError::TransferFailed),
+        Ok((Err(message),)) => Err(EscrowError::LedgerRejected {
+            method: "transfer".to_string(),
+            code: "TransferError".to_string(),
+            message,
+        }),
+        Err((code, message)) => Err(EscrowError::TransferFailed { reason: format!("transfer rejected ({:?}): {}", code, message) }),
     }
 }
 
@@ -194,6 +577,8 @@ pub fn generate_transfer_memo(operation: TransferOperation, hashlock: &[u8]) ->
         TransferOperation::Cancellation => 0x03,
         TransferOperation::Rescue => 0x04,
         TransferOperation::Fee => 0x05,
+        TransferOperation::Stake => 0x06,
+        TransferOperation::Migration => 0x07,
     };
 
     let mut memo_bytes = [0u8; 8];
@@ -206,6 +591,44 @@ pub fn generate_transfer_memo(operation: TransferOperation, hashlock: &[u8]) ->
     u64::from_be_bytes(memo_bytes)
 }
 
+/// Memo of an escrow transfer. The ICP ledger's legacy `transfer` only carries the 8-byte
+/// `value`, which abbreviates the hashlock; ICRC-1 memo blobs carry the full hashlock.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransferMemo {
+    pub value: u64,
+    pub hashlock: Vec<u8>,
+    pub created_at_time: Option<u64>, // Stamp of the settlement leg being retried; None sends at the current time
+}
+
+impl TransferMemo {
+    pub fn new(operation: TransferOperation, hashlock: &[u8]) -> Self {
+        Self { value: generate_transfer_memo(operation, hashlock), hashlock: hashlock.to_vec(), created_at_time: None }
+    }
+
+    /// ICRC-1 memo blob: the operation byte followed by the full hashlock, so its first
+    /// 8 bytes are `value`
+    pub fn icrc_blob(&self) -> Vec<u8> {
+        let mut blob = self.value.to_be_bytes().to_vec();
+        blob.extend(self.hashlock.iter().skip(7));
+        blob
+    }
+}
+
+/// Hashlock prefix a legacy memo abbreviates, with the operation byte cleared
+pub fn memo_prefix(memo: u64) -> u64 {
+    memo & 0x00ff_ffff_ffff_ffff
+}
+
+/// Whether a memo from `generate_transfer_memo` marks a fee payment
+pub fn is_fee_memo(memo: u64) -> bool {
+    memo >> 56 == 0x05
+}
+
+/// Account identifier (hex) of a principal's default account
+pub fn default_account(owner: &Principal) -> String {
+    AccountIdentifier::new(owner, &DEFAULT_SUBACCOUNT).to_string()
+}
+
 /// Transfer operation types for memo generation
 #[derive(CandidType, Clone, Copy, Debug)]
 pub enum TransferOperation {
@@ -214,6 +637,8 @@ pub enum TransferOperation {
     Cancellation,  // Refund on cancellation
     Rescue,        // Emergency rescue
     Fee,           // Fee payment
+    Stake,         // Resolver stake deposit or return
+    Migration,     // Held funds handed to a successor canister
 }
 
 /// Batch transfer for efficiency (when multiple transfers needed)
@@ -221,7 +646,7 @@ pub async fn batch_transfer(transfers: Vec<(Principal, u64, u64)>) -> Result<Vec
     let mut results = Vec::new();
     
     for (recipient, amount, memo) in transfers {
-        match transfer_to(recipient, amount, memo).await {
+        match transfer_to(recipient, amount, memo, None).await {
             Ok(block_index) => results.push(block_index),
             Err(e) => return Err(e),
         }
@@ -254,13 +679,57 @@ pub fn calculate_total_fees(num_transfers: u32) -> u64 {
     TRANSFER_FEE * num_transfers as u64
 }
 
+/// Calculate the treasury's share of positive slippage (surplus above the maker's minimum)
+pub fn calculate_surplus_fee(amount: u128, maker_min_amount: Option<u128>, share_bps: u64) -> u128 {
+    let surplus = match maker_min_amount {
+        Some(min) if amount > min => amount - min,
+        _ => return 0,
+    };
+
+    let share_bps = std::cmp::min(share_bps, 10_000);
+    crate::utils::mul_div(surplus, u128::from(share_bps), 10_000).expect("a share never exceeds the surplus")
+}
+
+/// Net amounts to send for a settlement's transfers once the ledger fee payer is applied.
+///
+/// `transfers` are gross amounts (zero entries are not sent and cost no fee);
+/// `deposit_leg` is the index of the transfer refunding the safety deposit, if any;
+/// `fee` is the ledger fee of one transfer of the escrow's token.
+/// A net amount of zero means the transfer should be skipped.
+pub fn charge_ledger_fees(
+    payer: &LedgerFeePayer,
+    transfers: &[u128],
+    deposit_leg: Option<usize>,
+    fee: u128,
+) -> Result<Vec<u128>> {
+    let mut net = transfers.to_vec();
+    match payer {
+        LedgerFeePayer::Canister => {}
+        LedgerFeePayer::Recipient => {
+            for amount in net.iter_mut().filter(|amount| **amount > 0) {
+                if *amount <= fee {
+                    return Err(EscrowError::AmountBelowMinimum { minimum: fee + 1, actual: *amount });
+                }
+                *amount -= fee;
+            }
+        }
+        LedgerFeePayer::SafetyDeposit => {
+            let fees = fee.saturating_mul(transfers.iter().filter(|amount| **amount > 0).count() as u128);
+            if let Some(deposit) = deposit_leg.and_then(|leg| net.get_mut(leg)) {
+                *deposit = deposit.saturating_sub(fees);
+            }
+        }
+    }
+    Ok(net)
+}
+
 /// Validate that an amount is sufficient for transfer including fees
-pub fn validate_transfer_amount(amount: u64, num_transfers: u32) -> Result<()> {
+pub fn validate_transfer_amount(amount: u128, num_transfers: u32) -> Result<()> {
     let total_fees = calculate_total_fees(num_transfers);
-    let min_required = total_fees + MIN_TRANSFER_AMOUNT;
+    let min_required = u128::from(total_fees + MIN_TRANSFER_AMOUNT);
     
     if amount < min_required {
-        return Err(EscrowError::InsufficientBalance);
+        return Err(EscrowError::InsufficientBalance { required: min_required, available: amount });
     }
     
     Ok(())
@@ -282,6 +751,87 @@ mod tests {
         // Should contain hashlock bytes
         assert_eq!(memo_bytes[1], 0x12);
         assert_eq!(memo_bytes[2], 0x34);
+
+        assert!(is_fee_memo(generate_transfer_memo(TransferOperation::Fee, &hashlock)));
+        assert!(!is_fee_memo(memo));
+    }
+
+    #[test]
+    fn test_icrc_memo_carries_full_hashlock() {
+        let hashlock: Vec<u8> = (0..32).collect();
+        let memo = TransferMemo::new(TransferOperation::Withdrawal, &hashlock);
+        let blob = memo.icrc_blob();
+
+        assert_eq!(blob.len(), 33);
+        assert_eq!(blob[0], 0x02);
+        assert_eq!(&blob[1..], &hashlock[..]);
+        assert_eq!(&blob[..8], &memo.value.to_be_bytes());
+
+        // Hashlocks sharing their first 7 bytes share a legacy memo but not a blob
+        let mut colliding = hashlock.clone();
+        colliding[31] ^= 1;
+        let other = TransferMemo::new(TransferOperation::Withdrawal, &colliding);
+        assert_eq!(other.value, memo.value);
+        assert_ne!(other.icrc_blob(), blob);
+
+        let deposit = generate_transfer_memo(TransferOperation::Deposit, &hashlock);
+        assert_eq!(memo_prefix(deposit), memo_prefix(memo.value));
+    }
+
+    #[test]
+    fn test_deposit_amount_requires_transfer_to_account() {
+        let evidence = LedgerBlockEvidence {
+            index: 9,
+            found: true,
+            operation: "Transfer".to_string(),
+            memo: 0,
+            from: Some("payer".to_string()),
+            to: Some("escrow".to_string()),
+            amount: 1_100_000,
+            fee: TRANSFER_FEE.into(),
+            timestamp: 1,
+            parent_hash: None,
+        };
+        assert!(matches!(deposit_amount(&evidence, "escrow"), Ok(1_100_000)));
+        assert!(matches!(deposit_amount(&evidence, "other"), Err(EscrowError::DepositNotFound)));
+
+        let mint = LedgerBlockEvidence { operation: "Mint".to_string(), ..evidence.clone() };
+        assert!(deposit_amount(&mint, "escrow").is_err());
+        let missing = LedgerBlockEvidence { found: false, ..evidence };
+        assert!(deposit_amount(&missing, "escrow").is_err());
+    }
+
+    #[test]
+    fn test_duplicate_transfer_reports_original_block() {
+        assert!(matches!(icp_transfer_result(Ok(12)), Ok(12)));
+        assert!(matches!(
+            icp_transfer_result(Err(ic_ledger_types::TransferError::TxDuplicate { duplicate_of: 7 })),
+            Ok(7)
+        ));
+        assert!(matches!(
+            icp_transfer_result(Err(ic_ledger_types::TransferError::TxCreatedInFuture)),
+            Err(EscrowError::TransferFailed { .. })
+        ));
+        assert!(matches!(
+            icp_transfer_result(Err(ic_ledger_types::TransferError::TxTooOld { allowed_window_nanos: 0 })),
+            Err(EscrowError::LedgerRejected { code, .. }) if code == "TxTooOld"
+        ));
+        assert_eq!(created_at_time(Some(5)), 5);
+    }
+
+    #[test]
+    fn test_escrow_subaccounts_are_distinct() {
+        let first = escrow_subaccount(&[1u8; 32]);
+        assert_eq!(first, escrow_subaccount(&[1u8; 32]));
+        assert_ne!(first, escrow_subaccount(&[2u8; 32]));
+        assert_ne!(first, DEFAULT_SUBACCOUNT);
+        assert_ne!(first, TREASURY_SWAP_SUBACCOUNT);
+        assert_ne!(first, FEE_SUBACCOUNT);
+        assert_ne!(FEE_SUBACCOUNT, TREASURY_SWAP_SUBACCOUNT);
+        // A batch stages its deposit apart from the escrow sharing its first hashlock
+        assert_ne!(first, batch_subaccount(&[1u8; 32]));
+        assert_eq!(icrc_subaccount(first), Some(first.0));
+        assert_eq!(icrc_subaccount(DEFAULT_SUBACCOUNT), None);
     }
 
     #[test]
@@ -290,12 +840,111 @@ mod tests {
         assert_eq!(calculate_total_fees(3), TRANSFER_FEE * 3);
     }
 
+    #[test]
+    fn test_calculate_surplus_fee() {
+        // No maker minimum means no surplus
+        assert_eq!(calculate_surplus_fee(1_000_000, None, 5_000), 0);
+
+        // Settling exactly at the minimum leaves nothing to capture
+        assert_eq!(calculate_surplus_fee(1_000_000, Some(1_000_000), 5_000), 0);
+
+        // Half of a 200_000 surplus goes to treasury
+        assert_eq!(calculate_surplus_fee(1_200_000, Some(1_000_000), 5_000), 100_000);
+
+        // Share is capped at 100%
+        assert_eq!(calculate_surplus_fee(1_200_000, Some(1_000_000), 20_000), 200_000);
+    }
+
+    #[test]
+    fn test_check_reserve() {
+        let reserve = 100_000_000;
+
+        // Leaves exactly the reserve after amount and fee
+        assert_eq!(check_reserve(300_000_000, 200_000_000 - TRANSFER_FEE, reserve).unwrap(), reserve);
+
+        // One e8 too many would dip into the reserve
+        assert!(matches!(
+            check_reserve(300_000_000, 200_000_000 - TRANSFER_FEE + 1, reserve),
+            Err(EscrowError::ReserveBreached)
+        ));
+
+        // Amount larger than the balance
+        assert!(check_reserve(1_000, 2_000, 0).is_err());
+    }
+
+    #[test]
+    fn test_charge_ledger_fees() {
+        const TRANSFER_FEE: u128 = super::TRANSFER_FEE as u128;
+        let transfers = [1_000_000, 0, 200_000];
+
+        let canister = charge_ledger_fees(&LedgerFeePayer::Canister, &transfers, Some(2), TRANSFER_FEE).unwrap();
+        assert_eq!(canister, vec![1_000_000, 0, 200_000]);
+
+        // Every transfer actually sent is shorted by one fee
+        let recipient = charge_ledger_fees(&LedgerFeePayer::Recipient, &transfers, Some(2), TRANSFER_FEE).unwrap();
+        assert_eq!(recipient, vec![1_000_000 - TRANSFER_FEE, 0, 200_000 - TRANSFER_FEE]);
+
+        // Both fees come out of the deposit refund
+        let deposit = charge_ledger_fees(&LedgerFeePayer::SafetyDeposit, &transfers, Some(2), TRANSFER_FEE).unwrap();
+        assert_eq!(deposit, vec![1_000_000, 0, 200_000 - 2 * TRANSFER_FEE]);
+
+        // A deposit too small for the fees is used up; the canister covers the rest
+        let small = charge_ledger_fees(&LedgerFeePayer::SafetyDeposit, &[1_000_000, TRANSFER_FEE], Some(1), TRANSFER_FEE).unwrap();
+        assert_eq!(small, vec![1_000_000, 0]);
+
+        // Transfers that cannot cover their own fee are rejected
+        assert!(matches!(
+            charge_ledger_fees(&LedgerFeePayer::Recipient, &[TRANSFER_FEE], None, TRANSFER_FEE),
+            Err(EscrowError::AmountBelowMinimum { minimum, actual }) if minimum == TRANSFER_FEE + 1 && actual == TRANSFER_FEE
+        ));
+
+        // ICRC tokens are charged their own ledger's fee
+        let ckbtc = charge_ledger_fees(&LedgerFeePayer::Recipient, &transfers, Some(2), 10).unwrap();
+        assert_eq!(ckbtc, vec![1_000_000 - 10, 0, 200_000 - 10]);
+
+        // 18-decimal amounts past u64::MAX are charged without truncation
+        let eth = 10u128.pow(18);
+        let cketh = charge_ledger_fees(&LedgerFeePayer::SafetyDeposit, &[100 * eth, eth], Some(1), 2_000_000_000_000).unwrap();
+        assert_eq!(cketh, vec![100 * eth, eth - 4_000_000_000_000]);
+    }
+
+    #[test]
+    fn test_block_evidence_flattens_transfer() {
+        let from = AccountIdentifier::new(&Principal::from_slice(&[1]), &DEFAULT_SUBACCOUNT);
+        let to = AccountIdentifier::new(&Principal::from_slice(&[2]), &DEFAULT_SUBACCOUNT);
+        let block = ic_ledger_types::Block {
+            parent_hash: Some([9u8; 32]),
+            transaction: ic_ledger_types::Transaction {
+                memo: Memo(42),
+                operation: Some(ic_ledger_types::Operation::Transfer {
+                    from,
+                    to,
+                    amount: Tokens::from_e8s(1_000),
+                    fee: Tokens::from_e8s(TRANSFER_FEE),
+                }),
+                created_at_time: ic_ledger_types::Timestamp { timestamp_nanos: 1 },
+                icrc1_memo: None,
+            },
+            timestamp: ic_ledger_types::Timestamp { timestamp_nanos: 2 },
+        };
+
+        let evidence = block_evidence(7, Some(block));
+        assert!(evidence.found);
+        assert_eq!(evidence.operation, "Transfer");
+        assert_eq!(evidence.memo, 42);
+        assert_eq!(evidence.to, Some(to.to_string()));
+        assert_eq!(evidence.amount, 1_000);
+        assert_eq!(evidence.timestamp, 2);
+
+        assert!(!block_evidence(8, None).found);
+    }
+
     #[test]
     fn test_validate_transfer_amount() {
         // Should fail for amounts too small
-        assert!(validate_transfer_amount(TRANSFER_FEE, 1).is_err());
+        assert!(validate_transfer_amount(TRANSFER_FEE.into(), 1).is_err());
         
         // Should succeed for sufficient amounts
-        assert!(validate_transfer_amount(MIN_TRANSFER_AMOUNT + TRANSFER_FEE, 1).is_ok());
+        assert!(validate_transfer_amount((MIN_TRANSFER_AMOUNT + TRANSFER_FEE).into(), 1).is_ok());
     }
 }
@@ -0,0 +1,534 @@
+//! Stored records as they were written while token amounts were `nat64`. Candid will not
+//! decode a stored `nat64` into the `nat` the amounts are now, so storage falls back to these
+//! twins when a record does not decode as the current type, and widens them on the way in.
+
+use candid::{CandidType, Deserialize, Principal};
+
+use crate::storage::EscrowMetrics;
+use crate::types::{
+    CycleCosts, DutchAuction, EncryptedNote, EscrowEvent, EscrowFreeze, EscrowImmutables, EscrowState, EscrowTemplate,
+    EscrowType, EventRecord, EvmEscrowStatus, EvmImmutables, EvmWithdrawal, FeeBreakdown, FundingWindow, HashAlgorithm,
+    ICPEscrow, MigrationReceipt, MigrationSnapshot, OrderIntent, OrderStatus, PartialFill, PendingTransfer,
+    ProcessingLock, ProcessingOperation, SafetyDepositFunding, SwapOrder, SwapSession, TimelockPreset, Timelocks,
+    TransferLeg,
+};
+
+#[derive(CandidType, Deserialize)]
+pub struct LegacyImmutables {
+    order_hash: Vec<u8>,
+    hashlock: Vec<u8>,
+    maker: String,
+    taker: String,
+    token: String,
+    amount: u64,
+    safety_deposit: u64,
+    timelocks: Timelocks,
+    maker_min_amount: Option<u64>,
+    min_received: Option<u64>,
+    safety_deposit_funding: Option<SafetyDepositFunding>,
+    chain_id: Option<u64>,
+    icp_token: Option<Principal>,
+    hash_algorithm: Option<HashAlgorithm>,
+    merkle_root: Option<Vec<u8>>,
+    parts_count: Option<u32>,
+    auction: Option<DutchAuction>,
+    metadata: Option<Vec<u8>>,
+}
+
+impl From<LegacyImmutables> for EscrowImmutables {
+    fn from(legacy: LegacyImmutables) -> Self {
+        EscrowImmutables {
+            order_hash: legacy.order_hash,
+            hashlock: legacy.hashlock,
+            maker: legacy.maker,
+            taker: legacy.taker,
+            token: legacy.token,
+            amount: legacy.amount.into(),
+            safety_deposit: legacy.safety_deposit.into(),
+            timelocks: legacy.timelocks,
+            maker_min_amount: legacy.maker_min_amount.map(u128::from),
+            min_received: legacy.min_received.map(u128::from),
+            safety_deposit_funding: legacy.safety_deposit_funding,
+            chain_id: legacy.chain_id,
+            icp_token: legacy.icp_token,
+            hash_algorithm: legacy.hash_algorithm,
+            merkle_root: legacy.merkle_root,
+            parts_count: legacy.parts_count,
+            auction: legacy.auction,
+            metadata: legacy.metadata,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+struct LegacyFeeBreakdown {
+    protocol_fees: u64,
+    ledger_fees_deducted: u64,
+    ledger_fees_covered: u64,
+    amount_credited: u64,
+}
+
+impl From<LegacyFeeBreakdown> for FeeBreakdown {
+    fn from(legacy: LegacyFeeBreakdown) -> Self {
+        FeeBreakdown {
+            protocol_fees: legacy.protocol_fees.into(),
+            ledger_fees_deducted: legacy.ledger_fees_deducted.into(),
+            ledger_fees_covered: legacy.ledger_fees_covered.into(),
+            amount_credited: legacy.amount_credited.into(),
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+struct LegacyMigrationSnapshot {
+    immutables: LegacyImmutables,
+    created_at: u64,
+    icp_tx_hash: Option<String>,
+    evm_address: Option<String>,
+    deposit_blocks: Vec<u64>,
+    creation_fee_block: Option<u64>,
+    safety_deposit_held: u64,
+    funds_transferred: u64,
+    funds_memo: u64,
+    source_canister: Principal,
+    successor: Principal,
+    migrated_at: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct LegacyMigrationReceipt {
+    snapshot: LegacyMigrationSnapshot,
+    signature: Vec<u8>,
+    delivered: bool,
+}
+
+impl From<LegacyMigrationReceipt> for MigrationReceipt {
+    fn from(legacy: LegacyMigrationReceipt) -> Self {
+        let snapshot = legacy.snapshot;
+        MigrationReceipt {
+            snapshot: MigrationSnapshot {
+                immutables: snapshot.immutables.into(),
+                created_at: snapshot.created_at,
+                icp_tx_hash: snapshot.icp_tx_hash,
+                evm_address: snapshot.evm_address,
+                deposit_blocks: snapshot.deposit_blocks,
+                creation_fee_block: snapshot.creation_fee_block,
+                safety_deposit_held: snapshot.safety_deposit_held.into(),
+                funds_transferred: snapshot.funds_transferred.into(),
+                funds_memo: snapshot.funds_memo,
+                source_canister: snapshot.source_canister,
+                successor: snapshot.successor,
+                migrated_at: snapshot.migrated_at,
+            },
+            signature: legacy.signature,
+            delivered: legacy.delivered,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum LegacyProcessingOperation {
+    Withdrawal { escrow_type: EscrowType, withdrawer: Principal, secret: Vec<u8> },
+    Cancellation { escrow_type: EscrowType, canceller: Principal },
+    Migration { receipt: Box<LegacyMigrationReceipt> },
+    PartialFill { withdrawer: Principal, secret: Vec<u8>, secret_index: u32, fill_amount: u64 },
+}
+
+#[derive(CandidType, Deserialize)]
+struct LegacyProcessingLock {
+    operation: LegacyProcessingOperation,
+    started_at: u64,
+}
+
+impl From<LegacyProcessingLock> for ProcessingLock {
+    fn from(legacy: LegacyProcessingLock) -> Self {
+        let operation = match legacy.operation {
+            LegacyProcessingOperation::Withdrawal { escrow_type, withdrawer, secret } => {
+                ProcessingOperation::Withdrawal { escrow_type, withdrawer, secret }
+            }
+            LegacyProcessingOperation::Cancellation { escrow_type, canceller } => {
+                ProcessingOperation::Cancellation { escrow_type, canceller }
+            }
+            LegacyProcessingOperation::Migration { receipt } => ProcessingOperation::Migration { receipt: Box::new((*receipt).into()) },
+            LegacyProcessingOperation::PartialFill { withdrawer, secret, secret_index, fill_amount } => {
+                ProcessingOperation::PartialFill { withdrawer, secret, secret_index, fill_amount: fill_amount.into() }
+            }
+        };
+        ProcessingLock { operation, started_at: legacy.started_at }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+struct LegacyTransferLeg {
+    memo: u64,
+    recipient: Principal,
+    amount: u64,
+    created_at_time: u64,
+    block: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct LegacyPartialFill {
+    filled_amount: u64,
+    last_secret_index: u32,
+    fills: u32,
+}
+
+#[derive(CandidType, Deserialize)]
+struct LegacyFundingWindow {
+    escrow_type: EscrowType,
+    required: u64,
+    deadline: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct LegacyEscrow {
+    immutables: LegacyImmutables,
+    state: EscrowState,
+    icp_tx_hash: Option<String>,
+    evm_address: Option<String>,
+    created_at: u64,
+    completed_at: Option<u64>,
+    revealed_secret: Option<Vec<u8>>,
+    surplus_captured: u64,
+    safety_deposit_held: u64,
+    fee_breakdown: LegacyFeeBreakdown,
+    settlement_blocks: Vec<u64>,
+    deposit_blocks: Vec<u64>,
+    notes: Vec<EncryptedNote>,
+    freeze: Option<EscrowFreeze>,
+    rescue_requested_at: Option<u64>,
+    processing: Option<LegacyProcessingLock>,
+    creation_fee_block: Option<u64>,
+    migration: Option<LegacyMigrationReceipt>,
+    cycles: Option<CycleCosts>,
+    token_fee: Option<u64>,
+    subaccount: Option<Vec<u8>>,
+    partial_fill: Option<LegacyPartialFill>,
+    funding: Option<LegacyFundingWindow>,
+    evm_verification: Option<EvmEscrowStatus>,
+    evm_immutables: Option<EvmImmutables>,
+    evm_withdrawal: Option<EvmWithdrawal>,
+    rescue_balance: Option<u64>,
+    transfer_legs: Option<Vec<LegacyTransferLeg>>,
+    escrow_type: Option<EscrowType>,
+}
+
+impl From<LegacyEscrow> for ICPEscrow {
+    fn from(legacy: LegacyEscrow) -> Self {
+        ICPEscrow {
+            immutables: legacy.immutables.into(),
+            state: legacy.state,
+            icp_tx_hash: legacy.icp_tx_hash,
+            evm_address: legacy.evm_address,
+            created_at: legacy.created_at,
+            completed_at: legacy.completed_at,
+            revealed_secret: legacy.revealed_secret,
+            surplus_captured: legacy.surplus_captured.into(),
+            safety_deposit_held: legacy.safety_deposit_held.into(),
+            fee_breakdown: legacy.fee_breakdown.into(),
+            settlement_blocks: legacy.settlement_blocks,
+            deposit_blocks: legacy.deposit_blocks,
+            notes: legacy.notes,
+            freeze: legacy.freeze,
+            rescue_requested_at: legacy.rescue_requested_at,
+            processing: legacy.processing.map(ProcessingLock::from),
+            creation_fee_block: legacy.creation_fee_block,
+            migration: legacy.migration.map(MigrationReceipt::from),
+            cycles: legacy.cycles,
+            token_fee: legacy.token_fee.map(u128::from),
+            subaccount: legacy.subaccount,
+            partial_fill: legacy.partial_fill.map(|fill| PartialFill {
+                filled_amount: fill.filled_amount.into(),
+                last_secret_index: fill.last_secret_index,
+                fills: fill.fills,
+            }),
+            funding: legacy.funding.map(|funding| FundingWindow {
+                escrow_type: funding.escrow_type,
+                required: funding.required.into(),
+                deadline: funding.deadline,
+            }),
+            evm_verification: legacy.evm_verification,
+            evm_immutables: legacy.evm_immutables,
+            evm_withdrawal: legacy.evm_withdrawal,
+            rescue_balance: legacy.rescue_balance.map(u128::from),
+            transfer_legs: legacy.transfer_legs.map(|legs| {
+                legs.into_iter()
+                    .map(|leg| TransferLeg {
+                        memo: leg.memo,
+                        recipient: leg.recipient,
+                        amount: leg.amount.into(),
+                        created_at_time: leg.created_at_time,
+                        block: leg.block,
+                    })
+                    .collect()
+            }),
+            escrow_type: legacy.escrow_type,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct LegacyPendingTransfer {
+    id: u64,
+    hashlock: Vec<u8>,
+    recipient: Principal,
+    amount: u64,
+    memo: u64,
+    queued_at: u64,
+    attempts: u32,
+    last_attempt_at: Option<u64>,
+}
+
+impl From<LegacyPendingTransfer> for PendingTransfer {
+    fn from(legacy: LegacyPendingTransfer) -> Self {
+        PendingTransfer {
+            id: legacy.id,
+            hashlock: legacy.hashlock,
+            recipient: legacy.recipient,
+            amount: legacy.amount.into(),
+            memo: legacy.memo,
+            queued_at: legacy.queued_at,
+            attempts: legacy.attempts,
+            last_attempt_at: legacy.last_attempt_at,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+struct LegacyOrderIntent {
+    src_immutables: LegacyImmutables,
+    dst_immutables: LegacyImmutables,
+    expires_at: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct LegacySwapOrder {
+    id: u64,
+    maker: Principal,
+    intent: LegacyOrderIntent,
+    announced_at: u64,
+    status: OrderStatus,
+}
+
+impl From<LegacySwapOrder> for SwapOrder {
+    fn from(legacy: LegacySwapOrder) -> Self {
+        SwapOrder {
+            id: legacy.id,
+            maker: legacy.maker,
+            intent: OrderIntent {
+                src_immutables: legacy.intent.src_immutables.into(),
+                dst_immutables: legacy.intent.dst_immutables.into(),
+                expires_at: legacy.intent.expires_at,
+            },
+            announced_at: legacy.announced_at,
+            status: legacy.status,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct LegacyTemplate {
+    id: String,
+    token: String,
+    min_amount: u64,
+    max_amount: u64,
+    safety_deposit: u64,
+    timelocks: TimelockPreset,
+    creation_fee: Option<u64>,
+}
+
+impl From<LegacyTemplate> for EscrowTemplate {
+    fn from(legacy: LegacyTemplate) -> Self {
+        EscrowTemplate {
+            id: legacy.id,
+            token: legacy.token,
+            min_amount: legacy.min_amount.into(),
+            max_amount: legacy.max_amount.into(),
+            safety_deposit: legacy.safety_deposit.into(),
+            timelocks: legacy.timelocks,
+            creation_fee: legacy.creation_fee,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct LegacySession {
+    hashlock: Vec<u8>,
+    order_hash: Vec<u8>,
+    resolver: Principal,
+    src_immutables: LegacyImmutables,
+    cancellation_gap: u64,
+    created_at: u64,
+}
+
+impl From<LegacySession> for SwapSession {
+    fn from(legacy: LegacySession) -> Self {
+        SwapSession {
+            hashlock: legacy.hashlock,
+            order_hash: legacy.order_hash,
+            resolver: legacy.resolver,
+            src_immutables: legacy.src_immutables.into(),
+            cancellation_gap: legacy.cancellation_gap,
+            created_at: legacy.created_at,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct LegacyMetrics {
+    total_escrows_created: u64,
+    total_escrows_completed: u64,
+    total_escrows_cancelled: u64,
+    total_volume_icp: u64,
+    total_fees_collected: u64,
+    total_surplus_collected: u64,
+    active_escrows_count: u64,
+    total_cycles_deposited: Option<u64>,
+}
+
+impl From<LegacyMetrics> for EscrowMetrics {
+    fn from(legacy: LegacyMetrics) -> Self {
+        EscrowMetrics {
+            total_escrows_created: legacy.total_escrows_created,
+            total_escrows_completed: legacy.total_escrows_completed,
+            total_escrows_cancelled: legacy.total_escrows_cancelled,
+            total_volume_icp: legacy.total_volume_icp.into(),
+            total_fees_collected: legacy.total_fees_collected.into(),
+            total_surplus_collected: legacy.total_surplus_collected.into(),
+            active_escrows_count: legacy.active_escrows_count,
+            total_cycles_deposited: legacy.total_cycles_deposited.map(u128::from),
+        }
+    }
+}
+
+/// The event variants that carried amounts; every other variant still decodes as `EscrowEvent`
+#[derive(CandidType, Deserialize)]
+enum LegacyEvent {
+    EscrowCreated {
+        hashlock: Vec<u8>,
+        escrow_type: EscrowType,
+        maker: String,
+        taker: String,
+        amount: u64,
+        metadata: Option<Vec<u8>>,
+        timestamp: u64,
+    },
+    EscrowFunded { hashlock: Vec<u8>, block_index: u64, amount: u64, timestamp: u64 },
+    EscrowPartiallyFilled {
+        hashlock: Vec<u8>,
+        withdrawer: Principal,
+        secret: Vec<u8>,
+        secret_index: u32,
+        fill_amount: u64,
+        remaining: u64,
+        timestamp: u64,
+    },
+    FundsRescued { hashlock: Vec<u8>, rescuer: Principal, amount: u64, timestamp: u64 },
+    SafetyDepositFunded { hashlock: Vec<u8>, funder: Principal, amount: u64, timestamp: u64 },
+    MetricsReset { scope: crate::types::MetricsResetScope, previous: LegacyMetrics, reset_by: Principal, timestamp: u64 },
+    EscrowMigrated { hashlock: Vec<u8>, successor: Principal, amount: u64, timestamp: u64 },
+    TransferQueued { hashlock: Vec<u8>, transfer_id: u64, recipient: Principal, amount: u64, timestamp: u64 },
+    QueuedTransferSent {
+        hashlock: Vec<u8>,
+        transfer_id: u64,
+        recipient: Principal,
+        amount: u64,
+        block: u64,
+        attempts: u32,
+        timestamp: u64,
+    },
+}
+
+impl From<LegacyEvent> for EscrowEvent {
+    fn from(legacy: LegacyEvent) -> Self {
+        match legacy {
+            LegacyEvent::EscrowCreated { hashlock, escrow_type, maker, taker, amount, metadata, timestamp } => {
+                EscrowEvent::EscrowCreated { hashlock, escrow_type, maker, taker, amount: amount.into(), metadata, timestamp }
+            }
+            LegacyEvent::EscrowFunded { hashlock, block_index, amount, timestamp } => {
+                EscrowEvent::EscrowFunded { hashlock, block_index, amount: amount.into(), timestamp }
+            }
+            LegacyEvent::EscrowPartiallyFilled { hashlock, withdrawer, secret, secret_index, fill_amount, remaining, timestamp } => {
+                EscrowEvent::EscrowPartiallyFilled {
+                    hashlock,
+                    withdrawer,
+                    secret,
+                    secret_index,
+                    fill_amount: fill_amount.into(),
+                    remaining: remaining.into(),
+                    timestamp,
+                }
+            }
+            LegacyEvent::FundsRescued { hashlock, rescuer, amount, timestamp } => {
+                EscrowEvent::FundsRescued { hashlock, rescuer, amount: amount.into(), timestamp }
+            }
+            LegacyEvent::SafetyDepositFunded { hashlock, funder, amount, timestamp } => {
+                EscrowEvent::SafetyDepositFunded { hashlock, funder, amount: amount.into(), timestamp }
+            }
+            LegacyEvent::MetricsReset { scope, previous, reset_by, timestamp } => {
+                EscrowEvent::MetricsReset { scope, previous: previous.into(), reset_by, timestamp }
+            }
+            LegacyEvent::EscrowMigrated { hashlock, successor, amount, timestamp } => {
+                EscrowEvent::EscrowMigrated { hashlock, successor, amount: amount.into(), timestamp }
+            }
+            LegacyEvent::TransferQueued { hashlock, transfer_id, recipient, amount, timestamp } => {
+                EscrowEvent::TransferQueued { hashlock, transfer_id, recipient, amount: amount.into(), timestamp }
+            }
+            LegacyEvent::QueuedTransferSent { hashlock, transfer_id, recipient, amount, block, attempts, timestamp } => {
+                EscrowEvent::QueuedTransferSent { hashlock, transfer_id, recipient, amount: amount.into(), block, attempts, timestamp }
+            }
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct LegacyEventRecord {
+    seq: u64,
+    event: LegacyEvent,
+}
+
+impl From<LegacyEventRecord> for EventRecord {
+    fn from(legacy: LegacyEventRecord) -> Self {
+        EventRecord { seq: legacy.seq, event: legacy.event.into() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_stable_structures::Storable;
+    use std::borrow::Cow;
+
+    #[test]
+    fn test_nat64_records_widen_on_read() {
+        let recipient = Principal::from_slice(&[3; 29]);
+        let transfer = LegacyPendingTransfer {
+            id: 4,
+            hashlock: vec![1; 32],
+            recipient,
+            amount: u64::MAX,
+            memo: 9,
+            queued_at: 10,
+            attempts: 2,
+            last_attempt_at: None,
+        };
+        let bytes = candid::encode_one(&transfer).unwrap();
+        let decoded = PendingTransfer::from_bytes(Cow::Owned(bytes));
+        assert_eq!(decoded.amount, u128::from(u64::MAX));
+        assert_eq!((decoded.id, decoded.attempts), (4, 2));
+
+        let record = LegacyEventRecord {
+            seq: 7,
+            event: LegacyEvent::FundsRescued { hashlock: vec![1; 32], rescuer: recipient, amount: 500, timestamp: 11 },
+        };
+        let bytes = candid::encode_one(&record).unwrap();
+        let decoded = EventRecord::from_bytes(Cow::Owned(bytes));
+        assert_eq!(decoded.seq, 7);
+        assert!(matches!(decoded.event, EscrowEvent::FundsRescued { amount: 500, timestamp: 11, .. }));
+
+        // A widened record is written back in the current layout
+        let rewritten = EventRecord::from_bytes(decoded.to_bytes());
+        assert!(matches!(rewritten.event, EscrowEvent::FundsRescued { amount: 500, .. }));
+    }
+}
@@ -1,27 +1,57 @@
 mod types;
+mod certification;
 mod utils;
 mod storage;
+mod legacy;
 mod ledger;
+mod timers;
+mod http;
+mod notifications;
+mod vetkeys;
+mod watchdog;
+mod guards;
+mod migration;
+mod cycles;
+mod icrc_ledger;
+mod evm;
+mod orders;
+mod icrc3;
+mod roles;
 
 use candid::Principal;
 use ic_cdk::{caller, export_candid, id, init, post_upgrade, pre_upgrade, query, update};
 use serde_bytes::ByteBuf;
 
 use types::{
-    EscrowConfig, EscrowError, EscrowEvent, EscrowImmutables, EscrowState, EscrowType, ICPEscrow,
-    Result,
+    CertifiedEscrow, CycleCosts, CycleReport, CyclesStatus, EncryptedNote, EscrowConfig, EvmLink, EscrowFilter, EscrowFreeze, EscrowListPage, EscrowPage, EscrowReminder, EscrowSummary, EscrowTemplate, EscrowTimeline, EscrowError, EscrowEvent, EscrowImmutables, EscrowProof, EscrowState, EscrowType, EscrowView, EvmEscrowStatus, EvmImmutables, EvmWithdrawal, InitArgs, MigrationReceipt, MigrationSnapshot,
+    OrderIntent, SwapOrder,
+    BatchCreation, EventLogPage, EventProof, EvidenceBundle, FeeBalance, FeeBreakdown, FeeReport, FundingWindow, ICPEscrow, MetricsResetScope, ObserverGrant, ObserverScope,
+    OperationCost, OperationTarget, ParticipantProfile, StandardRecord, AccountingRow, ChainConfig, TreasuryExport, ChainSpec, FeeSchedule, ProtocolLimits, ProtocolSpec, TimelockStageSpec, ProcessingLock, ProcessingOperation, PendingTransfer, PendingTreasuryRotation, ResolverRegistration, Role, RoleAssignment, PreflightCheck, PreflightReport, ReceiptProof, Result, SimulatedOperation, SwapSession, SwapTrace, SwapTraceLeg, Timelocks, TraceEntry,
+    VersionedConfig, VolumeUsage, MAX_BATCH_SIZE, MAX_EVENT_PAGE, MAX_FREEZE_REASON_LEN, MAX_PAGE_SIZE, VOLUME_PERIOD,
 };
-use utils::{current_time, validate_secret};
+use utils::current_time;
 
 /// Convert caller principal to candid principal
 fn caller_principal() -> Principal {
     Principal::from_text(&caller().to_text()).unwrap()
 }
 
-/// Initialize the canister
+/// Apply install or upgrade arguments over the current config, trapping if the result is invalid
+fn apply_init_args(args: Option<InitArgs>) {
+    if let Some(args) = args {
+        if let Err(err) = args.apply(storage::get_config()).and_then(storage::set_config) {
+            ic_cdk::trap(format!("Invalid init args: {:?}", err));
+        }
+    }
+}
+
+/// Initialize the canister, optionally configuring treasury, ledger, fees and bounds
 #[init]
-fn init() {
+fn init(args: Option<InitArgs>) {
     storage::init_storage();
+    apply_init_args(args);
+    certification::init_certification();
+    timers::start_timers();
 }
 
 /// Pre-upgrade hook
@@ -32,18 +62,21 @@ fn pre_upgrade_hook() {
 
 /// Post-upgrade hook  
 #[post_upgrade]
-fn post_upgrade_hook() {
+fn post_upgrade_hook(args: Option<InitArgs>) {
     storage::post_upgrade();
+    apply_init_args(args);
+    certification::init_certification();
+    storage::recertify();
+    timers::start_timers();
 }
 
 /// Check if caller is authorized for public operations
 #[query]
 fn is_authorized() -> bool {
     let caller = caller_principal();
-    let config = storage::get_config();
     
-    // Treasury is always authorized
-    if caller == config.treasury {
+    // Operators (and so admins and the treasury) are always authorized
+    if roles::has_role(&caller, Role::Operator) {
         return true;
     }
     
@@ -53,88 +86,769 @@ fn is_authorized() -> bool {
 
 /// Check if caller is maker or taker for an escrow
 fn is_maker_or_taker(escrow: &ICPEscrow, caller_str: &str) -> bool {
-    caller_str == escrow.immutables.maker || caller_str == escrow.immutables.taker
+    caller_str == escrow.immutables.maker || caller_str == escrow.immutables.taker || acts_as_treasury_maker(escrow, caller_str)
 }
 
-/// Validate timing constraints for an escrow operation
-fn check_timing(
-    escrow: &ICPEscrow,
-    operation: TimingCheck,
-) -> Result<()> {
-    let current_time = current_time();
-    let timelocks = &escrow.immutables.timelocks;
+/// Whether the caller may cancel: the maker of a source escrow (or a fee manager acting for the
+/// treasury maker), or the taker of a destination escrow
+fn may_cancel(escrow: &ICPEscrow, escrow_type: &EscrowType, caller_str: &str) -> bool {
+    match escrow_type {
+        EscrowType::Source => caller_str == escrow.immutables.maker || acts_as_treasury_maker(escrow, caller_str),
+        EscrowType::Destination => caller_str == escrow.immutables.taker,
+    }
+}
+
+/// Whether the canister itself is the maker, i.e. the escrow is a treasury swap
+fn is_treasury_swap(escrow: &ICPEscrow) -> bool {
+    escrow.immutables.maker == ic_cdk::api::canister_self().to_text()
+}
+
+/// Fee managers exercise the maker's rights on treasury swaps
+fn acts_as_treasury_maker(escrow: &ICPEscrow, caller_str: &str) -> bool {
+    is_treasury_swap(escrow)
+        && Principal::from_text(caller_str).is_ok_and(|caller| roles::has_role(&caller, Role::FeeManager))
+}
+
+/// Time window `[start, end)` in which the timelocks allow an operation (no end = open-ended)
+fn timing_window(escrow: &ICPEscrow, operation: &TimingCheck) -> (u64, Option<u64>) {
+    // Source escrows run on the Src stages, destination escrows on the Dst ones
+    let [withdrawal_start, public_withdrawal_start, cancellation_start] = escrow.stage_starts();
     
     match operation {
-        TimingCheck::PrivateWithdrawal => {
-            let start = timelocks.withdrawal_start();
-            let end = timelocks.cancellation_start();
-            if current_time < start || current_time >= end {
-                return Err(EscrowError::InvalidTime);
-            }
-        }
-        TimingCheck::PublicWithdrawal => {
-            let start = timelocks.public_withdrawal_start();
-            let end = timelocks.cancellation_start();
-            if current_time < start || current_time >= end {
-                return Err(EscrowError::InvalidTime);
-            }
-        }
-        TimingCheck::Cancellation => {
-            let start = timelocks.cancellation_start();
-            if current_time < start {
-                return Err(EscrowError::InvalidTime);
-            }
+        TimingCheck::PrivateWithdrawal => (withdrawal_start, Some(cancellation_start)),
+        TimingCheck::PublicWithdrawal => (public_withdrawal_start, Some(cancellation_start)),
+        TimingCheck::Cancellation => (cancellation_start, None),
+        // Never opens on escrows without a public cancellation stage
+        TimingCheck::PublicCancellation => (escrow.public_cancellation_start().unwrap_or(u64::MAX), None),
+        TimingCheck::PermissionlessWithdrawal => {
+            let config = storage::get_config();
+            let start = public_withdrawal_start.saturating_add(config.permissionless_delay);
+            (start, Some(cancellation_start))
         }
         TimingCheck::Rescue => {
             let config = storage::get_config();
-            let start = timelocks.rescue_start(config.rescue_delay);
-            if current_time < start {
-                return Err(EscrowError::InvalidTime);
-            }
+            (escrow.immutables.timelocks.rescue_start(config.rescue_delay), None)
+        }
+        TimingCheck::RescueNotice => {
+            // Never opens until a rescue has been requested
+            let config = storage::get_config();
+            let executable_at = escrow.rescue_requested_at
+                .map_or(u64::MAX, |requested_at| requested_at.saturating_add(config.rescue_notice_period));
+            (executable_at, None)
         }
     }
+}
+
+/// Validate timing constraints for an escrow operation
+fn check_timing(
+    escrow: &ICPEscrow,
+    operation: TimingCheck,
+) -> Result<()> {
+    check_timing_at(escrow, operation, current_time(), &storage::get_config())
+}
+
+/// `check_timing` at time `now`. With `enforce_timelocks` off the withdrawal windows are
+/// skipped; cancellation and rescue stages are always enforced.
+fn check_timing_at(
+    escrow: &ICPEscrow,
+    operation: TimingCheck,
+    now: u64,
+    config: &EscrowConfig,
+) -> Result<()> {
+    let withdrawal = matches!(operation, TimingCheck::PrivateWithdrawal | TimingCheck::PublicWithdrawal);
+    if withdrawal && !config.enforce_timelocks {
+        return Ok(());
+    }
+    
+    let (start, end) = timing_window(escrow, &operation);
+    if now < start || end.is_some_and(|end| now >= end) {
+        return Err(EscrowError::OutsideTimeWindow { now, opens_at: start, closes_at: end });
+    }
+    
+    Ok(())
+}
+
+/// Preflight result of a timing check, including when it would start passing
+fn preflight_timing(escrow: &ICPEscrow, operation: TimingCheck) -> PreflightCheck {
+    let current_time = current_time();
+    let (start, end) = timing_window(escrow, &operation);
     
+    let error = EscrowError::OutsideTimeWindow { now: current_time, opens_at: start, closes_at: end };
+    if current_time < start {
+        PreflightCheck::failed("timing", error, Some(start))
+    } else if end.is_some_and(|end| current_time >= end) {
+        PreflightCheck::failed("timing", error, None)
+    } else {
+        PreflightCheck::passed("timing")
+    }
+}
+
+/// Preflight result of the escrow state check
+fn preflight_active(escrow: &ICPEscrow) -> PreflightCheck {
+    if escrow.state == EscrowState::Active {
+        PreflightCheck::passed("state")
+    } else {
+        PreflightCheck::failed("state", EscrowError::InvalidState, None)
+    }
+}
+
+/// Freeze check shared by the cancellation and rescue preflights
+fn preflight_not_frozen(escrow: &ICPEscrow) -> PreflightCheck {
+    match check_not_frozen(escrow) {
+        Ok(()) => PreflightCheck::passed("freeze"),
+        Err(err) => PreflightCheck::failed("freeze", err, None),
+    }
+}
+
+/// Whether the caller may execute a public withdrawal under the configured mode
+fn can_public_withdraw(escrow: &ICPEscrow) -> bool {
+    let config = storage::get_config();
+    let (permissionless_start, _) = timing_window(escrow, &TimingCheck::PermissionlessWithdrawal);
+    config.public_withdraw_mode.allows(
+        is_authorized(),
+        storage::is_resolver(&caller_principal()),
+        current_time() >= permissionless_start,
+    )
+}
+
+/// Reject moving funds out of an escrow while it is frozen
+fn check_not_frozen(escrow: &ICPEscrow) -> Result<()> {
+    if escrow.freeze.is_some() {
+        return Err(EscrowError::EscrowFrozen);
+    }
+    Ok(())
+}
+
+/// Reject escrow creation and withdrawals while the canister is paused
+fn check_not_paused(config: &EscrowConfig) -> Result<()> {
+    if config.paused {
+        return Err(EscrowError::Paused);
+    }
+    Ok(())
+}
+
+/// Reject execution once the caller-supplied deadline has passed
+fn check_deadline(deadline_ns: Option<u64>) -> Result<()> {
+    match deadline_ns {
+        Some(deadline) if current_time() > deadline => Err(EscrowError::DeadlineExceeded),
+        _ => Ok(()),
+    }
+}
+
+/// Requests that must never execute twice, even across upgrades
+enum RequestKind {
+    Creation,
+    Withdrawal,
+}
+
+/// Derive the replay-protection key for a request on an escrow, scoped to its (order_hash, hashlock)
+fn request_key(kind: RequestKind, immutables: &EscrowImmutables) -> [u8; 32] {
+    let domain: &[u8] = match kind {
+        RequestKind::Creation => b"create",
+        RequestKind::Withdrawal => b"withdraw",
+    };
+    let mut data = domain.to_vec();
+    data.extend_from_slice(&immutables.order_hash);
+    data.extend_from_slice(&immutables.hashlock);
+
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&utils::sha256(&data));
+    key
+}
+
+/// Check a withdrawal secret against the escrow's hashlock and, when the caller names one,
+/// its order hash, so a secret revealed for one order cannot settle another sharing the hashlock
+fn check_secret(escrow: &ICPEscrow, secret: &[u8], order_hash: Option<&[u8]>) -> Result<()> {
+    utils::check_length("secret", secret.len(), utils::MAX_SECRET_LEN)?;
+    // Partially fillable escrows are unlocked part by part, through `withdraw_src_partial`
+    if escrow.immutables.is_partially_fillable() {
+        return Err(EscrowError::InvalidPartialFill);
+    }
+    if !utils::validate_order_secret(secret, &escrow.immutables, order_hash) {
+        return Err(EscrowError::InvalidSecret);
+    }
+    Ok(())
+}
+
+/// Reject a direction-specific endpoint on an escrow created for the other direction
+fn check_escrow_type(escrow: &ICPEscrow, expected: EscrowType) -> Result<()> {
+    if escrow.escrow_type()? != expected {
+        return Err(EscrowError::InvalidState);
+    }
+    Ok(())
+}
+
+/// Reject requests that were already processed and have not expired by `now`
+fn check_not_replayed(key: &[u8; 32], now: u64) -> Result<()> {
+    if storage::is_request_processed(key, now) {
+        return Err(EscrowError::DuplicateRequest);
+    }
     Ok(())
 }
 
+/// Remember a processed request for the configured TTL
+fn mark_processed(key: [u8; 32]) {
+    let config = storage::get_config();
+    storage::mark_request_processed(key, current_time().saturating_add(config.replay_protection_ttl));
+}
+
 enum TimingCheck {
     PrivateWithdrawal,
     PublicWithdrawal,
     Cancellation,
+    PublicCancellation,
     Rescue,
+    RescueNotice,
+    PermissionlessWithdrawal,
+}
+
+/// Gross transfers of a withdrawal: [payout, surplus to treasury, deposit refund]
+fn withdrawal_legs(escrow: &ICPEscrow, escrow_type: &EscrowType, config: &EscrowConfig) -> [u128; 3] {
+    // Surplus is only captured where the maker is the one being paid out
+    let surplus_fee = match escrow_type {
+        EscrowType::Source => 0,
+        EscrowType::Destination => ledger::calculate_surplus_fee(
+            escrow.immutables.amount,
+            escrow.immutables.maker_min_amount,
+            config.surplus_share_bps,
+        ),
+    };
+    [escrow.remaining_amount() - surplus_fee, surplus_fee, escrow.safety_deposit_held]
+}
+
+/// Transfer a payout of the escrow's token out of its subaccount; what is owed to the canister
+/// itself (the maker of a treasury swap, always ICP) goes to the treasury swap subaccount
+/// rather than mixing with escrowed funds
+async fn pay_out(escrow: &ICPEscrow, recipient: Principal, amount: u128, memo: &ledger::TransferMemo) -> Result<u64> {
+    if recipient != ic_cdk::api::canister_self() {
+        return transfer_leg(escrow, recipient, amount, memo).await;
+    }
+    let hashlock = &escrow.immutables.hashlock;
+    let memo = stamp_leg(hashlock, recipient, amount, memo)?;
+    let block = ledger::return_to_treasury_swaps(escrow.funds_subaccount(), utils::icp_amount(amount)?, &memo).await?;
+    settle_leg(hashlock, recipient, amount, &memo, block);
+    Ok(block)
+}
+
+/// Transfer the escrow's token out of its subaccount as one of its transfer legs, so a retry
+/// after a failed or timed-out attempt is deduplicated by the ledger rather than paid twice
+async fn transfer_leg(escrow: &ICPEscrow, recipient: Principal, amount: u128, memo: &ledger::TransferMemo) -> Result<u64> {
+    let hashlock = &escrow.immutables.hashlock;
+    let memo = stamp_leg(hashlock, recipient, amount, memo)?;
+    let block = ledger::transfer_token_to(escrow.immutables.icp_token, escrow.funds_subaccount(), recipient, amount, &memo).await?;
+    settle_leg(hashlock, recipient, amount, &memo, block);
+    Ok(block)
+}
+
+/// The memo stamped with the creation time of its transfer leg (see `ICPEscrow::open_transfer_leg`)
+fn stamp_leg(hashlock: &[u8], recipient: Principal, amount: u128, memo: &ledger::TransferMemo) -> Result<ledger::TransferMemo> {
+    let now = ledger::created_at_time(None);
+    let mut stamped = memo.clone();
+    storage::update_escrow(hashlock, |escrow| {
+        stamped.created_at_time = Some(escrow.open_transfer_leg(memo.value, recipient, amount, now));
+    })?;
+    Ok(stamped)
+}
+
+fn settle_leg(hashlock: &[u8], recipient: Principal, amount: u128, memo: &ledger::TransferMemo, block: u64) {
+    // The leg was opened on this escrow just before the transfer, so this cannot miss
+    let _ = storage::update_escrow(hashlock, |escrow| escrow.settle_transfer_leg(memo.value, &recipient, amount, block));
+}
+
+/// Pay a settlement transfer that follows one already sent. Funds have moved by then, so a
+/// failure no longer aborts the settlement; the transfer is queued for retry instead. Ledger
+/// calls are made with unbounded waits, so a failed call means nothing was transferred.
+async fn pay_out_or_queue(escrow: &ICPEscrow, hashlock: &[u8], recipient: Principal, amount: u128, memo: &ledger::TransferMemo) -> Option<u64> {
+    let error = match pay_out(escrow, recipient, amount, memo).await {
+        Ok(block) => return Some(block),
+        Err(error) => error,
+    };
+    ic_cdk::api::debug_print(format!("Queueing transfer of {} to {} after {:?}", amount, recipient, error));
+    let now = current_time();
+    let transfer_id = storage::queue_pending_transfer(PendingTransfer {
+        id: 0,
+        hashlock: hashlock.to_vec(),
+        recipient,
+        amount,
+        memo: memo.value,
+        queued_at: now,
+        attempts: 0,
+        last_attempt_at: None,
+    });
+    storage::add_event(EscrowEvent::TransferQueued {
+        hashlock: hashlock.to_vec(),
+        transfer_id,
+        recipient,
+        amount,
+        timestamp: now,
+    });
+    None
+}
+
+/// Top up the escrow's subaccount from the canister's default account with whatever the
+/// settlement's transfers (each amount plus its ledger fee) need beyond its balance, so
+/// covered fees never draw on another escrow's funds. Returns the top-up's block, if any.
+async fn cover_fee_shortfall(escrow: &ICPEscrow, hashlock: &[u8], amounts: &[u128]) -> Result<Option<u64>> {
+    let token = escrow.immutables.icp_token;
+    let subaccount = escrow.funds_subaccount();
+    let outgoing = utils::sum_amounts(amounts.iter().filter(|amount| **amount > 0).flat_map(|amount| [*amount, escrow.transfer_fee()]))?;
+    let balance = ledger::get_token_balance(token, subaccount).await?;
+    let shortfall = outgoing.saturating_sub(balance);
+    if shortfall == 0 {
+        return Ok(None);
+    }
+    let memo = ledger::TransferMemo::new(ledger::TransferOperation::Fee, hashlock);
+    ledger::top_up_subaccount(token, subaccount, shortfall, &memo).await.map(Some)
+}
+
+/// Recipients of a safety deposit being paid out: its funder, or, when it is forfeited on a
+/// public operation, the executor (or the executor, counterparty and treasury shares when a
+/// schedule is configured). `refund_public_deposit` keeps the legacy refund to the funder.
+fn deposit_recipients(
+    escrow: &ICPEscrow,
+    escrow_type: &EscrowType,
+    amount: u128,
+    executor: Option<Principal>,
+    config: &EscrowConfig,
+) -> Result<Vec<(Principal, u128)>> {
+    let recipients = match (executor, &config.deposit_forfeiture) {
+        (Some(executor), Some(schedule)) => {
+            let counterparty = utils::validate_principal(escrow.deposit_counterparty(escrow_type))?;
+            let [executor_share, counterparty_share, treasury_share] = schedule.split(amount);
+            vec![(executor, executor_share), (counterparty, counterparty_share), (config.treasury, treasury_share)]
+        }
+        (Some(executor), None) if !config.refund_public_deposit => vec![(executor, amount)],
+        _ => vec![(utils::validate_principal(escrow.safety_deposit_owner(escrow_type))?, amount)],
+    };
+    Ok(recipients.into_iter().filter(|(_, share)| *share > 0).collect())
+}
+
+/// Gross transfers of a cancellation and the index of the one refunding the deposit.
+/// A taker-funded deposit is refunded separately; otherwise it goes back with the unfilled amount.
+fn cancellation_legs(escrow: &ICPEscrow, escrow_type: &EscrowType) -> (Vec<u128>, usize) {
+    match escrow_type {
+        EscrowType::Source if escrow.immutables.is_taker_funded_deposit() => {
+            (vec![escrow.remaining_amount(), escrow.safety_deposit_held], 1)
+        }
+        EscrowType::Source | EscrowType::Destination => {
+            (vec![escrow.remaining_amount().saturating_add(escrow.safety_deposit_held)], 0)
+        }
+    }
+}
+
+/// Exact cost of an operation on an escrow under the given config
+fn operation_cost(op: &SimulatedOperation, escrow: &ICPEscrow, config: &EscrowConfig) -> Result<OperationCost> {
+    let immutables = &escrow.immutables;
+    
+    // Deposits are paid by the caller: amount, one ledger fee per transfer and the creation fee.
+    // The creation fee is always paid in ICP, whatever token the escrow locks.
+    let transfer_fee = escrow.transfer_fee();
+    let deposit_cost = |deposit: u128, protocol_fee: u128| -> Result<OperationCost> {
+        let transfer_legs = 1 + u32::from(protocol_fee > 0);
+        let ledger_fees = transfer_fee.saturating_add(if protocol_fee > 0 { ledger::TRANSFER_FEE.into() } else { 0 });
+        Ok(OperationCost {
+            deposit,
+            protocol_fee,
+            ledger_fees,
+            transfer_legs,
+            total_required: utils::sum_amounts([deposit, protocol_fee, ledger_fees])?,
+            net_payouts: Vec::new(),
+        })
+    };
+    
+    // Settlements are paid out by the canister; fees follow the configured payer
+    let settlement_cost = |gross: &[u128], deposit_leg: Option<usize>, protocol_fee: u128| -> Result<OperationCost> {
+        let net = ledger::charge_ledger_fees(&config.ledger_fee_payer, gross, deposit_leg, transfer_fee)?;
+        let transfer_legs = net.iter().filter(|amount| **amount > 0).count() as u32;
+        Ok(OperationCost {
+            deposit: 0,
+            protocol_fee,
+            ledger_fees: transfer_fee.saturating_mul(transfer_legs.into()),
+            transfer_legs,
+            total_required: 0,
+            net_payouts: net,
+        })
+    };
+    
+    match op {
+        SimulatedOperation::CreateSrc => {
+            let deposit = if immutables.is_taker_funded_deposit() {
+                immutables.amount
+            } else {
+                utils::add_amounts(immutables.amount, immutables.safety_deposit)?
+            };
+            deposit_cost(deposit, config.creation_fee.into())
+        }
+        SimulatedOperation::CreateDst => {
+            deposit_cost(utils::add_amounts(immutables.amount, immutables.safety_deposit)?, config.creation_fee.into())
+        }
+        SimulatedOperation::FundSafetyDeposit => deposit_cost(immutables.safety_deposit, 0),
+        SimulatedOperation::Withdraw(escrow_type) => {
+            let gross = withdrawal_legs(escrow, escrow_type, config);
+            settlement_cost(&gross, Some(2), gross[1])
+        }
+        SimulatedOperation::Cancel(escrow_type) => {
+            let (gross, deposit_leg) = cancellation_legs(escrow, escrow_type);
+            settlement_cost(&gross, Some(deposit_leg), 0)
+        }
+        SimulatedOperation::Rescue(amount) => settlement_cost(&[*amount], None, 0),
+    }
+}
+
+/// With `require_evm_verification` set, a source escrow pays out only once `verify_evm_escrow`
+/// has found its counterpart deployed on the EVM chain
+fn check_evm_verified(escrow: &ICPEscrow, config: &EscrowConfig) -> Result<()> {
+    if config.require_evm_verification && !escrow.evm_verified() {
+        return Err(EscrowError::EvmEscrowUnverified);
+    }
+    Ok(())
+}
+
+/// Move an active escrow into Processing so no other settlement can start while transfers are in flight
+fn acquire_processing(hashlock: &[u8], operation: ProcessingOperation) -> Result<()> {
+    let escrow = storage::get_escrow(hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    if escrow.state != EscrowState::Active {
+        return Err(EscrowError::InvalidState);
+    }
+    storage::update_escrow(hashlock, |escrow| {
+        escrow.state = EscrowState::Processing;
+        escrow.processing = Some(ProcessingLock {
+            operation,
+            started_at: current_time(),
+        });
+    })
+}
+
+/// Return the escrow to Active when its first transfer failed, as no funds have moved yet.
+/// Later failures leave it Processing for the watchdog to resolve against the ledger.
+fn release_on_error<T>(hashlock: &[u8], result: Result<T>) -> Result<T> {
+    if result.is_err() {
+        storage::update_escrow(hashlock, |escrow| {
+            escrow.state = EscrowState::Active;
+            escrow.processing = None;
+        })?;
+    }
+    result
+}
+
+/// Pay out a withdrawal and mark the escrow completed.
+///
+/// The locked amount goes to the receiving side of the swap (taker for source
+/// escrows, maker for destination escrows) and the safety deposit is returned
+/// to the party that funded it, unless `forfeit_deposit` is set and it goes to
+/// the withdrawer instead (see `deposit_recipients`).
+async fn complete_withdrawal(
+    escrow: &ICPEscrow,
+    hashlock: &[u8],
+    secret: &[u8],
+    withdrawer: Principal,
+    forfeit_deposit: bool,
+) -> Result<()> {
+    let current_time = current_time();
+    let config = storage::get_config();
+    let escrow_type = escrow.escrow_type()?;
+
+    check_not_paused(&config)?;
+    check_not_frozen(escrow)?;
+
+    let replay_key = request_key(RequestKind::Withdrawal, &escrow.immutables);
+    check_not_replayed(&replay_key, current_time)?;
+    if matches!(escrow_type, EscrowType::Source) {
+        check_evm_verified(escrow, &config)?;
+    }
+
+    let recipient = match escrow_type {
+        EscrowType::Source => &escrow.immutables.taker,
+        EscrowType::Destination => &escrow.immutables.maker,
+    };
+
+    // Apply the configured ledger fee payer to payout, surplus and deposit refund
+    let gross = withdrawal_legs(escrow, &escrow_type, &config);
+    let surplus_fee = gross[1];
+    let net = ledger::charge_ledger_fees(&config.ledger_fee_payer, &gross, Some(2), escrow.transfer_fee())?;
+    let (payout, surplus_sent, deposit_refund) = (net[0], net[1], net[2]);
+    let executor = forfeit_deposit.then_some(withdrawer);
+    let deposit_shares = deposit_recipients(escrow, &escrow_type, deposit_refund, executor, &config)?;
+    let forfeited_to_treasury = match (executor, &config.deposit_forfeiture) {
+        (Some(_), Some(schedule)) => schedule.split(deposit_refund)[2],
+        _ => 0,
+    };
+
+    // Enforce the recipient's price protection before moving any funds, pro rata for what
+    // remains of a partially filled escrow
+    if let Some(min_received) = escrow.min_received_for(escrow.remaining_amount()) {
+        if payout < min_received {
+            return Err(EscrowError::SlippageExceeded);
+        }
+    }
+
+    // Transfer the escrowed token to recipient
+    let recipient_principal = utils::validate_principal(recipient)?;
+    acquire_processing(hashlock, ProcessingOperation::Withdrawal {
+        escrow_type: escrow_type.clone(),
+        withdrawer,
+        secret: secret.to_vec(),
+    })?;
+    let outgoing: Vec<u128> = [payout, surplus_sent].into_iter().chain(deposit_shares.iter().map(|(_, share)| *share)).collect();
+    let top_up = release_on_error(hashlock, cover_fee_shortfall(escrow, hashlock, &outgoing).await)?;
+    let withdrawal_memo = ledger::TransferMemo::new(
+        ledger::TransferOperation::Withdrawal,
+        hashlock,
+    );
+    let first_transfer = transfer_leg(escrow, recipient_principal, payout, &withdrawal_memo).await;
+    let mut blocks = vec![release_on_error(hashlock, first_transfer)?];
+
+    // Route captured surplus to treasury
+    if surplus_sent > 0 {
+        let surplus_memo = ledger::TransferMemo::new(
+            ledger::TransferOperation::Fee,
+            hashlock,
+        );
+        blocks.extend(pay_out_or_queue(escrow, hashlock, config.treasury, surplus_sent, &surplus_memo).await);
+    }
+
+    // Return safety deposit to whoever funded it, or divide it if forfeited
+    let refund_memo = ledger::TransferMemo::new(
+        ledger::TransferOperation::Cancellation,
+        hashlock,
+    );
+    for (recipient, share) in deposit_shares {
+        blocks.extend(pay_out_or_queue(escrow, hashlock, recipient, share, &refund_memo).await);
+    }
+    mark_processed(replay_key);
+    let settlement_cycles = cycles::message_cost(blocks.len() as u64);
+
+    // Update escrow state
+    storage::update_escrow(hashlock, |escrow| {
+        escrow.state = EscrowState::Completed;
+        escrow.processing = None;
+        escrow.completed_at = Some(current_time);
+        escrow.revealed_secret = Some(secret.to_vec());
+        escrow.surplus_captured = surplus_fee;
+        escrow.safety_deposit_held = 0;
+        escrow.record_final_fill();
+        escrow.fee_breakdown.record_transfers(&gross, &net, escrow.transfer_fee());
+        let breakdown = &mut escrow.fee_breakdown;
+        breakdown.protocol_fees = breakdown.protocol_fees.saturating_add(surplus_fee).saturating_add(forfeited_to_treasury);
+        let credited = payout.saturating_add(deposit_refund).saturating_sub(forfeited_to_treasury);
+        breakdown.amount_credited = breakdown.amount_credited.saturating_add(credited);
+        if top_up.is_some() {
+            escrow.fee_breakdown.ledger_fees_covered = escrow.fee_breakdown.ledger_fees_covered.saturating_add(escrow.transfer_fee());
+        }
+        escrow.settlement_blocks.extend(top_up.into_iter().chain(blocks));
+        escrow.cycles_mut().settlement += settlement_cycles;
+    })?;
+
+    // Update metrics
+    storage::update_metrics(|metrics| {
+        metrics.total_escrows_completed += 1;
+        metrics.active_escrows_count = metrics.active_escrows_count.saturating_sub(1);
+        metrics.total_surplus_collected = metrics.total_surplus_collected.saturating_add(surplus_fee);
+    });
+    storage::update_daily_activity(current_time, |day| day.completed += 1);
+
+    // Log event
+    let event = EscrowEvent::EscrowWithdrawal {
+        hashlock: hashlock.to_vec(),
+        withdrawer,
+        secret: secret.to_vec(),
+        order_hash: Some(escrow.immutables.order_hash.clone()),
+        timestamp: current_time,
+    };
+    storage::add_event(event);
+    storage::add_event(EscrowEvent::SecretRevealed {
+        hashlock: hashlock.to_vec(),
+        order_hash: escrow.immutables.order_hash.clone(),
+        secret: secret.to_vec(),
+        timestamp: current_time,
+    });
+
+    // Let a maker canister react to settlement on-chain
+    if config.notify_maker_canisters {
+        if let Ok(maker) = Principal::from_text(&escrow.immutables.maker) {
+            if notifications::is_canister_principal(&maker) && maker != ic_cdk::api::canister_self() {
+                notifications::notify_escrow_completed(maker, hashlock, payout);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Pay one fill of a partially fillable source escrow to the taker, unlocked by the secret at
+/// `secret_index`, and return the escrow to Active with the fill recorded
+async fn fill_partially(
+    escrow: &ICPEscrow,
+    hashlock: &[u8],
+    secret: &[u8],
+    withdrawer: Principal,
+    secret_index: u32,
+    fill_amount: u128,
+) -> Result<()> {
+    let current_time = current_time();
+    let config = storage::get_config();
+
+    check_not_paused(&config)?;
+    check_not_frozen(escrow)?;
+    check_evm_verified(escrow, &config)?;
+
+    let net = ledger::charge_ledger_fees(&config.ledger_fee_payer, &[fill_amount], None, escrow.transfer_fee())?;
+    if let Some(min_received) = escrow.min_received_for(fill_amount) {
+        if net[0] < min_received {
+            return Err(EscrowError::SlippageExceeded);
+        }
+    }
+
+    let taker = utils::validate_principal(&escrow.immutables.taker)?;
+    acquire_processing(hashlock, ProcessingOperation::PartialFill {
+        withdrawer,
+        secret: secret.to_vec(),
+        secret_index,
+        fill_amount,
+    })?;
+    let top_up = release_on_error(hashlock, cover_fee_shortfall(escrow, hashlock, &net).await)?;
+    let withdrawal_memo = ledger::TransferMemo::new(
+        ledger::TransferOperation::Withdrawal,
+        hashlock,
+    );
+    let transfer = transfer_leg(escrow, taker, net[0], &withdrawal_memo).await;
+    let block = release_on_error(hashlock, transfer)?;
+    let settlement_cycles = cycles::message_cost(1 + top_up.is_some() as u64);
+
+    storage::update_escrow(hashlock, |escrow| {
+        escrow.state = EscrowState::Active;
+        escrow.processing = None;
+        escrow.record_partial_fill(fill_amount, secret_index);
+        escrow.fee_breakdown.record_transfers(&[fill_amount], &net, escrow.transfer_fee());
+        escrow.fee_breakdown.amount_credited = escrow.fee_breakdown.amount_credited.saturating_add(net[0]);
+        if top_up.is_some() {
+            escrow.fee_breakdown.ledger_fees_covered = escrow.fee_breakdown.ledger_fees_covered.saturating_add(escrow.transfer_fee());
+        }
+        escrow.settlement_blocks.extend(top_up);
+        escrow.settlement_blocks.push(block);
+        escrow.cycles_mut().settlement += settlement_cycles;
+    })?;
+
+    storage::add_event(EscrowEvent::EscrowPartiallyFilled {
+        hashlock: hashlock.to_vec(),
+        withdrawer,
+        secret: secret.to_vec(),
+        secret_index,
+        fill_amount,
+        remaining: escrow.remaining_amount() - fill_amount,
+        timestamp: current_time,
+    });
+
+    Ok(())
 }
 
 // =============================================================================
 // ESCROW CREATION FUNCTIONS
 // =============================================================================
 
+/// Expand a referenced template into the immutables, returning the config it implies
+fn apply_template(
+    immutables: &mut EscrowImmutables,
+    template_id: Option<&str>,
+    mut config: EscrowConfig,
+) -> Result<EscrowConfig> {
+    if let Some(id) = template_id {
+        let template = storage::get_template(id).ok_or(EscrowError::TemplateNotFound)?;
+        template.apply(immutables)?;
+        if let Some(creation_fee) = template.creation_fee {
+            config.creation_fee = creation_fee;
+        }
+    }
+    Ok(config)
+}
+
+/// Ledger fee to record on a new escrow: queried from the token's ledger for ICRC-1 tokens
+async fn icrc_token_fee(token: Option<Principal>) -> Result<Option<u128>> {
+    match token {
+        Some(_) => ledger::token_transfer_fee(token).await.map(Some),
+        None => Ok(None),
+    }
+}
+
 /// Create a source escrow for ICP→EVM swaps
 #[update]
-async fn create_src_escrow(immutables: EscrowImmutables) -> Result<Vec<u8>> {
-    let _caller = caller_principal();
+async fn create_src_escrow(
+    immutables: EscrowImmutables,
+    deadline_ns: Option<u64>,
+    template_id: Option<String>,
+) -> Result<Vec<u8>> {
+    let caller = caller_principal();
     let current_time = current_time();
-    let config = storage::get_config();
+    
+    check_deadline(deadline_ns)?;
+    
+    // Expand the referenced template, which may also override the creation fee
+    let mut immutables = immutables;
+    let config = apply_template(&mut immutables, template_id.as_deref(), storage::get_config())?;
+    check_not_paused(&config)?;
+    cycles::check_creation_allowed(&config)?;
+    guards::check_immutables(&immutables)?;
+    let _lock = guards::EscrowLock::acquire(&immutables.hashlock)?;
     
     // Validate immutables
-    immutables.validate(&config)?;
+    immutables.validate_src(&config)?;
+    
+    // Unverified principals are limited to a volume per period
+    if !volume_usage(&caller, &config).allows(immutables.amount) {
+        return Err(EscrowError::VolumeCapExceeded);
+    }
     
     // Check if escrow already exists
     if storage::get_escrow(&immutables.hashlock).is_some() {
         return Err(EscrowError::DuplicateEscrow);
     }
     
+    // Reject retried creations that already executed (e.g. before an upgrade)
+    let replay_key = request_key(RequestKind::Creation, &immutables);
+    check_not_replayed(&replay_key, current_time)?;
+    
+    let taker_funded = immutables.is_taker_funded_deposit();
+    let transfer_amount = immutables.required_deposit()?;
+    let token_fee = icrc_token_fee(immutables.icp_token).await?;
+    let subaccount = ledger::escrow_subaccount(&immutables.hashlock);
+    
     // Create escrow with deployment timestamp
     let mut escrow_immutables = immutables.clone();
     escrow_immutables.timelocks.deployed_at = current_time;
     
-    let escrow = ICPEscrow {
+    let mut escrow = ICPEscrow {
         immutables: escrow_immutables,
         state: EscrowState::Active,
         icp_tx_hash: None,
         evm_address: None,
         created_at: current_time,
         completed_at: None,
-        secret_hash: None,
+        revealed_secret: None,
+        surplus_captured: 0,
+        safety_deposit_held: if taker_funded { 0 } else { immutables.safety_deposit },
+        fee_breakdown: FeeBreakdown {
+            protocol_fees: config.creation_fee.into(),
+            ..FeeBreakdown::default()
+        },
+        settlement_blocks: Vec::new(),
+        deposit_blocks: Vec::new(),
+        notes: Vec::new(),
+        freeze: None,
+        rescue_requested_at: None,
+        processing: None,
+        creation_fee_block: None,
+        migration: None,
+        cycles: None,
+        token_fee,
+        subaccount: Some(subaccount.0.to_vec()),
+        partial_fill: None,
+        funding: None,
+        evm_verification: None,
+        evm_immutables: None,
+        evm_withdrawal: None,
+        rescue_balance: None,
+        transfer_legs: None,
+        escrow_type: Some(EscrowType::Source),
     };
     
     // Collect creation fee if configured
@@ -143,24 +857,32 @@ async fn create_src_escrow(immutables: EscrowImmutables) -> Result<Vec<u8>> {
             ledger::TransferOperation::Fee,
             &immutables.hashlock,
         );
-        ledger::transfer_to(config.treasury, config.creation_fee + 100, memo).await?;
+        escrow.creation_fee_block = Some(ledger::collect_fee(config.creation_fee, memo).await?);
+        storage::credit_fees(config.creation_fee);
         
         storage::update_metrics(|metrics| {
-            metrics.total_fees_collected += config.creation_fee;
+            metrics.total_fees_collected = metrics.total_fees_collected.saturating_add(config.creation_fee.into());
         });
     }
 
-    //Transfer ICP to escrow (safety deposit and amount)
-    let transfer_amount = immutables.amount + immutables.safety_deposit;
-    let deposit_memo = ledger::generate_transfer_memo(
+    // Transfer the escrowed token to escrow (safety deposit and amount)
+    // A taker-funded safety deposit is added later via fund_safety_deposit
+    let deposit_memo = ledger::TransferMemo::new(
         ledger::TransferOperation::Deposit,
         &immutables.hashlock,
     );
-    ledger::transfer_from_caller(transfer_amount, deposit_memo).await?;
+    let deposit_block = ledger::transfer_token_from_caller(immutables.icp_token, subaccount, transfer_amount, &deposit_memo).await?;
+    escrow.deposit_blocks.push(deposit_block);
+    
+    // Charge the creation call and its ledger transfers to the escrow
+    let ledger_calls = escrow.deposit_blocks.len() as u64 + escrow.creation_fee_block.is_some() as u64;
+    escrow.cycles_mut().creation += cycles::message_cost(ledger_calls);
     
     // Store escrow
     let hashlock = immutables.hashlock.clone();
     storage::insert_escrow(hashlock.clone(), escrow)?;
+    mark_processed(replay_key);
+    storage::add_volume(caller, current_time / VOLUME_PERIOD, immutables.amount);
     
     // Log event
     let event = EscrowEvent::EscrowCreated {
@@ -169,6 +891,7 @@ async fn create_src_escrow(immutables: EscrowImmutables) -> Result<Vec<u8>> {
         maker: immutables.maker.clone(),
         taker: immutables.taker.clone(),
         amount: immutables.amount,
+        metadata: immutables.metadata.clone(),
         timestamp: current_time,
     };
     storage::add_event(event);
@@ -178,43 +901,99 @@ async fn create_src_escrow(immutables: EscrowImmutables) -> Result<Vec<u8>> {
 
 /// Create a destination escrow for EVM→ICP swaps
 #[update]
-async fn create_dst_escrow(immutables: EscrowImmutables) -> Result<Vec<u8>> {
-    let _caller = caller_principal();
+async fn create_dst_escrow(
+    immutables: EscrowImmutables,
+    deadline_ns: Option<u64>,
+    template_id: Option<String>,
+) -> Result<Vec<u8>> {
+    let caller = caller_principal();
     let current_time = current_time();
-    let config = storage::get_config();
     
-    // Validate immutables
-    immutables.validate(&config)?;
+    check_deadline(deadline_ns)?;
+    
+    // Expand the referenced template, which may also override the creation fee
+    let mut immutables = immutables;
+    let config = apply_template(&mut immutables, template_id.as_deref(), storage::get_config())?;
+    check_not_paused(&config)?;
+    cycles::check_creation_allowed(&config)?;
+    guards::check_immutables(&immutables)?;
+    let _lock = guards::EscrowLock::acquire(&immutables.hashlock)?;
+    
+    // Validate immutables, and the amount against the order's auction price now
+    immutables.validate_dst(&config, None)?;
+    immutables.check_auction_price(current_time)?;
+    
+    // Unverified principals are limited to a volume per period
+    if !volume_usage(&caller, &config).allows(immutables.amount) {
+        return Err(EscrowError::VolumeCapExceeded);
+    }
     
     // Check if escrow already exists
     if storage::get_escrow(&immutables.hashlock).is_some() {
         return Err(EscrowError::DuplicateEscrow);
     }
     
+    // Only one resolver may lock funds against a maker order unless partial fills are allowed
+    if !config.allow_partial_fills
+        && storage::has_active_escrow_for_order(&immutables.order_hash, immutables.chain_id) {
+        return Err(EscrowError::DuplicateOrder);
+    }
+    
+    // Reject retried creations that already executed (e.g. before an upgrade)
+    let replay_key = request_key(RequestKind::Creation, &immutables);
+    check_not_replayed(&replay_key, current_time)?;
+    
     // Calculate total amount needed (amount + safety deposit + fees)
-    let transfer_amount = immutables.amount + immutables.safety_deposit;
+    let transfer_amount = utils::add_amounts(immutables.amount, immutables.safety_deposit)?;
     let _fees = ledger::calculate_total_fees(2); // One for deposit, one for fee
     ledger::validate_transfer_amount(transfer_amount, 2)?;
+    let token_fee = icrc_token_fee(immutables.icp_token).await?;
+    let subaccount = ledger::escrow_subaccount(&immutables.hashlock);
     
-    // Transfer ICP to escrow (deposit)
-    let deposit_memo = ledger::generate_transfer_memo(
+    // Transfer the escrowed token to escrow (deposit)
+    let deposit_memo = ledger::TransferMemo::new(
         ledger::TransferOperation::Deposit,
         &immutables.hashlock,
     );
-    ledger::transfer_from_caller(transfer_amount, deposit_memo).await?;
+    let deposit_block = ledger::transfer_token_from_caller(immutables.icp_token, subaccount, transfer_amount, &deposit_memo).await?;
     
     // Create escrow with deployment timestamp
     let mut escrow_immutables = immutables.clone();
     escrow_immutables.timelocks.deployed_at = current_time;
     
-    let escrow = ICPEscrow {
+    let mut escrow = ICPEscrow {
         immutables: escrow_immutables,
         state: EscrowState::Active,
         icp_tx_hash: None,
         evm_address: None,
         created_at: current_time,
         completed_at: None,
-        secret_hash: None,
+        revealed_secret: None,
+        surplus_captured: 0,
+        safety_deposit_held: immutables.safety_deposit,
+        fee_breakdown: FeeBreakdown {
+            protocol_fees: config.creation_fee.into(),
+            ..FeeBreakdown::default()
+        },
+        settlement_blocks: Vec::new(),
+        deposit_blocks: vec![deposit_block],
+        notes: Vec::new(),
+        freeze: None,
+        rescue_requested_at: None,
+        processing: None,
+        creation_fee_block: None,
+        migration: None,
+        cycles: None,
+        token_fee,
+        subaccount: Some(subaccount.0.to_vec()),
+        partial_fill: None,
+        funding: None,
+        evm_verification: None,
+        evm_immutables: None,
+        evm_withdrawal: None,
+        rescue_balance: None,
+        transfer_legs: None,
+        escrow_type: Some(EscrowType::Destination),
     };
     
     // Collect creation fee if configured
@@ -223,20 +1002,27 @@ async fn create_dst_escrow(immutables: EscrowImmutables) -> Result<Vec<u8>> {
             ledger::TransferOperation::Fee,
             &immutables.hashlock,
         );
-        ledger::transfer_from_caller(config.creation_fee, fee_memo).await?;
+        escrow.creation_fee_block = Some(ledger::collect_fee(config.creation_fee, fee_memo).await?);
+        storage::credit_fees(config.creation_fee);
         
         storage::update_metrics(|metrics| {
-            metrics.total_fees_collected += config.creation_fee;
+            metrics.total_fees_collected = metrics.total_fees_collected.saturating_add(config.creation_fee.into());
         });
     }
     
+    // Charge the creation call and its ledger transfers to the escrow
+    let ledger_calls = escrow.deposit_blocks.len() as u64 + escrow.creation_fee_block.is_some() as u64;
+    escrow.cycles_mut().creation += cycles::message_cost(ledger_calls);
+    
     // Store escrow
     let hashlock = immutables.hashlock.clone();
     storage::insert_escrow(hashlock.clone(), escrow)?;
+    mark_processed(replay_key);
+    storage::add_volume(caller, current_time / VOLUME_PERIOD, immutables.amount);
     
     // Update metrics
     storage::update_metrics(|metrics| {
-        metrics.total_volume_icp += immutables.amount;
+        metrics.total_volume_icp = metrics.total_volume_icp.saturating_add(immutables.amount);
     });
     
     // Log event
@@ -246,6 +1032,7 @@ async fn create_dst_escrow(immutables: EscrowImmutables) -> Result<Vec<u8>> {
         maker: immutables.maker.clone(),
         taker: immutables.taker.clone(),
         amount: immutables.amount,
+        metadata: immutables.metadata.clone(),
         timestamp: current_time,
     };
     storage::add_event(event);
@@ -253,294 +1040,877 @@ async fn create_dst_escrow(immutables: EscrowImmutables) -> Result<Vec<u8>> {
     Ok(hashlock)
 }
 
-// =============================================================================
-// WITHDRAWAL FUNCTIONS
-// =============================================================================
-
-/// Private withdrawal for source escrow (ICP→EVM)
+/// Announce an escrow without moving its funds. It is registered as PendingFunding and
+/// activates once `notify_deposit` proves the deposit reached the escrow's subaccount, so a
+/// failed or abandoned transfer never leaves a half-created escrow. Unfunded escrows expire
+/// after the configured funding window. The creation fee is still collected up front.
 #[update]
-async fn withdraw_src(secret: ByteBuf, hashlock: ByteBuf) -> Result<()> {
+async fn create_escrow(
+    immutables: EscrowImmutables,
+    escrow_type: EscrowType,
+    deadline_ns: Option<u64>,
+    template_id: Option<String>,
+) -> Result<Vec<u8>> {
     let caller = caller_principal();
-    let caller_str = caller.to_text();
     let current_time = current_time();
     
-    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    check_deadline(deadline_ns)?;
     
-    // Validate secret
-    if !validate_secret(&secret, &escrow.immutables.hashlock) {
-        return Err(EscrowError::InvalidSecret);
+    // Expand the referenced template, which may also override the creation fee
+    let mut immutables = immutables;
+    let config = apply_template(&mut immutables, template_id.as_deref(), storage::get_config())?;
+    check_not_paused(&config)?;
+    cycles::check_creation_allowed(&config)?;
+    guards::check_immutables(&immutables)?;
+    let _lock = guards::EscrowLock::acquire(&immutables.hashlock)?;
+    
+    // Validate immutables for the escrow's direction
+    match escrow_type {
+        EscrowType::Source => immutables.validate_src(&config)?,
+        EscrowType::Destination => {
+            immutables.validate_dst(&config, None)?;
+            immutables.check_auction_price(current_time)?;
+        }
     }
     
-    // Check state
-    if !matches!(escrow.state, EscrowState::Active) {
-        return Err(EscrowError::InvalidState);
+    // Unverified principals are limited to a volume per period
+    if !volume_usage(&caller, &config).allows(immutables.amount) {
+        return Err(EscrowError::VolumeCapExceeded);
     }
     
-    // Check timing
-    // check_timing(&escrow, TimingCheck::PrivateWithdrawal)?;
+    // Check if escrow already exists
+    if storage::get_escrow(&immutables.hashlock).is_some() {
+        return Err(EscrowError::DuplicateEscrow);
+    }
     
-    // Check authorization (maker or taker)
-    if !is_maker_or_taker(&escrow, &caller_str) {
-        return Err(EscrowError::InvalidCaller);
+    // Only one resolver may lock funds against a maker order unless partial fills are allowed
+    if matches!(escrow_type, EscrowType::Destination)
+        && !config.allow_partial_fills
+        && storage::has_active_escrow_for_order(&immutables.order_hash, immutables.chain_id) {
+        return Err(EscrowError::DuplicateOrder);
     }
     
-    // Transfer ICP to taker
-    let taker_principal = utils::validate_principal(&escrow.immutables.taker)?;
-    let withdrawal_memo = ledger::generate_transfer_memo(
-        ledger::TransferOperation::Withdrawal,
-        &hashlock,
-    );
-    ledger::transfer_to(taker_principal, escrow.immutables.amount, withdrawal_memo).await?;
+    // Reject retried creations that already executed (e.g. before an upgrade)
+    let replay_key = request_key(RequestKind::Creation, &immutables);
+    check_not_replayed(&replay_key, current_time)?;
     
-    // Return safety deposit to maker
-    let maker_principal = utils::validate_principal(&escrow.immutables.maker)?;
-    let refund_memo = ledger::generate_transfer_memo(
-        ledger::TransferOperation::Cancellation,
-        &hashlock,
-    );
-    ledger::transfer_to(maker_principal, escrow.immutables.safety_deposit, refund_memo).await?;
+    let token_fee = icrc_token_fee(immutables.icp_token).await?;
+    let subaccount = ledger::escrow_subaccount(&immutables.hashlock);
     
-    // Update escrow state
-    storage::update_escrow(&hashlock, |escrow| {
-        escrow.state = EscrowState::Completed;
-        escrow.completed_at = Some(current_time);
-        escrow.secret_hash = Some(secret.to_vec());
-    })?;
+    // The deposit covers the amount and, unless the taker funds it later, the safety deposit
+    let required = immutables.required_deposit()?;
     
-    // Update metrics
-    storage::update_metrics(|metrics| {
-        metrics.total_escrows_completed += 1;
-        metrics.active_escrows_count = metrics.active_escrows_count.saturating_sub(1);
-    });
+    let mut escrow = ICPEscrow {
+        immutables: immutables.clone(),
+        state: EscrowState::PendingFunding,
+        icp_tx_hash: None,
+        evm_address: None,
+        created_at: current_time,
+        completed_at: None,
+        revealed_secret: None,
+        surplus_captured: 0,
+        safety_deposit_held: 0,
+        fee_breakdown: FeeBreakdown {
+            protocol_fees: config.creation_fee.into(),
+            ..FeeBreakdown::default()
+        },
+        settlement_blocks: Vec::new(),
+        deposit_blocks: Vec::new(),
+        notes: Vec::new(),
+        freeze: None,
+        rescue_requested_at: None,
+        processing: None,
+        creation_fee_block: None,
+        migration: None,
+        cycles: None,
+        token_fee,
+        subaccount: Some(subaccount.0.to_vec()),
+        partial_fill: None,
+        funding: Some(FundingWindow {
+            escrow_type: escrow_type.clone(),
+            required,
+            deadline: current_time.saturating_add(config.funding_window),
+        }),
+        evm_verification: None,
+        evm_immutables: None,
+        evm_withdrawal: None,
+        rescue_balance: None,
+        transfer_legs: None,
+        escrow_type: Some(escrow_type.clone()),
+    };
+    
+    // Collect creation fee if configured
+    if config.creation_fee > 0 {
+        let fee_memo = ledger::generate_transfer_memo(
+            ledger::TransferOperation::Fee,
+            &immutables.hashlock,
+        );
+        escrow.creation_fee_block = Some(ledger::collect_fee(config.creation_fee, fee_memo).await?);
+        storage::credit_fees(config.creation_fee);
+        
+        storage::update_metrics(|metrics| {
+            metrics.total_fees_collected = metrics.total_fees_collected.saturating_add(config.creation_fee.into());
+        });
+    }
+    escrow.cycles_mut().creation += cycles::message_cost(escrow.creation_fee_block.is_some() as u64);
+    
+    // Store escrow
+    let hashlock = immutables.hashlock.clone();
+    storage::insert_escrow(hashlock.clone(), escrow)?;
+    mark_processed(replay_key);
+    storage::add_volume(caller, current_time / VOLUME_PERIOD, immutables.amount);
     
     // Log event
-    let event = EscrowEvent::EscrowWithdrawal {
-        hashlock: hashlock.to_vec(),
-        withdrawer: caller,
-        secret: secret.to_vec(),
+    let event = EscrowEvent::EscrowCreated {
+        hashlock: hashlock.clone(),
+        escrow_type,
+        maker: immutables.maker.clone(),
+        taker: immutables.taker.clone(),
+        amount: immutables.amount,
+        metadata: immutables.metadata.clone(),
         timestamp: current_time,
     };
     storage::add_event(event);
     
-    Ok(())
+    Ok(hashlock)
 }
 
-/// Private withdrawal for destination escrow (EVM→ICP)
+/// Entry of a batch creation that passed its checks, held locked until it is placed
+struct BatchEntry {
+    index: usize,
+    immutables: EscrowImmutables,
+    required: u128,                // Deposit its subaccount receives: the amount and any resolver-funded safety deposit
+    _lock: guards::EscrowLock,
+}
+
+/// Checks a batch entry must pass before any funds move: those of `create_src_escrow` or
+/// `create_dst_escrow`, counting the volume and destination orders of the entries accepted before it
+fn check_batch_entry(
+    immutables: &EscrowImmutables,
+    escrow_type: &EscrowType,
+    config: &EscrowConfig,
+    usage: &VolumeUsage,
+    batched: u128,
+    orders: &[(Vec<u8>, Option<u64>)],
+    current_time: u64,
+) -> Result<()> {
+    guards::check_immutables(immutables)?;
+    match escrow_type {
+        EscrowType::Source => immutables.validate_src(config)?,
+        EscrowType::Destination => {
+            immutables.validate_dst(config, None)?;
+            immutables.check_auction_price(current_time)?;
+        }
+    }
+    
+    if !usage.allows(batched.saturating_add(immutables.amount)) {
+        return Err(EscrowError::VolumeCapExceeded);
+    }
+    if storage::get_escrow(&immutables.hashlock).is_some() {
+        return Err(EscrowError::DuplicateEscrow);
+    }
+    
+    let order = (immutables.order_hash.clone(), immutables.chain_id);
+    if matches!(escrow_type, EscrowType::Destination)
+        && !config.allow_partial_fills
+        && (orders.contains(&order) || storage::has_active_escrow_for_order(&order.0, order.1)) {
+        return Err(EscrowError::DuplicateOrder);
+    }
+    
+    check_not_replayed(&request_key(RequestKind::Creation, immutables), current_time)
+}
+
+/// Create up to `MAX_BATCH_SIZE` escrows of one direction with a single pull from the caller.
+/// Entries are checked as `create_src_escrow` and `create_dst_escrow` check them and must all
+/// escrow the same token. The caller approves the deposits of the entries that pass plus one
+/// ledger fee each, which pays for splitting the pull across their subaccounts. An entry that
+/// fails a check, or whose split or record fails, is reported in its slot and not created; the
+/// funds of those entries go back to the caller in one refund. Creation fees are collected after the
+/// pull for every entry that passed its checks; if that fails the pull is returned and nothing
+/// is created. Fees of entries that were not created are refunded from the fee subaccount, less
+/// the ledger fee of the refund.
 #[update]
-async fn withdraw_dst(secret: ByteBuf, hashlock: ByteBuf) -> Result<()> {
+async fn create_escrows_batch(entries: Vec<EscrowImmutables>, escrow_type: EscrowType) -> Result<BatchCreation> {
     let caller = caller_principal();
-    let caller_str = caller.to_text();
     let current_time = current_time();
+    let config = storage::get_config();
+    check_not_paused(&config)?;
+    cycles::check_creation_allowed(&config)?;
     
-    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
-    
-    // Validate secret
-    if !validate_secret(&secret, &escrow.immutables.hashlock) {
-        return Err(EscrowError::InvalidSecret);
+    if entries.is_empty() || entries.len() > MAX_BATCH_SIZE {
+        return Err(EscrowError::MalformedInput(format!("batch must hold 1 to {} escrows", MAX_BATCH_SIZE)));
     }
     
-    // Check state
-    if !matches!(escrow.state, EscrowState::Active) {
-        return Err(EscrowError::InvalidState);
+    // Check every entry before any funds move
+    let token = entries[0].icp_token;
+    let usage = volume_usage(&caller, &config);
+    let mut results = Vec::with_capacity(entries.len());
+    let mut accepted = Vec::new();
+    let mut batched = 0u128;
+    let mut orders = Vec::new();
+    for (index, immutables) in entries.into_iter().enumerate() {
+        let checked = if immutables.icp_token != token {
+            Err(EscrowError::UnsupportedToken)
+        } else {
+            check_batch_entry(&immutables, &escrow_type, &config, &usage, batched, &orders, current_time)
+                .and_then(|()| immutables.required_deposit())
+                .and_then(|required| Ok((guards::EscrowLock::acquire(&immutables.hashlock)?, required)))
+        };
+        match checked {
+            Ok((lock, required)) => {
+                batched = batched.saturating_add(immutables.amount);
+                orders.push((immutables.order_hash.clone(), immutables.chain_id));
+                results.push(Ok(immutables.hashlock.clone()));
+                accepted.push(BatchEntry { index, immutables, required, _lock: lock });
+            }
+            Err(error) => results.push(Err(error)),
+        }
     }
+    let Some(first) = accepted.first() else {
+        return Ok(BatchCreation { results, deposit_block: None, refund: None, fee_refund: None });
+    };
     
-    // Check timing
-    check_timing(&escrow, TimingCheck::PrivateWithdrawal)?;
-    
-    // Check authorization (maker or taker)
-    if !is_maker_or_taker(&escrow, &caller_str) {
-        return Err(EscrowError::InvalidCaller);
-    }
+    // The pull is staged in a subaccount of the batch's own, then split across the escrows
+    let batch_hashlock = first.immutables.hashlock.clone();
+    let staging = ledger::batch_subaccount(&batch_hashlock);
+    let token_fee = icrc_token_fee(token).await?;
+    let split_fee = token_fee.unwrap_or(ledger::TRANSFER_FEE.into());
+    let total = accepted
+        .iter()
+        .try_fold(0u128, |total, entry| total.checked_add(entry.required)?.checked_add(split_fee))
+        .ok_or(EscrowError::InvalidAmount)?;
     
-    // Transfer ICP to maker
-    let maker_principal = utils::validate_principal(&escrow.immutables.maker)?;
-    let withdrawal_memo = ledger::generate_transfer_memo(
-        ledger::TransferOperation::Withdrawal,
-        &hashlock,
+    let deposit_memo = ledger::TransferMemo::new(
+        ledger::TransferOperation::Deposit,
+        &batch_hashlock,
     );
-    ledger::transfer_to(maker_principal, escrow.immutables.amount, withdrawal_memo).await?;
+    let deposit_block = ledger::transfer_token_from_caller(token, staging, total, &deposit_memo).await?;
     
-    // Return safety deposit to taker
-    let taker_principal = utils::validate_principal(&escrow.immutables.taker)?;
-    let refund_memo = ledger::generate_transfer_memo(
-        ledger::TransferOperation::Cancellation,
-        &hashlock,
-    );
-    ledger::transfer_to(taker_principal, escrow.immutables.safety_deposit, refund_memo).await?;
+    // Collect creation fees once the deposits are in, as create_dst_escrow does
+    let creation_fees = config.creation_fee.saturating_mul(accepted.len() as u64);
+    let mut creation_fee_block = None;
+    if creation_fees > 0 {
+        let fee_memo = ledger::generate_transfer_memo(
+            ledger::TransferOperation::Fee,
+            &batch_hashlock,
+        );
+        match ledger::collect_fee(creation_fees, fee_memo).await {
+            Ok(block) => creation_fee_block = Some(block),
+            Err(error) => {
+                // Nothing was created; one of the unused split fees pays for returning the pull
+                let refund_memo = ledger::TransferMemo::new(
+                    ledger::TransferOperation::Cancellation,
+                    &batch_hashlock,
+                );
+                if let Err(refund_error) = ledger::transfer_token_to(token, staging, caller, total - split_fee, &refund_memo).await {
+                    ic_cdk::api::debug_print(format!("Returning batch deposit failed: {:?}", refund_error));
+                }
+                return Err(error);
+            }
+        }
+        storage::credit_fees(creation_fees);
+        
+        storage::update_metrics(|metrics| {
+            metrics.total_fees_collected = metrics.total_fees_collected.saturating_add(creation_fees.into());
+        });
+    }
     
-    // Update escrow state
-    storage::update_escrow(&hashlock, |escrow| {
-        escrow.state = EscrowState::Completed;
-        escrow.completed_at = Some(current_time);
-        escrow.secret_hash = Some(secret.to_vec());
-    })?;
+    let mut unplaced = 0u128;
+    let mut unplaced_entries = 0u64;
+    for entry in accepted {
+        let immutables = entry.immutables;
+        let subaccount = ledger::escrow_subaccount(&immutables.hashlock);
+        let split_memo = ledger::TransferMemo::new(
+            ledger::TransferOperation::Deposit,
+            &immutables.hashlock,
+        );
+        let split_block = match ledger::move_token(token, staging, subaccount, entry.required, &split_memo).await {
+            Ok(block) => block,
+            Err(error) => {
+                unplaced = unplaced.saturating_add(entry.required).saturating_add(split_fee);
+                unplaced_entries += 1;
+                results[entry.index] = Err(error);
+                continue;
+            }
+        };
+        
+        let mut escrow_immutables = immutables.clone();
+        escrow_immutables.timelocks.deployed_at = current_time;
+        let escrow = ICPEscrow {
+            immutables: escrow_immutables,
+            state: EscrowState::Active,
+            icp_tx_hash: None,
+            evm_address: None,
+            created_at: current_time,
+            completed_at: None,
+            revealed_secret: None,
+            surplus_captured: 0,
+            safety_deposit_held: if immutables.is_taker_funded_deposit() { 0 } else { immutables.safety_deposit },
+            fee_breakdown: FeeBreakdown {
+                protocol_fees: config.creation_fee.into(),
+                ..FeeBreakdown::default()
+            },
+            settlement_blocks: Vec::new(),
+            deposit_blocks: vec![split_block],
+            notes: Vec::new(),
+            freeze: None,
+            rescue_requested_at: None,
+            processing: None,
+            creation_fee_block,
+            migration: None,
+            cycles: Some(CycleCosts { creation: cycles::message_cost(1), ..CycleCosts::default() }),
+            token_fee,
+            subaccount: Some(subaccount.0.to_vec()),
+            partial_fill: None,
+            funding: None,
+            evm_verification: None,
+            evm_immutables: None,
+            evm_withdrawal: None,
+            rescue_balance: None,
+            transfer_legs: None,
+            escrow_type: Some(escrow_type.clone()),
+        };
+        
+        let hashlock = immutables.hashlock.clone();
+        if let Err(error) = storage::insert_escrow(hashlock.clone(), escrow) {
+            // Without a record nothing could reach the split funds; return them to staging
+            let return_memo = ledger::TransferMemo::new(
+                ledger::TransferOperation::Cancellation,
+                &hashlock,
+            );
+            let returned = entry.required.saturating_sub(split_fee);
+            match ledger::move_token(token, subaccount, staging, returned, &return_memo).await {
+                Ok(_) => unplaced = unplaced.saturating_add(returned),
+                Err(move_error) => ic_cdk::api::debug_print(format!("Returning split funds to staging failed: {:?}", move_error)),
+            }
+            unplaced_entries += 1;
+            results[entry.index] = Err(error);
+            continue;
+        }
+        mark_processed(request_key(RequestKind::Creation, &immutables));
+        storage::add_volume(caller, current_time / VOLUME_PERIOD, immutables.amount);
+        if matches!(escrow_type, EscrowType::Destination) {
+            storage::update_metrics(|metrics| {
+                metrics.total_volume_icp = metrics.total_volume_icp.saturating_add(immutables.amount);
+            });
+        }
+        
+        storage::add_event(EscrowEvent::EscrowCreated {
+            hashlock,
+            escrow_type: escrow_type.clone(),
+            maker: immutables.maker,
+            taker: immutables.taker,
+            amount: immutables.amount,
+            metadata: immutables.metadata,
+            timestamp: current_time,
+        });
+    }
     
-    // Update metrics
-    storage::update_metrics(|metrics| {
-        metrics.total_escrows_completed += 1;
-        metrics.active_escrows_count = metrics.active_escrows_count.saturating_sub(1);
-    });
+    // Failed splits left their deposit and unused split fee behind, and entries that could not be
+    // stored had their split moved back; one ledger fee of that pays for the refund
+    let refund = if unplaced > split_fee {
+        let refund_memo = ledger::TransferMemo::new(
+            ledger::TransferOperation::Cancellation,
+            &batch_hashlock,
+        );
+        Some(ledger::transfer_token_to(token, staging, caller, unplaced - split_fee, &refund_memo).await)
+    } else {
+        None
+    };
     
-    // Log event
-    let event = EscrowEvent::EscrowWithdrawal {
-        hashlock: hashlock.to_vec(),
-        withdrawer: caller,
-        secret: secret.to_vec(),
-        timestamp: current_time,
+    // Entries that were not created do not keep their creation fee
+    let unplaced_fees = config.creation_fee.saturating_mul(unplaced_entries);
+    let fee_refund = if unplaced_fees > ledger::TRANSFER_FEE {
+        Some(refund_creation_fees(caller, unplaced_fees, &batch_hashlock, current_time).await)
+    } else {
+        None
     };
-    storage::add_event(event);
     
-    Ok(())
+    Ok(BatchCreation { results, deposit_block: Some(deposit_block), refund, fee_refund })
 }
 
-/// Public withdrawal by authorized principals
-#[update]
-async fn public_withdraw(secret: ByteBuf, hashlock: ByteBuf, escrow_type: EscrowType) -> Result<()> {
-    let caller = caller_principal();
-    let current_time = current_time();
-    
-    // Check authorization
-    if !is_authorized() {
-        return Err(EscrowError::Unauthorized);
-    }
+/// Return `fees` collected for batch entries that were not created to the caller, less the
+/// ledger fee, which the fee subaccount pays as it does for `withdraw_fees`
+async fn refund_creation_fees(caller: Principal, fees: u64, batch_hashlock: &[u8], now: u64) -> Result<u64> {
+    storage::debit_fees(fees)?;
+    let memo = ledger::generate_transfer_memo(ledger::TransferOperation::Cancellation, batch_hashlock);
+    let amount = fees - ledger::TRANSFER_FEE;
+    let block = match ledger::withdraw_fees(caller, amount, memo).await {
+        Ok(block) => block,
+        Err(err) => {
+            storage::credit_fees(fees);
+            return Err(err);
+        }
+    };
     
+    storage::update_metrics(|metrics| {
+        metrics.total_fees_collected = metrics.total_fees_collected.saturating_sub(fees.into());
+    });
+    // Recorded like a withdrawal so the fee subaccount's outflows stay complete in the export
+    storage::add_event(EscrowEvent::FeesWithdrawn {
+        to: caller,
+        amount,
+        block,
+        withdrawn_by: caller,
+        timestamp: now,
+    });
+    Ok(block)
+}
+
+/// Activate an escrow announced with `create_escrow`: `block_index` must be a ledger transfer
+/// of at least the required deposit into the escrow's subaccount, made before the funding
+/// deadline. Timelocks start from activation. Anyone may notify, as the ledger is the proof.
+#[update]
+async fn notify_deposit(hashlock: ByteBuf, block_index: u64) -> Result<()> {
+    guards::check_hash("hashlock", &hashlock)?;
+    let _lock = guards::EscrowLock::acquire(&hashlock)?;
     let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
     
-    // Validate secret
-    if !validate_secret(&secret, &escrow.immutables.hashlock) {
-        return Err(EscrowError::InvalidSecret);
-    }
-    
     // Check state
-    if !matches!(escrow.state, EscrowState::Active) {
-        return Err(EscrowError::InvalidState);
+    let funding = match (&escrow.state, &escrow.funding) {
+        (EscrowState::PendingFunding, Some(funding)) => funding.clone(),
+        _ => return Err(EscrowError::InvalidState),
+    };
+    if current_time() > funding.deadline {
+        return Err(EscrowError::InvalidTime);
     }
     
-    // Check timing
-    check_timing(&escrow, TimingCheck::PublicWithdrawal)?;
+    // Verify the deposit on the escrowed token's ledger
+    let amount = ledger::verify_token_deposit(escrow.immutables.icp_token, escrow.funds_subaccount(), block_index).await?;
+    if amount < funding.required {
+        return Err(EscrowError::InsufficientBalance { required: funding.required, available: amount });
+    }
     
-    // Execute withdrawal based on escrow type
-    match escrow_type {
-        EscrowType::Source => {
-            // Transfer ICP to taker
-            let taker_principal = utils::validate_principal(&escrow.immutables.taker)?;
-            let withdrawal_memo = ledger::generate_transfer_memo(
-                ledger::TransferOperation::Withdrawal,
-                &hashlock,
-            );
-            ledger::transfer_to(taker_principal, escrow.immutables.amount, withdrawal_memo).await?;
-            
-            // Return safety deposit to maker
-            let maker_principal = utils::validate_principal(&escrow.immutables.maker)?;
-            let refund_memo = ledger::generate_transfer_memo(
-                ledger::TransferOperation::Cancellation,
-                &hashlock,
-            );
-            ledger::transfer_to(maker_principal, escrow.immutables.safety_deposit, refund_memo).await?;
-        }
-        EscrowType::Destination => {
-            // Transfer ICP to maker
-            let maker_principal = utils::validate_principal(&escrow.immutables.maker)?;
-            let withdrawal_memo = ledger::generate_transfer_memo(
-                ledger::TransferOperation::Withdrawal,
-                &hashlock,
-            );
-            ledger::transfer_to(maker_principal, escrow.immutables.amount, withdrawal_memo).await?;
-            
-            // Return safety deposit to taker
-            let taker_principal = utils::validate_principal(&escrow.immutables.taker)?;
-            let refund_memo = ledger::generate_transfer_memo(
-                ledger::TransferOperation::Cancellation,
-                &hashlock,
-            );
-            ledger::transfer_to(taker_principal, escrow.immutables.safety_deposit, refund_memo).await?;
-        }
+    // Another notification may have activated it, or the timer expired it, meanwhile
+    let still_pending = storage::get_escrow(&hashlock).is_some_and(|escrow| escrow.state == EscrowState::PendingFunding);
+    if !still_pending {
+        return Err(EscrowError::InvalidState);
     }
     
-    // Update escrow state
+    let current_time = current_time();
+    let safety_deposit_held = if escrow.immutables.is_taker_funded_deposit() {
+        0
+    } else {
+        escrow.immutables.safety_deposit
+    };
     storage::update_escrow(&hashlock, |escrow| {
-        escrow.state = EscrowState::Completed;
-        escrow.completed_at = Some(current_time);
-        escrow.secret_hash = Some(secret.to_vec());
+        escrow.state = EscrowState::Active;
+        escrow.immutables.timelocks.deployed_at = current_time;
+        escrow.safety_deposit_held = safety_deposit_held;
+        escrow.deposit_blocks.push(block_index);
+        escrow.cycles_mut().creation += cycles::message_cost(1);
     })?;
     
-    // Update metrics
-    storage::update_metrics(|metrics| {
-        metrics.total_escrows_completed += 1;
-        metrics.active_escrows_count = metrics.active_escrows_count.saturating_sub(1);
-    });
+    if matches!(funding.escrow_type, EscrowType::Destination) {
+        storage::update_metrics(|metrics| {
+            metrics.total_volume_icp = metrics.total_volume_icp.saturating_add(escrow.immutables.amount);
+        });
+    }
     
-    // Log event
-    let event = EscrowEvent::EscrowWithdrawal {
+    storage::add_event(EscrowEvent::EscrowFunded {
         hashlock: hashlock.to_vec(),
-        withdrawer: caller,
-        secret: secret.to_vec(),
+        block_index,
+        amount,
         timestamp: current_time,
-    };
-    storage::add_event(event);
+    });
     
     Ok(())
 }
 
-// =============================================================================
-// CANCELLATION AND RESCUE FUNCTIONS
-// =============================================================================
-
-/// Cancel an escrow and return funds
+/// Create the ICP destination escrow and record the counterpart-chain source leg in one call.
+/// Only a registered resolver, as taker of the destination leg, can link a pair.
 #[update]
-async fn cancel_escrow(hashlock: ByteBuf, escrow_type: EscrowType) -> Result<()> {
+async fn create_linked_pair(src_immutables: EscrowImmutables, dst_immutables: EscrowImmutables) -> Result<SwapSession> {
     let caller = caller_principal();
-    let caller_str = caller.to_text();
-    let current_time = current_time();
     
-    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    guards::check_immutables(&src_immutables)?;
+    guards::check_immutables(&dst_immutables)?;
+    if !storage::is_resolver(&caller) {
+        return Err(EscrowError::Unauthorized);
+    }
+    if dst_immutables.taker != caller.to_text() {
+        return Err(EscrowError::InvalidCaller);
+    }
+    SwapSession::validate_legs(&src_immutables, &dst_immutables)?;
     
-    // Check state
-    if !matches!(escrow.state, EscrowState::Active) {
-        return Err(EscrowError::InvalidState);
+    // Compare the legs on absolute time: the source leg's recorded deployment against
+    // the destination escrow about to be deployed now
+    let now = current_time();
+    if src_immutables.timelocks.deployed_at == 0 || src_immutables.timelocks.deployed_at > now {
+        return Err(EscrowError::InvalidTime);
+    }
+    let mut projected_dst = dst_immutables.clone();
+    projected_dst.timelocks.deployed_at = now;
+    projected_dst.validate_dst(&storage::get_config(), Some(&src_immutables))?;
+    let cancellation_gap = SwapSession::cancellation_gap(&src_immutables.timelocks, &projected_dst.timelocks)?;
+    if storage::get_session(&dst_immutables.hashlock).is_some() {
+        return Err(EscrowError::DuplicateEscrow);
     }
     
-    // Check timing
-    check_timing(&escrow, TimingCheck::Cancellation)?;
+    // The session is only recorded once the destination escrow holds funds
+    let hashlock = create_dst_escrow(dst_immutables, None, None).await?;
     
-    // Check authorization and execute based on escrow type
-    match escrow_type {
-        EscrowType::Source => {
-            // Only maker can cancel source escrow
-            if caller_str != escrow.immutables.maker {
-                return Err(EscrowError::InvalidCaller);
-            }
-            
-            // Return all funds to maker
-            let maker_principal = utils::validate_principal(&escrow.immutables.maker)?;
-            let total_amount = escrow.immutables.amount + escrow.immutables.safety_deposit;
-            let cancel_memo = ledger::generate_transfer_memo(
+    let session = SwapSession {
+        hashlock: hashlock.clone(),
+        order_hash: src_immutables.order_hash.clone(),
+        resolver: caller,
+        src_immutables,
+        cancellation_gap,
+        created_at: now,
+    };
+    storage::insert_session(session.clone())?;
+    
+    storage::add_event(EscrowEvent::SwapSessionCreated {
+        hashlock,
+        order_hash: session.order_hash.clone(),
+        resolver: caller,
+        src_chain_id: session.src_immutables.chain_id,
+        timestamp: session.created_at,
+    });
+    
+    Ok(session)
+}
+
+/// Announce a swap intent for resolvers to fill. The source leg must come from the EVM address
+/// the caller linked by signature, and the caller must be the maker of the ICP leg.
+#[update]
+fn announce_order(order: OrderIntent) -> Result<SwapOrder> {
+    orders::announce(caller_principal(), order, current_time())
+}
+
+/// Orders resolvers can still accept, by id after `after`
+#[query]
+fn list_open_orders(after: Option<u64>, limit: u32) -> Vec<SwapOrder> {
+    let limit = (limit as usize).clamp(1, MAX_PAGE_SIZE);
+    storage::get_open_orders(after, current_time(), limit)
+}
+
+#[query]
+fn get_order(order_id: u64) -> Option<SwapOrder> {
+    storage::get_order(order_id)
+}
+
+/// Fill an open order: deploys the ICP destination escrow and links the source leg the
+/// resolver deploys from its linked EVM address (registered resolvers only)
+#[update]
+async fn accept_order(order_id: u64) -> Result<SwapSession> {
+    orders::accept(caller_principal(), order_id, current_time()).await
+}
+
+/// Withdraw an open order (maker only)
+#[update]
+fn cancel_order(order_id: u64) -> Result<SwapOrder> {
+    orders::cancel(caller_principal(), order_id, current_time())
+}
+
+/// Fund the safety deposit of a taker-funded source escrow (taker only)
+#[update]
+async fn fund_safety_deposit(hashlock: ByteBuf) -> Result<()> {
+    let caller = caller_principal();
+    guards::check_hash("hashlock", &hashlock)?;
+    let _lock = guards::EscrowLock::acquire(&hashlock)?;
+    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    
+    if !escrow.immutables.is_taker_funded_deposit() {
+        return Err(EscrowError::InvalidDepositMode);
+    }
+    
+    if caller.to_text() != escrow.immutables.taker {
+        return Err(EscrowError::InvalidCaller);
+    }
+    
+    // Only an active escrow with an unfunded deposit can be funded
+    let amount = escrow.immutables.safety_deposit;
+    if !matches!(escrow.state, EscrowState::Active) || escrow.safety_deposit_held > 0 || amount == 0 {
+        return Err(EscrowError::InvalidState);
+    }
+    
+    // Mark as funded before the transfer so concurrent calls cannot pull twice
+    storage::update_escrow(&hashlock, |escrow| escrow.safety_deposit_held = amount)?;
+    
+    let deposit_memo = ledger::TransferMemo::new(
+        ledger::TransferOperation::Deposit,
+        &hashlock,
+    );
+    match ledger::transfer_token_from_caller(escrow.immutables.icp_token, escrow.funds_subaccount(), amount, &deposit_memo).await {
+        Ok(block) => storage::update_escrow(&hashlock, |escrow| {
+            escrow.deposit_blocks.push(block);
+            escrow.cycles_mut().creation += cycles::message_cost(1);
+        })?,
+        Err(err) => {
+            storage::update_escrow(&hashlock, |escrow| escrow.safety_deposit_held = 0)?;
+            return Err(err);
+        }
+    }
+    
+    storage::add_event(EscrowEvent::SafetyDepositFunded {
+        hashlock: hashlock.to_vec(),
+        funder: caller,
+        amount,
+        timestamp: current_time(),
+    });
+    
+    Ok(())
+}
+
+// =============================================================================
+// WITHDRAWAL FUNCTIONS
+// =============================================================================
+
+/// Private withdrawal for source escrow (ICP→EVM)
+#[update]
+async fn withdraw_src(
+    secret: ByteBuf,
+    hashlock: ByteBuf,
+    deadline_ns: Option<u64>,
+    order_hash: Option<ByteBuf>,
+) -> Result<()> {
+    let caller = caller_principal();
+    let caller_str = caller.to_text();
+    
+    check_deadline(deadline_ns)?;
+    guards::check_withdrawal(&secret, &hashlock, order_hash.as_ref().map(|hash| hash.as_slice()))?;
+    let _lock = guards::EscrowLock::acquire(&hashlock)?;
+    
+    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    check_escrow_type(&escrow, EscrowType::Source)?;
+    
+    // Validate secret
+    check_secret(&escrow, &secret, order_hash.as_ref().map(|hash| hash.as_slice()))?;
+    
+    // Check state
+    if !matches!(escrow.state, EscrowState::Active) {
+        return Err(EscrowError::InvalidState);
+    }
+    
+    // Check timing
+    check_timing(&escrow, TimingCheck::PrivateWithdrawal)?;
+    
+    // Check authorization (maker or taker)
+    if !is_maker_or_taker(&escrow, &caller_str) {
+        return Err(EscrowError::InvalidCaller);
+    }
+    
+    complete_withdrawal(&escrow, &hashlock, &secret, caller, false).await
+}
+
+/// Partial withdrawal from a partially fillable source escrow. `secret` must be the one for
+/// the part the cumulative fill reaches, with `proof` linking it to the escrow's Merkle root.
+/// The fill that completes the order settles the escrow like `withdraw_src`; earlier fills
+/// pay the taker and leave the rest locked.
+#[update]
+async fn withdraw_src_partial(
+    secret: ByteBuf,
+    hashlock: ByteBuf,
+    proof: Vec<ByteBuf>,
+    fill_amount: u128,
+    deadline_ns: Option<u64>,
+) -> Result<()> {
+    let caller = caller_principal();
+    let caller_str = caller.to_text();
+    
+    check_deadline(deadline_ns)?;
+    guards::check_withdrawal(&secret, &hashlock, None)?;
+    let _lock = guards::EscrowLock::acquire(&hashlock)?;
+    let proof: Vec<Vec<u8>> = proof.into_iter().map(ByteBuf::into_vec).collect();
+    guards::check_merkle_proof(&proof)?;
+    
+    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    check_escrow_type(&escrow, EscrowType::Source)?;
+    
+    // Check state
+    if !matches!(escrow.state, EscrowState::Active) {
+        return Err(EscrowError::InvalidState);
+    }
+    
+    // Check authorization (maker or taker)
+    if !is_maker_or_taker(&escrow, &caller_str) {
+        return Err(EscrowError::InvalidCaller);
+    }
+    
+    // Validate the secret for the part this fill reaches against the Merkle root
+    let secret_index = escrow.fill_secret_index(fill_amount)?;
+    if !utils::validate_fill_secret(&secret, secret_index, &proof, &escrow.immutables) {
+        return Err(EscrowError::InvalidMerkleProof);
+    }
+    
+    if fill_amount == escrow.remaining_amount() {
+        return complete_withdrawal(&escrow, &hashlock, &secret, caller, false).await;
+    }
+    fill_partially(&escrow, &hashlock, &secret, caller, secret_index, fill_amount).await
+}
+
+/// Private withdrawal for destination escrow (EVM→ICP)
+#[update]
+async fn withdraw_dst(
+    secret: ByteBuf,
+    hashlock: ByteBuf,
+    deadline_ns: Option<u64>,
+    order_hash: Option<ByteBuf>,
+) -> Result<()> {
+    let caller = caller_principal();
+    let caller_str = caller.to_text();
+    
+    check_deadline(deadline_ns)?;
+    guards::check_withdrawal(&secret, &hashlock, order_hash.as_ref().map(|hash| hash.as_slice()))?;
+    let _lock = guards::EscrowLock::acquire(&hashlock)?;
+    
+    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    check_escrow_type(&escrow, EscrowType::Destination)?;
+    
+    // Validate secret
+    check_secret(&escrow, &secret, order_hash.as_ref().map(|hash| hash.as_slice()))?;
+    
+    // Check state
+    if !matches!(escrow.state, EscrowState::Active) {
+        return Err(EscrowError::InvalidState);
+    }
+    
+    // Check timing
+    check_timing(&escrow, TimingCheck::PrivateWithdrawal)?;
+    
+    // Check authorization (maker or taker)
+    if !is_maker_or_taker(&escrow, &caller_str) {
+        return Err(EscrowError::InvalidCaller);
+    }
+    
+    complete_withdrawal(&escrow, &hashlock, &secret, caller, false).await
+}
+
+/// Public withdrawal by authorized principals, resolvers or anyone, per the configured mode
+#[update]
+async fn public_withdraw(
+    secret: ByteBuf,
+    hashlock: ByteBuf,
+    deadline_ns: Option<u64>,
+    order_hash: Option<ByteBuf>,
+) -> Result<()> {
+    let caller = caller_principal();
+    
+    check_deadline(deadline_ns)?;
+    guards::check_withdrawal(&secret, &hashlock, order_hash.as_ref().map(|hash| hash.as_slice()))?;
+    let _lock = guards::EscrowLock::acquire(&hashlock)?;
+    
+    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    
+    // Check authorization
+    if !can_public_withdraw(&escrow) {
+        return Err(EscrowError::Unauthorized);
+    }
+    
+    // Validate secret
+    check_secret(&escrow, &secret, order_hash.as_ref().map(|hash| hash.as_slice()))?;
+    
+    // Check state
+    if !matches!(escrow.state, EscrowState::Active) {
+        return Err(EscrowError::InvalidState);
+    }
+    
+    // Check timing
+    check_timing(&escrow, TimingCheck::PublicWithdrawal)?;
+    
+    // The safety deposit rewards whoever completes the swap once it went public
+    complete_withdrawal(&escrow, &hashlock, &secret, caller, true).await
+}
+
+// =============================================================================
+// CANCELLATION AND RESCUE FUNCTIONS
+// =============================================================================
+
+/// Cancel an escrow and return funds, as its stored direction dictates
+#[update]
+async fn cancel_escrow(hashlock: ByteBuf) -> Result<()> {
+    let caller = caller_principal();
+    let caller_str = caller.to_text();
+    let current_time = current_time();
+    let config = storage::get_config();
+    
+    guards::check_hash("hashlock", &hashlock)?;
+    let _lock = guards::EscrowLock::acquire(&hashlock)?;
+    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    let escrow_type = escrow.escrow_type()?;
+    
+    // Check state
+    if !matches!(escrow.state, EscrowState::Active) {
+        return Err(EscrowError::InvalidState);
+    }
+    check_not_frozen(&escrow)?;
+    
+    // Check timing
+    check_timing(&escrow, TimingCheck::Cancellation)?;
+    
+    // Check authorization and execute based on escrow type
+    let (gross, deposit_leg) = cancellation_legs(&escrow, &escrow_type);
+    let net = ledger::charge_ledger_fees(&config.ledger_fee_payer, &gross, Some(deposit_leg), escrow.transfer_fee())?;
+    let mut blocks = Vec::new();
+    
+    // Only the maker can cancel a source escrow, and only the taker a destination escrow
+    if !may_cancel(&escrow, &escrow_type, &caller_str) {
+        return Err(EscrowError::InvalidCaller);
+    }
+    acquire_processing(&hashlock, ProcessingOperation::Cancellation {
+        escrow_type: escrow_type.clone(),
+        canceller: caller,
+    })?;
+    let top_up = release_on_error(&hashlock, cover_fee_shortfall(&escrow, &hashlock, &net).await)?;
+    
+    match escrow_type {
+        EscrowType::Source => {
+            let cancel_memo = ledger::TransferMemo::new(
                 ledger::TransferOperation::Cancellation,
                 &hashlock,
             );
-            ledger::transfer_to(maker_principal, total_amount, cancel_memo).await?;
+            
+            // Return the locked amount to maker, and the deposit to whoever funded it
+            let maker_principal = release_on_error(&hashlock, utils::validate_principal(&escrow.immutables.maker))?;
+            let first_transfer = pay_out(&escrow, maker_principal, net[0], &cancel_memo).await;
+            blocks.push(release_on_error(&hashlock, first_transfer)?);
+            
+            if net.len() > 1 && net[1] > 0 {
+                let taker_principal = utils::validate_principal(&escrow.immutables.taker)?;
+                blocks.extend(pay_out_or_queue(&escrow, &hashlock, taker_principal, net[1], &cancel_memo).await);
+            }
         }
         EscrowType::Destination => {
-            // Only taker can cancel destination escrow
-            if caller_str != escrow.immutables.taker {
-                return Err(EscrowError::InvalidCaller);
-            }
-            
             // Return all funds to taker
-            let taker_principal = utils::validate_principal(&escrow.immutables.taker)?;
-            let total_amount = escrow.immutables.amount + escrow.immutables.safety_deposit;
-            let cancel_memo = ledger::generate_transfer_memo(
+            let taker_principal = release_on_error(&hashlock, utils::validate_principal(&escrow.immutables.taker))?;
+            let cancel_memo = ledger::TransferMemo::new(
                 ledger::TransferOperation::Cancellation,
                 &hashlock,
             );
-            ledger::transfer_to(taker_principal, total_amount, cancel_memo).await?;
+            let first_transfer = transfer_leg(&escrow, taker_principal, net[0], &cancel_memo).await;
+            blocks.push(release_on_error(&hashlock, first_transfer)?);
         }
     }
     
+    record_cancellation(&hashlock, caller, &gross, &net, top_up, blocks, current_time)
+}
+
+/// Mark a cancelled escrow settled once its refunds went out
+fn record_cancellation(
+    hashlock: &[u8],
+    canceller: Principal,
+    gross: &[u128],
+    net: &[u128],
+    top_up: Option<u64>,
+    blocks: Vec<u64>,
+    current_time: u64,
+) -> Result<()> {
     // Update escrow state
-    storage::update_escrow(&hashlock, |escrow| {
+    let settlement_cycles = cycles::message_cost(blocks.len() as u64);
+    storage::update_escrow(hashlock, |escrow| {
         escrow.state = EscrowState::Cancelled;
+        escrow.processing = None;
         escrow.completed_at = Some(current_time);
+        escrow.safety_deposit_held = 0;
+        escrow.fee_breakdown.record_transfers(gross, net, escrow.transfer_fee());
+        let credited = net.iter().fold(0u128, |total, leg| total.saturating_add(*leg));
+        escrow.fee_breakdown.amount_credited = escrow.fee_breakdown.amount_credited.saturating_add(credited);
+        if top_up.is_some() {
+            escrow.fee_breakdown.ledger_fees_covered = escrow.fee_breakdown.ledger_fees_covered.saturating_add(escrow.transfer_fee());
+        }
+        escrow.settlement_blocks.extend(top_up.into_iter().chain(blocks));
+        escrow.cycles_mut().settlement += settlement_cycles;
     })?;
     
     // Update metrics
@@ -548,11 +1918,12 @@ async fn cancel_escrow(hashlock: ByteBuf, escrow_type: EscrowType) -> Result<()>
         metrics.total_escrows_cancelled += 1;
         metrics.active_escrows_count = metrics.active_escrows_count.saturating_sub(1);
     });
+    storage::update_daily_activity(current_time, |day| day.cancelled += 1);
     
     // Log event
     let event = EscrowEvent::EscrowCancelled {
         hashlock: hashlock.to_vec(),
-        canceller: caller,
+        canceller,
         timestamp: current_time,
     };
     storage::add_event(event);
@@ -560,13 +1931,100 @@ async fn cancel_escrow(hashlock: ByteBuf, escrow_type: EscrowType) -> Result<()>
     Ok(())
 }
 
-/// Emergency rescue of funds (by taker after delay)
+/// Cancel an escrow once its public cancellation stage opens (authorized principals and
+/// registered resolvers). The locked amount is refunded as on a regular cancellation and
+/// the canceller earns the safety deposit.
+#[update]
+async fn public_cancel(hashlock: ByteBuf) -> Result<()> {
+    let caller = caller_principal();
+    let current_time = current_time();
+    let config = storage::get_config();
+    
+    guards::check_hash("hashlock", &hashlock)?;
+    if !is_authorized() && !storage::is_resolver(&caller) {
+        return Err(EscrowError::Unauthorized);
+    }
+    let _lock = guards::EscrowLock::acquire(&hashlock)?;
+    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    let escrow_type = escrow.escrow_type()?;
+    
+    if !matches!(escrow.state, EscrowState::Active) {
+        return Err(EscrowError::InvalidState);
+    }
+    check_not_frozen(&escrow)?;
+    check_timing(&escrow, TimingCheck::PublicCancellation)?;
+    
+    // Refund to whoever a regular cancellation would, with the deposit split off for the canceller
+    let refunded = match escrow_type {
+        EscrowType::Source => &escrow.immutables.maker,
+        EscrowType::Destination => &escrow.immutables.taker,
+    };
+    let refunded = utils::validate_principal(refunded)?;
+    let gross = [escrow.remaining_amount(), escrow.safety_deposit_held];
+    let net = ledger::charge_ledger_fees(&config.ledger_fee_payer, &gross, Some(1), escrow.transfer_fee())?;
+    
+    acquire_processing(&hashlock, ProcessingOperation::Cancellation {
+        escrow_type,
+        canceller: caller,
+    })?;
+    let top_up = release_on_error(&hashlock, cover_fee_shortfall(&escrow, &hashlock, &net).await)?;
+    let cancel_memo = ledger::TransferMemo::new(
+        ledger::TransferOperation::Cancellation,
+        &hashlock,
+    );
+    let first_transfer = pay_out(&escrow, refunded, net[0], &cancel_memo).await;
+    let mut blocks = vec![release_on_error(&hashlock, first_transfer)?];
+    if net[1] > 0 {
+        blocks.extend(pay_out_or_queue(&escrow, &hashlock, caller, net[1], &cancel_memo).await);
+    }
+    
+    record_cancellation(&hashlock, caller, &gross, &net, top_up, blocks, current_time)
+}
+
+/// Give notice of an emergency rescue (by taker after delay).
+/// The counterparty can still complete the swap until the notice period ends.
+#[update]
+fn request_rescue(hashlock: ByteBuf) -> Result<u64> {
+    let caller = caller_principal();
+    let current_time = current_time();
+    
+    guards::check_hash("hashlock", &hashlock)?;
+    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    
+    // Only taker can rescue funds
+    if caller.to_text() != escrow.immutables.taker {
+        return Err(EscrowError::InvalidCaller);
+    }
+    
+    check_timing(&escrow, TimingCheck::Rescue)?;
+    
+    if escrow.rescue_requested_at.is_some() {
+        return Err(EscrowError::InvalidState);
+    }
+    
+    storage::update_escrow(&hashlock, |escrow| escrow.rescue_requested_at = Some(current_time))?;
+    
+    let executable_at = current_time.saturating_add(storage::get_config().rescue_notice_period);
+    storage::add_event(EscrowEvent::RescueRequested {
+        hashlock: hashlock.to_vec(),
+        requester: caller,
+        executable_at,
+        timestamp: current_time,
+    });
+    
+    Ok(executable_at)
+}
+
+/// Emergency rescue of funds (by taker, once the rescue notice period has passed).
+/// Rescues can be repeated until the escrow's own locked amount and deposit are drained.
 #[update]
-async fn rescue_funds(hashlock: ByteBuf, amount: u64) -> Result<()> {
+async fn execute_rescue(hashlock: ByteBuf, amount: u128) -> Result<()> {
     let caller = caller_principal();
     let caller_str = caller.to_text();
     let current_time = current_time();
     
+    guards::check_hash("hashlock", &hashlock)?;
+    let _lock = guards::EscrowLock::acquire(&hashlock)?;
     let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
     
     // Only taker can rescue funds
@@ -574,27 +2032,67 @@ async fn rescue_funds(hashlock: ByteBuf, amount: u64) -> Result<()> {
         return Err(EscrowError::InvalidCaller);
     }
     
-    // Check rescue timing
+    // A settlement in flight may still be paying out of the subaccount
+    if escrow.state == EscrowState::Processing {
+        return Err(EscrowError::InvalidState);
+    }
+    check_not_frozen(&escrow)?;
+    
+    // Check rescue timing and that notice was given early enough
     check_timing(&escrow, TimingCheck::Rescue)?;
+    if escrow.rescue_requested_at.is_none() {
+        return Err(EscrowError::InvalidState);
+    }
+    check_timing(&escrow, TimingCheck::RescueNotice)?;
     
-    // Validate amount
-    let canister_balance = ledger::get_balance().await?;
-    if amount > canister_balance {
-        return Err(EscrowError::InsufficientBalance);
+    // Cap the amount at what this escrow itself holds, and at its subaccount's balance. Escrows
+    // predating subaccounts keep their funds in the shared account, which only the cap protects.
+    let rescuable = escrow.rescuable();
+    if amount == 0 {
+        return Err(EscrowError::AmountBelowMinimum { minimum: 1, actual: 0 });
+    }
+    if amount > rescuable {
+        return Err(EscrowError::InsufficientBalance { required: amount, available: rescuable });
+    }
+    let token = escrow.immutables.icp_token;
+    let subaccount = escrow.funds_subaccount();
+    let escrow_balance = ledger::get_token_balance(token, subaccount).await?;
+    if amount > escrow_balance {
+        return Err(EscrowError::InsufficientBalance { required: amount, available: escrow_balance });
     }
     
     // Transfer requested amount to caller
-    let rescue_memo = ledger::generate_transfer_memo(
+    let config = storage::get_config();
+    let net = ledger::charge_ledger_fees(&config.ledger_fee_payer, &[amount], None, escrow.transfer_fee())?;
+    let top_up = cover_fee_shortfall(&escrow, &hashlock, &net).await?;
+    let rescue_memo = ledger::TransferMemo::new(
         ledger::TransferOperation::Rescue,
         &hashlock,
     );
-    ledger::transfer_to(caller, amount, rescue_memo).await?;
+    let block = transfer_leg(&escrow, caller, net[0], &rescue_memo).await?;
+    
+    let remaining = rescuable - amount;
+    let tracked = matches!(escrow.state, EscrowState::Active);
+    storage::update_escrow(&hashlock, |escrow| {
+        if tracked {
+            escrow.rescue_balance = Some(remaining);
+        }
+        escrow.fee_breakdown.record_transfers(&[amount], &net, escrow.transfer_fee());
+        escrow.fee_breakdown.amount_credited = escrow.fee_breakdown.amount_credited.saturating_add(net[0]);
+        if top_up.is_some() {
+            escrow.fee_breakdown.ledger_fees_covered = escrow.fee_breakdown.ledger_fees_covered.saturating_add(escrow.transfer_fee());
+        }
+        escrow.settlement_blocks.extend(top_up);
+        escrow.settlement_blocks.push(block);
+        escrow.cycles_mut().settlement += cycles::message_cost(1);
+    })?;
     
-    // Update escrow state if not already terminal
-    if matches!(escrow.state, EscrowState::Active) {
+    // The escrow only leaves Active once a rescue has drained it
+    if tracked && remaining == 0 {
         storage::update_escrow(&hashlock, |escrow| {
             escrow.state = EscrowState::Rescued;
             escrow.completed_at = Some(current_time);
+            escrow.safety_deposit_held = 0;
         })?;
         
         storage::update_metrics(|metrics| {
@@ -615,16 +2113,209 @@ async fn rescue_funds(hashlock: ByteBuf, amount: u64) -> Result<()> {
 }
 
 // =============================================================================
-// RECORD KEEPING FUNCTIONS
+// PREFLIGHT QUERIES
 // =============================================================================
 
-/// Record ICP transaction hash for verification
-#[update]
-fn record_icp_tx_hash(hashlock: ByteBuf, tx_hash: String) -> Result<()> {
-    let caller = caller_principal();
-    let caller_str = caller.to_text();
-    let current_time = current_time();
+/// Report which withdrawal checks would fail for the caller, without executing
+#[query]
+fn preflight_withdraw(hashlock: ByteBuf) -> Result<PreflightReport> {
+    let caller_str = caller_principal().to_text();
+    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    
+    // Maker/taker withdraw privately; everyone else allowed by the mode uses the public window
+    let (caller_check, timing) = if is_maker_or_taker(&escrow, &caller_str) {
+        (PreflightCheck::passed("caller"), TimingCheck::PrivateWithdrawal)
+    } else if can_public_withdraw(&escrow) {
+        (PreflightCheck::passed("caller"), TimingCheck::PublicWithdrawal)
+    } else {
+        (PreflightCheck::failed("caller", EscrowError::Unauthorized, None), TimingCheck::PublicWithdrawal)
+    };
+    
+    let freeze_check = if storage::get_config().paused {
+        PreflightCheck::failed("freeze", EscrowError::Paused, None)
+    } else if escrow.freeze.is_some() {
+        PreflightCheck::failed("freeze", EscrowError::EscrowFrozen, None)
+    } else {
+        PreflightCheck::passed("freeze")
+    };
+    
+    Ok(PreflightReport::new(vec![
+        preflight_active(&escrow),
+        caller_check,
+        freeze_check,
+        preflight_timing(&escrow, timing),
+    ]))
+}
+
+/// Report which cancellation checks would fail for the caller, without executing
+#[query]
+fn preflight_cancel(hashlock: ByteBuf) -> Result<PreflightReport> {
+    let caller_str = caller_principal().to_text();
+    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    
+    // Makers cancel source escrows and takers destination escrows
+    let caller_check = match escrow.escrow_type() {
+        Ok(escrow_type) if may_cancel(&escrow, &escrow_type, &caller_str) => PreflightCheck::passed("caller"),
+        Ok(_) => PreflightCheck::failed("caller", EscrowError::InvalidCaller, None),
+        Err(err) => PreflightCheck::failed("caller", err, None),
+    };
+    
+    Ok(PreflightReport::new(vec![
+        preflight_active(&escrow),
+        caller_check,
+        preflight_not_frozen(&escrow),
+        preflight_timing(&escrow, TimingCheck::Cancellation),
+    ]))
+}
+
+/// Report which rescue checks would fail for the caller, without executing
+#[query]
+fn preflight_rescue(hashlock: ByteBuf) -> Result<PreflightReport> {
+    let caller_str = caller_principal().to_text();
+    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    
+    let caller_check = if caller_str == escrow.immutables.taker {
+        PreflightCheck::passed("caller")
+    } else {
+        PreflightCheck::failed("caller", EscrowError::InvalidCaller, None)
+    };
+    
+    let request_check = if escrow.rescue_requested_at.is_some() {
+        PreflightCheck::passed("rescue_request")
+    } else {
+        PreflightCheck::failed("rescue_request", EscrowError::InvalidState, None)
+    };
+    let (executable_at, _) = timing_window(&escrow, &TimingCheck::RescueNotice);
+    let notice_check = if current_time() >= executable_at {
+        PreflightCheck::passed("rescue_notice")
+    } else {
+        let passes_at = escrow.rescue_requested_at.map(|_| executable_at);
+        PreflightCheck::failed("rescue_notice", EscrowError::InvalidTime, passes_at)
+    };
+    let balance_check = if escrow.rescuable() > 0 {
+        PreflightCheck::passed("rescue_balance")
+    } else {
+        PreflightCheck::failed("rescue_balance", EscrowError::InsufficientBalance { required: 1, available: 0 }, None)
+    };
+    
+    Ok(PreflightReport::new(vec![
+        caller_check,
+        preflight_not_frozen(&escrow),
+        preflight_timing(&escrow, TimingCheck::Rescue),
+        request_check,
+        notice_check,
+        balance_check,
+    ]))
+}
+
+/// Exact base units an operation will cost, so wallets can pre-fund precisely
+#[query]
+fn simulate_operation_cost(op: SimulatedOperation, target: OperationTarget) -> Result<OperationCost> {
+    let config = storage::get_config();
+    let escrow = match target {
+        OperationTarget::Hashlock(hashlock) => {
+            storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?
+        }
+        OperationTarget::Immutables(immutables) => {
+            // Simulate against the escrow these immutables would create
+            match op {
+                SimulatedOperation::CreateSrc => immutables.validate_src(&config)?,
+                SimulatedOperation::CreateDst => immutables.validate_dst(&config, None)?,
+                _ => immutables.validate(&config)?,
+            }
+            let safety_deposit_held = if immutables.is_taker_funded_deposit() { 0 } else { immutables.safety_deposit };
+            ICPEscrow {
+                immutables: *immutables,
+                state: EscrowState::Active,
+                icp_tx_hash: None,
+                evm_address: None,
+                created_at: current_time(),
+                completed_at: None,
+                revealed_secret: None,
+                surplus_captured: 0,
+                safety_deposit_held,
+                fee_breakdown: FeeBreakdown::default(),
+                settlement_blocks: Vec::new(),
+                deposit_blocks: Vec::new(),
+                notes: Vec::new(),
+                freeze: None,
+                rescue_requested_at: None,
+                processing: None,
+                creation_fee_block: None,
+                migration: None,
+                cycles: None,
+                token_fee: None,
+                subaccount: None,
+                partial_fill: None,
+                funding: None,
+                evm_verification: None,
+                evm_immutables: None,
+                evm_withdrawal: None,
+                rescue_balance: None,
+                transfer_legs: None,
+                escrow_type: None,
+            }
+        }
+    };
+    
+    operation_cost(&op, &escrow, &config)
+}
+
+// =============================================================================
+// RECORD KEEPING FUNCTIONS
+// =============================================================================
+
+/// Gather ledger blocks, recorded hashes and event history of an escrow (authorized only)
+#[update]
+async fn build_evidence(hashlock: ByteBuf) -> Result<EvidenceBundle> {
+    let caller = caller_principal();
+    if !is_authorized() {
+        return Err(EscrowError::Unauthorized);
+    }
+    
+    guards::check_hash("hashlock", &hashlock)?;
+    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    
+    // Deposits and settlements are on the escrowed token's ledger; the creation fee is always ICP
+    let token = escrow.immutables.icp_token;
+    let mut ledger_blocks = Vec::new();
+    for index in &escrow.deposit_blocks {
+        ledger_blocks.push(ledger::fetch_token_block_evidence(token, *index).await?);
+    }
+    if let Some(index) = escrow.creation_fee_block {
+        ledger_blocks.push(ledger::fetch_block_evidence(index).await?);
+    }
+    for index in &escrow.settlement_blocks {
+        ledger_blocks.push(ledger::fetch_token_block_evidence(token, *index).await?);
+    }
+    storage::update_escrow(&hashlock, |escrow| {
+        escrow.cycles_mut().outcalls += cycles::message_cost(ledger_blocks.len() as u64);
+    })?;
+    
+    let receipt = (escrow.state != EscrowState::Active).then(|| escrow.receipt());
+    
+    Ok(EvidenceBundle {
+        hashlock: hashlock.to_vec(),
+        recorded_tx_hash: escrow.icp_tx_hash.clone(),
+        evm_address: escrow.evm_address.clone(),
+        events: storage::get_events_for_hashlock(&hashlock),
+        ledger_blocks,
+        receipt,
+        escrow,
+        generated_at: current_time(),
+        generated_by: caller,
+    })
+}
+
+/// Record ICP transaction hash for verification
+#[update]
+fn record_icp_tx_hash(hashlock: ByteBuf, tx_hash: String) -> Result<()> {
+    utils::check_length("tx_hash", tx_hash.len(), utils::MAX_TX_HASH_LEN)?;
+    let caller = caller_principal();
+    let caller_str = caller.to_text();
+    let current_time = current_time();
     
+    guards::check_hash("hashlock", &hashlock)?;
     let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
     
     // Only maker or taker can record tx hash
@@ -648,165 +2339,1810 @@ fn record_icp_tx_hash(hashlock: ByteBuf, tx_hash: String) -> Result<()> {
     Ok(())
 }
 
-/// Record EVM address for verification
+/// Record EVM address for verification
+#[update]
+fn record_evm_address(hashlock: ByteBuf, evm_address: String) -> Result<()> {
+    utils::check_length("evm_address", evm_address.len(), utils::MAX_ADDRESS_LEN)?;
+    let caller = caller_principal();
+    let caller_str = caller.to_text();
+    let current_time = current_time();
+    
+    guards::check_hash("hashlock", &hashlock)?;
+    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    
+    // Only maker can record EVM address
+    if caller_str != escrow.immutables.maker {
+        return Err(EscrowError::InvalidCaller);
+    }
+    
+    // Validate the address against the format of the order's chain
+    if !utils::validate_chain_address(escrow.immutables.chain_id, &evm_address) {
+        return Err(EscrowError::InvalidAddress);
+    }
+    
+    // Update escrow
+    storage::update_escrow(&hashlock, |escrow| {
+        escrow.evm_address = Some(evm_address.clone());
+    })?;
+    
+    // Log event
+    let event = EscrowEvent::EVMAddressRecorded {
+        hashlock: hashlock.to_vec(),
+        address: evm_address,
+        timestamp: current_time,
+    };
+    storage::add_event(event);
+    
+    Ok(())
+}
+
+/// Check that the escrow's recorded EVM counterpart exists, through the EVM RPC canister and
+/// the chain's configured providers, and store the result (maker, taker or an operator)
+#[update]
+async fn verify_evm_escrow(hashlock: ByteBuf) -> Result<EvmEscrowStatus> {
+    let caller = caller_principal();
+    
+    guards::check_hash("hashlock", &hashlock)?;
+    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    if !is_maker_or_taker(&escrow, &caller.to_text()) && !roles::has_role(&caller, Role::Operator) {
+        return Err(EscrowError::Unauthorized);
+    }
+    
+    let address = escrow.evm_address.clone().ok_or(EscrowError::InvalidState)?;
+    let chain_id = escrow.immutables.chain_id.ok_or(EscrowError::ChainNotConfigured)?;
+    let chain = storage::get_chain_config(chain_id).ok_or(EscrowError::ChainNotConfigured)?;
+    let (status, cycles_spent) = evm::escrow_status(&chain, &address, current_time()).await?;
+    
+    storage::update_escrow(&hashlock, |escrow| {
+        escrow.evm_verification = Some(status.clone());
+        escrow.cycles_mut().outcalls += cycles_spent + cycles::message_cost(2 * status.providers as u64);
+    })?;
+    
+    storage::add_event(EscrowEvent::EvmEscrowVerified {
+        hashlock: hashlock.to_vec(),
+        address,
+        deployed: status.deployed,
+        block_number: status.block_number,
+        timestamp: status.checked_at,
+    });
+    
+    Ok(status)
+}
+
+/// EVM address of the canister's threshold ECDSA key, which signs `submit_evm_withdrawal`
+/// transactions; it needs gas on each chain, and an access token for public withdrawals
+#[update]
+async fn get_canister_evm_address() -> Result<String> {
+    evm::canister_address(&storage::get_config().ecdsa_key_name).await
+}
+
+/// Record the immutables of the escrow's EVM counterpart, so the canister can withdraw from
+/// it (by maker or taker). They must carry the same hashlock.
+#[update]
+fn record_evm_immutables(hashlock: ByteBuf, immutables: EvmImmutables) -> Result<()> {
+    let caller_str = caller_principal().to_text();
+    
+    guards::check_hash("hashlock", &hashlock)?;
+    guards::check_evm_immutables(&immutables)?;
+    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    if !is_maker_or_taker(&escrow, &caller_str) {
+        return Err(EscrowError::InvalidCaller);
+    }
+    if immutables.hashlock != escrow.immutables.hashlock {
+        return Err(EscrowError::InvalidHashlock);
+    }
+    
+    // Whichever leg is the destination must become cancellable first, as for session legs
+    let evm_timelocks = Timelocks::from_packed(&immutables.timelocks)?;
+    match escrow.escrow_type()? {
+        EscrowType::Source => SwapSession::cancellation_gap(&escrow.immutables.timelocks, &evm_timelocks)?,
+        EscrowType::Destination => SwapSession::cancellation_gap(&evm_timelocks, &escrow.immutables.timelocks)?,
+    };
+    
+    storage::update_escrow(&hashlock, |escrow| escrow.evm_immutables = Some(immutables))
+}
+
+/// The secret that completed an escrow, for the counterparty resolver to unlock the other
+/// chain (maker, taker, authorized principals and registered resolvers)
+#[query]
+fn get_revealed_secret(hashlock: ByteBuf) -> Result<ByteBuf> {
+    let caller = caller_principal();
+    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    if !can_view_secret(&escrow, &caller) {
+        return Err(EscrowError::Unauthorized);
+    }
+    escrow.revealed_secret()
+        .map(|secret| ByteBuf::from(secret.to_vec()))
+        .ok_or(EscrowError::InvalidState)
+}
+
+/// An escrow's timelocks as the packed word the EVM escrow contracts take, for building the
+/// counterpart's immutables
+#[query]
+fn get_packed_timelocks(hashlock: ByteBuf) -> Result<ByteBuf> {
+    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    escrow.immutables.timelocks.to_packed().map(|word| ByteBuf::from(word.to_vec()))
+}
+
+/// Complete the EVM leg of a settled escrow: sign a withdrawal revealing its secret with the
+/// canister's threshold ECDSA key and broadcast it through the EVM RPC canister (maker, taker
+/// or an operator). Submitted once per escrow.
+#[update]
+async fn submit_evm_withdrawal(hashlock: ByteBuf) -> Result<EvmWithdrawal> {
+    let caller = caller_principal();
+    let config = storage::get_config();
+    
+    guards::check_hash("hashlock", &hashlock)?;
+    let _lock = guards::EscrowLock::acquire(&hashlock)?;
+    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    if !is_maker_or_taker(&escrow, &caller.to_text()) && !roles::has_role(&caller, Role::Operator) {
+        return Err(EscrowError::Unauthorized);
+    }
+    if escrow.evm_withdrawal.is_some() {
+        return Err(EscrowError::DuplicateRequest);
+    }
+    
+    // The secret is revealed once the escrow completes; EVM escrows take it as a bytes32
+    let secret = escrow.revealed_secret()
+        .filter(|secret| secret.len() == 32)
+        .ok_or(EscrowError::InvalidState)?
+        .to_vec();
+    let address = escrow.evm_address.clone().ok_or(EscrowError::InvalidState)?;
+    let immutables = escrow.evm_immutables.clone().ok_or(EscrowError::InvalidState)?;
+    let chain_id = escrow.immutables.chain_id.ok_or(EscrowError::ChainNotConfigured)?;
+    let chain = storage::get_chain_config(chain_id).ok_or(EscrowError::ChainNotConfigured)?;
+    
+    let (withdrawal, cycles_spent) =
+        evm::submit_withdrawal(&chain, &config.ecdsa_key_name, &address, &secret, &immutables, current_time()).await?;
+    storage::update_escrow(&hashlock, |escrow| {
+        escrow.evm_withdrawal = Some(withdrawal.clone());
+        escrow.cycles_mut().outcalls += cycles_spent + cycles::message_cost(2 + chain.rpc_providers.len() as u64);
+    })?;
+    
+    storage::add_event(EscrowEvent::EvmWithdrawalSubmitted {
+        hashlock: hashlock.to_vec(),
+        tx_hash: withdrawal.tx_hash.clone(),
+        from: withdrawal.from.clone(),
+        timestamp: withdrawal.submitted_at,
+    });
+    
+    Ok(withdrawal)
+}
+
+// =============================================================================
+// ENCRYPTED NOTES
+// =============================================================================
+
+/// Caller must be the maker or taker of the escrow
+fn require_party(escrow: &ICPEscrow) -> Result<Principal> {
+    let caller = caller_principal();
+    let caller_str = caller.to_text();
+    if caller_str != escrow.immutables.maker && caller_str != escrow.immutables.taker {
+        return Err(EscrowError::InvalidCaller);
+    }
+    Ok(caller)
+}
+
+/// vetKD public key that escrow notes are encrypted under
+#[update]
+async fn get_notes_public_key() -> Result<ByteBuf> {
+    let config = storage::get_config();
+    vetkeys::notes_public_key(&config.vetkd_key_name).await.map(ByteBuf::from)
+}
+
+/// Escrow notes key encrypted to the caller's transport key (maker or taker only)
+#[update]
+async fn get_encrypted_notes_key(hashlock: ByteBuf, transport_public_key: ByteBuf) -> Result<ByteBuf> {
+    guards::check_hash("hashlock", &hashlock)?;
+    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    require_party(&escrow)?;
+    
+    let config = storage::get_config();
+    let key = vetkeys::encrypted_notes_key(&hashlock, transport_public_key.into_vec(), &config.vetkd_key_name).await?;
+    storage::update_escrow(&hashlock, |escrow| {
+        escrow.cycles_mut().outcalls += cycles::THRESHOLD_KEY_FEE + cycles::message_cost(1);
+    })?;
+    Ok(ByteBuf::from(key))
+}
+
+/// Attach a note encrypted under the escrow's notes key (maker or taker only)
+#[update]
+fn attach_encrypted_note(hashlock: ByteBuf, ciphertext: ByteBuf) -> Result<()> {
+    guards::check_hash("hashlock", &hashlock)?;
+    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    let author = require_party(&escrow)?;
+    
+    if !matches!(escrow.state, EscrowState::Active) {
+        return Err(EscrowError::InvalidState);
+    }
+    vetkeys::validate_note(&ciphertext, escrow.notes.len())?;
+    
+    let timestamp = current_time();
+    let size = ciphertext.len() as u64;
+    storage::update_escrow(&hashlock, |escrow| {
+        escrow.notes.push(EncryptedNote {
+            author,
+            ciphertext: ciphertext.to_vec(),
+            created_at: timestamp,
+        });
+    })?;
+    
+    storage::add_event(EscrowEvent::NoteAttached {
+        hashlock: hashlock.to_vec(),
+        author,
+        size,
+        timestamp,
+    });
+    
+    Ok(())
+}
+
+// =============================================================================
+// PARTICIPANT PROFILES
+// =============================================================================
+
+/// Set the caller's display alias and optional avatar URL (cosmetic only)
+#[update]
+fn set_profile(alias: String, avatar_url: Option<String>) -> Result<()> {
+    let caller = caller_principal();
+    if caller == Principal::anonymous() {
+        return Err(EscrowError::InvalidCaller);
+    }
+    
+    if !utils::validate_alias(&alias) {
+        return Err(EscrowError::InvalidProfile);
+    }
+    if avatar_url.as_deref().is_some_and(|url| !utils::validate_avatar_url(url)) {
+        return Err(EscrowError::InvalidProfile);
+    }
+    
+    storage::set_profile(caller.to_text(), ParticipantProfile {
+        alias,
+        avatar_url,
+        updated_at: current_time(),
+    })
+}
+
+/// Remove the caller's display profile
+#[update]
+fn clear_profile() -> Result<()> {
+    storage::remove_profile(&caller_principal().to_text())
+}
+
+/// Link an EVM address to the caller by a personal_sign signature over `evm_link_message`.
+/// A linked principal is exempt from the unverified volume cap.
+#[update]
+fn link_evm_address(signature: ByteBuf) -> Result<EvmLink> {
+    let caller = caller_principal();
+    if caller == Principal::anonymous() {
+        return Err(EscrowError::InvalidCaller);
+    }
+    
+    let message = utils::evm_link_message(&caller);
+    let address = utils::recover_evm_address(&message, &signature).ok_or(EscrowError::InvalidAddress)?;
+    let link = EvmLink {
+        address: address.clone(),
+        linked_at: current_time(),
+    };
+    storage::set_evm_link(caller, link.clone())?;
+    
+    storage::add_event(EscrowEvent::EvmAddressLinked {
+        principal: caller,
+        address,
+        timestamp: link.linked_at,
+    });
+    
+    Ok(link)
+}
+
+/// Get the EVM address a principal has linked
+#[query]
+fn get_evm_link(principal: Principal) -> Option<EvmLink> {
+    storage::get_evm_link(&principal)
+}
+
+/// Creation volume of `principal` in the current period and the cap of its tier
+fn volume_usage(principal: &Principal, config: &EscrowConfig) -> VolumeUsage {
+    let period = current_time() / VOLUME_PERIOD;
+    VolumeUsage {
+        period_start: period * VOLUME_PERIOD,
+        used: storage::get_volume_used(principal, period),
+        cap: config.unverified_monthly_cap,
+        verified: storage::is_resolver(principal) || storage::get_evm_link(principal).is_some(),
+    }
+}
+
+/// The caller's creation volume in the current period
+#[query]
+fn get_volume_usage() -> VolumeUsage {
+    volume_usage(&caller_principal(), &storage::get_config())
+}
+
+/// Attach the parties' display profiles to an escrow
+fn escrow_view(escrow: ICPEscrow) -> EscrowView {
+    EscrowView {
+        maker_profile: storage::get_profile(&escrow.immutables.maker),
+        taker_profile: storage::get_profile(&escrow.immutables.taker),
+        escrow: escrow_for_caller(escrow),
+    }
+}
+
+/// Whether `caller` may see an escrow's revealed secret: its parties, authorized principals
+/// and registered resolvers
+fn can_view_secret(escrow: &ICPEscrow, caller: &Principal) -> bool {
+    is_maker_or_taker(escrow, &caller.to_text()) || is_authorized() || storage::is_resolver(caller)
+}
+
+/// An escrow as served to the caller, without its secret unless they may see it
+fn escrow_for_caller(escrow: ICPEscrow) -> ICPEscrow {
+    if can_view_secret(&escrow, &caller_principal()) {
+        escrow
+    } else {
+        escrow.without_secret()
+    }
+}
+
+fn escrows_for_caller(escrows: Vec<(Vec<u8>, ICPEscrow)>) -> Vec<(Vec<u8>, ICPEscrow)> {
+    escrows.into_iter().map(|(hashlock, escrow)| (hashlock, escrow_for_caller(escrow))).collect()
+}
+
+fn page_for_caller(page: EscrowPage) -> EscrowPage {
+    EscrowPage { escrows: escrows_for_caller(page.escrows), ..page }
+}
+
+// =============================================================================
+// QUERY FUNCTIONS
+// =============================================================================
+
+/// Get escrow details
+#[query]
+fn get_escrow(hashlock: ByteBuf) -> Option<ICPEscrow> {
+    storage::get_escrow(&hashlock).map(escrow_for_caller)
+}
+
+/// Get a certified inclusion proof for an escrow record. Escrows are certified without their
+/// secret, so proofs never carry it; parties fetch it with `get_revealed_secret`.
+#[query]
+fn get_escrow_proof(hashlock: ByteBuf) -> Result<EscrowProof> {
+    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?.without_secret();
+    let certificate = certification::data_certificate().ok_or(EscrowError::CertificateUnavailable)?;
+    let escrow_candid = candid::encode_one(&escrow).map_err(|_| EscrowError::EscrowNotFound)?;
+    
+    Ok(EscrowProof {
+        hashlock: hashlock.to_vec(),
+        escrow,
+        escrow_candid,
+        witness: certification::encode_witness(&certification::escrow_witness(&hashlock)),
+        root_hash: certification::root_hash(),
+        certificate,
+    })
+}
+
+/// Certified lookup of an escrow; the witness proves the record, or its absence, against the certificate
+#[query]
+fn get_escrow_certified(hashlock: ByteBuf) -> Result<CertifiedEscrow> {
+    let certificate = certification::data_certificate().ok_or(EscrowError::CertificateUnavailable)?;
+    let escrow = storage::get_escrow(&hashlock).map(ICPEscrow::without_secret);
+    let escrow_candid = escrow.as_ref()
+        .map(|escrow| candid::encode_one(escrow).map_err(|_| EscrowError::EscrowNotFound))
+        .transpose()?;
+    
+    Ok(CertifiedEscrow {
+        hashlock: hashlock.to_vec(),
+        escrow,
+        escrow_candid,
+        witness: certification::encode_witness(&certification::escrow_witness(&hashlock)),
+        certificate,
+    })
+}
+
+/// Get a certified receipt of a settled escrow's outcome
+#[query]
+fn get_receipt(hashlock: ByteBuf) -> Result<ReceiptProof> {
+    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    if escrow.state == EscrowState::Active {
+        return Err(EscrowError::InvalidState);
+    }
+    
+    let certificate = certification::data_certificate().ok_or(EscrowError::CertificateUnavailable)?;
+    let receipt = escrow.receipt();
+    let receipt_candid = candid::encode_one(&receipt).map_err(|_| EscrowError::EscrowNotFound)?;
+    
+    Ok(ReceiptProof {
+        receipt,
+        receipt_candid,
+        witness: certification::encode_witness(&certification::receipt_witness(&hashlock)),
+        certificate,
+    })
+}
+
+/// Amount the escrow's Dutch auction requires now; None if the escrow has no auction
+#[query]
+fn get_current_required_amount(hashlock: ByteBuf) -> Result<Option<u128>> {
+    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    Ok(escrow.immutables.required_amount_at(current_time()))
+}
+
+/// Get ICP transaction hash for an escrow
+#[query]
+fn get_icp_tx_hash(hashlock: ByteBuf) -> Option<String> {
+    storage::get_escrow(&hashlock)
+        .and_then(|escrow| escrow.icp_tx_hash)
+}
+
+/// Get EVM address for an escrow
+#[query]
+fn get_evm_address(hashlock: ByteBuf) -> Option<String> {
+    storage::get_escrow(&hashlock)
+        .and_then(|escrow| escrow.evm_address)
+}
+
+/// Get current configuration and its schema version
+#[query]
+fn get_config() -> VersionedConfig {
+    storage::get_versioned_config()
+}
+
+/// Timelock stages, fees, chains, tokens and limits of this deployment, for resolvers to configure against
+#[query]
+fn get_protocol_spec() -> ProtocolSpec {
+    protocol_spec(&storage::get_config(), &storage::get_templates())
+}
+
+fn protocol_spec(config: &EscrowConfig, templates: &[EscrowTemplate]) -> ProtocolSpec {
+    let stage = |name: &str, starts_at: &str, operations: &[&str], callers: &str| TimelockStageSpec {
+        name: name.to_string(),
+        starts_at: starts_at.to_string(),
+        operations: operations.iter().map(|operation| operation.to_string()).collect(),
+        callers: callers.to_string(),
+    };
+    let permissionless = match config.public_withdraw_mode {
+        types::PublicWithdrawMode::AuthorizedPrincipals => "treasury and authorized principals",
+        types::PublicWithdrawMode::RegisteredResolvers => "treasury, authorized principals and registered resolvers",
+        types::PublicWithdrawMode::Permissionless => "treasury, authorized principals, registered resolvers, and anyone after permissionless_delay",
+    };
+
+    let mut tokens: Vec<String> = templates.iter().map(|template| template.token.clone()).collect();
+    tokens.sort();
+    tokens.dedup();
+
+    ProtocolSpec {
+        config_schema_version: types::CONFIG_SCHEMA_VERSION,
+        dev_mode: cfg!(feature = "dev-mode"),
+        hash_algorithms: types::HashAlgorithm::ALL.iter().map(|algorithm| algorithm.name().to_string()).collect(),
+        timelock_stages: vec![
+            stage("private_withdrawal", "timelocks.withdrawal (timelocks.dst.withdrawal on destination escrows)", &["withdraw_src", "withdraw_dst"], "maker or taker"),
+            stage("public_withdrawal", "timelocks.public_withdrawal (timelocks.dst.public_withdrawal on destination escrows)", &["public_withdraw"], permissionless),
+            stage("cancellation", "timelocks.cancellation (timelocks.dst.cancellation on destination escrows)", &["cancel_escrow"], "maker (source) or taker (destination)"),
+            stage("rescue", "deployed_at + rescue_delay, after request_rescue and rescue_notice_period", &["execute_rescue"], "taker"),
+        ],
+        fees: FeeSchedule {
+            creation_fee: config.creation_fee,
+            surplus_share_bps: config.surplus_share_bps,
+            ledger_transfer_fee: ledger::TRANSFER_FEE,
+            ledger_fee_payer: config.ledger_fee_payer.clone(),
+            deposit_forfeiture: config.deposit_forfeiture.clone(),
+        },
+        chains: utils::supported_chains()
+            .into_iter()
+            .map(|(chain_id, name, address_format)| ChainSpec {
+                chain_id,
+                name: name.to_string(),
+                address_format: address_format.to_string(),
+            })
+            .collect(),
+        tokens,
+        limits: ProtocolLimits {
+            min_amount: config.min_amount,
+            max_amount: config.max_amount,
+            min_safety_deposit: config.min_safety_deposit,
+            max_address_len: utils::MAX_ADDRESS_LEN as u64,
+            max_tx_hash_len: utils::MAX_TX_HASH_LEN as u64,
+            max_secret_len: utils::MAX_SECRET_LEN as u64,
+            max_page_size: MAX_PAGE_SIZE as u64,
+            max_note_size: vetkeys::MAX_NOTE_SIZE as u64,
+            max_notes_per_escrow: vetkeys::MAX_NOTES_PER_ESCROW as u64,
+            unverified_monthly_cap: config.unverified_monthly_cap,
+            volume_period: VOLUME_PERIOD,
+        },
+    }
+}
+
+/// Standards and custom interfaces this canister implements, as (name, specification URL).
+/// Only list an entry once its methods are deployed; clients feature-detect on these names.
+const SUPPORTED_STANDARDS: &[(&str, &str)] = &[
+    ("ICRC-10", "https://github.com/dfinity/ICRC/blob/main/ICRCs/ICRC-10/ICRC-10.md"),
+    ("AVGINCH-EVENTS-1", "https://github.com/LeonardoRyuta/avginch/blob/main/icp/README.md#capability-discovery"),
+    ("AVGINCH-HTTP-1", "https://github.com/LeonardoRyuta/avginch/blob/main/icp/README.md#capability-discovery"),
+    ("ICRC-3", "https://github.com/dfinity/ICRC-1/blob/main/standards/ICRC-3/README.md"),
+];
+
+/// ICRC-10 capability discovery
+#[query]
+fn icrc10_supported_standards() -> Vec<StandardRecord> {
+    SUPPORTED_STANDARDS
+        .iter()
+        .map(|(name, url)| StandardRecord { name: name.to_string(), url: url.to_string() })
+        .collect()
+}
+
+/// ICRC-3 blocks, one per event, by block index (the event's sequence number)
+#[query]
+fn icrc3_get_blocks(args: Vec<icrc3::GetBlocksArgs>) -> icrc3::GetBlocksResult {
+    icrc3::get_blocks(&args)
+}
+
+/// ICRC-3 certificate over the newest block's index and hash
+#[query]
+fn icrc3_get_tip_certificate() -> Option<icrc3::ICRC3DataCertificate> {
+    icrc3::tip_certificate()
+}
+
+/// ICRC-3 archives; the whole block log lives in this canister's stable memory
+#[query]
+fn icrc3_get_archives(_args: icrc3::GetArchivesArgs) -> Vec<icrc3::ICRC3ArchiveInfo> {
+    Vec::new()
+}
+
+/// ICRC-3 block types this log contains
+#[query]
+fn icrc3_supported_block_types() -> Vec<icrc3::SupportedBlockType> {
+    vec![icrc3::SupportedBlockType {
+        block_type: icrc3::EVENT_BLOCK_TYPE.to_string(),
+        url: "https://github.com/LeonardoRyuta/avginch/blob/main/icp/README.md#icrc-3-block-log".to_string(),
+    }]
+}
+
+/// Get escrows for a principal; unbounded, prefer `list_escrows_for_principal`
+#[query]
+fn get_escrows_for_principal(principal_str: String) -> Vec<(Vec<u8>, ICPEscrow)> {
+    escrows_for_caller(storage::get_escrows_for_principal(&principal_str))
+}
+
+/// Escrows where the caller is maker or taker, paged by hashlock
+#[query]
+fn my_escrows(cursor: Option<ByteBuf>, limit: u32) -> EscrowPage {
+    let limit = (limit as usize).clamp(1, MAX_PAGE_SIZE);
+    page_for_caller(storage::get_escrows_for_party(&caller_principal(), cursor.as_deref().map(|cursor| cursor.as_slice()), limit))
+}
+
+/// Escrows matching `filter`, paged by offset in hashlock order
+#[query]
+fn list_escrows(offset: u64, limit: u32, filter: Option<EscrowFilter>) -> EscrowListPage {
+    let limit = (limit as usize).clamp(1, MAX_PAGE_SIZE);
+    let mut page = storage::list_escrows(offset, limit, &filter.unwrap_or_default());
+    page.escrows = escrows_for_caller(page.escrows);
+    page
+}
+
+/// Escrows where `principal` is maker or taker, paged by hashlock
+#[query]
+fn list_escrows_for_principal(principal: Principal, cursor: Option<ByteBuf>, limit: u32) -> EscrowPage {
+    let limit = (limit as usize).clamp(1, MAX_PAGE_SIZE);
+    page_for_caller(storage::get_escrows_for_party(&principal, cursor.as_deref().map(|cursor| cursor.as_slice()), limit))
+}
+
+/// Escrows in `state`, paged by hashlock
+#[query]
+fn get_escrows_by_state(state: EscrowState, cursor: Option<ByteBuf>, limit: u32) -> EscrowPage {
+    let limit = (limit as usize).clamp(1, MAX_PAGE_SIZE);
+    page_for_caller(storage::get_escrows_by_state(&state, cursor.as_deref().map(|cursor| cursor.as_slice()), limit))
+}
+
+/// Active escrows, paged by hashlock
+#[query]
+fn get_active_escrows(cursor: Option<ByteBuf>, limit: u32) -> EscrowPage {
+    get_escrows_by_state(EscrowState::Active, cursor, limit)
+}
+
+/// Escrows whose maker is `maker`, paged by hashlock
+#[query]
+fn get_escrows_by_maker(maker: String, cursor: Option<ByteBuf>, limit: u32) -> EscrowPage {
+    let limit = (limit as usize).clamp(1, MAX_PAGE_SIZE);
+    page_for_caller(storage::get_escrows_by_maker(&maker, cursor.as_deref().map(|cursor| cursor.as_slice()), limit))
+}
+
+/// Escrows whose taker is `taker`, paged by hashlock
+#[query]
+fn get_escrows_by_taker(taker: String, cursor: Option<ByteBuf>, limit: u32) -> EscrowPage {
+    let limit = (limit as usize).clamp(1, MAX_PAGE_SIZE);
+    page_for_caller(storage::get_escrows_by_taker(&taker, cursor.as_deref().map(|cursor| cursor.as_slice()), limit))
+}
+
+/// Hashlocks of the escrows a legacy 8-byte transfer memo may belong to, for reconciling native ICP transfers
+#[query]
+fn lookup_memo(memo: u64) -> Vec<Vec<u8>> {
+    storage::lookup_memo(memo)
+}
+
+/// Every escrow created for `order_hash`, on any chain, in hashlock order
+#[query]
+fn get_escrow_by_order_hash(order_hash: ByteBuf) -> Vec<(Vec<u8>, ICPEscrow)> {
+    escrows_for_caller(storage::get_escrows_for_order(&order_hash))
+}
+
+/// The caller's active escrows whose private withdrawal window closes within the configured horizon, soonest first
+#[query]
+fn get_reminders() -> Vec<EscrowReminder> {
+    let now = current_time();
+    let horizon = storage::get_config().reminder_horizon;
+    let mut reminders: Vec<EscrowReminder> = storage::get_all_escrows_for_party(&caller_principal())
+        .iter()
+        .filter_map(|(_, escrow)| escrow.reminder(now, horizon))
+        .collect();
+    reminders.sort_by_key(|reminder| reminder.closes_at);
+    reminders
+}
+
+/// Parameters of one of the caller's past escrows, prefilled for a repeat swap with the same
+/// counterparty; the order hash and hashlock are placeholders to replace before creation
+#[query]
+fn clone_escrow_params(hashlock: ByteBuf) -> Result<EscrowImmutables> {
+    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    if !is_maker_or_taker(&escrow, &caller_principal().to_text()) {
+        return Err(EscrowError::InvalidCaller);
+    }
+    Ok(escrow.immutables.repeat_params(&storage::get_config()))
+}
+
+/// Swap session linking both legs of the swap under `hashlock`, if one was created
+#[query]
+fn get_swap_session(hashlock: ByteBuf) -> Option<SwapSession> {
+    storage::get_session(&hashlock)
+}
+
+/// Chronological trace of an order across its escrows: events, recorded ledger blocks,
+/// counter-chain records and when the secret was revealed
+#[query]
+fn trace_swap(order_hash: ByteBuf) -> Result<SwapTrace> {
+    let escrows = storage::get_escrows_for_order(&order_hash);
+    if escrows.is_empty() {
+        return Err(EscrowError::EscrowNotFound);
+    }
+    
+    let mut legs = Vec::new();
+    let mut timeline = Vec::new();
+    for (hashlock, escrow) in escrows {
+        timeline.extend(public_events(storage::get_events_for_hashlock(&hashlock)).into_iter().map(|event| TraceEntry {
+            timestamp: event.timestamp(),
+            hashlock: hashlock.clone(),
+            event,
+        }));
+        let session = storage::get_session(&hashlock);
+        legs.push(SwapTraceLeg::new(hashlock, escrow, session));
+    }
+    legs.sort_by(|a, b| a.hashlock.cmp(&b.hashlock));
+    
+    Ok(SwapTrace::new(order_hash.to_vec(), legs, timeline, current_time()))
+}
+
+/// Get a principal's display profile
+#[query]
+fn get_profile(principal_str: String) -> Option<ParticipantProfile> {
+    storage::get_profile(&principal_str)
+}
+
+/// Human-readable summary of an escrow, with amounts and timelock boundaries pre-formatted
+#[query]
+fn render_escrow_summary(hashlock: ByteBuf) -> Option<EscrowSummary> {
+    let config = storage::get_config();
+    storage::get_escrow(&hashlock).map(|escrow| escrow_summary(&escrow, &config))
+}
+
+/// Absolute timelock boundaries of an escrow, its current phase and the time left until the
+/// next one, for countdowns
+#[query]
+fn get_escrow_timeline(hashlock: ByteBuf) -> Option<EscrowTimeline> {
+    let config = storage::get_config();
+    storage::get_escrow(&hashlock).map(|escrow| EscrowTimeline::new(&escrow, config.rescue_delay, current_time()))
+}
+
+fn escrow_summary(escrow: &ICPEscrow, config: &EscrowConfig) -> EscrowSummary {
+    let immutables = &escrow.immutables;
+    let [withdrawal_start, public_withdrawal_start, cancellation_start] = escrow.stage_starts();
+    EscrowSummary {
+        hashlock: utils::bytes_to_hex(&immutables.hashlock),
+        state: format!("{:?}", escrow.state),
+        maker: immutables.maker.clone(),
+        taker: immutables.taker.clone(),
+        amount: utils::format_icp_amount(immutables.amount),
+        safety_deposit: utils::format_icp_amount(immutables.safety_deposit),
+        token: immutables.token.clone(),
+        created_at: utils::format_iso8601(escrow.created_at),
+        withdrawal_start: utils::format_iso8601(withdrawal_start),
+        public_withdrawal_start: utils::format_iso8601(public_withdrawal_start),
+        cancellation_start: utils::format_iso8601(cancellation_start),
+        rescue_start: utils::format_iso8601(immutables.timelocks.rescue_start(config.rescue_delay)),
+        completed_at: escrow.completed_at.map(utils::format_iso8601),
+    }
+}
+
+/// Get escrow details together with the parties' display profiles
+#[query]
+fn get_escrow_view(hashlock: ByteBuf) -> Option<EscrowView> {
+    storage::get_escrow(&hashlock).map(escrow_view)
+}
+
+/// Get escrows for a principal together with the parties' display profiles
+#[query]
+fn get_escrow_views_for_principal(principal_str: String) -> Vec<(Vec<u8>, EscrowView)> {
+    storage::get_escrows_for_principal(&principal_str)
+        .into_iter()
+        .map(|(hashlock, escrow)| (hashlock, escrow_view(escrow)))
+        .collect()
+}
+
+/// Get recent events
+#[query]
+fn get_recent_events(limit: u32) -> Vec<EscrowEvent> {
+    public_events(storage::get_recent_events(limit as usize))
+}
+
+/// Get events for a specific escrow
+#[query]
+fn get_events_for_hashlock(hashlock: ByteBuf) -> Vec<EscrowEvent> {
+    public_events(storage::get_events_for_hashlock(&hashlock))
+}
+
+/// Events from `event_id` on, with their ids, for indexers tailing the log. Pass back
+/// `next_event_id` to continue; ids are consecutive, so a skipped id means a missed event.
+#[query]
+fn get_events_since(event_id: u64, limit: u32) -> EventLogPage {
+    let mut page = storage::get_events_since(event_id, (limit as usize).clamp(1, MAX_EVENT_PAGE));
+    let now = current_time();
+    for logged in &mut page.events {
+        storage::redact_public_event(&mut logged.event, now);
+    }
+    page
+}
+
+/// Events as served publicly, with settled secrets redacted when configured
+fn public_events(mut events: Vec<EscrowEvent>) -> Vec<EscrowEvent> {
+    let now = current_time();
+    for event in &mut events {
+        storage::redact_public_event(event, now);
+    }
+    events
+}
+
+/// Get a certified inclusion proof for an event in the hot log
+#[query]
+fn get_event_proof(seq: u64) -> Result<EventProof> {
+    // Archived events are no longer committed in the certified tree
+    if !certification::is_event_certified(seq) {
+        return Err(EscrowError::EventNotFound);
+    }
+    let record = storage::get_event(seq).ok_or(EscrowError::EventNotFound)?;
+    let certificate = certification::data_certificate().ok_or(EscrowError::CertificateUnavailable)?;
+    let event_candid = storage::get_event_bytes(seq).ok_or(EscrowError::EventNotFound)?;
+    
+    Ok(EventProof {
+        seq,
+        event: record.event,
+        event_candid,
+        witness: certification::encode_witness(&certification::event_witness(seq)),
+        certificate,
+    })
+}
+
+/// Get the history of periodic escrow state-root commitments
+#[query]
+fn get_state_commitments(start_index: u64, limit: u32) -> Vec<storage::StateCommitment> {
+    storage::get_state_commitments(start_index, limit.min(100) as usize)
+}
+
+/// Get a deterministic digest of all escrows, config and authorized principals
+#[query]
+fn get_state_digest() -> storage::StateDigest {
+    storage::get_state_digest()
+}
+
+/// Get metrics
+#[query]
+fn get_metrics() -> storage::EscrowMetrics {
+    storage::get_metrics()
+}
+
+/// Get the latest metrics snapshot taken at or before a timestamp
+#[query]
+fn get_metrics_at(timestamp: u64) -> Option<storage::MetricsSnapshot> {
+    storage::get_metrics_at(timestamp)
+}
+
+/// Get recent metrics snapshots spaced at least `step` nanoseconds apart
+#[query]
+fn get_metrics_history(step: u64) -> Vec<storage::MetricsSnapshot> {
+    storage::get_metrics_history(step)
+}
+
+/// Per-day created/completed/cancelled counts for `from_day..=to_day` (days since the Unix epoch)
+#[query]
+fn get_daily_activity(from_day: u64, to_day: u64) -> Result<Vec<storage::DailyActivity>> {
+    if from_day > to_day || to_day - from_day >= storage::MAX_ACTIVITY_DAYS {
+        return Err(EscrowError::InvalidTime);
+    }
+    Ok(storage::get_daily_activity(from_day, to_day))
+}
+
+/// Get canister balance
+#[query]
+async fn get_balance() -> Result<u64> {
+    ledger::get_balance().await
+}
+
+/// Get storage statistics
+#[query]
+fn get_storage_stats() -> storage::StorageStats {
+    storage::get_storage_stats()
+}
+
+// =============================================================================
+// HTTP INTERFACE
+// =============================================================================
+
+/// Event feed for explorers: `GET /events?after_seq=N` (newline-delimited JSON, streamed)
+#[query]
+fn http_request(request: http::HttpRequest) -> http::HttpResponse {
+    http::handle_request(&request)
+}
+
+/// Streaming callback serving the remaining chunks of an `/events` response
+#[query]
+fn http_request_streaming_callback(token: http::StreamingCallbackToken) -> http::StreamingCallbackHttpResponse {
+    http::handle_streaming_callback(token)
+}
+
+// =============================================================================
+// ADMIN FUNCTIONS
+// =============================================================================
+
+/// Update configuration (admins only)
+#[update]
+fn set_config(new_config: EscrowConfig) -> Result<()> {
+    let caller = caller_principal();
+    
+    // Only admins can update config
+    roles::require(&caller, Role::Admin)?;
+    
+    // Reject configs that would brick creations or admin access
+    new_config.validate()?;
+    
+    storage::set_config(new_config)
+}
+
+/// Reset metrics counters (operators only)
+/// The previous values are recorded in a MetricsReset event and returned
+#[update]
+fn reset_metrics(scope: MetricsResetScope) -> Result<storage::EscrowMetrics> {
+    let caller = caller_principal();
+    
+    // Only operators can reset metrics
+    roles::require(&caller, Role::Operator)?;
+    
+    let previous = storage::reset_metrics(&scope);
+    
+    storage::add_event(EscrowEvent::MetricsReset {
+        scope,
+        previous: previous.clone(),
+        reset_by: caller,
+        timestamp: current_time(),
+    });
+    
+    Ok(previous)
+}
+
+/// Add authorized principal (admins only)
+#[update]
+fn add_authorized_principal(principal: Principal) -> Result<()> {
+    let caller = caller_principal();
+    
+    // Only admins can add authorized principals
+    roles::require(&caller, Role::Admin)?;
+    
+    storage::add_authorized_principal(principal)
+}
+
+/// Remove authorized principal (admins only)
+#[update]
+fn remove_authorized_principal(principal: Principal) -> Result<()> {
+    let caller = caller_principal();
+    
+    // Only admins can remove authorized principals
+    roles::require(&caller, Role::Admin)?;
+    
+    storage::remove_authorized_principal(&principal)
+}
+
+/// How long guardian approvals for a treasury rotation stay valid
+const TREASURY_ROTATION_TTL: u64 = 3 * 24 * 60 * 60 * 1_000_000_000; // 3 days
+
+/// Break-glass treasury rotation (guardians only)
+///
+/// Each guardian calls this with the same new treasury; the rotation executes
+/// once `guardian_threshold` distinct guardians have approved. A different
+/// treasury can only be proposed once the pending proposal has expired. Returns
+/// whether the rotation was executed by this call.
+#[update]
+fn rotate_treasury(new_treasury: Principal) -> Result<bool> {
+    let caller = caller_principal();
+    let current_time = current_time();
+    let mut config = storage::get_config();
+    
+    // Only configured guardians can approve a rotation
+    if config.guardian_threshold == 0 || !config.guardians.contains(&caller) {
+        return Err(EscrowError::Unauthorized);
+    }
+    
+    if new_treasury == Principal::anonymous() {
+        return Err(EscrowError::InvalidAddress);
+    }
+    
+    let (rotation, approvals) = approve_treasury_rotation(
+        storage::get_pending_treasury_rotation(),
+        new_treasury,
+        caller,
+        &config.guardians,
+        current_time,
+    )?;
+    
+    storage::add_event(EscrowEvent::TreasuryRotationApproved {
+        new_treasury,
+        guardian: caller,
+        approvals,
+        timestamp: current_time,
+    });
+    
+    if approvals < config.guardian_threshold {
+        storage::set_pending_treasury_rotation(Some(rotation));
+        return Ok(false);
+    }
+    
+    // Quorum reached: rotate the treasury
+    let old_treasury = config.treasury;
+    config.treasury = new_treasury;
+    storage::set_config(config)?;
+    storage::set_pending_treasury_rotation(None);
+    
+    storage::add_event(EscrowEvent::TreasuryRotated {
+        old_treasury,
+        new_treasury,
+        timestamp: current_time,
+    });
+    
+    Ok(true)
+}
+
+/// Add `guardian`'s approval to the pending rotation to `new_treasury`, or start a new
+/// proposal once no unexpired one is pending. Returns the proposal and its count of
+/// approvals from currently configured guardians.
+fn approve_treasury_rotation(
+    pending: Option<PendingTreasuryRotation>,
+    new_treasury: Principal,
+    guardian: Principal,
+    guardians: &[Principal],
+    now: u64,
+) -> Result<(PendingTreasuryRotation, u32)> {
+    let mut rotation = match pending {
+        // A competing proposal would discard the other guardians' approvals
+        Some(pending) if now < pending.proposed_at.saturating_add(TREASURY_ROTATION_TTL) => {
+            if pending.new_treasury != new_treasury {
+                return Err(EscrowError::OperationInProgress);
+            }
+            pending
+        }
+        _ => PendingTreasuryRotation {
+            new_treasury,
+            approvals: Vec::new(),
+            proposed_at: now,
+        },
+    };
+    
+    if !rotation.approvals.contains(&guardian) {
+        rotation.approvals.push(guardian);
+    }
+    
+    // Only approvals from currently configured guardians count
+    let approvals = rotation.approvals.iter()
+        .filter(|approver| guardians.contains(approver))
+        .count() as u32;
+    Ok((rotation, approvals))
+}
+
+/// Get the treasury rotation currently collecting guardian approvals
+#[query]
+fn get_pending_treasury_rotation() -> Option<PendingTreasuryRotation> {
+    storage::get_pending_treasury_rotation()
+}
+
+/// Block withdrawals, cancellations and rescues on an escrow while a report is investigated (pausers only)
+#[update]
+fn freeze_escrow(hashlock: ByteBuf, reason: String) -> Result<()> {
+    let caller = caller_principal();
+    let current_time = current_time();
+    
+    // Only pausers can freeze escrows
+    roles::require(&caller, Role::Pauser)?;
+    
+    if reason.trim().is_empty() || reason.len() > MAX_FREEZE_REASON_LEN {
+        return Err(EscrowError::InvalidState);
+    }
+    
+    guards::check_hash("hashlock", &hashlock)?;
+    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    if escrow.state != EscrowState::Active || escrow.freeze.is_some() {
+        return Err(EscrowError::InvalidState);
+    }
+    
+    storage::update_escrow(&hashlock, |escrow| {
+        escrow.freeze = Some(EscrowFreeze {
+            reason: reason.clone(),
+            frozen_by: caller,
+            frozen_at: current_time,
+        });
+    })?;
+    
+    storage::add_event(EscrowEvent::EscrowFrozen {
+        hashlock: hashlock.to_vec(),
+        reason,
+        frozen_by: caller,
+        timestamp: current_time,
+    });
+    
+    Ok(())
+}
+
+/// Swap treasury ICP for another asset (e.g. ckUSDC) with the canister as maker of a
+/// source escrow (fee managers only). The escrow is funded from the treasury swap subaccount,
+/// so escrowed user funds are never spent; no creation fee or volume cap applies.
+#[update]
+async fn create_treasury_swap(immutables: EscrowImmutables, deadline_ns: Option<u64>) -> Result<Vec<u8>> {
+    let caller = caller_principal();
+    let current_time = current_time();
+    let config = storage::get_config();
+    
+    // Only fee managers can swap treasury funds
+    roles::require(&caller, Role::FeeManager)?;
+    
+    check_not_paused(&config)?;
+    cycles::check_creation_allowed(&config)?;
+    check_deadline(deadline_ns)?;
+    
+    let mut immutables = immutables;
+    immutables.maker = ic_cdk::api::canister_self().to_text();
+    immutables.timelocks.deployed_at = current_time;
+    guards::check_immutables(&immutables)?;
+    let _lock = guards::EscrowLock::acquire(&immutables.hashlock)?;
+    immutables.validate_src(&config)?;
+    
+    // Treasury swaps are funded from the ICP treasury swap subaccount
+    if immutables.icp_token.is_some() {
+        return Err(EscrowError::UnsupportedToken);
+    }
+    
+    if storage::get_escrow(&immutables.hashlock).is_some() {
+        return Err(EscrowError::DuplicateEscrow);
+    }
+    let replay_key = request_key(RequestKind::Creation, &immutables);
+    check_not_replayed(&replay_key, current_time)?;
+    
+    let taker_funded = immutables.is_taker_funded_deposit();
+    let transfer_amount = immutables.required_deposit()?;
+    let deposit_memo = ledger::generate_transfer_memo(
+        ledger::TransferOperation::Deposit,
+        &immutables.hashlock,
+    );
+    let subaccount = ledger::escrow_subaccount(&immutables.hashlock);
+    let deposit_block = ledger::fund_from_treasury_swaps(subaccount, utils::icp_amount(transfer_amount)?, deposit_memo).await?;
+    
+    let hashlock = immutables.hashlock.clone();
+    let escrow = ICPEscrow {
+        immutables: immutables.clone(),
+        state: EscrowState::Active,
+        icp_tx_hash: None,
+        evm_address: None,
+        created_at: current_time,
+        completed_at: None,
+        revealed_secret: None,
+        surplus_captured: 0,
+        safety_deposit_held: if taker_funded { 0 } else { immutables.safety_deposit },
+        fee_breakdown: FeeBreakdown::default(),
+        settlement_blocks: Vec::new(),
+        deposit_blocks: vec![deposit_block],
+        notes: Vec::new(),
+        freeze: None,
+        rescue_requested_at: None,
+        processing: None,
+        creation_fee_block: None,
+        migration: None,
+        cycles: Some(CycleCosts { creation: cycles::message_cost(1), ..CycleCosts::default() }),
+        token_fee: None,
+        subaccount: Some(subaccount.0.to_vec()),
+        partial_fill: None,
+        funding: None,
+        evm_verification: None,
+        evm_immutables: None,
+        evm_withdrawal: None,
+        rescue_balance: None,
+        transfer_legs: None,
+        escrow_type: Some(EscrowType::Source),
+    };
+    storage::insert_escrow(hashlock.clone(), escrow)?;
+    mark_processed(replay_key);
+    
+    storage::add_event(EscrowEvent::EscrowCreated {
+        hashlock: hashlock.clone(),
+        escrow_type: EscrowType::Source,
+        maker: immutables.maker,
+        taker: immutables.taker,
+        amount: immutables.amount,
+        metadata: immutables.metadata,
+        timestamp: current_time,
+    });
+    
+    Ok(hashlock)
+}
+
+/// Ledger account the treasury tops up to fund treasury swaps
+#[query]
+fn get_treasury_swap_account() -> String {
+    ledger::treasury_swap_account().to_string()
+}
+
+/// Send unused treasury swap funds to the treasury (fee managers only)
+#[update]
+async fn sweep_treasury_swap_funds(amount: u64) -> Result<u64> {
+    let caller = caller_principal();
+    let config = storage::get_config();
+    
+    // Only fee managers can sweep treasury swap funds
+    roles::require(&caller, Role::FeeManager)?;
+    
+    let memo = ledger::generate_transfer_memo(ledger::TransferOperation::Fee, &[]);
+    let block = ledger::sweep_treasury_swaps(config.treasury, amount, memo).await?;
+    
+    storage::add_event(EscrowEvent::TreasurySwapFundsSwept {
+        to: config.treasury,
+        amount,
+        block,
+        timestamp: current_time(),
+    });
+    
+    Ok(block)
+}
+
+/// Collected creation fees awaiting withdrawal (fee managers or observers)
+#[query]
+fn get_fee_balance() -> Result<FeeBalance> {
+    if !can_observe(&ObserverScope::FeeReports) {
+        return Err(EscrowError::Unauthorized);
+    }
+    
+    Ok(FeeBalance {
+        account: ledger::fee_account().to_string(),
+        balance: storage::fee_balance(),
+    })
+}
+
+/// Send collected creation fees to `to` (fee managers only). Only the fee subaccount is
+/// debited, so escrowed funds are never touched; the ledger fee comes out of the balance too.
+#[update]
+async fn withdraw_fees(amount: u64, to: Principal) -> Result<u64> {
+    let caller = caller_principal();
+    
+    // Only fee managers can withdraw fees
+    roles::require(&caller, Role::FeeManager)?;
+    
+    if amount == 0 {
+        return Err(EscrowError::InvalidAmount);
+    }
+    if to == Principal::anonymous() {
+        return Err(EscrowError::InvalidAddress);
+    }
+    
+    // Debit before the call so concurrent withdrawals cannot overdraw the balance
+    let debit = amount.checked_add(ledger::TRANSFER_FEE).ok_or(EscrowError::InvalidAmount)?;
+    storage::debit_fees(debit)?;
+    let memo = ledger::generate_transfer_memo(ledger::TransferOperation::Fee, &[]);
+    let block = match ledger::withdraw_fees(to, amount, memo).await {
+        Ok(block) => block,
+        Err(err) => {
+            storage::credit_fees(debit);
+            return Err(err);
+        }
+    };
+    
+    storage::add_event(EscrowEvent::FeesWithdrawn {
+        to,
+        amount,
+        block,
+        withdrawn_by: caller,
+        timestamp: current_time(),
+    });
+    
+    Ok(block)
+}
+
+/// Where a candidate block of the treasury accounting export was recorded
+enum TreasuryBlockSource {
+    CreationFee,
+    Settlement,
+    Sweep,
+    FeeWithdrawal,
+}
+
+/// Export treasury movements (creation fees, fee withdrawals, surplus, forfeited deposit shares
+/// and treasury swap sweeps) from their recorded ledger blocks, `limit` blocks after `after_block` at a
+/// time (fee managers only). Forfeitures are recognized by payment to the current treasury.
+#[update]
+async fn export_treasury_accounting(after_block: Option<u64>, limit: u32) -> Result<TreasuryExport> {
+    let caller = caller_principal();
+    let config = storage::get_config();
+    
+    // Only fee managers can export treasury accounting
+    roles::require(&caller, Role::FeeManager)?;
+    
+    let mut candidates: Vec<(u64, TreasuryBlockSource, Option<Vec<u8>>)> = Vec::new();
+    for (hashlock, escrow) in storage::get_all_escrows() {
+        if let Some(block) = escrow.creation_fee_block {
+            candidates.push((block, TreasuryBlockSource::CreationFee, Some(hashlock.clone())));
+        }
+        for block in escrow.settlement_blocks {
+            candidates.push((block, TreasuryBlockSource::Settlement, Some(hashlock.clone())));
+        }
+    }
+    for record in storage::get_events_range(0, storage::next_event_seq(), usize::MAX) {
+        match record.event {
+            EscrowEvent::TreasurySwapFundsSwept { block, .. } => candidates.push((block, TreasuryBlockSource::Sweep, None)),
+            EscrowEvent::FeesWithdrawn { block, .. } => candidates.push((block, TreasuryBlockSource::FeeWithdrawal, None)),
+            _ => {}
+        }
+    }
+    candidates.retain(|(block, ..)| after_block.is_none_or(|after| *block > after));
+    candidates.sort_by_key(|(block, ..)| *block);
+    
+    let limit = (limit as usize).clamp(1, MAX_PAGE_SIZE);
+    let next_after_block = (candidates.len() > limit).then(|| candidates[limit - 1].0);
+    candidates.truncate(limit);
+    
+    let treasury_account = ledger::default_account(&config.treasury);
+    let mut rows = Vec::new();
+    for (index, source, hashlock) in candidates {
+        let block = ledger::fetch_block_evidence(index).await?;
+        let kind = match source {
+            TreasuryBlockSource::CreationFee => "creation_fee",
+            TreasuryBlockSource::Sweep => "sweep",
+            TreasuryBlockSource::FeeWithdrawal => "fee_withdrawal",
+            TreasuryBlockSource::Settlement if ledger::is_fee_memo(block.memo) => "surplus",
+            TreasuryBlockSource::Settlement if block.to.as_deref() == Some(treasury_account.as_str()) => "forfeiture",
+            TreasuryBlockSource::Settlement => continue,
+        };
+        rows.push(AccountingRow {
+            block_index: index,
+            timestamp: block.timestamp,
+            kind: kind.to_string(),
+            account: block.to.unwrap_or_default(),
+            amount: block.amount,
+            fee: block.fee,
+            memo: block.memo,
+            hashlock,
+        });
+    }
+    
+    let csv = std::iter::once(AccountingRow::CSV_HEADER.to_string())
+        .chain(rows.iter().map(AccountingRow::to_csv))
+        .collect::<Vec<_>>()
+        .join("\n");
+    
+    Ok(TreasuryExport { rows, csv, next_after_block })
+}
+
+/// Move the canister's clock forward by `delta_ns` so timelock stages can be exercised without
+/// waiting (admins only). Returns the new current time; fails unless built with `dev-mode`.
+#[update]
+fn advance_time(delta_ns: u64) -> Result<u64> {
+    let caller = caller_principal();
+    roles::require(&caller, Role::Admin)?;
+    
+    advance_clock(delta_ns)?;
+    Ok(current_time())
+}
+
+/// Move the simulated clock forward; only dev-mode builds have one
+fn advance_clock(delta_ns: u64) -> Result<()> {
+    #[cfg(feature = "dev-mode")]
+    {
+        utils::advance_time(delta_ns);
+        Ok(())
+    }
+    
+    #[cfg(not(feature = "dev-mode"))]
+    {
+        let _ = delta_ns;
+        Err(EscrowError::DevModeDisabled)
+    }
+}
+
+/// Resolve an escrow stuck in Processing (operators only). With `finalize` unset the ledger is
+/// checked as the watchdog would; otherwise the escrow is settled or returned to Active as given.
+#[update]
+async fn resolve_processing(hashlock: ByteBuf, finalize: Option<bool>) -> Result<()> {
+    let caller = caller_principal();
+    roles::require(&caller, Role::Operator)?;
+    
+    guards::check_hash("hashlock", &hashlock)?;
+    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    if escrow.state != EscrowState::Processing {
+        return Err(EscrowError::InvalidState);
+    }
+    
+    match finalize {
+        None => watchdog::check(&hashlock, &escrow).await,
+        Some(true) => watchdog::finalize(&hashlock, None, true),
+        Some(false) => watchdog::revert(&hashlock, true),
+    }
+}
+
+/// Retry up to `limit` settlement transfers queued after a partial failure (operators only),
+/// without waiting for the retry timer. Returns how many were paid.
+#[update]
+async fn retry_pending_transfers(limit: u32) -> Result<u32> {
+    roles::require(&caller_principal(), Role::Operator)?;
+    Ok(watchdog::retry_pending_transfers(limit as usize).await)
+}
+
+/// Settlement transfers queued for retry, oldest first
+#[query]
+fn get_pending_transfers() -> Vec<PendingTransfer> {
+    storage::get_pending_transfers(usize::MAX)
+}
+
+/// Lift a freeze so the escrow's funds can move again (pausers only)
+#[update]
+fn unfreeze_escrow(hashlock: ByteBuf) -> Result<()> {
+    let caller = caller_principal();
+    
+    // Only pausers can unfreeze escrows
+    roles::require(&caller, Role::Pauser)?;
+    
+    guards::check_hash("hashlock", &hashlock)?;
+    let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    if escrow.freeze.is_none() {
+        return Err(EscrowError::InvalidState);
+    }
+    
+    storage::update_escrow(&hashlock, |escrow| escrow.freeze = None)?;
+    
+    storage::add_event(EscrowEvent::EscrowUnfrozen {
+        hashlock: hashlock.to_vec(),
+        unfrozen_by: caller,
+        timestamp: current_time(),
+    });
+    
+    Ok(())
+}
+
+/// Stop escrow creation and withdrawals canister-wide while an incident is handled
+/// (pausers only). Cancellations and rescues keep running so funds can still leave.
+#[update]
+fn pause() -> Result<()> {
+    let caller = caller_principal();
+    roles::require(&caller, Role::Pauser)?;
+    set_paused(true)?;
+    
+    storage::add_event(EscrowEvent::CanisterPaused {
+        paused_by: caller,
+        timestamp: current_time(),
+    });
+    
+    Ok(())
+}
+
+/// Lift a global pause (admins only)
+#[update]
+fn unpause() -> Result<()> {
+    let caller = caller_principal();
+    roles::require(&caller, Role::Admin)?;
+    set_paused(false)?;
+    
+    storage::add_event(EscrowEvent::CanisterUnpaused {
+        unpaused_by: caller,
+        timestamp: current_time(),
+    });
+    
+    Ok(())
+}
+
+/// Set the config's pause flag, failing if it is already set that way
+fn set_paused(paused: bool) -> Result<()> {
+    let config = storage::get_config();
+    if config.paused == paused {
+        return Err(EscrowError::InvalidState);
+    }
+    storage::set_config(EscrowConfig { paused, ..config })
+}
+
+/// Hand an active escrow's funds and a signed snapshot of its record to a successor canister
+/// implementing `receive_migrated_escrow` (admins only). Calling it again on a migrated
+/// escrow whose snapshot was not accepted retries the delivery without moving funds.
 #[update]
-fn record_evm_address(hashlock: ByteBuf, evm_address: String) -> Result<()> {
+async fn migrate_escrow(hashlock: ByteBuf, successor: Principal) -> Result<()> {
     let caller = caller_principal();
-    let caller_str = caller.to_text();
-    let current_time = current_time();
+    let config = storage::get_config();
+    
+    // Only admins can migrate escrows
+    roles::require(&caller, Role::Admin)?;
+    
+    guards::check_hash("hashlock", &hashlock)?;
+    let _lock = guards::EscrowLock::acquire(&hashlock)?;
+    let source_canister = ic_cdk::api::canister_self();
+    if !notifications::is_canister_principal(&successor) || successor == source_canister {
+        return Err(EscrowError::InvalidAddress);
+    }
     
     let escrow = storage::get_escrow(&hashlock).ok_or(EscrowError::EscrowNotFound)?;
     
-    // Only maker can record EVM address
-    if caller_str != escrow.immutables.maker {
-        return Err(EscrowError::InvalidCaller);
+    // Retry an earlier hand-off the successor did not accept
+    if let Some(receipt) = &escrow.migration {
+        if receipt.delivered || receipt.snapshot.successor != successor {
+            return Err(EscrowError::InvalidState);
+        }
+        migration::deliver(successor, &receipt.snapshot, &receipt.signature).await?;
+        return storage::update_escrow(&hashlock, |escrow| {
+            if let Some(receipt) = escrow.migration.as_mut() {
+                receipt.delivered = true;
+            }
+            escrow.cycles_mut().outcalls += cycles::message_cost(1);
+        });
     }
     
-    // Validate EVM address format
-    if !utils::validate_evm_address(&evm_address) {
-        return Err(EscrowError::InvalidAddress);
+    if escrow.state != EscrowState::Active {
+        return Err(EscrowError::InvalidState);
+    }
+    check_not_frozen(&escrow)?;
+    // A snapshot carries the immutables alone, so a successor could not tell what was already filled
+    if escrow.filled_amount() > 0 {
+        return Err(EscrowError::InvalidPartialFill);
     }
     
-    // Update escrow
+    let held = escrow.immutables.amount + escrow.safety_deposit_held;
+    let funds_transferred = ledger::charge_ledger_fees(&config.ledger_fee_payer, &[held], None, escrow.transfer_fee())?[0];
+    let funds_memo = ledger::generate_transfer_memo(ledger::TransferOperation::Migration, &hashlock);
+    let snapshot = MigrationSnapshot {
+        immutables: escrow.immutables.clone(),
+        created_at: escrow.created_at,
+        icp_tx_hash: escrow.icp_tx_hash.clone(),
+        evm_address: escrow.evm_address.clone(),
+        deposit_blocks: escrow.deposit_blocks.clone(),
+        creation_fee_block: escrow.creation_fee_block,
+        safety_deposit_held: escrow.safety_deposit_held,
+        funds_transferred,
+        funds_memo,
+        source_canister,
+        successor,
+        migrated_at: current_time(),
+    };
+    
+    // Sign before any funds move, so a signing failure leaves the escrow untouched
+    let signature = migration::sign_snapshot(&snapshot, &config.ecdsa_key_name).await?;
+    let receipt = MigrationReceipt { snapshot, signature, delivered: false };
+    acquire_processing(&hashlock, ProcessingOperation::Migration { receipt: Box::new(receipt.clone()) })?;
+    
+    // The deposit may have been funded while the signature was pending
+    let held_now = storage::get_escrow(&hashlock).map(|escrow| escrow.safety_deposit_held);
+    if held_now != Some(receipt.snapshot.safety_deposit_held) {
+        return release_on_error(&hashlock, Err(EscrowError::InvalidState));
+    }
+    
+    let top_up = release_on_error(&hashlock, cover_fee_shortfall(&escrow, &hashlock, &[funds_transferred]).await)?;
+    let memo = ledger::TransferMemo { value: funds_memo, hashlock: hashlock.to_vec(), created_at_time: None };
+    let transfer = transfer_leg(&escrow, successor, funds_transferred, &memo).await;
+    let block = release_on_error(&hashlock, transfer)?;
+    
+    let current_time = current_time();
     storage::update_escrow(&hashlock, |escrow| {
-        escrow.evm_address = Some(evm_address.clone());
+        escrow.state = EscrowState::Migrated;
+        escrow.processing = None;
+        escrow.completed_at = Some(current_time);
+        escrow.safety_deposit_held = 0;
+        if top_up.is_some() {
+            escrow.fee_breakdown.ledger_fees_covered = escrow.fee_breakdown.ledger_fees_covered.saturating_add(escrow.transfer_fee());
+        }
+        escrow.settlement_blocks.extend(top_up);
+        escrow.settlement_blocks.push(block);
+        escrow.migration = Some(receipt.clone());
+        escrow.cycles_mut().settlement += cycles::message_cost(1);
+        escrow.cycles_mut().outcalls += cycles::THRESHOLD_KEY_FEE;
     })?;
-    
-    // Log event
-    let event = EscrowEvent::EVMAddressRecorded {
+    storage::update_metrics(|metrics| {
+        metrics.active_escrows_count = metrics.active_escrows_count.saturating_sub(1);
+    });
+    storage::add_event(EscrowEvent::EscrowMigrated {
         hashlock: hashlock.to_vec(),
-        address: evm_address,
+        successor,
+        amount: funds_transferred,
         timestamp: current_time,
-    };
-    storage::add_event(event);
+    });
     
-    Ok(())
+    // Funds have moved; a rejected snapshot is retried by calling again
+    migration::deliver(successor, &receipt.snapshot, &receipt.signature).await?;
+    storage::update_escrow(&hashlock, |escrow| {
+        if let Some(receipt) = escrow.migration.as_mut() {
+            receipt.delivered = true;
+        }
+        escrow.cycles_mut().outcalls += cycles::INTER_CANISTER_CALL_FEE;
+    })
 }
 
-// =============================================================================
-// QUERY FUNCTIONS
-// =============================================================================
+/// Public key successor canisters verify migration snapshot signatures against
+#[update]
+async fn get_migration_public_key() -> Result<ByteBuf> {
+    let config = storage::get_config();
+    migration::public_key(&config.ecdsa_key_name).await.map(ByteBuf::from)
+}
 
-/// Get escrow details
-#[query]
-fn get_escrow(hashlock: ByteBuf) -> Option<ICPEscrow> {
-    storage::get_escrow(&hashlock)
+/// Register or replace an escrow template (admins only)
+#[update]
+fn set_template(template: EscrowTemplate) -> Result<()> {
+    let caller = caller_principal();
+    let config = storage::get_config();
+    
+    // Only admins can manage templates
+    roles::require(&caller, Role::Admin)?;
+    
+    template.validate(&config)?;
+    storage::set_template(template)
 }
 
-/// Get ICP transaction hash for an escrow
-#[query]
-fn get_icp_tx_hash(hashlock: ByteBuf) -> Option<String> {
-    storage::get_escrow(&hashlock)
-        .and_then(|escrow| escrow.icp_tx_hash)
+/// Remove an escrow template (admins only)
+#[update]
+fn remove_template(id: String) -> Result<()> {
+    let caller = caller_principal();
+    
+    // Only admins can manage templates
+    roles::require(&caller, Role::Admin)?;
+    
+    storage::remove_template(&id)
 }
 
-/// Get EVM address for an escrow
+/// Get an escrow template by ID
 #[query]
-fn get_evm_address(hashlock: ByteBuf) -> Option<String> {
-    storage::get_escrow(&hashlock)
-        .and_then(|escrow| escrow.evm_address)
+fn get_template(id: String) -> Option<EscrowTemplate> {
+    storage::get_template(&id)
 }
 
-/// Get current configuration
+/// List all escrow templates
 #[query]
-fn get_config() -> EscrowConfig {
-    storage::get_config()
+fn list_templates() -> Vec<EscrowTemplate> {
+    storage::get_templates()
 }
 
-/// Get escrows for a principal
-#[query]
-fn get_escrows_for_principal(principal_str: String) -> Vec<(Vec<u8>, ICPEscrow)> {
-    storage::get_escrows_for_principal(&principal_str)
+/// Set how finality is judged on an EVM chain (admins only)
+#[update]
+fn set_chain_config(chain_config: ChainConfig) -> Result<()> {
+    let caller = caller_principal();
+    
+    // Only admins can manage chain configs
+    roles::require(&caller, Role::Admin)?;
+    
+    chain_config.validate()?;
+    storage::set_chain_config(chain_config)
 }
 
-/// Get recent events
-#[query]
-fn get_recent_events(limit: u32) -> Vec<EscrowEvent> {
-    storage::get_recent_events(limit as usize)
+/// Remove a chain's finality config (admins only)
+#[update]
+fn remove_chain_config(chain_id: u64) -> Result<()> {
+    let caller = caller_principal();
+    
+    // Only admins can manage chain configs
+    roles::require(&caller, Role::Admin)?;
+    
+    storage::remove_chain_config(chain_id)
 }
 
-/// Get events for a specific escrow
+/// Confirmation depth, RPC providers, gas price cap and finality lag used for an EVM chain
 #[query]
-fn get_events_for_hashlock(hashlock: ByteBuf) -> Vec<EscrowEvent> {
-    storage::get_events_for_hashlock(&hashlock)
+fn get_chain_config(chain_id: u64) -> Option<ChainConfig> {
+    storage::get_chain_config(chain_id)
 }
 
-/// Get metrics
+/// List all chain configs, by chain id
 #[query]
-fn get_metrics() -> storage::EscrowMetrics {
-    storage::get_metrics()
+fn list_chain_configs() -> Vec<ChainConfig> {
+    storage::get_chain_configs()
 }
 
-/// Get canister balance
-#[query]
-async fn get_balance() -> Result<u64> {
-    ledger::get_balance().await
+/// Register the caller as a resolver, locking the configured stake
+#[update]
+async fn register_resolver() -> Result<()> {
+    let caller = caller_principal();
+    let config = storage::get_config();
+    
+    if caller == Principal::anonymous() {
+        return Err(EscrowError::InvalidCaller);
+    }
+    if storage::is_resolver(&caller) {
+        return Err(EscrowError::InvalidState);
+    }
+    
+    // Record before the transfer so concurrent calls cannot stake twice
+    let stake = config.resolver_stake;
+    storage::set_resolver(ResolverRegistration {
+        principal: caller,
+        stake,
+        registered_at: current_time(),
+    })?;
+    
+    if stake > 0 {
+        let stake_memo = ledger::generate_transfer_memo(ledger::TransferOperation::Stake, caller.as_slice());
+        if let Err(err) = ledger::transfer_from_caller(stake, stake_memo).await {
+            storage::remove_resolver(&caller);
+            return Err(err);
+        }
+    }
+    
+    storage::add_event(EscrowEvent::ResolverRegistered {
+        resolver: caller,
+        stake,
+        timestamp: current_time(),
+    });
+    
+    Ok(())
 }
 
-/// Get storage statistics
-#[query]
-fn get_storage_stats() -> storage::StorageStats {
-    storage::get_storage_stats()
+/// Unregister the caller as a resolver and return its stake
+#[update]
+async fn unregister_resolver() -> Result<()> {
+    let caller = caller_principal();
+    let registration = storage::remove_resolver(&caller).ok_or(EscrowError::InvalidState)?;
+    
+    if registration.stake > 0 {
+        let stake_memo = ledger::generate_transfer_memo(ledger::TransferOperation::Stake, caller.as_slice());
+        if let Err(err) = ledger::transfer_to(caller, registration.stake, stake_memo, None).await {
+            storage::set_resolver(registration)?;
+            return Err(err);
+        }
+    }
+    
+    storage::add_event(EscrowEvent::ResolverUnregistered {
+        resolver: caller,
+        stake_returned: registration.stake,
+        timestamp: current_time(),
+    });
+    
+    Ok(())
 }
 
-// =============================================================================
-// ADMIN FUNCTIONS
-// =============================================================================
+/// List registered resolvers
+#[query]
+fn get_resolvers() -> Vec<ResolverRegistration> {
+    storage::get_resolvers()
+}
 
-/// Update configuration (treasury only)
-#[update]
-fn set_config(new_config: EscrowConfig) -> Result<()> {
+/// Whether the caller holds the role behind `scope` or an observer token for it
+fn can_observe(scope: &ObserverScope) -> bool {
     let caller = caller_principal();
-    let current_config = storage::get_config();
+    let role = match scope {
+        ObserverScope::AuthorizedPrincipals => Role::Operator,
+        ObserverScope::FeeReports => Role::FeeManager,
+    };
+    roles::has_role(&caller, role) || storage::observer_allows(&caller, scope, current_time())
+}
+
+/// Get authorized principals list (operators or observers)
+#[query]
+fn get_authorized_principals() -> Result<Vec<Principal>> {
+    if !can_observe(&ObserverScope::AuthorizedPrincipals) {
+        return Err(EscrowError::Unauthorized);
+    }
     
-    // Only treasury can update config
-    if caller != current_config.treasury {
+    Ok(storage::get_authorized_principals())
+}
+
+/// Fee totals across all escrows (fee managers or observers)
+#[query]
+fn get_fee_report() -> Result<FeeReport> {
+    if !can_observe(&ObserverScope::FeeReports) {
         return Err(EscrowError::Unauthorized);
     }
     
-    storage::set_config(new_config)
+    Ok(storage::get_fee_report(current_time()))
 }
 
-/// Add authorized principal (treasury only)
+/// Approximate cycle totals across all escrows, for calibrating fees (fee managers or observers)
+#[query]
+fn get_cycle_report() -> Result<CycleReport> {
+    if !can_observe(&ObserverScope::FeeReports) {
+        return Err(EscrowError::Unauthorized);
+    }
+    
+    Ok(storage::get_cycle_report(current_time()))
+}
+
+/// Top up the canister with the cycles attached to this call, like a wallet's `wallet_receive`.
+/// Anyone may deposit, even while paused; each top-up emits `CyclesDeposited` naming the caller.
 #[update]
-fn add_authorized_principal(principal: Principal) -> Result<()> {
+fn deposit_cycles() -> Result<u128> {
+    cycles::accept_deposit(caller_principal(), current_time())
+}
+
+/// Cycles balance, estimated burn rate and days until the canister would freeze. New escrows
+/// are refused while the balance is below the config's `min_cycles_for_creation`.
+#[query]
+fn get_cycles_status() -> CyclesStatus {
+    cycles::status(&storage::get_config(), current_time())
+}
+
+/// Issue or replace a read-only observer token (admins only)
+#[update]
+fn grant_observer(principal: Principal, scopes: Vec<ObserverScope>, expires_at: Option<u64>) -> Result<()> {
     let caller = caller_principal();
-    let config = storage::get_config();
+    let current_time = current_time();
     
-    // Only treasury can add authorized principals
-    if caller != config.treasury {
-        return Err(EscrowError::Unauthorized);
+    // Only admins can issue observer tokens
+    roles::require(&caller, Role::Admin)?;
+    
+    if principal == Principal::anonymous() {
+        return Err(EscrowError::InvalidAddress);
+    }
+    if scopes.is_empty() {
+        return Err(EscrowError::InvalidState);
+    }
+    if expires_at.is_some_and(|expires_at| expires_at <= current_time) {
+        return Err(EscrowError::InvalidTime);
     }
     
-    storage::add_authorized_principal(principal)
+    storage::set_observer(ObserverGrant {
+        principal,
+        scopes,
+        granted_at: current_time,
+        expires_at,
+    })
 }
 
-/// Remove authorized principal (treasury only)
+/// Revoke an observer token (admins only)
 #[update]
-fn remove_authorized_principal(principal: Principal) -> Result<()> {
+fn revoke_observer(principal: Principal) -> Result<()> {
     let caller = caller_principal();
-    let config = storage::get_config();
     
-    // Only treasury can remove authorized principals
-    if caller != config.treasury {
-        return Err(EscrowError::Unauthorized);
-    }
+    // Only admins can revoke observer tokens
+    roles::require(&caller, Role::Admin)?;
     
-    storage::remove_authorized_principal(&principal)
+    storage::remove_observer(&principal)
 }
 
-/// Get authorized principals list (treasury only)
+/// List issued observer tokens (admins only)
 #[query]
-fn get_authorized_principals() -> Result<Vec<Principal>> {
+fn get_observers() -> Result<Vec<ObserverGrant>> {
     let caller = caller_principal();
-    let config = storage::get_config();
     
-    // Only treasury can view authorized list
-    if caller != config.treasury {
-        return Err(EscrowError::Unauthorized);
-    }
+    roles::require(&caller, Role::Admin)?;
     
-    Ok(storage::get_authorized_principals())
+    Ok(storage::get_observers())
+}
+
+/// Grant a role to a principal (admins only)
+#[update]
+fn grant_role(principal: Principal, role: Role) -> Result<()> {
+    roles::grant(caller_principal(), principal, role, current_time())
+}
+
+/// Revoke a role from a principal (admins only)
+#[update]
+fn revoke_role(principal: Principal, role: Role) -> Result<()> {
+    roles::revoke(caller_principal(), principal, role, current_time())
+}
+
+/// Roles a principal holds, including the treasury's implicit Admin
+#[query]
+fn get_roles(principal: Principal) -> Vec<Role> {
+    roles::effective_roles(&principal)
+}
+
+/// List every explicit role grant (admins only)
+#[query]
+fn get_role_assignments() -> Result<Vec<RoleAssignment>> {
+    roles::require(&caller_principal(), Role::Admin)?;
+    Ok(storage::get_role_assignments())
 }
 
 // =============================================================================
@@ -819,6 +4155,18 @@ fn greet(name: String) -> String {
     format!("Hello, {}! ICP Fusion+ Escrow is ready for cross-chain atomic swaps.", name)
 }
 
+/// Get the Candid interface of this canister
+#[query]
+fn get_candid() -> String {
+    __export_service()
+}
+
+/// Candid interface endpoint used by dfx and the Candid UI
+#[query(hidden = true)]
+fn __get_candid_interface_tmp_hack() -> String {
+    __export_service()
+}
+
 /// Get canister info
 #[query]
 fn get_canister_info() -> String {
@@ -829,3 +4177,355 @@ fn get_canister_info() -> String {
 }
 
 export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid_parser::utils::{service_equal, CandidSource};
+
+    #[test]
+    fn candid_interface_matches_did_file() {
+        let generated = __export_service();
+        let did_path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("icp_backend.did");
+        service_equal(CandidSource::Text(&generated), CandidSource::File(&did_path))
+            .expect("icp_backend.did is out of sync with the canister interface");
+    }
+
+    fn test_escrow() -> ICPEscrow {
+        ICPEscrow {
+            immutables: EscrowImmutables {
+                order_hash: vec![0; 32],
+                hashlock: vec![1; 32],
+                maker: "maker".to_string(),
+                taker: "taker".to_string(),
+                token: "0x0".to_string(),
+                amount: 1_000_000,
+                safety_deposit: 100_000,
+                timelocks: types::Timelocks {
+                    deployed_at: 0,
+                    dst: None,
+                    withdrawal: 10,
+                    public_withdrawal: 20,
+                    cancellation: 30,
+                    public_cancellation: None,
+                },
+                maker_min_amount: Some(800_000),
+                min_received: None,
+                safety_deposit_funding: None,
+                chain_id: None,
+                icp_token: None,
+                hash_algorithm: None,
+                merkle_root: None,
+                parts_count: None,
+                auction: None,
+                metadata: None,
+            },
+            state: EscrowState::Active,
+            icp_tx_hash: None,
+            evm_address: None,
+            created_at: 0,
+            completed_at: None,
+            revealed_secret: None,
+            surplus_captured: 0,
+            safety_deposit_held: 100_000,
+            fee_breakdown: FeeBreakdown::default(),
+            settlement_blocks: Vec::new(),
+            deposit_blocks: Vec::new(),
+            notes: Vec::new(),
+            freeze: None,
+            rescue_requested_at: None,
+            processing: None,
+            creation_fee_block: None,
+            migration: None,
+            cycles: None,
+            token_fee: None,
+            subaccount: None,
+            partial_fill: None,
+            funding: None,
+            evm_verification: None,
+            evm_immutables: None,
+            evm_withdrawal: None,
+            rescue_balance: None,
+            transfer_legs: None,
+            escrow_type: Some(EscrowType::Source),
+        }
+    }
+
+    #[test]
+    fn simulated_creation_includes_deposit_and_fees() {
+        let config = EscrowConfig { creation_fee: 5_000, ..EscrowConfig::default() };
+        let cost = operation_cost(&SimulatedOperation::CreateSrc, &test_escrow(), &config).unwrap();
+
+        assert_eq!(cost.deposit, 1_100_000);
+        assert_eq!(cost.transfer_legs, 2);
+        assert_eq!(cost.total_required, 1_100_000 + 5_000 + 2 * u128::from(ledger::TRANSFER_FEE));
+    }
+
+    #[test]
+    fn simulated_withdrawal_matches_settlement_legs() {
+        let config = EscrowConfig {
+            surplus_share_bps: 5_000,
+            ledger_fee_payer: types::LedgerFeePayer::Recipient,
+            ..EscrowConfig::default()
+        };
+        let escrow = test_escrow();
+        let op = SimulatedOperation::Withdraw(EscrowType::Destination);
+        let cost = operation_cost(&op, &escrow, &config).unwrap();
+
+        // Half of the 200_000 surplus goes to treasury; every leg pays its own fee
+        let fee = u128::from(ledger::TRANSFER_FEE);
+        assert_eq!(cost.protocol_fee, 100_000);
+        assert_eq!(cost.net_payouts, vec![900_000 - fee, 100_000 - fee, 100_000 - fee]);
+        assert_eq!(cost.ledger_fees, 3 * fee);
+        assert_eq!(cost.total_required, 0);
+    }
+
+    #[test]
+    fn public_withdrawer_earns_safety_deposit() {
+        let mut escrow = test_escrow();
+        escrow.immutables.maker = Principal::from_slice(&[1]).to_text();
+        escrow.immutables.taker = Principal::from_slice(&[2]).to_text();
+        let executor = Principal::from_slice(&[3]);
+        let config = EscrowConfig::default();
+
+        let public = deposit_recipients(&escrow, &EscrowType::Destination, 100_000, Some(executor), &config).unwrap();
+        assert_eq!(public, vec![(executor, 100_000)]);
+        let private = deposit_recipients(&escrow, &EscrowType::Destination, 100_000, None, &config).unwrap();
+        assert_eq!(private, vec![(Principal::from_slice(&[2]), 100_000)]);
+
+        let legacy = EscrowConfig { refund_public_deposit: true, ..config };
+        let refunded = deposit_recipients(&escrow, &EscrowType::Destination, 100_000, Some(executor), &legacy).unwrap();
+        assert_eq!(refunded, vec![(Principal::from_slice(&[2]), 100_000)]);
+    }
+
+    #[test]
+    fn protocol_spec_reflects_config_and_templates() {
+        let config = EscrowConfig { creation_fee: 5_000, ..EscrowConfig::default() };
+        let template = |id: &str, token: &str| EscrowTemplate {
+            id: id.to_string(),
+            token: token.to_string(),
+            min_amount: 1_000,
+            max_amount: 1_000_000,
+            safety_deposit: 100_000,
+            timelocks: types::TimelockPreset { withdrawal: 10, public_withdrawal: 20, cancellation: 30 },
+            creation_fee: None,
+        };
+        let spec = protocol_spec(&config, &[template("a", "0xb"), template("b", "0xa"), template("c", "0xb")]);
+
+        assert_eq!(spec.fees.creation_fee, 5_000);
+        assert_eq!(spec.tokens, vec!["0xa".to_string(), "0xb".to_string()]);
+        assert_eq!(spec.hash_algorithms, ["sha256", "keccak256"]);
+        assert_eq!(spec.chains[0].chain_id, None);
+        assert!(spec.chains.iter().any(|chain| chain.chain_id == Some(utils::CHAIN_ID_SOLANA)));
+        let stages: Vec<&str> = spec.timelock_stages.iter().map(|stage| stage.name.as_str()).collect();
+        assert_eq!(stages, ["private_withdrawal", "public_withdrawal", "cancellation", "rescue"]);
+    }
+
+    #[test]
+    fn escrow_summary_formats_amounts_and_boundaries() {
+        let escrow = test_escrow();
+        let summary = escrow_summary(&escrow, &EscrowConfig::default());
+
+        assert_eq!(summary.amount, "0.01000000 ICP");
+        assert_eq!(summary.state, "Active");
+        assert_eq!(summary.hashlock, "01".repeat(32));
+        assert_eq!(summary.withdrawal_start, "1970-01-01T00:00:10Z");
+        // Default rescue delay is 7 days after deployment
+        assert_eq!(summary.rescue_start, "1970-01-08T00:00:00Z");
+        assert_eq!(summary.completed_at, None);
+    }
+
+    #[test]
+    fn private_withdrawal_window_boundaries() {
+        // Withdrawal opens at 10s and closes when cancellation opens at 30s
+        let escrow = test_escrow();
+        let config = EscrowConfig::default();
+        let at = |seconds: u64| check_timing_at(&escrow, TimingCheck::PrivateWithdrawal, seconds * 1_000_000_000, &config);
+
+        assert!(matches!(
+            at(0),
+            Err(EscrowError::OutsideTimeWindow { opens_at: 10_000_000_000, closes_at: Some(30_000_000_000), .. })
+        ));
+        assert!(matches!(
+            check_timing_at(&escrow, TimingCheck::PrivateWithdrawal, 10_000_000_000 - 1, &config),
+            Err(EscrowError::OutsideTimeWindow { .. })
+        ));
+        assert!(at(10).is_ok());
+        assert!(check_timing_at(&escrow, TimingCheck::PrivateWithdrawal, 30_000_000_000 - 1, &config).is_ok());
+        assert!(matches!(at(30), Err(EscrowError::OutsideTimeWindow { .. })));
+        assert!(matches!(at(1_000), Err(EscrowError::OutsideTimeWindow { .. })));
+
+        // The public window shares the cancellation boundary
+        let public = |seconds: u64| check_timing_at(&escrow, TimingCheck::PublicWithdrawal, seconds * 1_000_000_000, &config);
+        assert!(public(19).is_err());
+        assert!(public(20).is_ok());
+        assert!(public(30).is_err());
+    }
+
+    #[test]
+    fn destination_escrows_run_on_dst_stages() {
+        let mut escrow = test_escrow();
+        escrow.escrow_type = Some(EscrowType::Destination);
+        escrow.immutables.timelocks.public_cancellation = Some(40);
+        let config = EscrowConfig::default();
+
+        // Without dst stages the one set serves both sides, public cancellation included
+        assert!(check_timing_at(&escrow, TimingCheck::PrivateWithdrawal, 10_000_000_000, &config).is_ok());
+        assert!(check_timing_at(&escrow, TimingCheck::PublicCancellation, 40_000_000_000, &config).is_ok());
+
+        escrow.immutables.timelocks.dst = Some(types::DstTimelocks { withdrawal: 5, public_withdrawal: 8, cancellation: 15 });
+        let at = |operation, seconds: u64| check_timing_at(&escrow, operation, seconds * 1_000_000_000, &config);
+        assert!(at(TimingCheck::PrivateWithdrawal, 5).is_ok());
+        assert!(at(TimingCheck::PrivateWithdrawal, 15).is_err());
+        assert!(at(TimingCheck::Cancellation, 15).is_ok());
+        // Fusion+ has no public cancellation on the destination side
+        assert!(at(TimingCheck::PublicCancellation, 1_000).is_err());
+    }
+
+    #[test]
+    fn enforce_timelocks_only_relaxes_withdrawals() {
+        let escrow = test_escrow();
+        let config = EscrowConfig { enforce_timelocks: false, ..EscrowConfig::default() };
+
+        assert!(check_timing_at(&escrow, TimingCheck::PrivateWithdrawal, 0, &config).is_ok());
+        assert!(check_timing_at(&escrow, TimingCheck::PublicWithdrawal, 40_000_000_000, &config).is_ok());
+        assert!(matches!(
+            check_timing_at(&escrow, TimingCheck::Cancellation, 0, &config),
+            Err(EscrowError::OutsideTimeWindow { .. })
+        ));
+    }
+
+    #[test]
+    fn batch_entries_count_the_entries_accepted_before_them() {
+        storage::clear_all_storage();
+        let config = storage::get_config();
+        let mut immutables = test_escrow().immutables;
+        immutables.maker = Principal::from_slice(&[1; 29]).to_text();
+        immutables.taker = Principal::from_slice(&[2; 29]).to_text();
+        let usage = VolumeUsage { period_start: 0, used: 0, cap: Some(1_500_000), verified: false };
+        let check = |batched: u128, orders: &[(Vec<u8>, Option<u64>)]| {
+            check_batch_entry(&immutables, &EscrowType::Destination, &config, &usage, batched, orders, 0)
+        };
+
+        assert!(check(0, &[]).is_ok());
+        assert!(matches!(check(1_000_000, &[]), Err(EscrowError::VolumeCapExceeded)));
+        // Two destination escrows cannot lock funds against one order within a batch either
+        let order = (immutables.order_hash.clone(), immutables.chain_id);
+        assert!(matches!(check(0, &[order]), Err(EscrowError::DuplicateOrder)));
+    }
+
+    #[test]
+    fn pause_flag_is_kept_in_config() {
+        storage::clear_all_storage();
+        assert!(check_not_paused(&storage::get_config()).is_ok());
+
+        set_paused(true).unwrap();
+        assert!(matches!(check_not_paused(&storage::get_config()), Err(EscrowError::Paused)));
+        assert!(matches!(set_paused(true), Err(EscrowError::InvalidState)));
+
+        set_paused(false).unwrap();
+        assert!(!storage::get_config().paused);
+    }
+
+    #[test]
+    fn treasury_rotation_collects_guardian_quorum() {
+        let guardians = [Principal::from_slice(&[1]), Principal::from_slice(&[2]), Principal::from_slice(&[3])];
+        let treasury = Principal::from_slice(&[9]);
+
+        let (rotation, approvals) = approve_treasury_rotation(None, treasury, guardians[0], &guardians, 10).unwrap();
+        assert_eq!(approvals, 1);
+        // Approving twice does not count twice
+        let (rotation, approvals) = approve_treasury_rotation(Some(rotation), treasury, guardians[0], &guardians, 11).unwrap();
+        assert_eq!(approvals, 1);
+        let (rotation, approvals) = approve_treasury_rotation(Some(rotation), treasury, guardians[1], &guardians, 12).unwrap();
+        assert_eq!(approvals, 2);
+        assert_eq!(rotation.proposed_at, 10);
+
+        // Approvals of guardians removed from the config no longer count toward quorum
+        let (_, approvals) = approve_treasury_rotation(Some(rotation), treasury, guardians[2], &guardians[1..], 13).unwrap();
+        assert_eq!(approvals, 2);
+    }
+
+    #[test]
+    fn competing_treasury_rotation_waits_for_expiry() {
+        let guardians = [Principal::from_slice(&[1]), Principal::from_slice(&[2])];
+        let (treasury, rival) = (Principal::from_slice(&[9]), Principal::from_slice(&[8]));
+        let (pending, _) = approve_treasury_rotation(None, treasury, guardians[0], &guardians, 0).unwrap();
+
+        // One guardian cannot wipe the pending approvals by proposing another treasury
+        let competing = approve_treasury_rotation(Some(pending.clone()), rival, guardians[1], &guardians, TREASURY_ROTATION_TTL - 1);
+        assert!(matches!(competing, Err(EscrowError::OperationInProgress)));
+
+        // Once expired, the old approvals lapse and a new proposal starts from scratch
+        let expired = TREASURY_ROTATION_TTL;
+        let (rotation, approvals) = approve_treasury_rotation(Some(pending.clone()), rival, guardians[1], &guardians, expired).unwrap();
+        assert_eq!((rotation.new_treasury, rotation.approvals, approvals), (rival, vec![guardians[1]], 1));
+        let (rotation, approvals) = approve_treasury_rotation(Some(pending), treasury, guardians[1], &guardians, expired).unwrap();
+        assert_eq!((rotation.proposed_at, approvals), (expired, 1));
+    }
+
+    #[test]
+    fn frozen_escrow_blocks_every_outflow_check() {
+        let mut escrow = test_escrow();
+        assert!(check_not_frozen(&escrow).is_ok());
+        assert!(preflight_not_frozen(&escrow).error.is_none());
+
+        escrow.freeze = Some(EscrowFreeze {
+            reason: "reported".to_string(),
+            frozen_by: Principal::anonymous(),
+            frozen_at: 0,
+        });
+        // Withdrawals, cancellations, rescues and migration all share this check
+        assert!(matches!(check_not_frozen(&escrow), Err(EscrowError::EscrowFrozen)));
+        let check = preflight_not_frozen(&escrow);
+        assert_eq!(check.check, "freeze");
+        assert!(matches!(check.error, Some(EscrowError::EscrowFrozen)));
+    }
+
+    #[test]
+    fn only_the_direction_canceller_may_cancel() {
+        let escrow = test_escrow();
+        assert!(may_cancel(&escrow, &EscrowType::Source, "maker"));
+        assert!(may_cancel(&escrow, &EscrowType::Destination, "taker"));
+        // Being a party is not enough: the maker cannot cancel a destination escrow
+        assert!(!may_cancel(&escrow, &EscrowType::Destination, "maker"));
+    }
+
+    #[cfg(feature = "dev-mode")]
+    #[test]
+    fn advance_time_walks_through_stage_windows() {
+        storage::clear_all_storage();
+        let config = storage::get_config();
+        let escrow = test_escrow();
+        let seconds = |secs: u64| secs * 1_000_000_000;
+        // The canister clock is pinned at zero, so the simulated offset is the whole time
+        let open = |operation: TimingCheck| check_timing_at(&escrow, operation, utils::simulated_time(0), &config).is_ok();
+
+        assert!(!open(TimingCheck::PrivateWithdrawal));
+        advance_clock(seconds(10)).unwrap();
+        assert_eq!(utils::simulated_time(0), seconds(10));
+        assert!(open(TimingCheck::PrivateWithdrawal));
+        assert!(!open(TimingCheck::PublicWithdrawal));
+
+        advance_clock(seconds(10)).unwrap();
+        assert!(open(TimingCheck::PublicWithdrawal));
+        assert!(!open(TimingCheck::Cancellation));
+
+        // Cancellation closes both withdrawal windows
+        advance_clock(seconds(10)).unwrap();
+        assert!(open(TimingCheck::Cancellation));
+        assert!(!open(TimingCheck::PrivateWithdrawal));
+        assert!(!open(TimingCheck::PublicWithdrawal));
+        assert!(!open(TimingCheck::Rescue));
+
+        advance_clock(config.rescue_delay).unwrap();
+        assert!(open(TimingCheck::Rescue));
+    }
+
+    #[cfg(not(feature = "dev-mode"))]
+    #[test]
+    fn advance_time_requires_dev_mode() {
+        assert!(matches!(advance_clock(1_000), Err(EscrowError::DevModeDisabled)));
+        assert_eq!(utils::simulated_time(5), 5);
+    }
+}
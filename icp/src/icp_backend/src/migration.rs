@@ -0,0 +1,125 @@
+use candid::Principal;
+use ic_cdk::call::Call;
+use ic_cdk::management_canister::{
+    ecdsa_public_key, sign_with_ecdsa, EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgs, SignWithEcdsaArgs,
+};
+use serde_bytes::ByteBuf;
+
+use crate::types::{EscrowError, MigrationSnapshot, Result};
+use crate::utils;
+
+/// Method a successor canister implements to take over a migrated escrow
+const RECEIVE_MIGRATED_ESCROW: &str = "receive_migrated_escrow";
+
+/// Derivation path separating migration signatures from any other threshold ECDSA use
+const MIGRATION_CONTEXT: &[u8] = b"avginch/escrow-migration";
+
+pub fn key_id(key_name: &str) -> EcdsaKeyId {
+    EcdsaKeyId {
+        curve: EcdsaCurve::Secp256k1,
+        name: key_name.to_string(),
+    }
+}
+
+/// Digest the successor recomputes before checking the signature: SHA-256 of the Candid-encoded snapshot
+pub fn snapshot_digest(snapshot: &MigrationSnapshot) -> Vec<u8> {
+    utils::sha256(&candid::encode_one(snapshot).expect("candid encoding cannot fail"))
+}
+
+/// Public key successors verify migration signatures against
+pub async fn public_key(key_name: &str) -> Result<Vec<u8>> {
+    let args = EcdsaPublicKeyArgs {
+        canister_id: None,
+        derivation_path: vec![MIGRATION_CONTEXT.to_vec()],
+        key_id: key_id(key_name),
+    };
+
+    ecdsa_public_key(&args)
+        .await
+        .map(|result| result.public_key)
+        .map_err(|_| EscrowError::CanisterCallError)
+}
+
+/// Threshold ECDSA signature over the snapshot digest
+pub async fn sign_snapshot(snapshot: &MigrationSnapshot, key_name: &str) -> Result<Vec<u8>> {
+    let args = SignWithEcdsaArgs {
+        message_hash: snapshot_digest(snapshot),
+        derivation_path: vec![MIGRATION_CONTEXT.to_vec()],
+        key_id: key_id(key_name),
+    };
+
+    sign_with_ecdsa(&args)
+        .await
+        .map(|result| result.signature)
+        .map_err(|_| EscrowError::CanisterCallError)
+}
+
+/// Hand the signed snapshot to `receive_migrated_escrow(snapshot, signature)` on the successor
+pub async fn deliver(successor: Principal, snapshot: &MigrationSnapshot, signature: &[u8]) -> Result<()> {
+    let args = (snapshot, ByteBuf::from(signature.to_vec()));
+    let response = Call::unbounded_wait(successor, RECEIVE_MIGRATED_ESCROW)
+        .with_args(&args)
+        .await
+        .map_err(|_| EscrowError::CanisterCallError)?;
+
+    match response.candid::<std::result::Result<(), String>>() {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(reason)) => {
+            ic_cdk::api::debug_print(format!("Successor rejected migrated escrow: {}", reason));
+            Err(EscrowError::MigrationRejected)
+        }
+        Err(_) => Err(EscrowError::CanisterCallError),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{EscrowImmutables, Timelocks};
+
+    fn snapshot(amount: u128) -> MigrationSnapshot {
+        MigrationSnapshot {
+            immutables: EscrowImmutables {
+                order_hash: vec![0; 32],
+                hashlock: vec![1; 32],
+                maker: "maker".to_string(),
+                taker: "taker".to_string(),
+                token: "0x0".to_string(),
+                amount,
+                safety_deposit: 100_000,
+                timelocks: Timelocks { withdrawal: 10, public_withdrawal: 20, cancellation: 30, public_cancellation: None, deployed_at: 0, dst: None },
+                maker_min_amount: None,
+                min_received: None,
+                safety_deposit_funding: None,
+                chain_id: None,
+                icp_token: None,
+                hash_algorithm: None,
+                merkle_root: None,
+                parts_count: None,
+                auction: None,
+                metadata: None,
+            },
+            created_at: 1,
+            icp_tx_hash: None,
+            evm_address: None,
+            deposit_blocks: vec![3],
+            creation_fee_block: None,
+            safety_deposit_held: 100_000,
+            funds_transferred: amount + 100_000,
+            funds_memo: 7,
+            source_canister: Principal::anonymous(),
+            successor: Principal::management_canister(),
+            migrated_at: 2,
+        }
+    }
+
+    #[test]
+    fn test_snapshot_digest_binds_every_field() {
+        let digest = snapshot_digest(&snapshot(1_000_000));
+        assert_eq!(digest.len(), 32);
+        assert_eq!(digest, snapshot_digest(&snapshot(1_000_000)));
+        assert_ne!(digest, snapshot_digest(&snapshot(1_000_001)));
+        let redirected = MigrationSnapshot { successor: Principal::anonymous(), ..snapshot(1_000_000) };
+        assert_ne!(digest, snapshot_digest(&redirected));
+    }
+}
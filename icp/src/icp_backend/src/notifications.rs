@@ -0,0 +1,34 @@
+use candid::Principal;
+use ic_cdk::call::Call;
+
+/// Method invoked on maker canisters once their escrow settles
+const ON_ESCROW_COMPLETED: &str = "on_escrow_completed";
+
+/// Whether a principal is a canister ID (an opaque ID) rather than a user or derived principal
+pub fn is_canister_principal(principal: &Principal) -> bool {
+    principal.as_slice().last() == Some(&0x01)
+}
+
+/// Fire-and-forget `on_escrow_completed(hashlock, amount)` to a maker canister.
+/// Failures are only logged; settlement never depends on the maker's reaction.
+pub fn notify_escrow_completed(maker: Principal, hashlock: &[u8], amount: u128) {
+    let args = (serde_bytes::ByteBuf::from(hashlock.to_vec()), amount);
+    if let Err(e) = Call::unbounded_wait(maker, ON_ESCROW_COMPLETED).with_args(&args).oneway() {
+        ic_cdk::api::debug_print(format!("Maker notification error: {:?}", e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canister_principal_detection() {
+        let ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+        assert!(is_canister_principal(&ledger));
+        assert!(!is_canister_principal(&Principal::anonymous()));
+        assert!(!is_canister_principal(&Principal::management_canister()));
+        let user = Principal::self_authenticating([7u8; 32]);
+        assert!(!is_canister_principal(&user));
+    }
+}
@@ -0,0 +1,220 @@
+use candid::Principal;
+
+use crate::guards;
+use crate::storage;
+use crate::types::{
+    EscrowConfig, EscrowError, EscrowEvent, EscrowImmutables, OrderIntent, OrderStatus, Result, SwapOrder, SwapSession,
+    MAX_OPEN_ORDERS_PER_MAKER, MAX_ORDER_LIFETIME,
+};
+
+/// Resolver the legs of an announced order are checked against until a real one accepts it
+fn stand_in_resolver() -> Principal {
+    Principal::management_canister()
+}
+
+/// Checks `create_linked_pair` will run on the legs, so an order that could never be filled is not announced
+fn check_legs(src: &EscrowImmutables, dst: &EscrowImmutables, config: &EscrowConfig) -> Result<()> {
+    guards::check_immutables(src)?;
+    guards::check_immutables(dst)?;
+    SwapSession::validate_legs(src, dst)?;
+    dst.validate_dst(config, Some(src))
+}
+
+/// Record a swap intent signed for by `maker`. The maker must receive the ICP leg and must have
+/// linked, by signature, the EVM address the source funds come from.
+pub fn announce(maker: Principal, intent: OrderIntent, now: u64) -> Result<SwapOrder> {
+    if maker == Principal::anonymous() || intent.dst_immutables.maker != maker.to_text() {
+        return Err(EscrowError::InvalidCaller);
+    }
+    let link = storage::get_evm_link(&maker).ok_or(EscrowError::Unauthorized)?;
+    if !link.address.eq_ignore_ascii_case(&intent.src_immutables.maker) {
+        return Err(EscrowError::InvalidCaller);
+    }
+    if intent.expires_at <= now || intent.expires_at - now > MAX_ORDER_LIFETIME {
+        return Err(EscrowError::InvalidTime);
+    }
+    if storage::count_open_orders(&maker) >= MAX_OPEN_ORDERS_PER_MAKER {
+        return Err(EscrowError::TooManyOrders);
+    }
+
+    let mut order = SwapOrder { id: 0, maker, intent, announced_at: now, status: OrderStatus::Open };
+    let (src, dst) = order.legs_for(&stand_in_resolver(), &link.address, now);
+    check_legs(&src, &dst, &storage::get_config())?;
+    if storage::order_conflicts(&dst.order_hash, &dst.hashlock) {
+        return Err(EscrowError::DuplicateOrder);
+    }
+    if storage::get_escrow(&dst.hashlock).is_some() {
+        return Err(EscrowError::DuplicateEscrow);
+    }
+
+    order = storage::insert_order(order);
+    storage::add_event(EscrowEvent::OrderAnnounced {
+        hashlock: order.hashlock().to_vec(),
+        order_id: order.id,
+        maker,
+        expires_at: order.intent.expires_at,
+        timestamp: now,
+    });
+    Ok(order)
+}
+
+/// Withdraw an open order (maker only)
+pub fn cancel(caller: Principal, order_id: u64, now: u64) -> Result<SwapOrder> {
+    let order = storage::get_order(order_id).ok_or(EscrowError::OrderNotFound)?;
+    if order.maker != caller {
+        return Err(EscrowError::InvalidCaller);
+    }
+    if order.status != OrderStatus::Open {
+        return Err(EscrowError::InvalidState);
+    }
+
+    let order = storage::update_order(order_id, |order| order.status = OrderStatus::Cancelled { at: now })?;
+    storage::add_event(EscrowEvent::OrderCancelled {
+        hashlock: order.hashlock().to_vec(),
+        order_id,
+        timestamp: now,
+    });
+    Ok(order)
+}
+
+/// Fill an open order as `resolver`: deploy the ICP destination escrow and record the source leg
+/// the resolver deploys from its linked EVM address, as `create_linked_pair` would
+pub async fn accept(resolver: Principal, order_id: u64, now: u64) -> Result<SwapSession> {
+    if !storage::is_resolver(&resolver) {
+        return Err(EscrowError::Unauthorized);
+    }
+    let order = storage::get_order(order_id).ok_or(EscrowError::OrderNotFound)?;
+    if order.status != OrderStatus::Open {
+        return Err(EscrowError::InvalidState);
+    }
+    if !order.is_open(now) {
+        return Err(EscrowError::DeadlineExceeded);
+    }
+    let link = storage::get_evm_link(&resolver).ok_or(EscrowError::InvalidAddress)?;
+    let (src, dst) = order.legs_for(&resolver, &link.address, now);
+
+    // Claim the order before funding the escrow so no other resolver can accept it meanwhile
+    storage::update_order(order_id, |order| order.status = OrderStatus::Accepted { resolver, at: now })?;
+    match crate::create_linked_pair(src, dst).await {
+        Ok(session) => {
+            storage::add_event(EscrowEvent::OrderAccepted {
+                hashlock: session.hashlock.clone(),
+                order_id,
+                resolver,
+                timestamp: now,
+            });
+            Ok(session)
+        }
+        Err(err) => {
+            storage::update_order(order_id, |order| order.status = OrderStatus::Open)?;
+            Err(err)
+        }
+    }
+}
+
+/// Close up to `limit` open orders that passed their expiry unaccepted
+pub fn expire(now: u64, limit: usize) {
+    for order in storage::get_expired_orders(now, limit) {
+        if storage::update_order(order.id, |order| order.status = OrderStatus::Expired { at: now }).is_ok() {
+            storage::add_event(EscrowEvent::OrderExpired {
+                hashlock: order.hashlock().to_vec(),
+                order_id: order.id,
+                timestamp: now,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{EvmLink, Timelocks};
+
+    const MAKER_EVM: &str = "0x1111111111111111111111111111111111111111";
+    const NOW: u64 = 1_000_000_000_000;
+
+    fn maker() -> Principal {
+        Principal::from_slice(&[1; 29])
+    }
+
+    fn leg(maker: &str, chain_id: Option<u64>, cancellation: u64) -> EscrowImmutables {
+        EscrowImmutables {
+            order_hash: vec![4; 32],
+            hashlock: vec![5; 32],
+            maker: maker.to_string(),
+            taker: String::new(),
+            token: "0x0".to_string(),
+            amount: 1_000_000,
+            safety_deposit: 100_000,
+            timelocks: Timelocks { withdrawal: 60, public_withdrawal: 600, cancellation, public_cancellation: None, deployed_at: 0, dst: None },
+            maker_min_amount: None,
+            min_received: None,
+            safety_deposit_funding: None,
+            chain_id,
+            icp_token: None,
+            hash_algorithm: None,
+            merkle_root: None,
+            parts_count: None,
+            auction: None,
+            metadata: None,
+        }
+    }
+
+    fn intent(expires_at: u64) -> OrderIntent {
+        OrderIntent {
+            src_immutables: leg(MAKER_EVM, Some(1), 7_200),
+            dst_immutables: leg(&maker().to_text(), None, 3_600),
+            expires_at,
+        }
+    }
+
+    fn setup() {
+        storage::clear_all_storage();
+        storage::set_evm_link(maker(), EvmLink { address: MAKER_EVM.to_string(), linked_at: 0 }).unwrap();
+    }
+
+    #[test]
+    fn test_announce_requires_linked_source_maker() {
+        setup();
+        let mut foreign = intent(NOW + 60_000_000_000);
+        foreign.src_immutables.maker = "0x2222222222222222222222222222222222222222".to_string();
+        assert!(matches!(announce(maker(), foreign, NOW), Err(EscrowError::InvalidCaller)));
+        assert!(matches!(announce(maker(), intent(NOW), NOW), Err(EscrowError::InvalidTime)));
+        assert!(matches!(
+            announce(maker(), intent(NOW + MAX_ORDER_LIFETIME + 1), NOW),
+            Err(EscrowError::InvalidTime)
+        ));
+
+        let order = announce(maker(), intent(NOW + 60_000_000_000), NOW).unwrap();
+        assert_eq!(order.status, OrderStatus::Open);
+        assert!(matches!(announce(maker(), intent(NOW + 60_000_000_000), NOW), Err(EscrowError::DuplicateOrder)));
+    }
+
+    #[test]
+    fn test_open_orders_expire_and_cancel() {
+        setup();
+        let order = announce(maker(), intent(NOW + 60_000_000_000), NOW).unwrap();
+        assert_eq!(storage::get_open_orders(None, NOW, 10).len(), 1);
+        assert!(storage::get_open_orders(Some(order.id), NOW, 10).is_empty());
+
+        // Past expiry the order is hidden from resolvers before the timer closes it
+        let later = NOW + 61_000_000_000;
+        assert!(storage::get_open_orders(None, later, 10).is_empty());
+        expire(later, 10);
+        assert_eq!(storage::get_order(order.id).unwrap().status, OrderStatus::Expired { at: later });
+        assert!(matches!(cancel(maker(), order.id, later), Err(EscrowError::InvalidState)));
+    }
+
+    #[test]
+    fn test_accepted_legs_carry_resolver() {
+        setup();
+        let order = announce(maker(), intent(NOW + 60_000_000_000), NOW).unwrap();
+        let resolver = Principal::from_slice(&[2; 29]);
+        let resolver_evm = "0x3333333333333333333333333333333333333333";
+        let (src, dst) = order.legs_for(&resolver, resolver_evm, NOW + 1);
+        assert_eq!(src.taker, resolver_evm);
+        assert_eq!(dst.taker, resolver.to_text());
+        assert_eq!(src.timelocks.deployed_at, NOW + 1);
+        assert!(check_legs(&src, &dst, &storage::get_config()).is_ok());
+    }
+}
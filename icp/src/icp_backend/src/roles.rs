@@ -0,0 +1,91 @@
+use candid::Principal;
+
+use crate::storage;
+use crate::types::{EscrowError, EscrowEvent, Result, Role};
+
+/// Whether `principal` holds `role`. The treasury is always an Admin, and Admins hold every role.
+pub fn has_role(principal: &Principal, role: Role) -> bool {
+    if *principal == storage::get_config().treasury {
+        return true;
+    }
+    let held = storage::get_roles(principal);
+    held.contains(&Role::Admin) || held.contains(&role)
+}
+
+/// Fail with `Unauthorized` unless `principal` holds `role`
+pub fn require(principal: &Principal, role: Role) -> Result<()> {
+    if has_role(principal, role) {
+        Ok(())
+    } else {
+        Err(EscrowError::Unauthorized)
+    }
+}
+
+/// Roles `principal` holds, counting the treasury's implicit Admin
+pub fn effective_roles(principal: &Principal) -> Vec<Role> {
+    let mut roles = storage::get_roles(principal);
+    if *principal == storage::get_config().treasury && !roles.contains(&Role::Admin) {
+        roles.insert(0, Role::Admin);
+    }
+    roles
+}
+
+/// Grant `role` to `principal` (admins only); granting a held role is a no-op
+pub fn grant(caller: Principal, principal: Principal, role: Role, now: u64) -> Result<()> {
+    require(&caller, Role::Admin)?;
+    if principal == Principal::anonymous() {
+        return Err(EscrowError::InvalidAddress);
+    }
+
+    if storage::grant_role(principal, role) {
+        storage::add_event(EscrowEvent::RoleGranted { principal, role, granted_by: caller, timestamp: now });
+    }
+    Ok(())
+}
+
+/// Revoke `role` from `principal` (admins only). The treasury's implicit Admin cannot be
+/// revoked; rotate the treasury instead.
+pub fn revoke(caller: Principal, principal: Principal, role: Role, now: u64) -> Result<()> {
+    require(&caller, Role::Admin)?;
+
+    if storage::revoke_role(&principal, role) {
+        storage::add_event(EscrowEvent::RoleRevoked { principal, role, revoked_by: caller, timestamp: now });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn principal(byte: u8) -> Principal {
+        Principal::from_slice(&[byte; 29])
+    }
+
+    #[test]
+    fn test_treasury_is_implicit_admin() {
+        storage::clear_all_storage();
+        let treasury = storage::get_config().treasury;
+        assert!(has_role(&treasury, Role::Pauser));
+        assert_eq!(effective_roles(&treasury), vec![Role::Admin]);
+        assert!(matches!(require(&principal(1), Role::Operator), Err(EscrowError::Unauthorized)));
+    }
+
+    #[test]
+    fn test_roles_are_separate_and_admin_implies_all() {
+        storage::clear_all_storage();
+        let treasury = storage::get_config().treasury;
+        grant(treasury, principal(1), Role::Pauser, 0).unwrap();
+        assert!(has_role(&principal(1), Role::Pauser));
+        assert!(!has_role(&principal(1), Role::FeeManager));
+
+        // Only admins grant, so a pauser cannot escalate itself
+        assert!(matches!(grant(principal(1), principal(1), Role::Admin, 0), Err(EscrowError::Unauthorized)));
+
+        grant(treasury, principal(2), Role::Admin, 0).unwrap();
+        assert!(has_role(&principal(2), Role::FeeManager));
+        revoke(principal(2), principal(1), Role::Pauser, 0).unwrap();
+        assert!(!has_role(&principal(1), Role::Pauser));
+        assert!(storage::get_role_assignments().iter().all(|assignment| assignment.principal != principal(1)));
+    }
+}
@@ -1,31 +1,430 @@
 use candid::{CandidType, Deserialize, Principal};
-use std::collections::HashMap;
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell, StableLog, Storable};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 
-use crate::types::{ICPEscrow, EscrowConfig, EscrowEvent, EscrowError, Result};
+use crate::certification;
+use crate::icrc3;
+use crate::ledger;
+use crate::legacy::{
+    LegacyEscrow, LegacyEventRecord, LegacyMetrics, LegacyPendingTransfer, LegacySession, LegacySwapOrder, LegacyTemplate,
+};
+use crate::utils;
+use crate::types::{
+    CycleReport, ICPEscrow, EscrowConfig, EscrowEvent, EscrowFilter, EscrowListPage, EscrowPage, EvmLink, EscrowState, EventLogPage, EventRecord, LoggedEvent, EscrowError, FeeReport, MetricsResetScope,
+    ChainConfig, EscrowTemplate, ObserverGrant, ObserverScope, ParticipantProfile, OrderStatus, PendingTransfer, PendingTreasuryRotation, ResolverRegistration, Result, Role, RoleAssignment, SwapOrder, SwapSession, VersionedConfig, CONFIG_SCHEMA_VERSION,
+};
 
-/// Storage for escrows indexed by hashlock
-static mut ESCROWS: Option<HashMap<Vec<u8>, ICPEscrow>> = None;
+type Memory = VirtualMemory<DefaultMemoryImpl>;
 
-/// Storage for configuration
-static mut CONFIG: Option<EscrowConfig> = None;
+/// Stable memory regions (ids must never be reused or reordered)
+const PROCESSED_REQUESTS_MEMORY_ID: MemoryId = MemoryId::new(0);
+const PROCESSED_REQUESTS_EXPIRY_MEMORY_ID: MemoryId = MemoryId::new(1);
+const STATE_COMMITMENTS_MEMORY_ID: MemoryId = MemoryId::new(2);
+const CONFIG_MEMORY_ID: MemoryId = MemoryId::new(3);
+const EVENT_ARCHIVE_INDEX_MEMORY_ID: MemoryId = MemoryId::new(4);
+const EVENT_ARCHIVE_DATA_MEMORY_ID: MemoryId = MemoryId::new(5);
+const METRICS_HISTORY_MEMORY_ID: MemoryId = MemoryId::new(6);
+const DAILY_ACTIVITY_MEMORY_ID: MemoryId = MemoryId::new(7);
+const ESCROWS_MEMORY_ID: MemoryId = MemoryId::new(8);
+const UPGRADE_STATE_MEMORY_ID: MemoryId = MemoryId::new(9);
+const PENDING_TRANSFERS_MEMORY_ID: MemoryId = MemoryId::new(10);
+const ORDERS_MEMORY_ID: MemoryId = MemoryId::new(11);
+const BLOCK_HASHES_MEMORY_ID: MemoryId = MemoryId::new(12);
+const HASHLOCK_EVENTS_MEMORY_ID: MemoryId = MemoryId::new(13);
+const HASHLOCK_EVENTS_INDEXED_MEMORY_ID: MemoryId = MemoryId::new(14);
 
-/// Storage for authorized principals (who can perform public operations)
-static mut AUTHORIZED_PRINCIPALS: Option<Vec<Principal>> = None;
+/// Store Candid-encodable records in stable structures
+macro_rules! impl_candid_storable {
+    ($type:ty) => {
+        impl Storable for $type {
+            fn to_bytes(&self) -> Cow<'_, [u8]> {
+                Cow::Owned(candid::encode_one(self).expect("candid encoding cannot fail"))
+            }
+
+            fn from_bytes(bytes: Cow<[u8]>) -> Self {
+                candid::decode_one(&bytes).expect("stored record must decode")
+            }
 
-/// Storage for events log
-static mut EVENTS: Option<Vec<EscrowEvent>> = None;
+            const BOUND: Bound = Bound::Unbounded;
+        }
+    };
+    // Records written while amounts were nat64 decode as `$legacy` and are widened
+    ($type:ty, $legacy:ty) => {
+        impl Storable for $type {
+            fn to_bytes(&self) -> Cow<'_, [u8]> {
+                Cow::Owned(candid::encode_one(self).expect("candid encoding cannot fail"))
+            }
 
-/// Storage for metrics
-static mut METRICS: Option<EscrowMetrics> = None;
+            fn from_bytes(bytes: Cow<[u8]>) -> Self {
+                candid::decode_one(&bytes)
+                    .or_else(|_| candid::decode_one::<$legacy>(&bytes).map(<$type>::from))
+                    .expect("stored record must decode")
+            }
 
-#[derive(CandidType, Deserialize, Clone, Debug)]
+            const BOUND: Bound = Bound::Unbounded;
+        }
+    };
+}
+
+/// Maximum number of snapshots returned by a history query
+pub const MAX_METRICS_HISTORY: usize = 500;
+
+/// Longest range of days a single activity query may span
+pub const MAX_ACTIVITY_DAYS: u64 = 366;
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// Number of events kept in the hot ring once GC has caught up
+pub const MAX_EVENTS: usize = 1000;
+
+thread_local! {
+    /// Manager splitting stable memory into independent regions
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+    /// Processed request keys mapped to their expiry time (survives upgrades)
+    static PROCESSED_REQUESTS: RefCell<StableBTreeMap<[u8; 32], u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(PROCESSED_REQUESTS_MEMORY_ID)));
+
+    /// Expiry index over processed requests, ordered for TTL cleanup
+    static PROCESSED_REQUESTS_EXPIRY: RefCell<StableBTreeMap<(u64, [u8; 32]), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(PROCESSED_REQUESTS_EXPIRY_MEMORY_ID)));
+
+    /// History of periodic escrow state-root commitments (survives upgrades)
+    static STATE_COMMITMENTS: RefCell<StableBTreeMap<u64, StateCommitment, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(STATE_COMMITMENTS_MEMORY_ID)));
+
+    /// Periodic metrics snapshots keyed by timestamp (survives upgrades)
+    static METRICS_HISTORY: RefCell<StableBTreeMap<u64, MetricsSnapshot, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(METRICS_HISTORY_MEMORY_ID)));
+
+    /// Escrow activity counters keyed by day number (survives upgrades)
+    static DAILY_ACTIVITY: RefCell<StableBTreeMap<u64, DailyActivity, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(DAILY_ACTIVITY_MEMORY_ID)));
+
+    /// Storage for escrows indexed by hashlock (survives upgrades)
+    static ESCROWS: RefCell<StableBTreeMap<Vec<u8>, ICPEscrow, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(ESCROWS_MEMORY_ID)));
+
+    /// Settlement transfers awaiting a retry, by queue id (survives upgrades)
+    static PENDING_TRANSFERS: RefCell<StableBTreeMap<u64, PendingTransfer, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(PENDING_TRANSFERS_MEMORY_ID)));
+
+    /// Swap orders announced by makers, by order id (survives upgrades)
+    static ORDERS: RefCell<StableBTreeMap<u64, SwapOrder, Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(ORDERS_MEMORY_ID)));
+
+    /// ICRC-3 block hashes by block index, one per archived event (survives upgrades)
+    static BLOCK_HASHES: RefCell<StableBTreeMap<u64, [u8; 32], Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(BLOCK_HASHES_MEMORY_ID)));
+
+    /// Sequence numbers of each escrow's events, keyed by (hashlock, seq) (survives upgrades)
+    static HASHLOCK_EVENTS: RefCell<StableBTreeMap<([u8; 32], u64), (), Memory>> =
+        RefCell::new(StableBTreeMap::init(get_memory(HASHLOCK_EVENTS_MEMORY_ID)));
+
+    /// Sequence number up to which the archive has been added to `HASHLOCK_EVENTS`
+    static HASHLOCK_EVENTS_INDEXED: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(get_memory(HASHLOCK_EVENTS_INDEXED_MEMORY_ID), 0)
+            .expect("failed to initialize hashlock event index cell")
+    );
+
+    /// Heap state written by `pre_upgrade` and restored by `post_upgrade` (Candid-encoded)
+    static UPGRADE_STATE: RefCell<StableCell<Vec<u8>, Memory>> = RefCell::new(
+        StableCell::init(get_memory(UPGRADE_STATE_MEMORY_ID), Vec::new())
+            .expect("failed to initialize upgrade state cell")
+    );
+
+    /// Versioned config envelope persisted across upgrades (CBOR-encoded)
+    static STORED_CONFIG: RefCell<StableCell<Vec<u8>, Memory>> = RefCell::new(
+        StableCell::init(get_memory(CONFIG_MEMORY_ID), Vec::new())
+            .expect("failed to initialize config cell")
+    );
+
+    /// Append-only archive of every event, indexed by sequence number (survives upgrades)
+    static EVENT_ARCHIVE: RefCell<StableLog<EventRecord, Memory, Memory>> = RefCell::new(
+        StableLog::init(
+            get_memory(EVENT_ARCHIVE_INDEX_MEMORY_ID),
+            get_memory(EVENT_ARCHIVE_DATA_MEMORY_ID),
+        )
+        .expect("failed to initialize event archive")
+    );
+}
+
+thread_local! {
+    /// Escrow updates since the last state commitment
+    static UPDATES_SINCE_COMMITMENT: Cell<u64> = const { Cell::new(0) };
+
+    /// Storage for configuration
+    static CONFIG: RefCell<EscrowConfig> = RefCell::new(EscrowConfig::default());
+
+    /// Storage for authorized principals (who can perform public operations)
+    static AUTHORIZED_PRINCIPALS: RefCell<Vec<Principal>> = const { RefCell::new(Vec::new()) };
+
+    /// Read-only observer tokens, keyed by the observing principal
+    static OBSERVERS: RefCell<HashMap<Principal, ObserverGrant>> = RefCell::new(HashMap::new());
+
+    /// Roles granted on top of the treasury's implicit Admin, by principal
+    static ROLES: RefCell<HashMap<Principal, BTreeSet<Role>>> = RefCell::new(HashMap::new());
+
+    /// Staked resolvers allowed to execute public withdrawals, by principal
+    static RESOLVERS: RefCell<HashMap<Principal, ResolverRegistration>> = RefCell::new(HashMap::new());
+
+    /// Treasury-registered escrow templates, by template ID
+    static TEMPLATES: RefCell<HashMap<String, EscrowTemplate>> = RefCell::new(HashMap::new());
+
+    /// Treasury-set finality parameters, by EVM chain id
+    static CHAIN_CONFIGS: RefCell<HashMap<u64, ChainConfig>> = RefCell::new(HashMap::new());
+
+    /// Swap sessions linking both legs of a swap, keyed by hashlock
+    static SESSIONS: RefCell<HashMap<Vec<u8>, SwapSession>> = RefCell::new(HashMap::new());
+
+    /// EVM addresses principals have linked by signature
+    static EVM_LINKS: RefCell<HashMap<Principal, EvmLink>> = RefCell::new(HashMap::new());
+
+    /// Creation volume per principal in its latest period: (period index, volume)
+    static VOLUMES: RefCell<HashMap<Principal, (u64, u128)>> = RefCell::new(HashMap::new());
+
+    /// Opt-in display profiles, keyed by principal text (as stored in escrow immutables)
+    static PROFILES: RefCell<HashMap<String, ParticipantProfile>> = RefCell::new(HashMap::new());
+
+    /// Hot ring of recent events (oldest first; trimmed by the event GC timer)
+    static EVENTS: RefCell<VecDeque<EventRecord>> = const { RefCell::new(VecDeque::new()) };
+
+    /// Event GC activity, reported in storage stats
+    static EVENTS_PRUNED_TOTAL: Cell<u64> = const { Cell::new(0) };
+    static LAST_EVENT_GC_AT: Cell<Option<u64>> = const { Cell::new(None) };
+
+    /// Creation fees held in the fee subaccount and not yet withdrawn (e8s)
+    static FEE_BALANCE: Cell<u64> = const { Cell::new(0) };
+
+    /// Treasury rotation collecting guardian approvals
+    static PENDING_TREASURY_ROTATION: RefCell<Option<PendingTreasuryRotation>> = const { RefCell::new(None) };
+
+    /// Sequence number assigned to the next event
+    static NEXT_EVENT_SEQ: Cell<u64> = const { Cell::new(0) };
+
+    /// Storage for metrics
+    static METRICS: RefCell<EscrowMetrics> = RefCell::new(EscrowMetrics::default());
+
+    /// Hashlocks with an async operation in flight (see `guards::EscrowLock`)
+    static LOCKED_ESCROWS: RefCell<HashSet<Vec<u8>>> = RefCell::new(HashSet::new());
+
+    /// Secondary indexes over `ESCROWS`; derived from it, so rebuilt after an upgrade
+    static ESCROW_INDEXES: RefCell<EscrowIndexes> = RefCell::new(EscrowIndexes::default());
+}
+
+/// Hashlocks of stored escrows grouped by state, maker, taker, order hash and legacy memo
+#[derive(Default)]
+struct EscrowIndexes {
+    by_state: HashMap<EscrowState, BTreeSet<Vec<u8>>>,
+    by_maker: HashMap<String, BTreeSet<Vec<u8>>>,
+    by_taker: HashMap<String, BTreeSet<Vec<u8>>>,
+    by_order_hash: HashMap<Vec<u8>, BTreeSet<Vec<u8>>>,
+    by_memo: HashMap<u64, BTreeSet<Vec<u8>>>, // Keyed by `ledger::memo_prefix`
+}
+
+/// Index key of the 8-byte memos an escrow's native ICP transfers carry
+fn memo_key(hashlock: &[u8]) -> u64 {
+    ledger::memo_prefix(ledger::generate_transfer_memo(ledger::TransferOperation::Deposit, hashlock))
+}
+
+impl EscrowIndexes {
+    fn insert(&mut self, hashlock: &[u8], escrow: &ICPEscrow) {
+        self.by_state.entry(escrow.state.clone()).or_default().insert(hashlock.to_vec());
+        self.by_maker.entry(escrow.immutables.maker.clone()).or_default().insert(hashlock.to_vec());
+        self.by_taker.entry(escrow.immutables.taker.clone()).or_default().insert(hashlock.to_vec());
+        self.by_order_hash.entry(escrow.immutables.order_hash.clone()).or_default().insert(hashlock.to_vec());
+        self.by_memo.entry(memo_key(hashlock)).or_default().insert(hashlock.to_vec());
+    }
+
+    fn remove(&mut self, hashlock: &[u8], escrow: &ICPEscrow) {
+        unindex(&mut self.by_state, &escrow.state, hashlock);
+        unindex(&mut self.by_maker, &escrow.immutables.maker, hashlock);
+        unindex(&mut self.by_taker, &escrow.immutables.taker, hashlock);
+        unindex(&mut self.by_order_hash, &escrow.immutables.order_hash, hashlock);
+        unindex(&mut self.by_memo, &memo_key(hashlock), hashlock);
+    }
+}
+
+/// Drop `hashlock` from the set under `key`, and the set once it is empty
+fn unindex<K>(index: &mut HashMap<K, BTreeSet<Vec<u8>>>, key: &K, hashlock: &[u8])
+where
+    K: std::hash::Hash + Eq,
+{
+    if let Some(hashlocks) = index.get_mut(key) {
+        hashlocks.remove(hashlock);
+        if hashlocks.is_empty() {
+            index.remove(key);
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+#[serde(default)] // Snapshots taken before a counter existed decode it as zero
 pub struct EscrowMetrics {
     pub total_escrows_created: u64,
     pub total_escrows_completed: u64,
     pub total_escrows_cancelled: u64,
-    pub total_volume_icp: u64,           // Total ICP volume processed
-    pub total_fees_collected: u64,       // Total fees collected
+    #[serde(with = "utils::wide_amount")]
+    pub total_volume_icp: u128,          // Total volume processed, in base units of the locked tokens
+    #[serde(with = "utils::wide_amount")]
+    pub total_fees_collected: u128,      // Total fees collected
+    #[serde(with = "utils::wide_amount")]
+    pub total_surplus_collected: u128,   // Total surplus captured for treasury
     pub active_escrows_count: u64,       // Currently active escrows
+    #[serde(with = "utils::wide_amount::option")]
+    pub total_cycles_deposited: Option<u128>, // Cycles accepted through deposit_cycles; optional so archived MetricsReset events still decode
+}
+
+/// Audit digest of the full canister state
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct StateDigest {
+    pub digest: Vec<u8>,
+    pub escrow_count: u64,
+    pub authorized_principals_count: u64,
+}
+
+/// Hash commitment over the full escrow map at a point in time
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct StateCommitment {
+    pub index: u64,
+    pub timestamp: u64,
+    pub escrows_root: Vec<u8>,     // Root hash of the certified ["escrows"] subtree
+    pub escrow_count: u64,
+}
+
+impl_candid_storable!(StateCommitment);
+impl_candid_storable!(DailyActivity);
+impl_candid_storable!(EventRecord, LegacyEventRecord);
+impl_candid_storable!(PendingTransfer, LegacyPendingTransfer);
+impl_candid_storable!(SwapOrder, LegacySwapOrder);
+
+/// The secret as escrows written before `revealed_secret` stored it
+#[derive(CandidType, Deserialize)]
+struct LegacySecret {
+    secret_hash: Option<Vec<u8>>,
+}
+
+// Escrow fields added later must be `Option`s so records written earlier still decode
+impl Storable for ICPEscrow {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).expect("candid encoding cannot fail"))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let mut escrow: ICPEscrow = candid::decode_one(&bytes)
+            .or_else(|_| candid::decode_one::<LegacyEscrow>(&bytes).map(ICPEscrow::from))
+            .expect("stored record must decode");
+        // Completed escrows written earlier keep their secret under the old field name
+        if escrow.state == EscrowState::Completed && escrow.revealed_secret.is_none() {
+            if let Ok(legacy) = candid::decode_one::<LegacySecret>(&bytes) {
+                escrow.revealed_secret = legacy.secret_hash;
+            }
+        }
+        escrow
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Heap-resident state carried across an upgrade; fields added later must be `Option`s
+#[derive(CandidType, Deserialize, Default)]
+struct UpgradeState {
+    metrics: EscrowMetrics,
+    authorized_principals: Vec<Principal>,
+    observers: HashMap<Principal, ObserverGrant>,
+    resolvers: HashMap<Principal, ResolverRegistration>,
+    templates: HashMap<String, EscrowTemplate>,
+    chain_configs: HashMap<u64, ChainConfig>,
+    sessions: HashMap<Vec<u8>, SwapSession>,
+    evm_links: HashMap<Principal, EvmLink>,
+    volumes: HashMap<Principal, (u64, u128)>,
+    profiles: HashMap<String, ParticipantProfile>,
+    pending_treasury_rotation: Option<PendingTreasuryRotation>,
+    roles: Option<HashMap<Principal, BTreeSet<Role>>>,
+    fee_balance: Option<u64>,
+    updates_since_commitment: u64,
+    events_pruned_total: u64,
+    last_event_gc_at: Option<u64>,
+}
+
+/// Upgrade state saved by a build whose amounts were still nat64
+#[derive(CandidType, Deserialize)]
+struct LegacyUpgradeState {
+    metrics: LegacyMetrics,
+    authorized_principals: Vec<Principal>,
+    observers: HashMap<Principal, ObserverGrant>,
+    resolvers: HashMap<Principal, ResolverRegistration>,
+    templates: HashMap<String, LegacyTemplate>,
+    chain_configs: HashMap<u64, ChainConfig>,
+    sessions: HashMap<Vec<u8>, LegacySession>,
+    evm_links: HashMap<Principal, EvmLink>,
+    volumes: HashMap<Principal, (u64, u64)>,
+    profiles: HashMap<String, ParticipantProfile>,
+    pending_treasury_rotation: Option<PendingTreasuryRotation>,
+    roles: Option<HashMap<Principal, BTreeSet<Role>>>,
+    fee_balance: Option<u64>,
+    updates_since_commitment: u64,
+    events_pruned_total: u64,
+    last_event_gc_at: Option<u64>,
+}
+
+impl From<LegacyUpgradeState> for UpgradeState {
+    fn from(legacy: LegacyUpgradeState) -> Self {
+        UpgradeState {
+            metrics: legacy.metrics.into(),
+            authorized_principals: legacy.authorized_principals,
+            observers: legacy.observers,
+            resolvers: legacy.resolvers,
+            templates: legacy.templates.into_iter().map(|(id, template)| (id, template.into())).collect(),
+            chain_configs: legacy.chain_configs,
+            sessions: legacy.sessions.into_iter().map(|(hashlock, session)| (hashlock, session.into())).collect(),
+            evm_links: legacy.evm_links,
+            volumes: legacy.volumes.into_iter().map(|(principal, (period, used))| (principal, (period, used.into()))).collect(),
+            profiles: legacy.profiles,
+            pending_treasury_rotation: legacy.pending_treasury_rotation,
+            roles: legacy.roles,
+            fee_balance: legacy.fee_balance,
+            updates_since_commitment: legacy.updates_since_commitment,
+            events_pruned_total: legacy.events_pruned_total,
+            last_event_gc_at: legacy.last_event_gc_at,
+        }
+    }
+}
+
+/// Metrics as they were at a point in time
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct MetricsSnapshot {
+    pub timestamp: u64,
+    pub metrics: EscrowMetrics,
+    pub cycles_balance: Option<u64>, // Canister cycles when taken; None for snapshots taken before they were sampled
+}
+
+// CBOR-encoded like the config, so new metrics fields stay additive
+impl Storable for MetricsSnapshot {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(serde_cbor::to_vec(self).expect("snapshot encoding cannot fail"))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        serde_cbor::from_slice(&bytes).expect("stored snapshot must decode")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Escrows created, completed and cancelled on one UTC day
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct DailyActivity {
+    pub day: u64,                  // Days since the Unix epoch
+    pub created: u64,
+    pub completed: u64,
+    pub cancelled: u64,
 }
 
 impl Default for EscrowMetrics {
@@ -36,234 +435,1188 @@ impl Default for EscrowMetrics {
             total_escrows_cancelled: 0,
             total_volume_icp: 0,
             total_fees_collected: 0,
+            total_surplus_collected: 0,
             active_escrows_count: 0,
+            total_cycles_deposited: None,
         }
     }
 }
 
-/// Initialize storage
-pub fn init_storage() {
-    unsafe {
-        if ESCROWS.is_none() {
-            ESCROWS = Some(HashMap::new());
+impl EscrowMetrics {
+    /// Clear the counters covered by `scope`. The active escrow count tracks
+    /// live state rather than history, and the cycles deposited total backs the
+    /// top-up audit trail, so neither is ever reset.
+    pub fn reset(&mut self, scope: &MetricsResetScope) {
+        if matches!(scope, MetricsResetScope::Counters | MetricsResetScope::All) {
+            self.total_escrows_created = 0;
+            self.total_escrows_completed = 0;
+            self.total_escrows_cancelled = 0;
         }
-        if CONFIG.is_none() {
-            CONFIG = Some(EscrowConfig::default());
+        if matches!(scope, MetricsResetScope::Totals | MetricsResetScope::All) {
+            self.total_volume_icp = 0;
+            self.total_fees_collected = 0;
+            self.total_surplus_collected = 0;
         }
-        if AUTHORIZED_PRINCIPALS.is_none() {
-            AUTHORIZED_PRINCIPALS = Some(Vec::new());
+    }
+
+    /// Add cycles accepted through `deposit_cycles` to the running total
+    pub fn record_cycles_deposit(&mut self, cycles: u128) {
+        self.total_cycles_deposited = Some(self.total_cycles_deposited.unwrap_or(0).saturating_add(cycles));
+    }
+}
+
+/// Restore the persisted config and warm the hot event ring from the archive
+pub fn init_storage() {
+    // Restore the persisted config, upgrading older schema versions in place
+    let stored = STORED_CONFIG.with_borrow(|cell| decode_config(cell.get()));
+    if let Some(versioned) = stored {
+        if versioned.schema_version != CONFIG_SCHEMA_VERSION {
+            persist_config(&versioned.config);
         }
-        if EVENTS.is_none() {
-            EVENTS = Some(Vec::new());
+        CONFIG.set(versioned.config);
+    }
+    
+    // Continue numbering after the archived history and warm the hot ring
+    EVENT_ARCHIVE.with_borrow(|archive| {
+        let first_hot = archive.len().saturating_sub(MAX_EVENTS as u64);
+        NEXT_EVENT_SEQ.set(archive.len());
+        EVENTS.set((first_hot..archive.len()).filter_map(|seq| archive.get(seq)).collect());
+    });
+
+    // Index events archived before the hashlock index existed
+    let indexed = HASHLOCK_EVENTS_INDEXED.with_borrow(|cell| *cell.get());
+    for seq in indexed..next_event_seq() {
+        if let Some(record) = get_event(seq) {
+            index_hashlock_event(&record);
         }
-        if METRICS.is_none() {
-            METRICS = Some(EscrowMetrics::default());
+    }
+
+    // Chain blocks for events archived before the block log existed
+    let chained = BLOCK_HASHES.with_borrow(|hashes| hashes.len());
+    for seq in chained..next_event_seq() {
+        if let Some(record) = get_event(seq) {
+            chain_block(&record);
         }
     }
 }
 
+/// Get a virtual memory region from the memory manager
+fn get_memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with_borrow(|manager| manager.get(id))
+}
+
 /// Escrow storage operations
 pub fn get_escrow(hashlock: &[u8]) -> Option<ICPEscrow> {
-    unsafe {
-        ESCROWS.as_ref()?.get(hashlock).cloned()
-    }
+    ESCROWS.with_borrow(|escrows| escrows.get(&hashlock.to_vec()))
 }
 
 pub fn insert_escrow(hashlock: Vec<u8>, escrow: ICPEscrow) -> Result<()> {
-    unsafe {
-        if let Some(escrows) = ESCROWS.as_mut() {
-            if escrows.contains_key(&hashlock) {
-                return Err(EscrowError::DuplicateEscrow);
-            }
-            escrows.insert(hashlock, escrow);
-            
-            // Update metrics
-            if let Some(metrics) = METRICS.as_mut() {
-                metrics.total_escrows_created += 1;
-                metrics.active_escrows_count += 1;
-            }
-            
-            Ok(())
-        } else {
-            Err(EscrowError::ConfigError)
-        }
+    if ESCROWS.with_borrow(|escrows| escrows.contains_key(&hashlock)) {
+        return Err(EscrowError::DuplicateEscrow);
     }
+    certification::certify_escrow(&hashlock, escrow_leaf(&escrow));
+    let created_at = escrow.created_at;
+    ESCROW_INDEXES.with_borrow_mut(|indexes| indexes.insert(&hashlock, &escrow));
+    ESCROWS.with_borrow_mut(|escrows| escrows.insert(hashlock, escrow));
+    record_state_update();
+    
+    // Update metrics
+    update_metrics(|metrics| {
+        metrics.total_escrows_created += 1;
+        metrics.active_escrows_count += 1;
+    });
+    update_daily_activity(created_at, |day| day.created += 1);
+    
+    Ok(())
 }
 
+/// Apply `updater` to a stored escrow and re-certify it. The escrow is not borrowed while
+/// `updater` runs, so it may read other storage.
 pub fn update_escrow<F>(hashlock: &[u8], updater: F) -> Result<()>
 where
     F: FnOnce(&mut ICPEscrow),
 {
-    unsafe {
-        if let Some(escrows) = ESCROWS.as_mut() {
-            if let Some(escrow) = escrows.get_mut(hashlock) {
-                updater(escrow);
-                Ok(())
-            } else {
-                Err(EscrowError::EscrowNotFound)
-            }
-        } else {
-            Err(EscrowError::ConfigError)
+    let mut escrow = get_escrow(hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    let previous = escrow.clone();
+    updater(&mut escrow);
+    certify(hashlock, &escrow);
+    ESCROW_INDEXES.with_borrow_mut(|indexes| {
+        indexes.remove(hashlock, &previous);
+        indexes.insert(hashlock, &escrow);
+    });
+    ESCROWS.with_borrow_mut(|escrows| escrows.insert(hashlock.to_vec(), escrow));
+    record_state_update();
+    Ok(())
+}
+
+/// Certified leaf of an escrow: the record as served to everyone, without its secret
+fn escrow_leaf(escrow: &ICPEscrow) -> Vec<u8> {
+    certification::leaf_hash(&escrow.clone().without_secret())
+}
+
+/// Certify an escrow record, plus the receipt of its outcome once it has settled
+fn certify(hashlock: &[u8], escrow: &ICPEscrow) {
+    certification::certify_escrow(hashlock, escrow_leaf(escrow));
+    if !matches!(escrow.state, EscrowState::Active | EscrowState::PendingFunding) {
+        certification::certify_receipt(hashlock, certification::leaf_hash(&escrow.receipt()));
+    }
+}
+
+/// Rebuild the certified tree after an upgrade from the escrows and hot events in storage
+pub fn recertify() {
+    for (hashlock, escrow) in get_all_escrows() {
+        certify(&hashlock, &escrow);
+    }
+    EVENTS.with_borrow(|events| {
+        for record in events {
+            certification::certify_event(record.seq, event_hash(record));
+        }
+    });
+    if let Some(last) = next_event_seq().checked_sub(1) {
+        if let Some(hash) = block_hash(last) {
+            certification::certify_tip(icrc3::leb128(last), hash.to_vec());
         }
     }
 }
 
-pub fn get_all_escrows() -> Vec<(Vec<u8>, ICPEscrow)> {
-    unsafe {
-        ESCROWS.as_ref()
-            .map(|escrows| escrows.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
-            .unwrap_or_default()
+/// Rebuild the secondary indexes from the escrows in stable memory
+fn reindex_escrows() {
+    let mut indexes = EscrowIndexes::default();
+    ESCROWS.with_borrow(|escrows| {
+        for (hashlock, escrow) in escrows.iter() {
+            indexes.insert(&hashlock, &escrow);
+        }
+    });
+    ESCROW_INDEXES.set(indexes);
+}
+
+/// Record the direction of escrows created before it was stored on the escrow, taken from
+/// their creation event
+fn backfill_escrow_types() {
+    let missing: Vec<Vec<u8>> = ESCROWS.with_borrow(|escrows| {
+        escrows.iter()
+            .filter(|(_, escrow)| escrow.escrow_type.is_none())
+            .map(|(hashlock, _)| hashlock)
+            .collect()
+    });
+    for hashlock in missing {
+        let created = get_events_for_hashlock(&hashlock).into_iter().find_map(|event| match event {
+            EscrowEvent::EscrowCreated { escrow_type, .. } => Some(escrow_type),
+            _ => None,
+        });
+        let Some(escrow_type) = created else { continue };
+        ESCROWS.with_borrow_mut(|escrows| {
+            if let Some(mut escrow) = escrows.get(&hashlock) {
+                escrow.escrow_type = Some(escrow_type);
+                escrows.insert(hashlock, escrow);
+            }
+        });
     }
 }
 
+/// Escrows under the hashlocks of an index entry, in hashlock order
+fn indexed_escrows(hashlocks: Option<&BTreeSet<Vec<u8>>>) -> Vec<(Vec<u8>, ICPEscrow)> {
+    ESCROWS.with_borrow(|escrows| {
+        hashlocks.into_iter()
+            .flatten()
+            .filter_map(|hashlock| escrows.get(hashlock).map(|escrow| (hashlock.clone(), escrow)))
+            .collect()
+    })
+}
+
+/// Page of escrows under the hashlocks of an index entry, after `cursor` (exclusive)
+fn indexed_page(hashlocks: Option<&BTreeSet<Vec<u8>>>, cursor: Option<&[u8]>, limit: usize) -> EscrowPage {
+    let start = match cursor {
+        Some(cursor) => std::ops::Bound::Excluded(cursor.to_vec()),
+        None => std::ops::Bound::Unbounded,
+    };
+    let (escrows, next_cursor) = ESCROWS.with_borrow(|escrows| {
+        let entries = hashlocks.into_iter()
+            .flat_map(|hashlocks| hashlocks.range((start.clone(), std::ops::Bound::Unbounded)))
+            .filter_map(|hashlock| escrows.get(hashlock).map(|escrow| (hashlock.clone(), escrow)));
+        take_page(entries, limit)
+    });
+    EscrowPage { escrows, next_cursor }
+}
+
+/// Escrows in `state`, paged by hashlock
+pub fn get_escrows_by_state(state: &EscrowState, cursor: Option<&[u8]>, limit: usize) -> EscrowPage {
+    ESCROW_INDEXES.with_borrow(|indexes| indexed_page(indexes.by_state.get(state), cursor, limit))
+}
+
+/// Escrows whose maker is `maker`, paged by hashlock
+pub fn get_escrows_by_maker(maker: &str, cursor: Option<&[u8]>, limit: usize) -> EscrowPage {
+    ESCROW_INDEXES.with_borrow(|indexes| indexed_page(indexes.by_maker.get(maker), cursor, limit))
+}
+
+/// Escrows whose taker is `taker`, paged by hashlock
+pub fn get_escrows_by_taker(taker: &str, cursor: Option<&[u8]>, limit: usize) -> EscrowPage {
+    ESCROW_INDEXES.with_borrow(|indexes| indexed_page(indexes.by_taker.get(taker), cursor, limit))
+}
+
+/// Hashlocks of the escrows a legacy 8-byte transfer memo may belong to, whatever its operation.
+/// More than one means their hashlocks share the first 7 bytes the memo keeps.
+pub fn lookup_memo(memo: u64) -> Vec<Vec<u8>> {
+    ESCROW_INDEXES.with_borrow(|indexes| {
+        indexes.by_memo.get(&ledger::memo_prefix(memo)).into_iter().flatten().cloned().collect()
+    })
+}
+
+/// Every escrow in `state`, in hashlock order
+fn get_all_escrows_in_state(state: &EscrowState) -> Vec<(Vec<u8>, ICPEscrow)> {
+    ESCROW_INDEXES.with_borrow(|indexes| indexed_escrows(indexes.by_state.get(state)))
+}
+
+/// Count an escrow update and commit the state root when the interval is reached
+fn record_state_update() {
+    let interval = get_config().state_commitment_interval;
+    if interval == 0 {
+        return;
+    }
+    
+    let updates = UPDATES_SINCE_COMMITMENT.get() + 1;
+    if updates < interval {
+        UPDATES_SINCE_COMMITMENT.set(updates);
+        return;
+    }
+    UPDATES_SINCE_COMMITMENT.set(0);
+    
+    let commitment = StateCommitment {
+        index: STATE_COMMITMENTS.with_borrow(|commitments| commitments.len()),
+        timestamp: crate::utils::current_time(),
+        escrows_root: certification::escrows_root(),
+        escrow_count: ESCROWS.with_borrow(|escrows| escrows.len()),
+    };
+    STATE_COMMITMENTS.with_borrow_mut(|commitments| commitments.insert(commitment.index, commitment));
+}
+
+/// Get state commitments starting at `start_index`
+pub fn get_state_commitments(start_index: u64, limit: usize) -> Vec<StateCommitment> {
+    STATE_COMMITMENTS.with_borrow(|commitments| {
+        commitments.range(start_index..)
+            .take(limit)
+            .map(|(_, commitment)| commitment)
+            .collect()
+    })
+}
+
+pub fn get_all_escrows() -> Vec<(Vec<u8>, ICPEscrow)> {
+    ESCROWS.with_borrow(|escrows| escrows.iter().collect())
+}
+
+/// Stored escrows matching `filter`, in hashlock order
+fn filter_escrows<F>(filter: F) -> Vec<(Vec<u8>, ICPEscrow)>
+where
+    F: Fn(&ICPEscrow) -> bool,
+{
+    ESCROWS.with_borrow(|escrows| escrows.iter().filter(|(_, escrow)| filter(escrow)).collect())
+}
+
 /// Get escrows for a specific principal (as maker or taker)
 pub fn get_escrows_for_principal(principal_str: &str) -> Vec<(Vec<u8>, ICPEscrow)> {
-    unsafe {
-        ESCROWS.as_ref()
-            .map(|escrows| {
-                escrows.iter()
-                    .filter(|(_, escrow)| {
-                        escrow.immutables.maker == principal_str || 
-                        escrow.immutables.taker == principal_str
-                    })
-                    .map(|(k, v)| (k.clone(), v.clone()))
-                    .collect()
-            })
-            .unwrap_or_default()
+    let hashlocks: BTreeSet<Vec<u8>> = ESCROW_INDEXES.with_borrow(|indexes| {
+        indexes.by_maker.get(principal_str).into_iter()
+            .chain(indexes.by_taker.get(principal_str))
+            .flatten()
+            .cloned()
+            .collect()
+    });
+    indexed_escrows(Some(&hashlocks))
+}
+
+/// Deterministic digest over escrows, config and authorized principals
+///
+/// Escrows are hashed in hashlock order and principals in byte order, so the
+/// digest only depends on the state itself and not on how it was inserted.
+pub fn compute_state_digest(
+    escrows: &[(Vec<u8>, ICPEscrow)],
+    config: &EscrowConfig,
+    authorized_principals: &[Principal],
+) -> Vec<u8> {
+    let mut escrows: Vec<&(Vec<u8>, ICPEscrow)> = escrows.iter().collect();
+    escrows.sort_by(|a, b| a.0.cmp(&b.0));
+    
+    let mut principals: Vec<&Principal> = authorized_principals.iter().collect();
+    principals.sort_by(|a, b| a.as_slice().cmp(b.as_slice()));
+    
+    let mut data = b"icp-fusion-state-v1".to_vec();
+    let mut append = |bytes: &[u8]| {
+        data.extend_from_slice(&(bytes.len() as u64).to_be_bytes());
+        data.extend_from_slice(bytes);
+    };
+    
+    append(&candid::encode_one(config).expect("config encoding cannot fail"));
+    for principal in principals {
+        append(principal.as_slice());
+    }
+    for (hashlock, escrow) in escrows {
+        append(hashlock);
+        append(&candid::encode_one(escrow).expect("escrow encoding cannot fail"));
+    }
+    
+    crate::utils::sha256(&data)
+}
+
+/// Digest of the live canister state for audit comparison
+pub fn get_state_digest() -> StateDigest {
+    let escrows = get_all_escrows();
+    let principals = get_authorized_principals();
+    
+    StateDigest {
+        digest: compute_state_digest(&escrows, &get_config(), &principals),
+        escrow_count: escrows.len() as u64,
+        authorized_principals_count: principals.len() as u64,
     }
 }
 
 /// Configuration operations
 pub fn get_config() -> EscrowConfig {
-    unsafe {
-        CONFIG.as_ref().cloned().unwrap_or_default()
-    }
+    CONFIG.with_borrow(|config| config.clone())
 }
 
 pub fn set_config(config: EscrowConfig) -> Result<()> {
-    unsafe {
-        CONFIG = Some(config);
-        Ok(())
+    persist_config(&config);
+    CONFIG.set(config);
+    Ok(())
+}
+
+/// Get the config with its schema version
+pub fn get_versioned_config() -> VersionedConfig {
+    VersionedConfig {
+        schema_version: CONFIG_SCHEMA_VERSION,
+        config: get_config(),
+    }
+}
+
+/// Write the config to stable memory under the current schema version
+fn persist_config(config: &EscrowConfig) {
+    STORED_CONFIG.with_borrow_mut(|cell| {
+        cell.set(encode_config(config)).expect("failed to persist config");
+    });
+}
+
+/// Encode a config envelope as self-describing CBOR
+pub fn encode_config(config: &EscrowConfig) -> Vec<u8> {
+    let versioned = VersionedConfig {
+        schema_version: CONFIG_SCHEMA_VERSION,
+        config: config.clone(),
+    };
+    serde_cbor::to_vec(&versioned).expect("config encoding cannot fail")
+}
+
+/// Decode a stored config envelope; fields added after it was written get defaults
+pub fn decode_config(bytes: &[u8]) -> Option<VersionedConfig> {
+    if bytes.is_empty() {
+        return None;
     }
+    serde_cbor::from_slice(bytes).ok()
+}
+
+/// Treasury rotation operations
+pub fn get_pending_treasury_rotation() -> Option<PendingTreasuryRotation> {
+    PENDING_TREASURY_ROTATION.with_borrow(|rotation| rotation.clone())
+}
+
+pub fn set_pending_treasury_rotation(rotation: Option<PendingTreasuryRotation>) {
+    PENDING_TREASURY_ROTATION.set(rotation);
 }
 
 /// Authorized principals operations
 pub fn is_authorized_principal(principal: &Principal) -> bool {
-    unsafe {
-        AUTHORIZED_PRINCIPALS.as_ref()
-            .map(|auths| auths.contains(principal))
-            .unwrap_or(false)
-    }
+    AUTHORIZED_PRINCIPALS.with_borrow(|auths| auths.contains(principal))
 }
 
 pub fn add_authorized_principal(principal: Principal) -> Result<()> {
-    unsafe {
-        if let Some(auths) = AUTHORIZED_PRINCIPALS.as_mut() {
-            if !auths.contains(&principal) {
-                auths.push(principal);
-            }
-            Ok(())
-        } else {
-            Err(EscrowError::ConfigError)
+    AUTHORIZED_PRINCIPALS.with_borrow_mut(|auths| {
+        if !auths.contains(&principal) {
+            auths.push(principal);
         }
-    }
+    });
+    Ok(())
 }
 
 pub fn remove_authorized_principal(principal: &Principal) -> Result<()> {
-    unsafe {
-        if let Some(auths) = AUTHORIZED_PRINCIPALS.as_mut() {
-            auths.retain(|p| p != principal);
-            Ok(())
-        } else {
-            Err(EscrowError::ConfigError)
+    AUTHORIZED_PRINCIPALS.with_borrow_mut(|auths| auths.retain(|p| p != principal));
+    Ok(())
+}
+
+pub fn get_authorized_principals() -> Vec<Principal> {
+    AUTHORIZED_PRINCIPALS.with_borrow(|auths| auths.clone())
+}
+
+/// Observer token operations
+pub fn observer_allows(principal: &Principal, scope: &ObserverScope, now: u64) -> bool {
+    OBSERVERS.with_borrow(|observers| {
+        observers.get(principal).is_some_and(|grant| grant.allows(scope, now))
+    })
+}
+
+pub fn set_observer(grant: ObserverGrant) -> Result<()> {
+    OBSERVERS.with_borrow_mut(|observers| observers.insert(grant.principal, grant));
+    Ok(())
+}
+
+pub fn remove_observer(principal: &Principal) -> Result<()> {
+    OBSERVERS.with_borrow_mut(|observers| observers.remove(principal));
+    Ok(())
+}
+
+pub fn get_observers() -> Vec<ObserverGrant> {
+    OBSERVERS.with_borrow(|observers| observers.values().cloned().collect())
+}
+
+/// Role operations; grant and revoke return whether anything changed
+pub fn get_roles(principal: &Principal) -> Vec<Role> {
+    ROLES.with_borrow(|roles| roles.get(principal).map(|held| held.iter().copied().collect()).unwrap_or_default())
+}
+
+pub fn grant_role(principal: Principal, role: Role) -> bool {
+    ROLES.with_borrow_mut(|roles| roles.entry(principal).or_default().insert(role))
+}
+
+pub fn revoke_role(principal: &Principal, role: Role) -> bool {
+    ROLES.with_borrow_mut(|roles| {
+        let Some(held) = roles.get_mut(principal) else { return false };
+        let removed = held.remove(&role);
+        if held.is_empty() {
+            roles.remove(principal);
+        }
+        removed
+    })
+}
+
+pub fn get_role_assignments() -> Vec<RoleAssignment> {
+    ROLES.with_borrow(|roles| {
+        roles
+            .iter()
+            .map(|(principal, held)| RoleAssignment { principal: *principal, roles: held.iter().copied().collect() })
+            .collect()
+    })
+}
+
+/// Entries keyed by hashlock plus the cursor of the following page
+type Page<T> = (Vec<(Vec<u8>, T)>, Option<Vec<u8>>);
+
+/// First `limit` entries of an iterator already in hashlock order, plus the cursor of the following page
+fn take_page<T>(entries: impl Iterator<Item = (Vec<u8>, T)>, limit: usize) -> Page<T> {
+    let mut page: Vec<_> = entries.take(limit + 1).collect();
+    let next_cursor = if page.len() > limit {
+        page.truncate(limit);
+        page.last().map(|(hashlock, _)| hashlock.clone())
+    } else {
+        None
+    };
+    (page, next_cursor)
+}
+
+/// Whether an active escrow already references the order `(order_hash, chain_id)`
+pub fn has_active_escrow_for_order(order_hash: &[u8], chain_id: Option<u64>) -> bool {
+    get_escrows_for_order(order_hash).iter().any(|(_, escrow)| {
+        matches!(escrow.state, EscrowState::Active | EscrowState::PendingFunding)
+            && escrow.immutables.chain_id == chain_id
+    })
+}
+
+/// Every escrow created for `order_hash`, on any chain
+pub fn get_escrows_for_order(order_hash: &[u8]) -> Vec<(Vec<u8>, ICPEscrow)> {
+    ESCROW_INDEXES.with_borrow(|indexes| indexed_escrows(indexes.by_order_hash.get(order_hash)))
+}
+
+/// Escrows that have been Processing for longer than `timeout`
+pub fn get_stuck_escrows(now: u64, timeout: u64) -> Vec<(Vec<u8>, ICPEscrow)> {
+    let mut escrows = get_all_escrows_in_state(&EscrowState::Processing);
+    escrows.retain(|(_, escrow)| escrow.processing.as_ref().is_some_and(|lock| lock.is_stuck(now, timeout)));
+    escrows
+}
+
+/// Announced escrows still unfunded past their funding deadline
+pub fn get_unfunded_escrows(now: u64) -> Vec<(Vec<u8>, ICPEscrow)> {
+    let mut escrows = get_all_escrows_in_state(&EscrowState::PendingFunding);
+    escrows.retain(|(_, escrow)| escrow.funding_expired(now));
+    escrows
+}
+
+/// Mark an operation on the escrow as in flight; false if one already is
+pub fn lock_escrow(hashlock: &[u8]) -> bool {
+    LOCKED_ESCROWS.with_borrow_mut(|locked| locked.insert(hashlock.to_vec()))
+}
+
+/// Release the hold taken by `lock_escrow`
+pub fn unlock_escrow(hashlock: &[u8]) {
+    LOCKED_ESCROWS.with_borrow_mut(|locked| locked.remove(hashlock));
+}
+
+/// Queue a failed settlement transfer for retry under an id after the newest queued one
+pub fn queue_pending_transfer(mut transfer: PendingTransfer) -> u64 {
+    PENDING_TRANSFERS.with_borrow_mut(|transfers| {
+        transfer.id = transfers.last_key_value().map_or(0, |(id, _)| id + 1);
+        transfers.insert(transfer.id, transfer.clone());
+        transfer.id
+    })
+}
+
+/// Up to `limit` queued transfers, oldest first
+pub fn get_pending_transfers(limit: usize) -> Vec<PendingTransfer> {
+    PENDING_TRANSFERS.with_borrow(|transfers| transfers.iter().take(limit).map(|(_, transfer)| transfer).collect())
+}
+
+/// Count another failed attempt at a queued transfer
+pub fn record_transfer_attempt(id: u64, now: u64) {
+    PENDING_TRANSFERS.with_borrow_mut(|transfers| {
+        if let Some(mut transfer) = transfers.get(&id) {
+            transfer.attempts += 1;
+            transfer.last_attempt_at = Some(now);
+            transfers.insert(id, transfer);
+        }
+    });
+}
+
+/// Drop a queued transfer once it has been paid
+pub fn remove_pending_transfer(id: u64) -> Option<PendingTransfer> {
+    PENDING_TRANSFERS.with_borrow_mut(|transfers| transfers.remove(&id))
+}
+
+/// Record a newly announced order under an id after the newest one
+pub fn insert_order(mut order: SwapOrder) -> SwapOrder {
+    ORDERS.with_borrow_mut(|orders| {
+        order.id = orders.last_key_value().map_or(0, |(id, _)| id + 1);
+        orders.insert(order.id, order.clone());
+        order
+    })
+}
+
+pub fn get_order(id: u64) -> Option<SwapOrder> {
+    ORDERS.with_borrow(|orders| orders.get(&id))
+}
+
+/// Apply `f` to a stored order
+pub fn update_order<F>(id: u64, f: F) -> Result<SwapOrder>
+where
+    F: FnOnce(&mut SwapOrder),
+{
+    ORDERS.with_borrow_mut(|orders| {
+        let mut order = orders.get(&id).ok_or(EscrowError::OrderNotFound)?;
+        f(&mut order);
+        orders.insert(id, order.clone());
+        Ok(order)
+    })
+}
+
+/// Up to `limit` orders still open at `now` with ids after `after`, oldest first
+pub fn get_open_orders(after: Option<u64>, now: u64, limit: usize) -> Vec<SwapOrder> {
+    let start = after.map_or(0, |id| id.saturating_add(1));
+    ORDERS.with_borrow(|orders| {
+        orders.range(start..)
+            .map(|(_, order)| order)
+            .filter(|order| order.is_open(now))
+            .take(limit)
+            .collect()
+    })
+}
+
+/// Open orders announced by `maker`, including any past expiry the timer has not reached yet
+pub fn count_open_orders(maker: &Principal) -> usize {
+    ORDERS.with_borrow(|orders| {
+        orders.iter()
+            .filter(|(_, order)| order.maker == *maker && order.status == OrderStatus::Open)
+            .count()
+    })
+}
+
+/// Whether an order that is open or was accepted already uses `order_hash` or `hashlock`
+pub fn order_conflicts(order_hash: &[u8], hashlock: &[u8]) -> bool {
+    ORDERS.with_borrow(|orders| {
+        orders.iter().any(|(_, order)| {
+            matches!(order.status, OrderStatus::Open | OrderStatus::Accepted { .. })
+                && (order.intent.dst_immutables.order_hash == order_hash || order.hashlock() == hashlock)
+        })
+    })
+}
+
+/// Open orders past their expiry at `now`, oldest first
+pub fn get_expired_orders(now: u64, limit: usize) -> Vec<SwapOrder> {
+    ORDERS.with_borrow(|orders| {
+        orders.iter()
+            .map(|(_, order)| order)
+            .filter(|order| order.status == OrderStatus::Open && now > order.intent.expires_at)
+            .take(limit)
+            .collect()
+    })
+}
+
+/// All escrows where `principal` is maker or taker, in hashlock order
+pub fn get_all_escrows_for_party(principal: &Principal) -> Vec<(Vec<u8>, ICPEscrow)> {
+    filter_escrows(|escrow| escrow.is_party(principal))
+}
+
+/// Escrows matching `filter`, skipping the first `offset` matches, in hashlock order.
+/// Reads only as far into the map as the page needs.
+pub fn list_escrows(offset: u64, limit: usize, filter: &EscrowFilter) -> EscrowListPage {
+    ESCROWS.with_borrow(|escrows| {
+        let mut matches = escrows.iter().filter(|(_, escrow)| filter.matches(escrow)).skip(offset as usize);
+        let escrows: Vec<_> = matches.by_ref().take(limit).collect();
+        let next_offset = matches.next().map(|_| offset + escrows.len() as u64);
+        EscrowListPage { escrows, next_offset }
+    })
+}
+
+/// Escrows where `principal` is maker or taker after `cursor` (exclusive), paged by hashlock.
+/// Scans forward from the cursor instead of collecting every escrow first.
+pub fn get_escrows_for_party(principal: &Principal, cursor: Option<&[u8]>, limit: usize) -> EscrowPage {
+    let start = match cursor {
+        Some(cursor) => std::ops::Bound::Excluded(cursor.to_vec()),
+        None => std::ops::Bound::Unbounded,
+    };
+    let (escrows, next_cursor) = ESCROWS.with_borrow(|escrows| {
+        take_page(escrows.range((start, std::ops::Bound::Unbounded)).filter(|(_, escrow)| escrow.is_party(principal)), limit)
+    });
+    EscrowPage { escrows, next_cursor }
+}
+
+/// Resolver registry operations
+pub fn is_resolver(principal: &Principal) -> bool {
+    RESOLVERS.with_borrow(|resolvers| resolvers.contains_key(principal))
+}
+
+pub fn set_resolver(registration: ResolverRegistration) -> Result<()> {
+    RESOLVERS.with_borrow_mut(|resolvers| resolvers.insert(registration.principal, registration));
+    Ok(())
+}
+
+pub fn remove_resolver(principal: &Principal) -> Option<ResolverRegistration> {
+    RESOLVERS.with_borrow_mut(|resolvers| resolvers.remove(principal))
+}
+
+pub fn get_resolvers() -> Vec<ResolverRegistration> {
+    RESOLVERS.with_borrow(|resolvers| resolvers.values().cloned().collect())
+}
+
+/// Escrow template operations
+pub fn get_template(id: &str) -> Option<EscrowTemplate> {
+    TEMPLATES.with_borrow(|templates| templates.get(id).cloned())
+}
+
+pub fn set_template(template: EscrowTemplate) -> Result<()> {
+    TEMPLATES.with_borrow_mut(|templates| templates.insert(template.id.clone(), template));
+    Ok(())
+}
+
+pub fn remove_template(id: &str) -> Result<()> {
+    TEMPLATES.with_borrow_mut(|templates| {
+        templates.remove(id).map(|_| ()).ok_or(EscrowError::TemplateNotFound)
+    })
+}
+
+pub fn get_templates() -> Vec<EscrowTemplate> {
+    TEMPLATES.with_borrow(|templates| templates.values().cloned().collect())
+}
+
+/// Chain config operations
+pub fn get_chain_config(chain_id: u64) -> Option<ChainConfig> {
+    CHAIN_CONFIGS.with_borrow(|configs| configs.get(&chain_id).cloned())
+}
+
+pub fn set_chain_config(config: ChainConfig) -> Result<()> {
+    CHAIN_CONFIGS.with_borrow_mut(|configs| configs.insert(config.chain_id, config));
+    Ok(())
+}
+
+pub fn remove_chain_config(chain_id: u64) -> Result<()> {
+    CHAIN_CONFIGS.with_borrow_mut(|configs| {
+        configs.remove(&chain_id).map(|_| ()).ok_or(EscrowError::ChainNotConfigured)
+    })
+}
+
+pub fn get_chain_configs() -> Vec<ChainConfig> {
+    let mut configs: Vec<ChainConfig> = CHAIN_CONFIGS.with_borrow(|configs| configs.values().cloned().collect());
+    configs.sort_by_key(|config| config.chain_id);
+    configs
+}
+
+/// Swap session operations
+pub fn get_session(hashlock: &[u8]) -> Option<SwapSession> {
+    SESSIONS.with_borrow(|sessions| sessions.get(hashlock).cloned())
+}
+
+pub fn insert_session(session: SwapSession) -> Result<()> {
+    SESSIONS.with_borrow_mut(|sessions| sessions.insert(session.hashlock.clone(), session));
+    Ok(())
+}
+
+/// EVM link operations
+pub fn get_evm_link(principal: &Principal) -> Option<EvmLink> {
+    EVM_LINKS.with_borrow(|links| links.get(principal).cloned())
+}
+
+pub fn set_evm_link(principal: Principal, link: EvmLink) -> Result<()> {
+    EVM_LINKS.with_borrow_mut(|links| links.insert(principal, link));
+    Ok(())
+}
+
+/// Volume `principal` created during `period`
+pub fn get_volume_used(principal: &Principal, period: u64) -> u128 {
+    VOLUMES.with_borrow(|volumes| {
+        volumes.get(principal)
+            .filter(|(recorded_period, _)| *recorded_period == period)
+            .map(|(_, volume)| *volume)
+            .unwrap_or(0)
+    })
+}
+
+/// Add created volume for `principal`, starting over when a new period begins
+pub fn add_volume(principal: Principal, period: u64, amount: u128) {
+    let used = get_volume_used(&principal, period);
+    VOLUMES.with_borrow_mut(|volumes| volumes.insert(principal, (period, used.saturating_add(amount))));
+}
+
+/// Participant profile operations
+pub fn get_profile(principal_str: &str) -> Option<ParticipantProfile> {
+    PROFILES.with_borrow(|profiles| profiles.get(principal_str).cloned())
+}
+
+pub fn set_profile(principal_str: String, profile: ParticipantProfile) -> Result<()> {
+    PROFILES.with_borrow_mut(|profiles| profiles.insert(principal_str, profile));
+    Ok(())
+}
+
+pub fn remove_profile(principal_str: &str) -> Result<()> {
+    PROFILES.with_borrow_mut(|profiles| profiles.remove(principal_str));
+    Ok(())
+}
+
+/// Sum the fee breakdowns of all stored escrows
+pub fn get_fee_report(now: u64) -> FeeReport {
+    let mut report = FeeReport { generated_at: now, ..FeeReport::default() };
+    ESCROWS.with_borrow(|escrows| {
+        for (_, escrow) in escrows.iter() {
+            report.escrows_count += 1;
+            report.totals.accumulate(&escrow.fee_breakdown);
         }
+    });
+    report
+}
+
+/// Sum the cycle costs attributed to all stored escrows
+pub fn get_cycle_report(now: u64) -> CycleReport {
+    let mut report = CycleReport { generated_at: now, ..CycleReport::default() };
+    ESCROWS.with_borrow(|escrows| {
+        for (_, escrow) in escrows.iter() {
+            report.escrows_count += 1;
+            report.totals.accumulate(&escrow.cycles.unwrap_or_default());
+        }
+    });
+    report.average_per_escrow = report.totals.total().checked_div(report.escrows_count).unwrap_or(0);
+    report
+}
+
+/// Event logging operations
+pub fn add_event(event: EscrowEvent) -> u64 {
+    let seq = NEXT_EVENT_SEQ.get();
+    let record = EventRecord { seq, event };
+    
+    // Commit the event hash to the certified log before storing it
+    certification::certify_event(seq, certification::leaf_hash(&record));
+    
+    EVENT_ARCHIVE.with_borrow(|archive| {
+        archive.append(&record).expect("failed to archive event");
+    });
+    index_hashlock_event(&record);
+    let hash = chain_block(&record);
+    certification::certify_tip(icrc3::leb128(seq), hash.to_vec());
+    EVENTS.with_borrow_mut(|events| events.push_back(record));
+    
+    NEXT_EVENT_SEQ.set(seq + 1);
+    seq
+}
+
+/// Add an archived event to its escrow's entry in the hashlock index
+fn index_hashlock_event(record: &EventRecord) {
+    if let Some(hashlock) = record.event.hashlock().and_then(|hashlock| <[u8; 32]>::try_from(hashlock).ok()) {
+        HASHLOCK_EVENTS.with_borrow_mut(|index| index.insert((hashlock, record.seq), ()));
     }
+    HASHLOCK_EVENTS_INDEXED.with_borrow_mut(|cell| {
+        cell.set(record.seq + 1).expect("failed to persist hashlock index position");
+    });
 }
 
-pub fn get_authorized_principals() -> Vec<Principal> {
-    unsafe {
-        AUTHORIZED_PRINCIPALS.as_ref().cloned().unwrap_or_default()
+/// Hash and store the ICRC-3 block of an archived event, chained to the block before it
+fn chain_block(record: &EventRecord) -> [u8; 32] {
+    let phash = record.seq.checked_sub(1).and_then(block_hash);
+    let hash = icrc3::event_block(record, phash).hash();
+    BLOCK_HASHES.with_borrow_mut(|hashes| hashes.insert(record.seq, hash));
+    hash
+}
+
+/// Hash of ICRC-3 block `index`
+pub fn block_hash(index: u64) -> Option<[u8; 32]> {
+    BLOCK_HASHES.with_borrow(|hashes| hashes.get(&index))
+}
+
+/// Event `seq` Candid-encoded exactly as archived
+pub fn get_event_bytes(seq: u64) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    EVENT_ARCHIVE.with_borrow(|archive| archive.read_entry(seq, &mut bytes)).ok()?;
+    Some(bytes)
+}
+
+/// Leaf hash an event was certified and chained under: the hash of its bytes as archived.
+/// Events archived while amounts were nat64 encode differently once decoded, so their
+/// hash cannot be recomputed from the record.
+pub fn event_hash(record: &EventRecord) -> Vec<u8> {
+    match get_event_bytes(record.seq) {
+        Some(bytes) => utils::sha256(&bytes),
+        None => certification::leaf_hash(record),
     }
 }
 
-/// Event logging operations
-pub fn add_event(event: EscrowEvent) {
-    unsafe {
-        if let Some(events) = EVENTS.as_mut() {
-            events.push(event);
-            
-            // Keep only last 1000 events to prevent unbounded growth
-            if events.len() > 1000 {
-                events.remove(0);
+/// Whether the secret revealed for `hashlock` is no longer needed by anyone: its escrow has
+/// settled and a linked counterpart leg, if any, has reached cancellation and cannot be withdrawn
+fn is_swap_settled(hashlock: &[u8], now: u64) -> bool {
+    let settled = get_escrow(hashlock)
+        .is_some_and(|escrow| !matches!(escrow.state, EscrowState::Active | EscrowState::Processing));
+    settled && get_session(hashlock).is_none_or(|session| now >= session.src_immutables.timelocks.cancellation_start())
+}
+
+/// Apply the configured secret redaction to an event served by a public query
+pub fn redact_public_event(event: &mut EscrowEvent, now: u64) {
+    let reveals_secret = matches!(
+        event,
+        EscrowEvent::EscrowWithdrawal { .. } | EscrowEvent::EscrowPartiallyFilled { .. } | EscrowEvent::SecretRevealed { .. }
+    );
+    if !get_config().redact_settled_secrets || !reveals_secret {
+        return;
+    }
+    if event.hashlock().is_some_and(|hashlock| is_swap_settled(hashlock, now)) {
+        event.redact_secret();
+    }
+}
+
+/// Sequence number of the oldest event in the hot ring
+fn first_hot_seq() -> u64 {
+    EVENTS.with_borrow(|events| events.front().map(|record| record.seq))
+        .unwrap_or_else(next_event_seq)
+}
+
+/// Sequence number the next event will receive (one past the newest event)
+pub fn next_event_seq() -> u64 {
+    NEXT_EVENT_SEQ.get()
+}
+
+/// Up to `limit` events in log order, starting at `start_seq` and stopping before `end_seq`
+pub fn get_events_range(start_seq: u64, end_seq: u64, limit: usize) -> Vec<EventRecord> {
+    (start_seq..end_seq.min(next_event_seq()))
+        .take(limit)
+        .map_while(get_event)
+        .collect()
+}
+
+/// Up to `limit` events from `event_id` on, read through to the archive so a reader can
+/// resume anywhere in the log
+pub fn get_events_since(event_id: u64, limit: usize) -> EventLogPage {
+    let events: Vec<LoggedEvent> = get_events_range(event_id, next_event_seq(), limit)
+        .into_iter()
+        .map(LoggedEvent::from)
+        .collect();
+    EventLogPage {
+        next_event_id: events.last().map_or(event_id, |event| event.event_id + 1),
+        latest_event_id: next_event_seq().checked_sub(1),
+        events,
+    }
+}
+
+/// Most recent events, newest first, continuing into the archive past the hot ring
+pub fn get_recent_events(limit: usize) -> Vec<EscrowEvent> {
+    let limit = limit.min(MAX_EVENTS);
+    let mut recent: Vec<EscrowEvent> = EVENTS.with_borrow(|events| {
+        events.iter()
+            .rev()
+            .take(limit)
+            .map(|record| record.event.clone())
+            .collect()
+    });
+    
+    let mut seq = first_hot_seq();
+    EVENT_ARCHIVE.with_borrow(|archive| {
+        while recent.len() < limit && seq > 0 {
+            seq -= 1;
+            match archive.get(seq) {
+                Some(record) => recent.push(record.event),
+                None => break,
             }
         }
+    });
+    recent
+}
+
+/// Drop the oldest events beyond `max_len`, at most `limit` of them.
+/// Returns the sequence numbers removed.
+fn drain_excess_events(events: &mut VecDeque<EventRecord>, max_len: usize, limit: usize) -> Vec<u64> {
+    let excess = events.len().saturating_sub(max_len).min(limit);
+    events.drain(..excess).map(|record| record.seq).collect()
+}
+
+/// Prune events beyond the retention cap in one bounded batch
+pub fn prune_events(now: u64, limit: usize) -> usize {
+    let pruned = EVENTS.with_borrow_mut(|events| drain_excess_events(events, MAX_EVENTS, limit));
+    certification::uncertify_events(&pruned);
+    record_event_gc(pruned.len(), now);
+    pruned.len()
+}
+
+/// Count a GC run in the storage stats
+fn record_event_gc(pruned: usize, now: u64) {
+    EVENTS_PRUNED_TOTAL.set(EVENTS_PRUNED_TOTAL.get() + pruned as u64);
+    LAST_EVENT_GC_AT.set(Some(now));
+}
+
+/// Drop events emitted before `cutoff` from the front of the hot ring, at most `limit` of them.
+/// Returns the sequence numbers removed.
+fn drain_aged_events(events: &mut VecDeque<EventRecord>, cutoff: u64, limit: usize) -> Vec<u64> {
+    let aged = events.iter()
+        .take(limit)
+        .take_while(|record| record.event.timestamp() < cutoff)
+        .count();
+    events.drain(..aged).map(|record| record.seq).collect()
+}
+
+/// Move events older than `retention` out of the hot ring in one bounded batch,
+/// logging an EventsPruned summary. They remain readable from the archive.
+pub fn prune_aged_events(now: u64, retention: u64, limit: usize) -> usize {
+    if retention == 0 {
+        return 0;
     }
+    let cutoff = now.saturating_sub(retention);
+    let pruned = EVENTS.with_borrow_mut(|events| drain_aged_events(events, cutoff, limit));
+    let (Some(first_seq), Some(last_seq)) = (pruned.first().copied(), pruned.last().copied()) else {
+        return 0;
+    };
+    certification::uncertify_events(&pruned);
+    record_event_gc(pruned.len(), now);
+    add_event(EscrowEvent::EventsPruned {
+        count: pruned.len() as u64,
+        first_seq,
+        last_seq,
+        cutoff,
+        timestamp: now,
+    });
+    pruned.len()
 }
 
-pub fn get_recent_events(limit: usize) -> Vec<EscrowEvent> {
-    unsafe {
-        EVENTS.as_ref()
-            .map(|events| {
-                events.iter()
-                    .rev()
-                    .take(limit)
-                    .cloned()
-                    .collect()
-            })
-            .unwrap_or_default()
+/// Get an event by sequence number from the hot ring or the archive
+pub fn get_event(seq: u64) -> Option<EventRecord> {
+    if let Some(index) = seq.checked_sub(first_hot_seq()) {
+        return EVENTS.with_borrow(|events| events.get(index as usize).cloned());
     }
+    EVENT_ARCHIVE.with_borrow(|archive| archive.get(seq))
 }
 
+/// All events of an escrow in log order, including those aged out of the hot ring.
+/// Looked up through the hashlock index, so the cost follows the escrow's own history
+/// rather than the size of the archive. Hashlocks are always 32 bytes; any other length has no events.
 pub fn get_events_for_hashlock(hashlock: &[u8]) -> Vec<EscrowEvent> {
-    unsafe {
-        EVENTS.as_ref()
-            .map(|events| {
-                events.iter()
-                    .filter(|event| {
-                        match event {
-                            EscrowEvent::EscrowCreated { hashlock: h, .. } |
-                            EscrowEvent::EscrowWithdrawal { hashlock: h, .. } |
-                            EscrowEvent::EscrowCancelled { hashlock: h, .. } |
-                            EscrowEvent::FundsRescued { hashlock: h, .. } |
-                            EscrowEvent::ICPTxRecorded { hashlock: h, .. } |
-                            EscrowEvent::EVMAddressRecorded { hashlock: h, .. } => h == hashlock,
-                        }
-                    })
-                    .cloned()
-                    .collect()
-            })
-            .unwrap_or_default()
-    }
+    let Ok(hashlock) = <[u8; 32]>::try_from(hashlock) else { return Vec::new() };
+    let seqs: Vec<u64> = HASHLOCK_EVENTS.with_borrow(|index| {
+        index.range((hashlock, 0)..=(hashlock, u64::MAX))
+            .map(|((_, seq), _)| seq)
+            .collect()
+    });
+    seqs.into_iter()
+        .filter_map(get_event)
+        .map(|record| record.event)
+        .collect()
+}
+
+/// Replay protection operations
+pub fn is_request_processed(key: &[u8; 32], now: u64) -> bool {
+    PROCESSED_REQUESTS.with_borrow(|requests| requests.get(key).is_some_and(|expires_at| expires_at > now))
+}
+
+pub fn mark_request_processed(key: [u8; 32], expires_at: u64) {
+    let previous = PROCESSED_REQUESTS.with_borrow_mut(|requests| requests.insert(key, expires_at));
+    PROCESSED_REQUESTS_EXPIRY.with_borrow_mut(|expiry| {
+        if let Some(previous) = previous {
+            expiry.remove(&(previous, key));
+        }
+        expiry.insert((expires_at, key), ());
+    });
+}
+
+/// Remove up to `limit` expired request keys, returning how many were pruned
+pub fn prune_expired_requests(now: u64, limit: usize) -> usize {
+    let expired: Vec<(u64, [u8; 32])> = PROCESSED_REQUESTS_EXPIRY.with_borrow(|expiry| {
+        expiry.iter()
+            .take_while(|((expires_at, _), _)| *expires_at <= now)
+            .take(limit)
+            .map(|(entry, _)| entry)
+            .collect()
+    });
+
+    PROCESSED_REQUESTS_EXPIRY.with_borrow_mut(|expiry| {
+        for entry in &expired {
+            expiry.remove(entry);
+        }
+    });
+    PROCESSED_REQUESTS.with_borrow_mut(|requests| {
+        for (_, key) in &expired {
+            requests.remove(key);
+        }
+    });
+
+    expired.len()
 }
 
 /// Metrics operations
 pub fn get_metrics() -> EscrowMetrics {
-    unsafe {
-        METRICS.as_ref().cloned().unwrap_or_default()
+    METRICS.with_borrow(|metrics| metrics.clone())
+}
+
+/// Fee balance operations: credited as creation fees arrive, debited before a withdrawal is sent
+pub fn fee_balance() -> u64 {
+    FEE_BALANCE.get()
+}
+
+pub fn credit_fees(amount: u64) {
+    FEE_BALANCE.set(FEE_BALANCE.get().saturating_add(amount));
+}
+
+pub fn debit_fees(amount: u64) -> Result<()> {
+    let available = FEE_BALANCE.get();
+    if amount > available {
+        return Err(EscrowError::InsufficientBalance { required: amount.into(), available: available.into() });
     }
+    FEE_BALANCE.set(available - amount);
+    Ok(())
 }
 
 pub fn update_metrics<F>(updater: F)
 where
     F: FnOnce(&mut EscrowMetrics),
 {
-    unsafe {
-        if let Some(metrics) = METRICS.as_mut() {
-            updater(metrics);
+    METRICS.with_borrow_mut(updater);
+}
+
+/// Reset metrics for the given scope, returning the values before the reset
+pub fn reset_metrics(scope: &MetricsResetScope) -> EscrowMetrics {
+    METRICS.with_borrow_mut(|metrics| {
+        let previous = metrics.clone();
+        metrics.reset(scope);
+        previous
+    })
+}
+
+/// Record a snapshot of the current metrics and cycles balance
+pub fn snapshot_metrics(now: u64, cycles_balance: u64) {
+    let snapshot = MetricsSnapshot {
+        timestamp: now,
+        metrics: get_metrics(),
+        cycles_balance: Some(cycles_balance),
+    };
+    METRICS_HISTORY.with_borrow_mut(|history| history.insert(now, snapshot));
+}
+
+/// Cycles balances snapshotted at or after `since`, oldest first
+pub fn cycles_samples(since: u64) -> Vec<(u64, u64)> {
+    METRICS_HISTORY.with_borrow(|history| {
+        history.range(since..)
+            .filter_map(|(timestamp, snapshot)| Some((timestamp, snapshot.cycles_balance?)))
+            .collect()
+    })
+}
+
+/// Cycles balance of the newest snapshot
+pub fn latest_cycles_balance() -> Option<u64> {
+    METRICS_HISTORY.with_borrow(|history| history.last_key_value().and_then(|(_, snapshot)| snapshot.cycles_balance))
+}
+
+/// Latest snapshot taken at or before `timestamp`
+pub fn get_metrics_at(timestamp: u64) -> Option<MetricsSnapshot> {
+    METRICS_HISTORY.with_borrow(|history| {
+        history.range(..=timestamp)
+            .next_back()
+            .map(|(_, snapshot)| snapshot)
+    })
+}
+
+/// Pick snapshots at least `step` apart walking back from the newest, returned oldest first
+fn sample_snapshots<I>(newest_first: I, step: u64, limit: usize) -> Vec<MetricsSnapshot>
+where
+    I: Iterator<Item = MetricsSnapshot>,
+{
+    let mut sampled: Vec<MetricsSnapshot> = Vec::new();
+    for snapshot in newest_first {
+        if sampled.len() >= limit {
+            break;
+        }
+        let due = sampled.last()
+            .map(|last| last.timestamp.saturating_sub(snapshot.timestamp) >= step)
+            .unwrap_or(true);
+        if due {
+            sampled.push(snapshot);
         }
     }
+    sampled.reverse();
+    sampled
+}
+
+/// Most recent snapshots spaced at least `step` nanoseconds apart
+pub fn get_metrics_history(step: u64) -> Vec<MetricsSnapshot> {
+    METRICS_HISTORY.with_borrow(|history| {
+        let newest_first = history.iter().rev().map(|(_, snapshot)| snapshot);
+        sample_snapshots(newest_first, step, MAX_METRICS_HISTORY)
+    })
+}
+
+/// UTC day number of a timestamp (nanoseconds)
+pub fn day_of(timestamp: u64) -> u64 {
+    timestamp / NANOS_PER_DAY
+}
+
+/// Update the activity counters of the day containing `timestamp`
+pub fn update_daily_activity<F>(timestamp: u64, updater: F)
+where
+    F: FnOnce(&mut DailyActivity),
+{
+    let day = day_of(timestamp);
+    DAILY_ACTIVITY.with_borrow_mut(|activity| {
+        let mut counters = activity.get(&day).unwrap_or(DailyActivity { day, ..DailyActivity::default() });
+        updater(&mut counters);
+        activity.insert(day, counters);
+    });
+}
+
+/// One entry per day in `from_day..=to_day`, with zeroed counters for quiet days
+fn fill_days<I>(from_day: u64, to_day: u64, recorded: I) -> Vec<DailyActivity>
+where
+    I: Iterator<Item = DailyActivity>,
+{
+    let mut recorded = recorded.peekable();
+    (from_day..=to_day)
+        .map(|day| match recorded.next_if(|activity| activity.day == day) {
+            Some(activity) => activity,
+            None => DailyActivity { day, ..DailyActivity::default() },
+        })
+        .collect()
+}
+
+/// Activity counters for each day in `from_day..=to_day`
+pub fn get_daily_activity(from_day: u64, to_day: u64) -> Vec<DailyActivity> {
+    DAILY_ACTIVITY.with_borrow(|activity| {
+        fill_days(from_day, to_day, activity.range(from_day..=to_day).map(|(_, day)| day))
+    })
 }
 
 /// Utility functions for storage management
 
 /// Get storage statistics
 pub fn get_storage_stats() -> StorageStats {
-    unsafe {
-        StorageStats {
-            escrows_count: ESCROWS.as_ref().map(|e| e.len()).unwrap_or(0),
-            events_count: EVENTS.as_ref().map(|e| e.len()).unwrap_or(0),
-            authorized_principals_count: AUTHORIZED_PRINCIPALS.as_ref().map(|a| a.len()).unwrap_or(0),
-        }
+    StorageStats {
+        escrows_count: ESCROWS.with_borrow(|escrows| escrows.len() as usize),
+        events_count: EVENTS.with_borrow(|events| events.len()),
+        authorized_principals_count: AUTHORIZED_PRINCIPALS.with_borrow(|auths| auths.len()),
+        processed_requests_count: PROCESSED_REQUESTS.with_borrow(|requests| requests.len()),
+        archived_events_count: EVENT_ARCHIVE.with_borrow(|archive| archive.len()),
+        events_pruned_total: EVENTS_PRUNED_TOTAL.get(),
+        last_event_gc_at: LAST_EVENT_GC_AT.get(),
     }
 }
 
@@ -272,28 +1625,553 @@ pub struct StorageStats {
     pub escrows_count: usize,
     pub events_count: usize,
     pub authorized_principals_count: usize,
+    pub processed_requests_count: u64,
+    pub archived_events_count: u64,     // Full event history in stable memory
+    pub events_pruned_total: u64,       // Events moved out of the hot ring since install/upgrade
+    pub last_event_gc_at: Option<u64>,  // Time of the last GC run
 }
 
 /// Clear all storage (use with caution - only for testing)
 #[cfg(test)]
 pub fn clear_all_storage() {
-    unsafe {
-        ESCROWS = Some(HashMap::new());
-        CONFIG = Some(EscrowConfig::default());
-        AUTHORIZED_PRINCIPALS = Some(Vec::new());
-        EVENTS = Some(Vec::new());
-        METRICS = Some(EscrowMetrics::default());
-    }
+    ESCROWS.with_borrow_mut(|escrows| {
+        let hashlocks: Vec<Vec<u8>> = escrows.iter().map(|(hashlock, _)| hashlock).collect();
+        for hashlock in hashlocks {
+            escrows.remove(&hashlock);
+        }
+    });
+    PENDING_TRANSFERS.with_borrow_mut(|transfers| {
+        let ids: Vec<u64> = transfers.iter().map(|(id, _)| id).collect();
+        for id in ids {
+            transfers.remove(&id);
+        }
+    });
+    ORDERS.with_borrow_mut(|orders| {
+        let ids: Vec<u64> = orders.iter().map(|(id, _)| id).collect();
+        for id in ids {
+            orders.remove(&id);
+        }
+    });
+    ESCROW_INDEXES.set(EscrowIndexes::default());
+    CONFIG.set(EscrowConfig::default());
+    AUTHORIZED_PRINCIPALS.set(Vec::new());
+    ROLES.set(HashMap::new());
+    FEE_BALANCE.set(0);
+    EVENTS.set(VecDeque::new());
+    METRICS.set(EscrowMetrics::default());
 }
 
-/// Pre/post upgrade hooks for stable storage
+/// Save the heap-resident state to stable memory; escrows, config and events already live there
 pub fn pre_upgrade() {
-    // TODO: Implement stable storage serialization
-    // For now, this is a placeholder
+    let state = UpgradeState {
+        metrics: METRICS.take(),
+        authorized_principals: AUTHORIZED_PRINCIPALS.take(),
+        observers: OBSERVERS.take(),
+        resolvers: RESOLVERS.take(),
+        templates: TEMPLATES.take(),
+        chain_configs: CHAIN_CONFIGS.take(),
+        sessions: SESSIONS.take(),
+        evm_links: EVM_LINKS.take(),
+        volumes: VOLUMES.take(),
+        profiles: PROFILES.take(),
+        pending_treasury_rotation: PENDING_TREASURY_ROTATION.take(),
+        roles: Some(ROLES.take()),
+        fee_balance: Some(FEE_BALANCE.get()),
+        updates_since_commitment: UPDATES_SINCE_COMMITMENT.get(),
+        events_pruned_total: EVENTS_PRUNED_TOTAL.get(),
+        last_event_gc_at: LAST_EVENT_GC_AT.get(),
+    };
+    UPGRADE_STATE.with_borrow_mut(|cell| {
+        cell.set(encode_upgrade_state(&state)).expect("failed to persist upgrade state");
+    });
 }
 
+/// Reattach the stable structures and restore the heap state saved by `pre_upgrade`
 pub fn post_upgrade() {
-    // TODO: Implement stable storage deserialization
-    // For now, reinitialize
     init_storage();
+    reindex_escrows();
+    backfill_escrow_types();
+    let saved = UPGRADE_STATE.with_borrow(|cell| decode_upgrade_state(cell.get()));
+    if let Some(state) = saved {
+        METRICS.set(state.metrics);
+        AUTHORIZED_PRINCIPALS.set(state.authorized_principals);
+        OBSERVERS.set(state.observers);
+        RESOLVERS.set(state.resolvers);
+        TEMPLATES.set(state.templates);
+        CHAIN_CONFIGS.set(state.chain_configs);
+        SESSIONS.set(state.sessions);
+        EVM_LINKS.set(state.evm_links);
+        VOLUMES.set(state.volumes);
+        PROFILES.set(state.profiles);
+        PENDING_TREASURY_ROTATION.set(state.pending_treasury_rotation);
+        ROLES.set(state.roles.unwrap_or_default());
+        FEE_BALANCE.set(state.fee_balance.unwrap_or_default());
+        UPDATES_SINCE_COMMITMENT.set(state.updates_since_commitment);
+        EVENTS_PRUNED_TOTAL.set(state.events_pruned_total);
+        LAST_EVENT_GC_AT.set(state.last_event_gc_at);
+    }
+    
+    // The snapshot is only valid for this upgrade; the next pre_upgrade writes a fresh one
+    UPGRADE_STATE.with_borrow_mut(|cell| {
+        cell.set(Vec::new()).expect("failed to clear upgrade state");
+    });
+}
+
+fn encode_upgrade_state(state: &UpgradeState) -> Vec<u8> {
+    candid::encode_one(state).expect("upgrade state encoding cannot fail")
+}
+
+/// Decode a saved upgrade state; a snapshot that is present but unreadable traps, so the
+/// upgrade rolls back instead of starting without resolvers, sessions or metrics
+fn decode_upgrade_state(bytes: &[u8]) -> Option<UpgradeState> {
+    if bytes.is_empty() {
+        return None;
+    }
+    let state = candid::decode_one(bytes)
+        .or_else(|_| candid::decode_one::<LegacyUpgradeState>(bytes).map(UpgradeState::from))
+        .expect("saved upgrade state must decode");
+    Some(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::EscrowType;
+
+    #[test]
+    fn test_state_digest_ignores_insertion_order() {
+        let config = EscrowConfig::default();
+        let a = Principal::from_slice(&[1]);
+        let b = Principal::from_slice(&[2]);
+
+        let digest_ab = compute_state_digest(&[], &config, &[a, b]);
+        let digest_ba = compute_state_digest(&[], &config, &[b, a]);
+        assert_eq!(digest_ab, digest_ba);
+        assert_eq!(digest_ab.len(), 32);
+    }
+
+    #[test]
+    fn test_upgrade_state_roundtrip() {
+        let mut state = UpgradeState::default();
+        state.metrics.active_escrows_count = 3;
+        state.volumes.insert(Principal::anonymous(), (7, 1_000));
+        state.last_event_gc_at = Some(42);
+
+        let decoded = decode_upgrade_state(&encode_upgrade_state(&state)).unwrap();
+        assert_eq!(decoded.metrics.active_escrows_count, 3);
+        assert_eq!(decoded.volumes.get(&Principal::anonymous()), Some(&(7, 1_000)));
+        assert_eq!(decoded.last_event_gc_at, Some(42));
+        assert!(decode_upgrade_state(&[]).is_none());
+    }
+
+    #[test]
+    fn test_config_envelope_roundtrip() {
+        let config = EscrowConfig { min_amount: 42, ..EscrowConfig::default() };
+        let decoded = decode_config(&encode_config(&config)).unwrap();
+        assert_eq!(decoded.schema_version, CONFIG_SCHEMA_VERSION);
+        assert_eq!(decoded.config.min_amount, 42);
+    }
+
+    #[test]
+    fn test_older_config_decodes_with_defaults() {
+        // A config written before most fields existed
+        #[derive(serde::Serialize)]
+        struct LegacyConfig {
+            min_amount: u64,
+            max_amount: u64,
+        }
+        #[derive(serde::Serialize)]
+        struct LegacyEnvelope {
+            schema_version: u32,
+            config: LegacyConfig,
+        }
+
+        let bytes = serde_cbor::to_vec(&LegacyEnvelope {
+            schema_version: 0,
+            config: LegacyConfig { min_amount: 5_000, max_amount: 9_000 },
+        }).unwrap();
+
+        let decoded = decode_config(&bytes).unwrap();
+        let defaults = EscrowConfig::default();
+        assert_eq!(decoded.schema_version, 0);
+        assert_eq!(decoded.config.min_amount, 5_000);
+        assert_eq!(decoded.config.max_amount, 9_000);
+        assert_eq!(decoded.config.rescue_delay, defaults.rescue_delay);
+        assert_eq!(decoded.config.treasury, defaults.treasury);
+    }
+
+    #[test]
+    fn test_state_digest_covers_config() {
+        let config = EscrowConfig::default();
+        let mut changed = config.clone();
+        changed.min_amount += 1;
+
+        assert_ne!(
+            compute_state_digest(&[], &config, &[]),
+            compute_state_digest(&[], &changed, &[]),
+        );
+    }
+
+    #[test]
+    fn test_metrics_reset_scopes() {
+        let metrics = EscrowMetrics {
+            total_escrows_created: 5,
+            total_escrows_completed: 3,
+            total_escrows_cancelled: 1,
+            total_volume_icp: 1_000,
+            total_fees_collected: 10,
+            total_surplus_collected: 7,
+            active_escrows_count: 1,
+            total_cycles_deposited: Some(9),
+        };
+
+        let mut counters = metrics.clone();
+        counters.reset(&MetricsResetScope::Counters);
+        assert_eq!(counters.total_escrows_created, 0);
+        assert_eq!(counters.total_volume_icp, 1_000);
+
+        let mut totals = metrics.clone();
+        totals.reset(&MetricsResetScope::Totals);
+        assert_eq!(totals.total_escrows_created, 5);
+        assert_eq!(totals.total_fees_collected, 0);
+
+        let mut all = metrics;
+        all.reset(&MetricsResetScope::All);
+        assert_eq!(all.total_escrows_completed, 0);
+        assert_eq!(all.total_surplus_collected, 0);
+        assert_eq!(all.active_escrows_count, 1);
+        assert_eq!(all.total_cycles_deposited, Some(9));
+    }
+
+    fn event_log(seqs: std::ops::Range<u64>) -> VecDeque<EventRecord> {
+        seqs.map(|seq| EventRecord {
+            seq,
+            event: EscrowEvent::ICPTxRecorded {
+                hashlock: vec![0; 32],
+                tx_hash: String::new(),
+                timestamp: seq * 10,
+            },
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_event_gc_drops_oldest_in_batches() {
+        let mut events = event_log(0..10);
+
+        assert_eq!(drain_excess_events(&mut events, 4, 3), vec![0, 1, 2]);
+        assert_eq!(events.front().map(|record| record.seq), Some(3));
+
+        assert_eq!(drain_excess_events(&mut events, 4, 3), vec![3, 4, 5]);
+        assert_eq!(events.len(), 4);
+
+        assert!(drain_excess_events(&mut events, 4, 3).is_empty());
+    }
+
+    #[test]
+    fn test_events_since_resumes_by_id() {
+        clear_all_storage();
+        let first = next_event_seq();
+        for timestamp in 0..5 {
+            add_event(EscrowEvent::EscrowExpired { hashlock: vec![timestamp as u8; 32], timestamp });
+        }
+
+        let page = get_events_since(first, 3);
+        let ids: Vec<u64> = page.events.iter().map(|event| event.event_id).collect();
+        assert_eq!(ids, vec![first, first + 1, first + 2]);
+        assert_eq!(page.events[2].timestamp, 2);
+        assert_eq!(page.latest_event_id, Some(first + 4));
+
+        let rest = get_events_since(page.next_event_id, 3);
+        assert_eq!(rest.events.len(), 2);
+        assert_eq!(rest.next_event_id, first + 5);
+
+        // Caught up: nothing newer, and the cursor stays put
+        let idle = get_events_since(rest.next_event_id, 3);
+        assert!(idle.events.is_empty());
+        assert_eq!(idle.next_event_id, first + 5);
+    }
+
+    #[test]
+    fn test_processed_requests_expire_after_ttl() {
+        let key = [3; 32];
+        assert!(!is_request_processed(&key, 0));
+
+        mark_request_processed(key, 100);
+        assert!(is_request_processed(&key, 99));
+        // A key stops blocking replays at its expiry, before pruning removes it
+        assert!(!is_request_processed(&key, 100));
+
+        // Marking again moves the expiry rather than adding a second index entry
+        mark_request_processed(key, 200);
+        assert!(is_request_processed(&key, 150));
+        assert_eq!(PROCESSED_REQUESTS_EXPIRY.with_borrow(|expiry| expiry.len()), 1);
+    }
+
+    #[test]
+    fn test_prune_expired_requests_in_expiry_order() {
+        for (byte, expires_at) in [(1u8, 30), (2, 10), (3, 20), (4, 50)] {
+            mark_request_processed([byte; 32], expires_at);
+        }
+
+        // Only expired keys go, oldest first and at most `limit` per call
+        assert_eq!(prune_expired_requests(25, 1), 1);
+        assert!(!PROCESSED_REQUESTS.with_borrow(|requests| requests.contains_key(&[2; 32])));
+        assert!(PROCESSED_REQUESTS.with_borrow(|requests| requests.contains_key(&[3; 32])));
+        assert_eq!(prune_expired_requests(25, 10), 1);
+        assert_eq!(prune_expired_requests(25, 10), 0);
+
+        assert_eq!(prune_expired_requests(30, 10), 1);
+        assert!(is_request_processed(&[4; 32], 30));
+        assert_eq!(PROCESSED_REQUESTS.with_borrow(|requests| requests.len()), 1);
+        assert_eq!(PROCESSED_REQUESTS_EXPIRY.with_borrow(|expiry| expiry.len()), 1);
+    }
+
+    #[test]
+    fn test_events_for_hashlock_come_from_the_index() {
+        clear_all_storage();
+        for timestamp in 0..6 {
+            add_event(EscrowEvent::EscrowExpired { hashlock: vec![timestamp as u8 % 2; 32], timestamp });
+        }
+        let timestamps = |hashlock: &[u8]| -> Vec<u64> {
+            get_events_for_hashlock(hashlock).iter().map(EscrowEvent::timestamp).collect()
+        };
+        assert_eq!(timestamps(&[1; 32]), vec![1, 3, 5]);
+
+        // Events aged out of the hot ring are read back from the archive
+        EVENTS.set(VecDeque::new());
+        assert_eq!(timestamps(&[0; 32]), vec![0, 2, 4]);
+        assert!(get_events_for_hashlock(&[7; 32]).is_empty());
+    }
+
+    #[test]
+    fn test_aged_events_drain_in_batches_up_to_cutoff() {
+        let mut events = event_log(0..10);
+
+        // Events 0..5 (timestamps 0..40) are older than the cutoff
+        assert_eq!(drain_aged_events(&mut events, 45, 3), vec![0, 1, 2]);
+        assert_eq!(drain_aged_events(&mut events, 45, 3), vec![3, 4]);
+        assert!(drain_aged_events(&mut events, 45, 3).is_empty());
+        assert_eq!(events.front().map(|record| record.seq), Some(5));
+    }
+
+    fn snapshots_at(timestamps: &[u64]) -> Vec<MetricsSnapshot> {
+        timestamps.iter()
+            .map(|&timestamp| MetricsSnapshot { timestamp, metrics: EscrowMetrics::default(), cycles_balance: None })
+            .collect()
+    }
+
+    #[test]
+    fn test_cycles_deposits_accumulate_past_u64() {
+        let mut metrics = EscrowMetrics::default();
+        metrics.record_cycles_deposit(u128::from(u64::MAX));
+        metrics.record_cycles_deposit(5);
+        let total = u128::from(u64::MAX) + 5;
+        assert_eq!(metrics.total_cycles_deposited, Some(total));
+
+        // The wide total survives both the candid and CBOR encodings
+        let candid: EscrowMetrics = candid::decode_one(&candid::encode_one(&metrics).unwrap()).unwrap();
+        assert_eq!(candid.total_cycles_deposited, Some(total));
+        let snapshot = MetricsSnapshot { timestamp: 1, metrics, cycles_balance: None };
+        let stored = MetricsSnapshot::from_bytes(snapshot.to_bytes());
+        assert_eq!(stored.metrics.total_cycles_deposited, Some(total));
+    }
+
+    #[test]
+    fn test_metrics_history_sampling() {
+        let newest_first = snapshots_at(&[100, 90, 80, 70, 60, 50]);
+
+        let sampled = sample_snapshots(newest_first.clone().into_iter(), 20, 10);
+        let timestamps: Vec<u64> = sampled.iter().map(|s| s.timestamp).collect();
+        assert_eq!(timestamps, vec![60, 80, 100]);
+
+        let limited = sample_snapshots(newest_first.into_iter(), 0, 2);
+        let timestamps: Vec<u64> = limited.iter().map(|s| s.timestamp).collect();
+        assert_eq!(timestamps, vec![90, 100]);
+    }
+
+    #[test]
+    fn test_metrics_snapshot_decodes_with_missing_counters() {
+        let snapshot = MetricsSnapshot { timestamp: 7, metrics: EscrowMetrics::default(), cycles_balance: Some(5) };
+        let mut value = serde_cbor::value::to_value(&snapshot).unwrap();
+        if let serde_cbor::Value::Map(fields) = &mut value {
+            // Snapshots from before cycles were sampled have no balance
+            fields.remove(&serde_cbor::Value::Text("cycles_balance".into()));
+            if let Some(serde_cbor::Value::Map(metrics)) = fields.get_mut(&serde_cbor::Value::Text("metrics".into())) {
+                metrics.remove(&serde_cbor::Value::Text("total_surplus_collected".into()));
+            }
+        }
+        let bytes = serde_cbor::to_vec(&value).unwrap();
+
+        let decoded = MetricsSnapshot::from_bytes(Cow::Owned(bytes));
+        assert_eq!(decoded.timestamp, 7);
+        assert_eq!(decoded.metrics.total_surplus_collected, 0);
+        assert_eq!(decoded.cycles_balance, None);
+    }
+
+    #[test]
+    fn test_daily_activity_fills_quiet_days() {
+        let recorded = vec![
+            DailyActivity { day: 11, created: 2, completed: 1, cancelled: 0 },
+            DailyActivity { day: 13, created: 0, completed: 0, cancelled: 3 },
+        ];
+        let days = fill_days(10, 14, recorded.into_iter());
+
+        assert_eq!(days.iter().map(|activity| activity.day).collect::<Vec<_>>(), vec![10, 11, 12, 13, 14]);
+        assert_eq!(days[1].created, 2);
+        assert_eq!(days[2], DailyActivity { day: 12, ..DailyActivity::default() });
+        assert_eq!(days[3].cancelled, 3);
+        assert_eq!(day_of(NANOS_PER_DAY * 3 + 1), 3);
+    }
+
+    #[test]
+    fn test_take_page_hands_back_cursor() {
+        let entries = vec![(vec![1], 'a'), (vec![2], 'b'), (vec![3], 'c'), (vec![4], 'd')];
+
+        let (page, cursor) = take_page(entries.clone().into_iter(), 3);
+        assert_eq!(page.iter().map(|(_, v)| *v).collect::<String>(), "abc");
+        assert_eq!(cursor, Some(vec![3]));
+
+        let (page, cursor) = take_page(entries.into_iter().skip(3), 3);
+        assert_eq!(page.iter().map(|(_, v)| *v).collect::<String>(), "d");
+        assert_eq!(cursor, None);
+    }
+    #[test]
+    fn test_pending_transfer_queue() {
+        let transfer = PendingTransfer {
+            id: 0,
+            hashlock: vec![1; 32],
+            recipient: Principal::anonymous(),
+            amount: 1_000,
+            memo: 7,
+            queued_at: 1,
+            attempts: 0,
+            last_attempt_at: None,
+        };
+        assert_eq!(queue_pending_transfer(transfer.clone()), 0);
+        assert_eq!(queue_pending_transfer(transfer), 1);
+
+        record_transfer_attempt(0, 5);
+        let queued = get_pending_transfers(10);
+        assert_eq!(queued.len(), 2);
+        assert_eq!((queued[0].attempts, queued[0].last_attempt_at), (1, Some(5)));
+        assert_eq!(queued[1].attempts, 0);
+
+        assert_eq!(remove_pending_transfer(0).map(|transfer| transfer.id), Some(0));
+        assert_eq!(get_pending_transfers(10).len(), 1);
+        // Ids keep increasing after the oldest entry is paid
+        assert_eq!(queue_pending_transfer(get_pending_transfers(1)[0].clone()), 2);
+    }
+
+    fn indexed_escrow(state: EscrowState, maker: &str) -> ICPEscrow {
+        use crate::types::{EscrowImmutables, FeeBreakdown, Timelocks};
+        ICPEscrow {
+            immutables: EscrowImmutables {
+                order_hash: vec![9; 32],
+                hashlock: vec![1; 32],
+                maker: maker.to_string(),
+                taker: "taker".to_string(),
+                token: "0x0".to_string(),
+                amount: 1_000_000,
+                safety_deposit: 100_000,
+                timelocks: Timelocks { withdrawal: 10, public_withdrawal: 20, cancellation: 30, public_cancellation: None, deployed_at: 0, dst: None },
+                maker_min_amount: None,
+                min_received: None,
+                safety_deposit_funding: None,
+                chain_id: None,
+                icp_token: None,
+                hash_algorithm: None,
+                merkle_root: None,
+                parts_count: None,
+                auction: None,
+                metadata: None,
+            },
+            state,
+            icp_tx_hash: None,
+            evm_address: None,
+            created_at: 0,
+            completed_at: None,
+            revealed_secret: None,
+            surplus_captured: 0,
+            safety_deposit_held: 100_000,
+            fee_breakdown: FeeBreakdown::default(),
+            settlement_blocks: Vec::new(),
+            deposit_blocks: Vec::new(),
+            notes: Vec::new(),
+            freeze: None,
+            rescue_requested_at: None,
+            processing: None,
+            creation_fee_block: None,
+            migration: None,
+            cycles: None,
+            token_fee: None,
+            subaccount: None,
+            partial_fill: None,
+            funding: None,
+            evm_verification: None,
+            evm_immutables: None,
+            evm_withdrawal: None,
+            rescue_balance: None,
+            transfer_legs: None,
+            escrow_type: None,
+        }
+    }
+
+    #[test]
+    fn test_escrow_indexes_follow_state_changes() {
+        let mut indexes = EscrowIndexes::default();
+        let active = indexed_escrow(EscrowState::Active, "alice");
+        indexes.insert(&[1], &active);
+        indexes.insert(&[2], &indexed_escrow(EscrowState::Active, "bob"));
+
+        assert_eq!(indexes.by_state[&EscrowState::Active].len(), 2);
+        assert_eq!(indexes.by_maker["alice"], BTreeSet::from([vec![1]]));
+        assert_eq!(indexes.by_order_hash[&vec![9; 32]].len(), 2);
+        assert_eq!(indexes.by_memo[&memo_key(&[1])], BTreeSet::from([vec![1]]));
+
+        let completed = ICPEscrow { state: EscrowState::Completed, ..active.clone() };
+        indexes.remove(&[1], &active);
+        indexes.insert(&[1], &completed);
+        assert_eq!(indexes.by_state[&EscrowState::Active], BTreeSet::from([vec![2]]));
+        assert_eq!(indexes.by_state[&EscrowState::Completed], BTreeSet::from([vec![1]]));
+
+        // Emptied entries are dropped rather than left behind
+        indexes.remove(&[2], &indexed_escrow(EscrowState::Active, "bob"));
+        assert!(!indexes.by_state.contains_key(&EscrowState::Active));
+        assert!(!indexes.by_maker.contains_key("bob"));
+        assert_eq!(indexes.by_taker["taker"], BTreeSet::from([vec![1]]));
+    }
+
+    #[test]
+    fn test_certified_escrow_leaf_withholds_secret() {
+        let completed = ICPEscrow { state: EscrowState::Completed, ..indexed_escrow(EscrowState::Active, "alice") };
+        let revealed = ICPEscrow { revealed_secret: Some(vec![7; 32]), ..completed.clone() };
+        assert_eq!(escrow_leaf(&revealed), escrow_leaf(&completed));
+
+        // The stored record keeps the secret for its parties
+        clear_all_storage();
+        insert_escrow(vec![1; 32], revealed).unwrap();
+        assert_eq!(get_escrow(&[1; 32]).unwrap().revealed_secret, Some(vec![7; 32]));
+    }
+
+    #[test]
+    fn test_backfill_escrow_types_from_creation_events() {
+        clear_all_storage();
+        insert_escrow(vec![1; 32], indexed_escrow(EscrowState::Active, "alice")).unwrap();
+        insert_escrow(vec![2; 32], indexed_escrow(EscrowState::Active, "bob")).unwrap();
+        add_event(EscrowEvent::EscrowCreated {
+            hashlock: vec![1; 32],
+            escrow_type: EscrowType::Destination,
+            maker: "alice".to_string(),
+            taker: "taker".to_string(),
+            amount: 1_000,
+            metadata: None,
+            timestamp: 0,
+        });
+
+        backfill_escrow_types();
+        assert_eq!(get_escrow(&[1; 32]).unwrap().escrow_type, Some(EscrowType::Destination));
+        // Without a creation event the direction stays unknown rather than guessed
+        assert_eq!(get_escrow(&[2; 32]).unwrap().escrow_type, None);
+    }
 }
@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use crate::{cycles, orders, storage, watchdog};
+use crate::utils::current_time;
+
+/// How often expired replay-protection keys are pruned
+const REQUEST_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Maximum number of expired keys removed per tick
+const REQUEST_PRUNE_BATCH: usize = 500;
+
+/// How often the event log is trimmed back to its retention cap
+const EVENT_GC_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Maximum number of events dropped per tick
+const EVENT_GC_BATCH: usize = 500;
+
+/// How often events past the configured retention window are pruned
+const EVENT_RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Maximum number of aged events pruned per tick
+const EVENT_RETENTION_BATCH: usize = 200;
+
+/// How often a metrics snapshot, with the cycles balance, is recorded for history queries
+const METRICS_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often escrows stuck in Processing are checked against the ledger
+const PROCESSING_WATCHDOG_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// How often announced escrows past their funding deadline are expired
+const FUNDING_EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Maximum number of unfunded escrows expired per tick
+const FUNDING_EXPIRY_BATCH: usize = 200;
+
+/// How often queued settlement transfers are retried
+const PENDING_TRANSFER_RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Maximum number of queued transfers retried per tick
+const PENDING_TRANSFER_RETRY_BATCH: usize = 50;
+
+/// How often announced orders past their expiry are closed
+const ORDER_EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Maximum number of orders expired per tick
+const ORDER_EXPIRY_BATCH: usize = 200;
+
+/// Register periodic maintenance jobs (timers do not survive upgrades)
+pub fn start_timers() {
+    ic_cdk_timers::set_timer_interval(REQUEST_PRUNE_INTERVAL, || {
+        storage::prune_expired_requests(current_time(), REQUEST_PRUNE_BATCH);
+    });
+    ic_cdk_timers::set_timer_interval(EVENT_GC_INTERVAL, || {
+        storage::prune_events(current_time(), EVENT_GC_BATCH);
+    });
+    ic_cdk_timers::set_timer_interval(EVENT_RETENTION_INTERVAL, || {
+        let retention = storage::get_config().event_retention;
+        storage::prune_aged_events(current_time(), retention, EVENT_RETENTION_BATCH);
+    });
+    ic_cdk_timers::set_timer_interval(METRICS_SNAPSHOT_INTERVAL, || {
+        cycles::record_sample(current_time());
+    });
+    ic_cdk_timers::set_timer_interval(PROCESSING_WATCHDOG_INTERVAL, || {
+        ic_cdk::futures::spawn(watchdog::run());
+    });
+    ic_cdk_timers::set_timer_interval(FUNDING_EXPIRY_INTERVAL, || {
+        watchdog::expire_unfunded(current_time(), FUNDING_EXPIRY_BATCH);
+    });
+    ic_cdk_timers::set_timer_interval(PENDING_TRANSFER_RETRY_INTERVAL, || {
+        ic_cdk::futures::spawn(async {
+            watchdog::retry_pending_transfers(PENDING_TRANSFER_RETRY_BATCH).await;
+        });
+    });
+    ic_cdk_timers::set_timer_interval(ORDER_EXPIRY_INTERVAL, || {
+        orders::expire(current_time(), ORDER_EXPIRY_BATCH);
+    });
+}
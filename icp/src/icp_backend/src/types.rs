@@ -1,46 +1,395 @@
-use candid::{CandidType, Deserialize, Principal};
+use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_ledger_types::{Subaccount, DEFAULT_SUBACCOUNT};
+use serde::Serialize;
+use crate::utils;
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct ICPAddress {
     pub address: String,
 }
 
+/// Stages of a Fusion+ swap, in the order of the Solidity `TimelocksLib.Stage` enum
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimelockStage {
+    SrcWithdrawal,
+    SrcPublicWithdrawal,
+    SrcCancellation,
+    SrcPublicCancellation,
+    DstWithdrawal,
+    DstPublicWithdrawal,
+    DstCancellation,
+}
+
+impl TimelockStage {
+    pub const ALL: [TimelockStage; 7] = [
+        TimelockStage::SrcWithdrawal,
+        TimelockStage::SrcPublicWithdrawal,
+        TimelockStage::SrcCancellation,
+        TimelockStage::SrcPublicCancellation,
+        TimelockStage::DstWithdrawal,
+        TimelockStage::DstPublicWithdrawal,
+        TimelockStage::DstCancellation,
+    ];
+
+    /// Private withdrawal, public withdrawal and cancellation stages of an escrow on `escrow_type`'s side
+    pub fn for_escrow(escrow_type: &EscrowType) -> [TimelockStage; 3] {
+        match escrow_type {
+            EscrowType::Source => [TimelockStage::SrcWithdrawal, TimelockStage::SrcPublicWithdrawal, TimelockStage::SrcCancellation],
+            EscrowType::Destination => [TimelockStage::DstWithdrawal, TimelockStage::DstPublicWithdrawal, TimelockStage::DstCancellation],
+        }
+    }
+}
+
+/// Destination-side stage offsets (seconds from deployment)
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct DstTimelocks {
+    pub withdrawal: u64,
+    pub public_withdrawal: u64,
+    pub cancellation: u64,
+}
+
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct Timelocks {
-    pub withdrawal: u64,           // Private withdrawal period start (seconds from deployment)
-    pub public_withdrawal: u64,    // Public withdrawal period start (seconds from deployment)
-    pub cancellation: u64,         // Cancellation period start (seconds from deployment)
+    pub withdrawal: u64,           // SrcWithdrawal start (seconds from deployment)
+    pub public_withdrawal: u64,    // SrcPublicWithdrawal start (seconds from deployment)
+    pub cancellation: u64,         // SrcCancellation start (seconds from deployment)
+    pub public_cancellation: Option<u64>, // SrcPublicCancellation start (seconds from deployment); None = never public
     pub deployed_at: u64,          // Deployment timestamp (nanoseconds)
+    pub dst: Option<DstTimelocks>, // Dst stages; None = the stages above serve both sides
 }
 
+/// Bit position of the deployment time in a packed timelocks word
+const PACKED_DEPLOYED_AT_SHIFT: usize = 224;
+
 impl Timelocks {
-    pub fn withdrawal_start(&self) -> u64 {
-        self.deployed_at + (self.withdrawal * 1_000_000_000) // Convert seconds to nanoseconds
+    /// Offset of `stage` (seconds from deployment); None for a public cancellation that never opens
+    pub fn offset(&self, stage: TimelockStage) -> Option<u64> {
+        let dst = self.dst.clone().unwrap_or(DstTimelocks {
+            withdrawal: self.withdrawal,
+            public_withdrawal: self.public_withdrawal,
+            cancellation: self.cancellation,
+        });
+        match stage {
+            TimelockStage::SrcWithdrawal => Some(self.withdrawal),
+            TimelockStage::SrcPublicWithdrawal => Some(self.public_withdrawal),
+            TimelockStage::SrcCancellation => Some(self.cancellation),
+            TimelockStage::SrcPublicCancellation => self.public_cancellation,
+            TimelockStage::DstWithdrawal => Some(dst.withdrawal),
+            TimelockStage::DstPublicWithdrawal => Some(dst.public_withdrawal),
+            TimelockStage::DstCancellation => Some(dst.cancellation),
+        }
     }
 
-    pub fn public_withdrawal_start(&self) -> u64 {
-        self.deployed_at + (self.public_withdrawal * 1_000_000_000)
+    /// Start of `stage` (nanoseconds); None if it never opens
+    pub fn start(&self, stage: TimelockStage) -> Option<u64> {
+        self.offset(stage).map(|offset| self.deployed_at + (offset * 1_000_000_000)) // Convert seconds to nanoseconds
     }
 
     pub fn cancellation_start(&self) -> u64 {
         self.deployed_at + (self.cancellation * 1_000_000_000)
     }
 
+    pub fn public_cancellation_start(&self) -> Option<u64> {
+        self.start(TimelockStage::SrcPublicCancellation)
+    }
+
     pub fn rescue_start(&self, rescue_delay: u64) -> u64 {
         self.deployed_at + rescue_delay
     }
+
+    /// Whether each side's stages are strictly increasing, source public cancellation last
+    pub fn stages_increasing(&self) -> bool {
+        let src = self.withdrawal < self.public_withdrawal
+            && self.public_withdrawal < self.cancellation
+            && self.public_cancellation.is_none_or(|start| start > self.cancellation);
+        let dst = self.dst.as_ref()
+            .is_none_or(|dst| dst.withdrawal < dst.public_withdrawal && dst.public_withdrawal < dst.cancellation);
+        src && dst
+    }
+
+    /// Latest stage offset on either side (seconds from deployment)
+    pub fn last_offset(&self) -> u64 {
+        TimelockStage::ALL.iter().filter_map(|stage| self.offset(*stage)).max().unwrap_or_default()
+    }
+
+    /// The timelocks as the 32-byte big-endian word Solidity's `TimelocksLib` packs: stage `i`
+    /// as a uint32 offset at bit `32 * i`, and the deployment time in seconds in the top 32 bits.
+    /// A public cancellation that never opens is encoded as the largest offset.
+    pub fn to_packed(&self) -> Result<[u8; 32]> {
+        let field = |value: u64| u32::try_from(value).map_err(|_| EscrowError::InvalidTime);
+        let mut word = [0u8; 32];
+        let mut put = |shift: usize, value: u32| {
+            let end = 32 - shift / 8;
+            word[end - 4..end].copy_from_slice(&value.to_be_bytes());
+        };
+        put(PACKED_DEPLOYED_AT_SHIFT, field(self.deployed_at / 1_000_000_000)?);
+        for stage in TimelockStage::ALL {
+            let offset = self.offset(stage).unwrap_or(u64::from(u32::MAX));
+            put(stage as usize * 32, field(offset)?);
+        }
+        Ok(word)
+    }
+
+    /// Decode a packed `TimelocksLib` word (see `to_packed`)
+    pub fn from_packed(word: &[u8]) -> Result<Timelocks> {
+        if word.len() != 32 {
+            return Err(EscrowError::InvalidTime);
+        }
+        let get = |shift: usize| {
+            let end = 32 - shift / 8;
+            u64::from(u32::from_be_bytes(word[end - 4..end].try_into().expect("slice is four bytes")))
+        };
+        let stage = |stage: TimelockStage| get(stage as usize * 32);
+        Ok(Timelocks {
+            withdrawal: stage(TimelockStage::SrcWithdrawal),
+            public_withdrawal: stage(TimelockStage::SrcPublicWithdrawal),
+            cancellation: stage(TimelockStage::SrcCancellation),
+            public_cancellation: Some(stage(TimelockStage::SrcPublicCancellation)).filter(|offset| *offset != u64::from(u32::MAX)),
+            deployed_at: get(PACKED_DEPLOYED_AT_SHIFT) * 1_000_000_000,
+            dst: Some(DstTimelocks {
+                withdrawal: stage(TimelockStage::DstWithdrawal),
+                public_withdrawal: stage(TimelockStage::DstPublicWithdrawal),
+                cancellation: stage(TimelockStage::DstCancellation),
+            }),
+        })
+    }
+}
+
+/// Timelock offsets (seconds from deployment) a template applies
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct TimelockPreset {
+    pub withdrawal: u64,
+    pub public_withdrawal: u64,
+    pub cancellation: u64,
+}
+
+/// Treasury-registered escrow parameters that integrators reference by ID at creation
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct EscrowTemplate {
+    pub id: String,
+    pub token: String,
+    pub min_amount: u128,          // Allowed amount range for escrows using this template
+    pub max_amount: u128,
+    pub safety_deposit: u128,
+    pub timelocks: TimelockPreset,
+    pub creation_fee: Option<u64>, // Overrides the configured creation fee
+}
+
+/// Longest template ID accepted
+pub const MAX_TEMPLATE_ID_LEN: usize = 64;
+
+impl EscrowTemplate {
+    /// Reject templates that could never produce a valid escrow
+    pub fn validate(&self, config: &EscrowConfig) -> Result<()> {
+        if self.id.is_empty() || self.id.len() > MAX_TEMPLATE_ID_LEN {
+            return Err(EscrowError::InvalidConfig(format!(
+                "template id must be 1-{} bytes", MAX_TEMPLATE_ID_LEN
+            )));
+        }
+        if self.min_amount > self.max_amount {
+            return Err(EscrowError::InvalidConfig(format!(
+                "template min_amount ({}) exceeds max_amount ({})", self.min_amount, self.max_amount
+            )));
+        }
+        if self.safety_deposit < config.min_safety_deposit {
+            return Err(EscrowError::InvalidConfig(format!(
+                "template safety_deposit ({}) is below the configured minimum ({})",
+                self.safety_deposit, config.min_safety_deposit
+            )));
+        }
+        if self.timelocks.withdrawal >= self.timelocks.public_withdrawal
+            || self.timelocks.public_withdrawal >= self.timelocks.cancellation {
+            return Err(EscrowError::InvalidConfig("template timelocks must be strictly increasing".to_string()));
+        }
+        if self.creation_fee.is_some_and(|fee| fee > MAX_CREATION_FEE) {
+            return Err(EscrowError::InvalidConfig(format!(
+                "template creation_fee exceeds the cap of {} e8s", MAX_CREATION_FEE
+            )));
+        }
+        Ok(())
+    }
+
+    /// Fill the template's token, deposit and timelocks into `immutables`,
+    /// and check its amount against the template's range
+    pub fn apply(&self, immutables: &mut EscrowImmutables) -> Result<()> {
+        if immutables.amount < self.min_amount || immutables.amount > self.max_amount {
+            return Err(EscrowError::InvalidAmount);
+        }
+        immutables.token = self.token.clone();
+        immutables.safety_deposit = self.safety_deposit;
+        immutables.timelocks.withdrawal = self.timelocks.withdrawal;
+        immutables.timelocks.public_withdrawal = self.timelocks.public_withdrawal;
+        immutables.timelocks.cancellation = self.timelocks.cancellation;
+        // One preset serves both sides of the swap
+        immutables.timelocks.dst = None;
+        Ok(())
+    }
+}
+
+/// How the canister judges finality on one EVM chain
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChainConfig {
+    pub chain_id: u64,
+    pub confirmations: u32,        // Blocks on top of a transaction before it counts as final
+    pub rpc_providers: Vec<String>, // HTTPS endpoints queried for chain state
+    pub max_gas_price: u64,        // Highest gas price (wei) the canister will pay when submitting transactions
+    pub finality_lag: u64,         // Extra wait after the confirmations are reached (seconds)
+}
+
+/// Most RPC providers configured per chain
+pub const MAX_RPC_PROVIDERS: usize = 8;
+
+/// Longest RPC provider URL accepted
+pub const MAX_RPC_URL_LEN: usize = 256;
+
+impl ChainConfig {
+    /// Reject configs that could never confirm a transaction
+    pub fn validate(&self) -> Result<()> {
+        if !utils::is_evm_chain(self.chain_id) {
+            return Err(EscrowError::InvalidConfig(format!(
+                "chain {} is not an EVM chain", self.chain_id
+            )));
+        }
+        if self.confirmations == 0 {
+            return Err(EscrowError::InvalidConfig("confirmations must be at least 1".to_string()));
+        }
+        if self.rpc_providers.is_empty() || self.rpc_providers.len() > MAX_RPC_PROVIDERS {
+            return Err(EscrowError::InvalidConfig(format!(
+                "between 1 and {} rpc providers are required", MAX_RPC_PROVIDERS
+            )));
+        }
+        if let Some(url) = self.rpc_providers.iter().find(|url| !url.starts_with("https://") || url.len() > MAX_RPC_URL_LEN) {
+            return Err(EscrowError::InvalidConfig(format!(
+                "rpc provider {} must be an https URL of at most {} bytes", url, MAX_RPC_URL_LEN
+            )));
+        }
+        if self.max_gas_price == 0 {
+            return Err(EscrowError::InvalidConfig("max_gas_price must be non-zero".to_string()));
+        }
+        Ok(())
+    }
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct EscrowImmutables {
     pub order_hash: Vec<u8>,       // 32 bytes - Order hash from EVM
-    pub hashlock: Vec<u8>,         // 32 bytes - SHA256 hash of the secret
+    pub hashlock: Vec<u8>,         // 32 bytes - hash of the secret under `hash_algorithm`
     pub maker: String,             // EVM address as string (the initiator)
     pub taker: String,             // EVM address as string (the counterparty)
     pub token: String,             // EVM token address (0x0000...0000 for ETH)
-    pub amount: u64,               // Amount in smallest unit (wei for ETH, token units)
-    pub safety_deposit: u64,       // Safety deposit in ICP e8s (to prevent griefing)
+    pub amount: u128,              // Amount in smallest unit (wei for ETH, token units)
+    pub safety_deposit: u128,      // Safety deposit in the locked token's base units (to prevent griefing)
     pub timelocks: Timelocks,
+    pub maker_min_amount: Option<u128>, // Maker's minimum acceptable amount; anything above is surplus
+    pub min_received: Option<u128>, // Minimum net payout to the recipient after all fees
+    pub safety_deposit_funding: Option<SafetyDepositFunding>, // Who funds the safety deposit (default: bundled)
+    pub chain_id: Option<u64>,     // EVM chain the order was placed on
+    pub icp_token: Option<Principal>, // ICRC-1 ledger of the locked token; None locks native ICP
+    pub hash_algorithm: Option<HashAlgorithm>, // Hash binding the secret to the hashlock (default: SHA-256)
+    pub merkle_root: Option<Vec<u8>>, // Root of the Merkle tree of secrets when the order fills in parts
+    pub parts_count: Option<u32>,  // Equal parts the order fills in; the tree holds parts_count + 1 secrets
+    pub auction: Option<DutchAuction>, // Time-decaying price the locked amount must meet at creation
+    pub metadata: Option<Vec<u8>>, // Opaque integrator data (frontend order id, referral code, ...), stored as given
+}
+
+/// Fusion+ style Dutch auction: the amount a resolver must lock starts at `maker_min_amount`
+/// plus `start_rate_bps` and decays to `maker_min_amount` plus `end_rate_bps` over `duration`
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct DutchAuction {
+    pub start_time: u64,     // Auction start (ns since epoch); the start rate applies until then
+    pub duration: u64,       // Seconds from start until the end rate applies
+    pub start_rate_bps: u32, // Premium over maker_min_amount at the start
+    pub end_rate_bps: u32,   // Premium over maker_min_amount once the auction ends
+    pub curve: AuctionCurve,
+}
+
+/// How the premium decays between the start and end rates
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum AuctionCurve {
+    Linear,
+    Points(Vec<AuctionPoint>), // Piecewise linear through each point in turn, as Fusion+ auctions are
+}
+
+/// Premium the auction passes through `delay` seconds after its start
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct AuctionPoint {
+    pub delay: u64,
+    pub rate_bps: u32,
+}
+
+/// Highest premium an auction may start at (100%)
+pub const MAX_AUCTION_RATE_BPS: u32 = 10_000;
+
+/// Most intermediate points a piecewise auction curve may have
+pub const MAX_AUCTION_POINTS: usize = 16;
+
+impl DutchAuction {
+    /// Start and end rates bound a non-increasing curve that fits within the duration
+    pub fn validate(&self) -> Result<()> {
+        if self.duration == 0 || self.start_rate_bps > MAX_AUCTION_RATE_BPS || self.end_rate_bps > self.start_rate_bps {
+            return Err(EscrowError::InvalidAuction);
+        }
+        if let AuctionCurve::Points(points) = &self.curve {
+            if points.len() > MAX_AUCTION_POINTS {
+                return Err(EscrowError::InvalidAuction);
+            }
+            let mut previous = AuctionPoint { delay: 0, rate_bps: self.start_rate_bps };
+            for point in points {
+                if point.delay <= previous.delay || point.delay >= self.duration
+                    || point.rate_bps > previous.rate_bps || point.rate_bps < self.end_rate_bps {
+                    return Err(EscrowError::InvalidAuction);
+                }
+                previous = point.clone();
+            }
+        }
+        Ok(())
+    }
+
+    /// Premium in force at `now`, interpolated along the curve
+    pub fn rate_bps_at(&self, now: u64) -> u32 {
+        let elapsed = now.saturating_sub(self.start_time) / 1_000_000_000;
+        if elapsed >= self.duration {
+            return self.end_rate_bps;
+        }
+
+        let start = AuctionPoint { delay: 0, rate_bps: self.start_rate_bps };
+        let end = AuctionPoint { delay: self.duration, rate_bps: self.end_rate_bps };
+        let points: Vec<AuctionPoint> = match &self.curve {
+            AuctionCurve::Linear => vec![start, end],
+            AuctionCurve::Points(points) => std::iter::once(start).chain(points.iter().cloned()).chain(std::iter::once(end)).collect(),
+        };
+        let Some(segment) = points.windows(2).find(|pair| elapsed < pair[1].delay) else {
+            return self.end_rate_bps;
+        };
+        let (from, to) = (&segment[0], &segment[1]);
+        let drop = u64::from(from.rate_bps - to.rate_bps) * (elapsed - from.delay) / (to.delay - from.delay);
+        from.rate_bps - drop as u32
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum HashAlgorithm {
+    Sha256,    // ICP-native hashlocks
+    Keccak256, // EVM hashlocks, as 1inch Fusion+ escrows use
+}
+
+impl HashAlgorithm {
+    /// Every algorithm a hashlock may use, as listed in the protocol spec
+    pub const ALL: [HashAlgorithm; 2] = [HashAlgorithm::Sha256, HashAlgorithm::Keccak256];
+
+    /// Lowercase name published in the protocol spec
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Keccak256 => "keccak256",
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum SafetyDepositFunding {
+    Bundled, // Escrow creator deposits it together with the amount
+    Taker,   // Taker/resolver funds it separately after creation (source escrows only)
 }
 
 #[derive(CandidType, Deserialize, Clone, Debug)]
@@ -51,148 +400,2995 @@ pub struct ICPEscrow {
     pub evm_address: Option<String>,    // EVM address for cross-chain verification
     pub created_at: u64,                // Creation timestamp
     pub completed_at: Option<u64>,      // Completion timestamp
-    pub secret_hash: Option<Vec<u8>>,   // Store secret hash after withdrawal
+    pub revealed_secret: Option<Vec<u8>>, // Plaintext secret once a withdrawal revealed it; queries hide it from outsiders
+    pub surplus_captured: u128,         // Surplus routed to treasury on withdrawal (token base units)
+    pub safety_deposit_held: u128,      // Safety deposit currently held by the canister (token base units)
+    pub fee_breakdown: FeeBreakdown,    // Fees charged versus amounts credited to the parties
+    pub settlement_blocks: Vec<u64>,    // Ledger block indices of payout/refund transfers
+    pub deposit_blocks: Vec<u64>,       // Ledger block indices of deposits into the escrow
+    pub notes: Vec<EncryptedNote>,      // vetKD-encrypted notes only maker and taker can decrypt
+    pub freeze: Option<EscrowFreeze>,   // Set while withdrawals are blocked pending an investigation
+    pub rescue_requested_at: Option<u64>, // When the taker gave notice of a rescue
+    pub processing: Option<ProcessingLock>, // Settlement in flight while the escrow is Processing
+    pub creation_fee_block: Option<u64>, // Ledger block index of the creation fee payment
+    pub migration: Option<MigrationReceipt>, // Signed hand-off to a successor canister, once migrated
+    pub cycles: Option<CycleCosts>,     // Approximate cycles spent on the escrow's lifecycle
+    pub token_fee: Option<u128>,        // Ledger fee of the ICRC-1 token at creation; None for native ICP
+    pub subaccount: Option<Vec<u8>>,    // Canister subaccount holding the escrow's funds; None for escrows predating isolation
+    pub partial_fill: Option<PartialFill>, // Fill progress of a partially fillable escrow; None until its first fill
+    pub funding: Option<FundingWindow>, // Deposit an announced escrow awaits; None for escrows funded at creation
+    pub evm_verification: Option<EvmEscrowStatus>, // Latest EVM RPC check of the recorded EVM escrow; None until checked
+    pub evm_immutables: Option<EvmImmutables>, // Immutables of the EVM escrow, recorded so the canister can withdraw from it
+    pub evm_withdrawal: Option<EvmWithdrawal>, // Withdrawal the canister submitted on the EVM chain, if any
+    pub rescue_balance: Option<u128>, // What a rescue can still take once one has paid out; None before any rescue
+    pub transfer_legs: Option<Vec<TransferLeg>>, // Outgoing transfers with the creation time they were sent under; None before the first
+    pub escrow_type: Option<EscrowType>, // Direction fixed at creation; None only on records predating it until the upgrade backfills them
 }
 
+/// One outgoing transfer of an escrow. Every attempt is sent with the same `created_at_time`, so
+/// when an earlier attempt did reach the ledger, a retry gets back its block instead of paying again.
 #[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
-pub enum EscrowState {
-    Active,      // Escrow is active and waiting for action
-    Completed,   // Escrow completed successfully (secret revealed)
-    Cancelled,   // Escrow was cancelled (timeout reached)
-    Rescued,     // Funds were rescued after delay
+pub struct TransferLeg {
+    pub memo: u64,
+    pub recipient: Principal,
+    pub amount: u128,
+    pub created_at_time: u64,
+    pub block: Option<u64>,        // Set once the ledger accepted the transfer
 }
 
-#[derive(CandidType, Deserialize, Clone, Debug)]
-pub enum EscrowType {
-    Source,      // ICP→EVM (ICP locked on ICP, released when EVM secret revealed)
-    Destination, // EVM→ICP (ICP released when secret from EVM is provided)
+impl TransferLeg {
+    fn is_open_for(&self, memo: u64, recipient: &Principal, amount: u128) -> bool {
+        self.block.is_none() && self.memo == memo && self.recipient == *recipient && self.amount == amount
+    }
 }
 
+/// Record of an escrow as handed to a successor canister, signed by this canister
 #[derive(CandidType, Deserialize, Clone, Debug)]
-pub struct EscrowConfig {
-    pub rescue_delay: u64,         // Rescue delay in nanoseconds (default: 7 days)
-    pub min_amount: u64,           // Minimum ICP amount in e8s
-    pub max_amount: u64,           // Maximum ICP amount in e8s  
-    pub creation_fee: u64,         // Creation fee in ICP e8s
-    pub treasury: Principal,       // Treasury principal for fee collection
-    pub min_safety_deposit: u64,   // Minimum safety deposit required
+pub struct MigrationSnapshot {
+    pub immutables: EscrowImmutables,
+    pub created_at: u64,
+    pub icp_tx_hash: Option<String>,
+    pub evm_address: Option<String>,
+    pub deposit_blocks: Vec<u64>,
+    pub creation_fee_block: Option<u64>,
+    pub safety_deposit_held: u128,
+    pub funds_transferred: u128,   // Base units sent to the successor, net of the ledger fee
+    pub funds_memo: u64,           // Memo of that transfer, so the successor can find it on the ledger
+    pub source_canister: Principal,
+    pub successor: Principal,
+    pub migrated_at: u64,
 }
 
-impl Default for EscrowConfig {
-    fn default() -> Self {
-        Self {
-            rescue_delay: 7 * 24 * 60 * 60 * 1_000_000_000, // 7 days in nanoseconds
-            min_amount: 1_000,                               // 0.00001 ICP
-            max_amount: 100_000_000_000,                    // 1000 ICP
-            creation_fee: 0,                           // 0.0001 ICP
-            treasury: Principal::from_text("f5hu5-c5eqs-4m2bm-fxb27-5mnk2-lpbva-l3tb5-7xv5p-w65wt-a3uyd-lqe").unwrap(),
-            min_safety_deposit: 100_000,                    // 0.001 ICP
-        }
-    }
+/// Signed snapshot kept on a migrated escrow so delivery can be retried
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct MigrationReceipt {
+    pub snapshot: MigrationSnapshot,
+    pub signature: Vec<u8>,        // Threshold ECDSA over SHA-256 of the Candid-encoded snapshot
+    pub delivered: bool,           // Whether the successor accepted the snapshot
 }
 
-// Error types
+/// Deposit an escrow announced with `create_escrow` must receive before it activates
 #[derive(CandidType, Deserialize, Clone, Debug)]
-pub enum EscrowError {
-    InvalidCaller,
-    InvalidSecret,
-    InvalidTime,
-    InvalidAmount,
-    InvalidState,
-    EscrowNotFound,
-    TransferFailed,
-    Unauthorized,
-    InvalidHashlock,
-    InsufficientBalance,
-    InvalidAddress,
-    DuplicateEscrow,
-    ConfigError,
-    CanisterCallSuccLedgerError,
-    CanisterCallError,
-    CanisterCallAndLedgerSuccConversionError,
-    
+pub struct FundingWindow {
+    pub escrow_type: EscrowType,
+    pub required: u128,            // Smallest deposit into the escrow's subaccount that activates it
+    pub deadline: u64,             // The escrow expires if still unfunded after this (nanoseconds)
 }
 
-pub type Result<T> = std::result::Result<T, EscrowError>;
+/// Most escrows `create_escrows_batch` creates in one call
+pub const MAX_BATCH_SIZE: usize = 20;
 
-// Event types for logging
+/// Outcome of `create_escrows_batch`
 #[derive(CandidType, Deserialize, Clone, Debug)]
-pub enum EscrowEvent {
-    EscrowCreated {
-        hashlock: Vec<u8>,
-        escrow_type: EscrowType,
-        maker: String,
-        taker: String,
-        amount: u64,
-        timestamp: u64,
-    },
-    EscrowWithdrawal {
-        hashlock: Vec<u8>,
-        withdrawer: Principal,
-        secret: Vec<u8>,
-        timestamp: u64,
-    },
-    EscrowCancelled {
-        hashlock: Vec<u8>,
-        canceller: Principal,
-        timestamp: u64,
-    },
-    FundsRescued {
-        hashlock: Vec<u8>,
-        rescuer: Principal,
-        amount: u64,
-        timestamp: u64,
-    },
-    ICPTxRecorded {
-        hashlock: Vec<u8>,
-        tx_hash: String,
-        timestamp: u64,
-    },
-    EVMAddressRecorded {
-        hashlock: Vec<u8>,
-        address: String,
-        timestamp: u64,
-    },
+pub struct BatchCreation {
+    pub results: Vec<Result<Vec<u8>>>, // One per entry, in order: its hashlock, or why it was not created
+    pub deposit_block: Option<u64>,    // The single transfer pulling the batch from the caller; None if no entry passed its checks
+    pub refund: Option<Result<u64>>,   // Return of the funds of entries that failed after the pull; None if all were created
+    pub fee_refund: Option<Result<u64>>, // Return of those entries' creation fees, less the ledger fee; None if there were none
 }
 
-// Validation helpers
-impl EscrowImmutables {
-    pub fn validate(&self, config: &EscrowConfig) -> Result<()> {
-        // Validate hashlock length (should be 32 bytes for SHA256)
-        if self.hashlock.len() != 32 {
-            return Err(EscrowError::InvalidHashlock);
-        }
+/// Settlement transfer that failed after an earlier transfer of the same operation went out.
+/// The settlement stands, so the transfer is queued and retried until it is paid.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct PendingTransfer {
+    pub id: u64,
+    pub hashlock: Vec<u8>,         // Escrow whose subaccount the transfer is paid from
+    pub recipient: Principal,
+    pub amount: u128,              // Net of the ledger fee, as originally attempted
+    pub memo: u64,
+    pub queued_at: u64,
+    pub attempts: u32,             // Failed retries so far
+    pub last_attempt_at: Option<u64>,
+}
 
-        // Validate order hash length (should be 32 bytes)
-        if self.order_hash.len() != 32 {
-            return Err(EscrowError::InvalidHashlock);
-        }
+/// What the configured EVM RPC providers report for an escrow's counterpart on its EVM chain
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct EvmEscrowStatus {
+    pub chain_id: u64,
+    pub address: String,           // EVM escrow address checked, as recorded on the escrow
+    pub deployed: bool,            // Contract code found at `address` by every provider
+    pub block_number: u64,         // Lowest confirmed block the providers were queried at
+    pub providers: u32,            // Providers queried; they must all agree
+    pub checked_at: u64,
+}
 
-        // Validate amounts
-        if self.amount < config.min_amount || self.amount > config.max_amount {
-            return Err(EscrowError::InvalidAmount);
+/// Immutables of the EVM counterpart escrow, as its contract takes them in calls
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct EvmImmutables {
+    pub order_hash: Vec<u8>,
+    pub hashlock: Vec<u8>,
+    pub maker: String,             // 0x-prefixed EVM addresses
+    pub taker: String,
+    pub token: String,             // Zero address for the native token
+    pub amount: Nat,               // Token base units; EVM amounts can exceed u64
+    pub safety_deposit: Nat,       // Wei
+    pub timelocks: Vec<u8>,        // Packed Timelocks word, 32 bytes
+}
+
+/// EVM withdrawal transaction signed with the canister's threshold ECDSA key and broadcast
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct EvmWithdrawal {
+    pub tx_hash: String,
+    pub from: String,              // Canister's EVM address
+    pub nonce: u64,
+    pub public: bool,              // Sent as publicWithdraw, the canister being neither maker nor taker there
+    pub submitted_at: u64,
+}
+
+/// Cumulative progress of a partially fillable escrow
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct PartialFill {
+    pub filled_amount: u128,       // Sum of the fills paid out so far
+    pub last_secret_index: u32,    // Index of the latest secret used; each unlocks one fill, in order
+    pub fills: u32,
+}
+
+/// Admin hold placed on every path moving a single escrow's funds out
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct EscrowFreeze {
+    pub reason: String,
+    pub frozen_by: Principal,
+    pub frozen_at: u64,
+}
+
+/// Longest reason accepted when freezing an escrow
+pub const MAX_FREEZE_REASON_LEN: usize = 256;
+
+/// Opaque note attached by a party, encrypted under the escrow's vetKD-derived key
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct EncryptedNote {
+    pub author: Principal,
+    pub ciphertext: Vec<u8>,
+    pub created_at: u64,
+}
+
+/// Reconciliation of the fees an escrow paid versus what its parties received (base units)
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct FeeBreakdown {
+    pub protocol_fees: u128,       // Creation fee plus surplus captured for treasury
+    pub ledger_fees_deducted: u128, // Ledger fees taken out of transfers
+    pub ledger_fees_covered: u128, // Ledger fees the canister paid from its own balance
+    pub amount_credited: u128,     // Total sent to maker/taker after deductions
+}
+
+impl FeeBreakdown {
+    /// Account for the ledger fees of settlement transfers given their gross and net amounts
+    pub fn record_transfers(&mut self, gross: &[u128], net: &[u128], fee: u128) {
+        let fees = fee.saturating_mul(net.iter().filter(|amount| **amount > 0).count() as u128);
+        let deducted = gross.iter().zip(net)
+            .map(|(gross, net)| gross.saturating_sub(*net))
+            .fold(0, u128::saturating_add)
+            .min(fees);
+        self.ledger_fees_deducted = self.ledger_fees_deducted.saturating_add(deducted);
+        self.ledger_fees_covered = self.ledger_fees_covered.saturating_add(fees - deducted);
+    }
+
+    /// Add another escrow's breakdown into this running total
+    pub fn accumulate(&mut self, other: &FeeBreakdown) {
+        self.protocol_fees = self.protocol_fees.saturating_add(other.protocol_fees);
+        self.ledger_fees_deducted = self.ledger_fees_deducted.saturating_add(other.ledger_fees_deducted);
+        self.ledger_fees_covered = self.ledger_fees_covered.saturating_add(other.ledger_fees_covered);
+        self.amount_credited = self.amount_credited.saturating_add(other.amount_credited);
+    }
+}
+
+/// Approximate cycles an escrow's lifecycle has consumed, by phase
+#[derive(CandidType, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct CycleCosts {
+    pub creation: u64,             // Creation call with its deposit and fee transfers
+    pub settlement: u64,           // Withdrawal, cancellation, rescue or migration transfers
+    pub timers: u64,               // Watchdog checks run on the escrow's behalf
+    pub outcalls: u64,             // Signing, key derivation and ledger evidence fetched for the escrow
+}
+
+impl CycleCosts {
+    pub fn total(&self) -> u64 {
+        self.creation + self.settlement + self.timers + self.outcalls
+    }
+
+    /// Add another escrow's costs into this running total
+    pub fn accumulate(&mut self, other: &CycleCosts) {
+        self.creation += other.creation;
+        self.settlement += other.settlement;
+        self.timers += other.timers;
+        self.outcalls += other.outcalls;
+    }
+}
+
+/// Cycle totals across every stored escrow, for calibrating the fee schedule
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct CycleReport {
+    pub escrows_count: u64,
+    pub totals: CycleCosts,
+    pub average_per_escrow: u64,
+    pub generated_at: u64,
+}
+
+/// Fee totals across every stored escrow
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct FeeReport {
+    pub escrows_count: u64,
+    pub totals: FeeBreakdown,
+    pub generated_at: u64,
+}
+
+impl ICPEscrow {
+    /// Direction the escrow was created with, which decides who is paid and who is refunded
+    pub fn escrow_type(&self) -> Result<EscrowType> {
+        self.escrow_type.clone().ok_or(EscrowError::InvalidState)
+    }
+
+    /// The secret that completed the escrow; None until it has
+    pub fn revealed_secret(&self) -> Option<&[u8]> {
+        match (&self.state, &self.revealed_secret) {
+            (EscrowState::Completed, Some(secret)) => Some(secret),
+            _ => None,
         }
+    }
 
-        if self.safety_deposit < config.min_safety_deposit {
-            return Err(EscrowError::InvalidAmount);
+    /// The escrow as served to callers who may not see its secret, and as certified
+    pub fn without_secret(mut self) -> Self {
+        self.revealed_secret = None;
+        self
+    }
+
+    /// Starts (nanoseconds) of the private withdrawal, public withdrawal and cancellation stages
+    /// on this escrow's side of the swap. Escrows without a recorded direction predate the dst
+    /// stages, so either side reads the same offsets for them.
+    pub fn stage_starts(&self) -> [u64; 3] {
+        let escrow_type = self.escrow_type.clone().unwrap_or(EscrowType::Source);
+        TimelockStage::for_escrow(&escrow_type)
+            .map(|stage| self.immutables.timelocks.start(stage).unwrap_or(u64::MAX))
+    }
+
+    /// Start of the public cancellation stage, if the escrow has one. Fusion+ only defines it
+    /// for source escrows; a destination escrow has it only when one set of stages serves both sides.
+    pub fn public_cancellation_start(&self) -> Option<u64> {
+        let timelocks = &self.immutables.timelocks;
+        if self.escrow_type == Some(EscrowType::Destination) && timelocks.dst.is_some() {
+            return None;
         }
+        timelocks.public_cancellation_start()
+    }
 
-        // Validate addresses (basic check for non-empty)
-        if self.maker.is_empty() || self.taker.is_empty() {
-            return Err(EscrowError::InvalidAddress);
+    /// Creation time to send a transfer under: that of an earlier attempt at the same transfer
+    /// that never got a block, or `now` for a new leg, which is recorded here. Stamps about to
+    /// fall out of the ledger's deduplication window are renewed; the ledger would reject them.
+    pub fn open_transfer_leg(&mut self, memo: u64, recipient: Principal, amount: u128, now: u64) -> u64 {
+        let legs = self.transfer_legs.get_or_insert_with(Vec::new);
+        match legs.iter_mut().find(|leg| leg.is_open_for(memo, &recipient, amount)) {
+            Some(leg) => {
+                if now.saturating_sub(leg.created_at_time) > crate::ledger::TRANSFER_STAMP_TTL {
+                    leg.created_at_time = now;
+                }
+                leg.created_at_time
+            }
+            None => {
+                legs.push(TransferLeg { memo, recipient, amount, created_at_time: now, block: None });
+                now
+            }
         }
+    }
 
-        // Validate maker != taker
-        if self.maker == self.taker {
-            return Err(EscrowError::InvalidAddress);
+    /// Record the block an open transfer leg was accepted in
+    pub fn settle_transfer_leg(&mut self, memo: u64, recipient: &Principal, amount: u128, block: u64) {
+        let open = self.transfer_legs.iter_mut().flatten().find(|leg| leg.is_open_for(memo, recipient, amount));
+        if let Some(leg) = open {
+            leg.block = Some(block);
         }
+    }
 
-        // Validate timelock ordering
-        if self.timelocks.withdrawal >= self.timelocks.public_withdrawal ||
-           self.timelocks.public_withdrawal >= self.timelocks.cancellation {
-            return Err(EscrowError::InvalidTime);
+    /// Close the open legs sent with `memo` once a settlement is resolved without the canister
+    /// seeing their outcome: they take `block` when the ledger showed the transfer, and are
+    /// dropped otherwise, so a later transfer of the same amount is never mistaken for a retry
+    pub fn close_transfer_legs(&mut self, memo: u64, block: Option<u64>) {
+        if let Some(legs) = self.transfer_legs.as_mut() {
+            match block {
+                Some(block) => legs.iter_mut()
+                    .filter(|leg| leg.block.is_none() && leg.memo == memo)
+                    .for_each(|leg| leg.block = Some(block)),
+                None => legs.retain(|leg| leg.block.is_some() || leg.memo != memo),
+            }
         }
+    }
 
-        Ok(())
+    /// Ledger fee of one transfer of the escrowed token
+    pub fn transfer_fee(&self) -> u128 {
+        self.token_fee.unwrap_or(u128::from(crate::ledger::TRANSFER_FEE))
+    }
+
+    /// Subaccount the escrow's funds are held in; escrows created before per-escrow
+    /// subaccounts keep theirs in the canister's default account
+    pub fn funds_subaccount(&self) -> Subaccount {
+        self.subaccount
+            .as_deref()
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+            .map(Subaccount)
+            .unwrap_or(DEFAULT_SUBACCOUNT)
+    }
+
+    /// Whether the escrow is still awaiting its deposit after its funding deadline
+    pub fn funding_expired(&self, now: u64) -> bool {
+        self.state == EscrowState::PendingFunding && self.funding.as_ref().is_some_and(|funding| now > funding.deadline)
+    }
+
+    /// Whether the latest EVM RPC check found contract code at the EVM address still recorded
+    pub fn evm_verified(&self) -> bool {
+        self.evm_verification.as_ref().is_some_and(|status| {
+            status.deployed && self.evm_address.as_deref() == Some(status.address.as_str())
+        })
+    }
+
+    /// Amount already paid out by partial fills
+    pub fn filled_amount(&self) -> u128 {
+        self.partial_fill.as_ref().map_or(0, |fill| fill.filled_amount)
+    }
+
+    /// Locked amount not yet filled, which a withdrawal or cancellation settles
+    pub fn remaining_amount(&self) -> u128 {
+        self.immutables.amount - self.filled_amount()
+    }
+
+    /// Most a rescue can still take: the escrow's own unfilled amount and held deposit,
+    /// less anything already rescued. An expired announcement never locked anything, so
+    /// only its own subaccount's balance bounds the recovery of stray deposits; settled
+    /// escrows hold nothing to rescue.
+    pub fn rescuable(&self) -> u128 {
+        match self.state {
+            EscrowState::Active => self.rescue_balance.unwrap_or(self.remaining_amount().saturating_add(self.safety_deposit_held)),
+            EscrowState::Expired if self.subaccount.is_some() => u128::MAX,
+            _ => 0,
+        }
+    }
+
+    /// Index of the secret unlocking a fill of `fill_amount` on top of what is already filled,
+    /// as Fusion+ assigns them: the part the cumulative fill reaches, or `parts_count` for the
+    /// fill that completes the order. A secret can only be used once, so the index must advance.
+    pub fn fill_secret_index(&self, fill_amount: u128) -> Result<u32> {
+        let parts = self.immutables.parts_count.ok_or(EscrowError::InvalidPartialFill)?;
+        if fill_amount == 0 || fill_amount > self.remaining_amount() {
+            return Err(EscrowError::InvalidPartialFill);
+        }
+
+        let amount = self.immutables.amount;
+        let filled = self.filled_amount() + fill_amount;
+        let index = if filled == amount {
+            parts
+        } else {
+            utils::mul_div(filled - 1, u128::from(parts), amount)
+                .and_then(|index| u32::try_from(index).ok())
+                .ok_or(EscrowError::InvalidPartialFill)?
+        };
+        if self.partial_fill.as_ref().is_some_and(|fill| fill.last_secret_index >= index) {
+            return Err(EscrowError::InvalidPartialFill);
+        }
+        Ok(index)
+    }
+
+    /// Record a fill paid out with the secret at `secret_index`
+    pub fn record_partial_fill(&mut self, fill_amount: u128, secret_index: u32) {
+        let fill = self.partial_fill.get_or_insert_with(PartialFill::default);
+        fill.filled_amount += fill_amount;
+        fill.last_secret_index = secret_index;
+        fill.fills += 1;
+    }
+
+    /// Record the withdrawal of whatever remains of a partially fillable escrow, which
+    /// always uses the last secret; other escrows are left untouched
+    pub fn record_final_fill(&mut self) {
+        if let Some(parts) = self.immutables.parts_count {
+            self.record_partial_fill(self.remaining_amount(), parts);
+        }
+    }
+
+    /// The recipient's payout floor pro rata for `portion` of the locked amount
+    pub fn min_received_for(&self, portion: u128) -> Option<u128> {
+        self.immutables
+            .min_received
+            .and_then(|min_received| utils::mul_div(min_received, portion, self.immutables.amount))
+    }
+
+    /// Cycle costs to charge this escrow's lifecycle with
+    pub fn cycles_mut(&mut self) -> &mut CycleCosts {
+        self.cycles.get_or_insert_with(CycleCosts::default)
+    }
+
+    /// Compact receipt of the escrow's outcome; only meaningful once it has settled
+    pub fn receipt(&self) -> EscrowReceipt {
+        EscrowReceipt {
+            hashlock: self.immutables.hashlock.clone(),
+            order_hash: self.immutables.order_hash.clone(),
+            maker: self.immutables.maker.clone(),
+            taker: self.immutables.taker.clone(),
+            token: self.immutables.token.clone(),
+            amount: self.immutables.amount,
+            outcome: self.state.clone(),
+            block_indices: self.settlement_blocks.clone(),
+            created_at: self.created_at,
+            settled_at: self.completed_at,
+            fee_breakdown: self.fee_breakdown.clone(),
+        }
+    }
+
+    /// Party the held safety deposit is returned to when the escrow settles
+    pub fn safety_deposit_owner(&self, escrow_type: &EscrowType) -> &str {
+        match escrow_type {
+            EscrowType::Source if !self.immutables.is_taker_funded_deposit() => &self.immutables.maker,
+            EscrowType::Source | EscrowType::Destination => &self.immutables.taker,
+        }
+    }
+
+    /// The party that did not fund the safety deposit
+    pub fn deposit_counterparty(&self, escrow_type: &EscrowType) -> &str {
+        if self.safety_deposit_owner(escrow_type) == self.immutables.maker {
+            &self.immutables.taker
+        } else {
+            &self.immutables.maker
+        }
+    }
+
+    /// Reminder if the private withdrawal window closes within `horizon` of `now`
+    pub fn reminder(&self, now: u64, horizon: u64) -> Option<EscrowReminder> {
+        let [withdrawal_start, _, closes_at] = self.stage_starts();
+        let due = self.state == EscrowState::Active
+            && self.freeze.is_none()
+            && now < closes_at
+            && closes_at - now <= horizon;
+        due.then(|| EscrowReminder {
+            hashlock: self.immutables.hashlock.clone(),
+            window_open: now >= withdrawal_start,
+            closes_at,
+            remaining: closes_at - now,
+        })
+    }
+
+    /// Whether `principal` is the maker or taker. Stored party strings are parsed
+    /// rather than compared as text, so formatting differences cannot cause a mismatch.
+    pub fn is_party(&self, principal: &Principal) -> bool {
+        [&self.immutables.maker, &self.immutables.taker].into_iter()
+            .any(|party| Principal::from_text(party.trim()).is_ok_and(|party| party == *principal))
+    }
+}
+
+/// Both legs of one swap, set up together by a single resolver.
+/// The source leg lives on the counterpart chain and is only recorded here.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SwapSession {
+    pub hashlock: Vec<u8>,                // Shared hashlock; also the key of the ICP destination escrow
+    pub order_hash: Vec<u8>,
+    pub resolver: Principal,
+    pub src_immutables: EscrowImmutables, // Source escrow parameters on the counterpart chain
+    pub cancellation_gap: u64,            // How long before the source leg the destination leg becomes cancellable (nanoseconds)
+    pub created_at: u64,
+}
+
+impl SwapSession {
+    /// Check that a counterpart-chain source leg belongs to the same swap as the ICP destination leg
+    pub fn validate_legs(src: &EscrowImmutables, dst: &EscrowImmutables) -> Result<()> {
+        if src.hashlock != dst.hashlock || src.order_hash != dst.order_hash {
+            return Err(EscrowError::InvalidSession);
+        }
+        if src.chain_id.is_none() || src.chain_id == Some(utils::CHAIN_ID_ICP) {
+            return Err(EscrowError::InvalidSession);
+        }
+        if !utils::validate_chain_address(src.chain_id, &src.maker)
+            || !utils::validate_chain_address(src.chain_id, &src.taker) {
+            return Err(EscrowError::InvalidAddress);
+        }
+        if !src.timelocks.stages_increasing() {
+            return Err(EscrowError::InvalidTime);
+        }
+        Ok(())
+    }
+
+    /// Time between the destination and source legs becoming cancellable, from each leg's
+    /// deployment time. The destination leg must become cancellable strictly first, or the
+    /// resolver could reclaim the source funds while the maker's ICP is still claimable.
+    pub fn cancellation_gap(src: &Timelocks, dst: &Timelocks) -> Result<u64> {
+        let start = |timelocks: &Timelocks, stage| timelocks.start(stage).unwrap_or_default();
+        start(src, TimelockStage::SrcCancellation)
+            .checked_sub(start(dst, TimelockStage::DstCancellation))
+            .filter(|gap| *gap > 0)
+            .ok_or(EscrowError::InvalidTime)
+    }
+}
+
+/// Longest an announced order stays open (nanoseconds)
+pub const MAX_ORDER_LIFETIME: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// Most orders one maker can have open at a time
+pub const MAX_OPEN_ORDERS_PER_MAKER: usize = 20;
+
+/// Swap intent a maker announces for resolvers to pick up.
+/// Parties a resolver supplies are left for `accept_order` to fill in.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct OrderIntent {
+    pub src_immutables: EscrowImmutables, // Maker's counterpart-chain leg; taker and deployment are set on acceptance
+    pub dst_immutables: EscrowImmutables, // ICP leg paid to the maker; taker and deployment are set on acceptance
+    pub expires_at: u64,                  // Last moment a resolver can accept (nanoseconds)
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum OrderStatus {
+    Open,
+    Accepted { resolver: Principal, at: u64 },
+    Cancelled { at: u64 },
+    Expired { at: u64 },
+}
+
+/// An announced order, by id
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SwapOrder {
+    pub id: u64,
+    pub maker: Principal,
+    pub intent: OrderIntent,
+    pub announced_at: u64,
+    pub status: OrderStatus,
+}
+
+impl SwapOrder {
+    pub fn hashlock(&self) -> &[u8] {
+        &self.intent.dst_immutables.hashlock
+    }
+
+    /// Whether a resolver can still accept the order at `now`
+    pub fn is_open(&self, now: u64) -> bool {
+        self.status == OrderStatus::Open && now <= self.intent.expires_at
+    }
+
+    /// Both legs as the resolver `taker` (with linked EVM address `taker_evm`) would deploy them at `now`.
+    /// The source leg is stamped as deployed now: the resolver can only deploy it later, which
+    /// delays its cancellation and so only widens the gap to the destination leg.
+    pub fn legs_for(&self, taker: &Principal, taker_evm: &str, now: u64) -> (EscrowImmutables, EscrowImmutables) {
+        let mut src = self.intent.src_immutables.clone();
+        src.taker = taker_evm.to_string();
+        src.timelocks.deployed_at = now;
+
+        let mut dst = self.intent.dst_immutables.clone();
+        dst.taker = taker.to_text();
+        dst.timelocks.deployed_at = now;
+        if let Some(amount) = dst.required_amount_at(now) {
+            dst.amount = amount;
+        }
+        (src, dst)
+    }
+}
+
+/// Escrow whose private withdrawal window is about to close
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct EscrowReminder {
+    pub hashlock: Vec<u8>,
+    pub window_open: bool,         // Whether the private withdrawal window has opened yet
+    pub closes_at: u64,            // When private withdrawal ends (nanoseconds)
+    pub remaining: u64,            // Nanoseconds left until then
+}
+
+/// One page of escrows, ordered by hashlock
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct EscrowPage {
+    pub escrows: Vec<(Vec<u8>, ICPEscrow)>,
+    pub next_cursor: Option<Vec<u8>>, // Pass back as `cursor` to fetch the next page
+}
+
+/// Largest page a listing query returns
+pub const MAX_PAGE_SIZE: usize = 100;
+
+/// One page of `list_escrows`, ordered by hashlock
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct EscrowListPage {
+    pub escrows: Vec<(Vec<u8>, ICPEscrow)>,
+    pub next_offset: Option<u64>, // Pass back as `offset` to fetch the next page; None on the last page
+}
+
+/// Criteria for `list_escrows`; unset fields match every escrow
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct EscrowFilter {
+    pub state: Option<EscrowState>,
+    pub party: Option<Principal>,      // Maker or taker
+    pub icp_token: Option<Principal>,  // ICRC-1 ledger of the locked token
+    pub created_after: Option<u64>,    // Inclusive
+    pub created_before: Option<u64>,   // Exclusive
+}
+
+impl EscrowFilter {
+    pub fn matches(&self, escrow: &ICPEscrow) -> bool {
+        self.state.as_ref().is_none_or(|state| escrow.state == *state)
+            && self.party.as_ref().is_none_or(|party| escrow.is_party(party))
+            && self.icp_token.is_none_or(|token| escrow.immutables.icp_token == Some(token))
+            && self.created_after.is_none_or(|after| escrow.created_at >= after)
+            && self.created_before.is_none_or(|before| escrow.created_at < before)
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum EscrowState {
+    Active,      // Escrow is active and waiting for action
+    Completed,   // Escrow completed successfully (secret revealed)
+    Cancelled,   // Escrow was cancelled (timeout reached)
+    Rescued,     // Funds were rescued after delay
+    Processing,  // Settlement transfers are in flight; no other operation may start
+    Migrated,    // Funds and record were handed to a successor canister
+    PendingFunding, // Announced with create_escrow; activates once notify_deposit proves the deposit
+    Expired,     // Announced but never funded within its funding window
+}
+
+/// Settlement that moved an escrow into Processing
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum ProcessingOperation {
+    Withdrawal {
+        escrow_type: EscrowType,
+        withdrawer: Principal,
+        secret: Vec<u8>,
+    },
+    Cancellation {
+        escrow_type: EscrowType,
+        canceller: Principal,
+    },
+    Migration {
+        receipt: Box<MigrationReceipt>,
+    },
+    PartialFill {
+        withdrawer: Principal,
+        secret: Vec<u8>,
+        secret_index: u32,
+        fill_amount: u128,
+    },
+}
+
+/// Lock held on an escrow while its payout transfers are in flight
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ProcessingLock {
+    pub operation: ProcessingOperation,
+    pub started_at: u64,
+}
+
+impl ProcessingLock {
+    /// Whether the settlement has been in flight for longer than `timeout`
+    pub fn is_stuck(&self, now: u64, timeout: u64) -> bool {
+        now.saturating_sub(self.started_at) > timeout
+    }
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum EscrowType {
+    Source,      // ICP→EVM (ICP locked on ICP, released when EVM secret revealed)
+    Destination, // EVM→ICP (ICP released when secret from EVM is provided)
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+#[serde(default)] // Fields missing from older stored configs take their default values
+pub struct EscrowConfig {
+    pub rescue_delay: u64,         // Rescue delay in nanoseconds (default: 7 days)
+    #[serde(with = "utils::wide_amount")]
+    pub min_amount: u128,          // Minimum escrow amount in token base units
+    #[serde(with = "utils::wide_amount")]
+    pub max_amount: u128,          // Maximum escrow amount in token base units
+    pub creation_fee: u64,         // Creation fee in ICP e8s
+    pub treasury: Principal,       // Treasury principal for fee collection
+    #[serde(with = "utils::wide_amount")]
+    pub min_safety_deposit: u128,  // Minimum safety deposit required
+    pub surplus_share_bps: u64,    // Share of positive slippage routed to treasury (basis points)
+    pub replay_protection_ttl: u64, // How long processed requests are remembered (nanoseconds)
+    pub state_commitment_interval: u64, // Commit the escrow state root every N escrow updates (0 = off)
+    pub guardians: Vec<Principal>, // Break-glass guardians able to rotate the treasury
+    pub guardian_threshold: u32,   // Guardian approvals required for a treasury rotation
+    pub operational_reserve: u64,  // ICP (e8s) outgoing transfers must leave on the canister (0 = off)
+    pub reserve_alert_margin: u64, // Emit ReserveLow when the balance falls within this margin of the reserve
+    pub ledger_fee_payer: LedgerFeePayer, // Who absorbs the ledger fee on settlement transfers
+    pub vetkd_key_name: String,    // vetKD master key used to derive escrow note keys
+    pub event_retention: u64,      // Age after which events leave the hot log for the archive (nanoseconds, 0 = off)
+    pub rescue_notice_period: u64, // Notice between request_rescue and execute_rescue (nanoseconds)
+    pub public_withdraw_mode: PublicWithdrawMode, // Who may call public_withdraw
+    pub permissionless_delay: u64, // Permissionless mode: delay after the public window opens before anyone may withdraw
+    pub resolver_stake: u64,       // ICP (e8s) a resolver locks to register
+    pub allow_partial_fills: bool, // Allow several active escrows against one (order_hash, chain_id)
+    pub notify_maker_canisters: bool, // Call on_escrow_completed on makers that are canisters after withdrawal
+    pub reminder_horizon: u64,     // How far ahead get_reminders looks for closing windows (nanoseconds)
+    pub processing_timeout: u64,   // How long an escrow may stay Processing before the watchdog resolves it (nanoseconds)
+    pub deposit_forfeiture: Option<ForfeitureSchedule>, // Split of the deposit on public operations (None = all to the executor)
+    #[serde(with = "utils::wide_amount::option")]
+    pub unverified_monthly_cap: Option<u128>, // Creation volume per period for principals without a resolver registration or EVM link (None = uncapped)
+    pub redact_settled_secrets: bool, // Blank revealed secrets in public event queries once the swap has settled (proofs and evidence keep them)
+    pub ecdsa_key_name: String,    // Threshold ECDSA key signing escrow migration snapshots and EVM withdrawals
+    pub icrc_ledgers: Vec<Principal>, // ICRC-1 ledgers escrows may lock instead of ICP (empty = ICP only)
+    pub funding_window: u64,       // How long an escrow announced with create_escrow may await its deposit (nanoseconds)
+    pub require_evm_verification: bool, // Source escrows only release funds once verify_evm_escrow has found their EVM counterpart
+    pub refund_public_deposit: bool, // Legacy routing: public withdrawals refund the deposit to its funder (ignored with deposit_forfeiture)
+    pub icp_ledger: Principal,     // ICP ledger native ICP is held on: mainnet by default, a local or test ledger elsewhere
+    pub min_timelock: u64,         // Shortest cancellation offset an escrow may set (seconds after deployment)
+    pub max_timelock: u64,         // Longest offset any timelock stage may set (seconds after deployment)
+    pub paused: bool,              // Circuit breaker: blocks escrow creation and withdrawals; cancellations and rescues still run
+    pub enforce_timelocks: bool,   // Hold withdrawals to their timelock windows (off only for local testing)
+    pub min_cycles_for_creation: u64, // Cycles balance below which escrow creation is refused; settlements still run (0 = off)
+    pub cycles_alert_margin: u64,  // Emit CyclesLow when the balance falls within this margin of min_cycles_for_creation
+}
+
+/// Who may execute withdrawals during the public withdrawal window
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum PublicWithdrawMode {
+    AuthorizedPrincipals, // Treasury and treasury-listed principals only
+    RegisteredResolvers,  // Also any resolver that has registered with a stake
+    Permissionless,       // Also anyone, once `permissionless_delay` has passed
+}
+
+impl PublicWithdrawMode {
+    /// Whether a caller with the given standing may execute a public withdrawal
+    pub fn allows(&self, authorized: bool, resolver: bool, permissionless_open: bool) -> bool {
+        match self {
+            PublicWithdrawMode::AuthorizedPrincipals => authorized,
+            PublicWithdrawMode::RegisteredResolvers => authorized || resolver,
+            PublicWithdrawMode::Permissionless => authorized || resolver || permissionless_open,
+        }
+    }
+}
+
+/// Resolver registered for public withdrawals, with the stake it locked
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ResolverRegistration {
+    pub principal: Principal,
+    pub stake: u64,
+    pub registered_at: u64,
+}
+
+/// Party absorbing the ledger fee charged on each outgoing transfer
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum LedgerFeePayer {
+    Recipient,     // Deducted from each transfer, so recipients receive amount - fee
+    SafetyDeposit, // Deducted from the safety deposit refund; any shortfall falls to the canister
+    Canister,      // Paid from the canister's own balance; recipients receive the full amount
+}
+
+impl Default for EscrowConfig {
+    fn default() -> Self {
+        Self {
+            rescue_delay: 7 * 24 * 60 * 60 * 1_000_000_000, // 7 days in nanoseconds
+            min_amount: 1_000,                               // 0.00001 ICP
+            max_amount: 100_000_000_000,                    // 1000 ICP
+            creation_fee: 0,                           // 0.0001 ICP
+            treasury: Principal::from_text("f5hu5-c5eqs-4m2bm-fxb27-5mnk2-lpbva-l3tb5-7xv5p-w65wt-a3uyd-lqe").unwrap(),
+            min_safety_deposit: 100_000,                    // 0.001 ICP
+            surplus_share_bps: 0,                           // Surplus capture disabled
+            replay_protection_ttl: 30 * 24 * 60 * 60 * 1_000_000_000, // 30 days in nanoseconds
+            state_commitment_interval: 100,                 // Every 100 escrow updates
+            guardians: Vec::new(),                          // No guardians configured
+            guardian_threshold: 0,
+            operational_reserve: 0,                         // Reserve guard disabled
+            reserve_alert_margin: 50_000_000,               // 0.5 ICP
+            ledger_fee_payer: LedgerFeePayer::Canister,
+            vetkd_key_name: "key_1".to_string(),
+            event_retention: 90 * 24 * 60 * 60 * 1_000_000_000, // 90 days in nanoseconds
+            rescue_notice_period: 24 * 60 * 60 * 1_000_000_000, // 24 hours in nanoseconds
+            public_withdraw_mode: PublicWithdrawMode::AuthorizedPrincipals,
+            permissionless_delay: 60 * 60 * 1_000_000_000,  // 1 hour in nanoseconds
+            resolver_stake: 100_000_000,                    // 1 ICP
+            allow_partial_fills: false,
+            notify_maker_canisters: false,
+            reminder_horizon: 3 * 60 * 60 * 1_000_000_000,  // 3 hours in nanoseconds
+            processing_timeout: 10 * 60 * 1_000_000_000,    // 10 minutes in nanoseconds
+            deposit_forfeiture: None,
+            unverified_monthly_cap: None,
+            redact_settled_secrets: false,
+            ecdsa_key_name: "key_1".to_string(),
+            icrc_ledgers: Vec::new(),
+            funding_window: 60 * 60 * 1_000_000_000,       // 1 hour in nanoseconds
+            require_evm_verification: false,
+            refund_public_deposit: false,
+            icp_ledger: ic_ledger_types::MAINNET_LEDGER_CANISTER_ID,
+            min_timelock: 0,
+            max_timelock: crate::guards::MAX_TIMELOCK_OFFSET,
+            paused: false,
+            enforce_timelocks: true,
+            min_cycles_for_creation: 200_000_000_000,       // 0.2T cycles
+            cycles_alert_margin: 300_000_000_000,           // 0.3T cycles
+        }
+    }
+}
+
+/// Division of a forfeited safety deposit, in basis points summing to 10_000
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ForfeitureSchedule {
+    pub executor_bps: u64,         // Resolver executing the public operation
+    pub counterparty_bps: u64,     // Party that did not fund the deposit
+    pub treasury_bps: u64,
+}
+
+impl ForfeitureSchedule {
+    /// Shares of `amount` as [executor, counterparty, treasury]; rounding dust goes to the treasury
+    pub fn split(&self, amount: u128) -> [u128; 3] {
+        let share = |bps: u64| utils::mul_div(amount, u128::from(bps), 10_000).expect("a share never exceeds the amount");
+        let executor = share(self.executor_bps);
+        let counterparty = share(self.counterparty_bps);
+        [executor, counterparty, amount - executor - counterparty]
+    }
+}
+
+/// Schema version of `EscrowConfig`; bump whenever fields are added
+pub const CONFIG_SCHEMA_VERSION: u32 = 23;
+
+/// Config together with the schema version it was written with
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct VersionedConfig {
+    pub schema_version: u32,
+    pub config: EscrowConfig,
+}
+
+/// Shortest rescue delay a config may set (1 hour in nanoseconds)
+pub const MIN_RESCUE_DELAY: u64 = 60 * 60 * 1_000_000_000;
+
+/// Highest creation fee a config may set (1 ICP in e8s)
+pub const MAX_CREATION_FEE: u64 = 100_000_000;
+
+impl EscrowConfig {
+    /// Reject configurations that would brick escrow creation or admin access
+    pub fn validate(&self) -> Result<()> {
+        if self.min_amount == 0 || self.min_amount >= self.max_amount {
+            return Err(EscrowError::InvalidConfig(format!(
+                "min_amount ({}) must be non-zero and below max_amount ({})",
+                self.min_amount, self.max_amount
+            )));
+        }
+
+        if self.rescue_delay < MIN_RESCUE_DELAY {
+            return Err(EscrowError::InvalidConfig(format!(
+                "rescue_delay ({} ns) must be at least {} ns",
+                self.rescue_delay, MIN_RESCUE_DELAY
+            )));
+        }
+
+        if self.creation_fee > MAX_CREATION_FEE {
+            return Err(EscrowError::InvalidConfig(format!(
+                "creation_fee ({} e8s) exceeds the cap of {} e8s",
+                self.creation_fee, MAX_CREATION_FEE
+            )));
+        }
+
+        if self.treasury == Principal::anonymous() {
+            return Err(EscrowError::InvalidConfig(
+                "treasury must not be the anonymous principal".to_string(),
+            ));
+        }
+
+        if self.icp_ledger == Principal::anonymous() || self.icp_ledger == Principal::management_canister() {
+            return Err(EscrowError::InvalidConfig(
+                "icp_ledger must be a ledger canister".to_string(),
+            ));
+        }
+
+        if self.surplus_share_bps > 10_000 {
+            return Err(EscrowError::InvalidConfig(format!(
+                "surplus_share_bps ({}) cannot exceed 10000",
+                self.surplus_share_bps
+            )));
+        }
+
+        if self.guardian_threshold as usize > self.guardians.len() {
+            return Err(EscrowError::InvalidConfig(format!(
+                "guardian_threshold ({}) exceeds the number of guardians ({})",
+                self.guardian_threshold,
+                self.guardians.len()
+            )));
+        }
+
+        if self.vetkd_key_name.is_empty() {
+            return Err(EscrowError::InvalidConfig(
+                "vetkd_key_name must not be empty".to_string(),
+            ));
+        }
+
+        if self.ecdsa_key_name.is_empty() {
+            return Err(EscrowError::InvalidConfig(
+                "ecdsa_key_name must not be empty".to_string(),
+            ));
+        }
+
+        if let Some(schedule) = &self.deposit_forfeiture {
+            let total = schedule.executor_bps.saturating_add(schedule.counterparty_bps).saturating_add(schedule.treasury_bps);
+            if total != 10_000 {
+                return Err(EscrowError::InvalidConfig(format!(
+                    "deposit_forfeiture shares must sum to 10000 bps, got {}",
+                    total
+                )));
+            }
+        }
+
+        if self.processing_timeout == 0 {
+            return Err(EscrowError::InvalidConfig(
+                "processing_timeout must be non-zero".to_string(),
+            ));
+        }
+
+        if self.funding_window == 0 {
+            return Err(EscrowError::InvalidConfig(
+                "funding_window must be non-zero".to_string(),
+            ));
+        }
+
+        if self.min_timelock >= self.max_timelock || self.max_timelock > crate::guards::MAX_TIMELOCK_OFFSET {
+            return Err(EscrowError::InvalidConfig(format!(
+                "min_timelock ({}) must be below max_timelock ({}), which cannot exceed {} seconds",
+                self.min_timelock, self.max_timelock, crate::guards::MAX_TIMELOCK_OFFSET
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Settings fixed at install time and optionally changed on upgrade; unset fields keep the
+/// default on install and the current value on upgrade
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct InitArgs {
+    pub treasury: Option<Principal>,
+    pub icp_ledger: Option<Principal>,
+    pub creation_fee: Option<u64>,
+    pub min_amount: Option<u128>,
+    pub max_amount: Option<u128>,
+    pub min_safety_deposit: Option<u128>,
+    pub rescue_delay: Option<u64>,
+    pub min_timelock: Option<u64>,
+    pub max_timelock: Option<u64>,
+}
+
+impl InitArgs {
+    /// `config` with the set fields overridden, validated as a whole
+    pub fn apply(&self, config: EscrowConfig) -> Result<EscrowConfig> {
+        let config = EscrowConfig {
+            treasury: self.treasury.unwrap_or(config.treasury),
+            icp_ledger: self.icp_ledger.unwrap_or(config.icp_ledger),
+            creation_fee: self.creation_fee.unwrap_or(config.creation_fee),
+            min_amount: self.min_amount.unwrap_or(config.min_amount),
+            max_amount: self.max_amount.unwrap_or(config.max_amount),
+            min_safety_deposit: self.min_safety_deposit.unwrap_or(config.min_safety_deposit),
+            rescue_delay: self.rescue_delay.unwrap_or(config.rescue_delay),
+            min_timelock: self.min_timelock.unwrap_or(config.min_timelock),
+            max_timelock: self.max_timelock.unwrap_or(config.max_timelock),
+            ..config
+        };
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+// Error types
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum EscrowError {
+    InvalidCaller,
+    InvalidSecret,
+    InvalidTime,
+    InvalidAmount,
+    InvalidState,
+    EscrowNotFound,
+    TransferFailed { reason: String },
+    Unauthorized,
+    InvalidHashlock,
+    InsufficientBalance { required: u128, available: u128 },
+    InvalidAddress,
+    DuplicateEscrow,
+    ConfigError,
+    LedgerRejected { method: String, code: String, message: String }, // The ledger answered with an error
+    CanisterCallError,
+    CanisterCallAndLedgerSuccConversionError,
+    SlippageExceeded,
+    DeadlineExceeded,
+    DuplicateRequest,
+    EventNotFound,
+    CertificateUnavailable,
+    InvalidConfig(String),
+    InvalidDepositMode,
+    ReserveBreached,
+    InvalidNote,
+    InvalidProfile,
+    EscrowFrozen,
+    TemplateNotFound,
+    DuplicateOrder,
+    FieldTooLong(String),
+    InvalidSession,
+    VolumeCapExceeded,
+    ChainNotConfigured,
+    DevModeDisabled,
+    MalformedInput(String),
+    MigrationRejected,
+    UnsupportedToken,
+    InvalidPartialFill,
+    InvalidMerkleProof,
+    DepositNotFound,
+    OperationInProgress,
+    EvmVerificationFailed,
+    EvmEscrowUnverified,
+    EvmRpcFailed,
+    InvalidAuction,
+    BelowAuctionPrice,
+    OrderNotFound,
+    TooManyOrders,
+    AmountBelowMinimum { minimum: u128, actual: u128 },
+    OutsideTimeWindow { now: u64, opens_at: u64, closes_at: Option<u64> }, // Timelock stage not open at `now`
+    Paused,                        // The canister is paused; only cancellations and rescues run
+    CyclesLow { balance: u128, minimum: u64 }, // Cycles are too low to take on new escrows; settlements still run
+}
+
+impl EscrowError {
+    /// Error a ledger returned from `method`; `code` is the error's variant name, e.g. `InsufficientFunds`
+    pub fn ledger_rejected(method: &str, error: &impl std::fmt::Debug) -> Self {
+        let message = format!("{:?}", error);
+        let code = message.split(|c: char| !c.is_alphanumeric()).next().unwrap_or_default().to_string();
+        EscrowError::LedgerRejected { method: method.to_string(), code, message }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, EscrowError>;
+
+// Event types for logging
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub enum EscrowEvent {
+    EscrowCreated {
+        hashlock: Vec<u8>,
+        escrow_type: EscrowType,
+        maker: String,
+        taker: String,
+        amount: u128,
+        metadata: Option<Vec<u8>>,
+        timestamp: u64,
+    },
+    EscrowWithdrawal {
+        hashlock: Vec<u8>,
+        withdrawer: Principal,
+        secret: Vec<u8>,
+        order_hash: Option<Vec<u8>>, // None on events recorded before secrets were scoped to orders
+        timestamp: u64,
+    },
+    EscrowFunded {
+        hashlock: Vec<u8>,
+        block_index: u64,
+        amount: u128,
+        timestamp: u64,
+    },
+    EscrowExpired {
+        hashlock: Vec<u8>,
+        timestamp: u64,
+    },
+    EscrowPartiallyFilled {
+        hashlock: Vec<u8>,
+        withdrawer: Principal,
+        secret: Vec<u8>,
+        secret_index: u32,
+        fill_amount: u128,
+        remaining: u128,           // Locked amount still unfilled after this fill
+        timestamp: u64,
+    },
+    EscrowCancelled {
+        hashlock: Vec<u8>,
+        canceller: Principal,
+        timestamp: u64,
+    },
+    RescueRequested {
+        hashlock: Vec<u8>,
+        requester: Principal,
+        executable_at: u64,
+        timestamp: u64,
+    },
+    FundsRescued {
+        hashlock: Vec<u8>,
+        rescuer: Principal,
+        amount: u128,
+        timestamp: u64,
+    },
+    ICPTxRecorded {
+        hashlock: Vec<u8>,
+        tx_hash: String,
+        timestamp: u64,
+    },
+    EVMAddressRecorded {
+        hashlock: Vec<u8>,
+        address: String,
+        timestamp: u64,
+    },
+    EvmEscrowVerified {
+        hashlock: Vec<u8>,
+        address: String,
+        deployed: bool,
+        block_number: u64,
+        timestamp: u64,
+    },
+    EvmWithdrawalSubmitted {
+        hashlock: Vec<u8>,
+        tx_hash: String,
+        from: String,
+        timestamp: u64,
+    },
+    TreasuryRotationApproved {
+        new_treasury: Principal,
+        guardian: Principal,
+        approvals: u32,
+        timestamp: u64,
+    },
+    TreasuryRotated {
+        old_treasury: Principal,
+        new_treasury: Principal,
+        timestamp: u64,
+    },
+    SafetyDepositFunded {
+        hashlock: Vec<u8>,
+        funder: Principal,
+        amount: u128,
+        timestamp: u64,
+    },
+    NoteAttached {
+        hashlock: Vec<u8>,
+        author: Principal,
+        size: u64,
+        timestamp: u64,
+    },
+    EscrowFrozen {
+        hashlock: Vec<u8>,
+        reason: String,
+        frozen_by: Principal,
+        timestamp: u64,
+    },
+    EscrowUnfrozen {
+        hashlock: Vec<u8>,
+        unfrozen_by: Principal,
+        timestamp: u64,
+    },
+    ResolverRegistered {
+        resolver: Principal,
+        stake: u64,
+        timestamp: u64,
+    },
+    ResolverUnregistered {
+        resolver: Principal,
+        stake_returned: u64,
+        timestamp: u64,
+    },
+    ProcessingResolved {
+        hashlock: Vec<u8>,
+        finalized: bool,          // Settled (true) or returned to Active (false)
+        block: Option<u64>,       // Ledger block proving the payout, when found
+        forced: bool,             // Resolved by the admin override rather than the watchdog
+        timestamp: u64,
+    },
+    EvmAddressLinked {
+        principal: Principal,
+        address: String,
+        timestamp: u64,
+    },
+    SwapSessionCreated {
+        hashlock: Vec<u8>,
+        order_hash: Vec<u8>,
+        resolver: Principal,
+        src_chain_id: Option<u64>,
+        timestamp: u64,
+    },
+    TreasurySwapFundsSwept {
+        to: Principal,
+        amount: u64,
+        block: u64,
+        timestamp: u64,
+    },
+    FeesWithdrawn {
+        to: Principal,
+        amount: u64,
+        block: u64,
+        withdrawn_by: Principal,
+        timestamp: u64,
+    },
+    ReserveLow {
+        balance: u64,
+        reserve: u64,
+        timestamp: u64,
+    },
+    EventsPruned {
+        count: u64,
+        first_seq: u64,
+        last_seq: u64,
+        cutoff: u64,
+        timestamp: u64,
+    },
+    MetricsReset {
+        scope: MetricsResetScope,
+        previous: crate::storage::EscrowMetrics,
+        reset_by: Principal,
+        timestamp: u64,
+    },
+    EscrowMigrated {
+        hashlock: Vec<u8>,
+        successor: Principal,
+        amount: u128,
+        timestamp: u64,
+    },
+    TransferQueued {
+        hashlock: Vec<u8>,
+        transfer_id: u64,
+        recipient: Principal,
+        amount: u128,
+        timestamp: u64,
+    },
+    QueuedTransferSent {
+        hashlock: Vec<u8>,
+        transfer_id: u64,
+        recipient: Principal,
+        amount: u128,
+        block: u64,
+        attempts: u32,            // Failed retries before this one succeeded
+        timestamp: u64,
+    },
+    OrderAnnounced {
+        hashlock: Vec<u8>,
+        order_id: u64,
+        maker: Principal,
+        expires_at: u64,
+        timestamp: u64,
+    },
+    OrderAccepted {
+        hashlock: Vec<u8>,
+        order_id: u64,
+        resolver: Principal,
+        timestamp: u64,
+    },
+    OrderCancelled {
+        hashlock: Vec<u8>,
+        order_id: u64,
+        timestamp: u64,
+    },
+    OrderExpired {
+        hashlock: Vec<u8>,
+        order_id: u64,
+        timestamp: u64,
+    },
+    RoleGranted {
+        principal: Principal,
+        role: Role,
+        granted_by: Principal,
+        timestamp: u64,
+    },
+    RoleRevoked {
+        principal: Principal,
+        role: Role,
+        revoked_by: Principal,
+        timestamp: u64,
+    },
+    CanisterPaused {
+        paused_by: Principal,
+        timestamp: u64,
+    },
+    CanisterUnpaused {
+        unpaused_by: Principal,
+        timestamp: u64,
+    },
+    SecretRevealed {               // The secret that completed an escrow, for unlocking the other chain
+        hashlock: Vec<u8>,
+        order_hash: Vec<u8>,
+        secret: Vec<u8>,
+        timestamp: u64,
+    },
+    CyclesLow {
+        balance: u64,
+        minimum: u64,              // min_cycles_for_creation at the time
+        timestamp: u64,
+    },
+    CyclesDeposited {
+        depositor: Principal,
+        amount: u128,
+        balance: u128,             // Canister cycles after the top-up
+        timestamp: u64,
+    },
+}
+
+impl EscrowEvent {
+    /// Hashlock of the escrow this event belongs to, if any
+    pub fn hashlock(&self) -> Option<&[u8]> {
+        match self {
+            EscrowEvent::EscrowCreated { hashlock, .. } |
+            EscrowEvent::EscrowWithdrawal { hashlock, .. } |
+            EscrowEvent::SecretRevealed { hashlock, .. } |
+            EscrowEvent::EscrowPartiallyFilled { hashlock, .. } |
+            EscrowEvent::EscrowFunded { hashlock, .. } |
+            EscrowEvent::EscrowExpired { hashlock, .. } |
+            EscrowEvent::SwapSessionCreated { hashlock, .. } |
+            EscrowEvent::ProcessingResolved { hashlock, .. } |
+            EscrowEvent::EscrowMigrated { hashlock, .. } |
+            EscrowEvent::TransferQueued { hashlock, .. } |
+            EscrowEvent::QueuedTransferSent { hashlock, .. } |
+            EscrowEvent::OrderAnnounced { hashlock, .. } |
+            EscrowEvent::OrderAccepted { hashlock, .. } |
+            EscrowEvent::OrderCancelled { hashlock, .. } |
+            EscrowEvent::OrderExpired { hashlock, .. } |
+            EscrowEvent::EscrowCancelled { hashlock, .. } |
+            EscrowEvent::RescueRequested { hashlock, .. } |
+            EscrowEvent::FundsRescued { hashlock, .. } |
+            EscrowEvent::ICPTxRecorded { hashlock, .. } |
+            EscrowEvent::EVMAddressRecorded { hashlock, .. } |
+            EscrowEvent::EvmEscrowVerified { hashlock, .. } |
+            EscrowEvent::EvmWithdrawalSubmitted { hashlock, .. } |
+            EscrowEvent::SafetyDepositFunded { hashlock, .. } |
+            EscrowEvent::NoteAttached { hashlock, .. } |
+            EscrowEvent::EscrowFrozen { hashlock, .. } |
+            EscrowEvent::EscrowUnfrozen { hashlock, .. } => Some(hashlock),
+            EscrowEvent::TreasuryRotationApproved { .. } |
+            EscrowEvent::TreasuryRotated { .. } |
+            EscrowEvent::ResolverRegistered { .. } |
+            EscrowEvent::ResolverUnregistered { .. } |
+            EscrowEvent::EvmAddressLinked { .. } |
+            EscrowEvent::TreasurySwapFundsSwept { .. } |
+            EscrowEvent::FeesWithdrawn { .. } |
+            EscrowEvent::ReserveLow { .. } |
+            EscrowEvent::EventsPruned { .. } |
+            EscrowEvent::MetricsReset { .. } |
+            EscrowEvent::RoleGranted { .. } |
+            EscrowEvent::RoleRevoked { .. } |
+            EscrowEvent::CanisterPaused { .. } |
+            EscrowEvent::CanisterUnpaused { .. } |
+            EscrowEvent::CyclesLow { .. } |
+            EscrowEvent::CyclesDeposited { .. } => None,
+        }
+    }
+
+    /// Drop the secret revealed by a withdrawal or partial fill; the hashlock or Merkle root still commits to it
+    pub fn redact_secret(&mut self) {
+        if let EscrowEvent::EscrowWithdrawal { secret, .. }
+            | EscrowEvent::EscrowPartiallyFilled { secret, .. }
+            | EscrowEvent::SecretRevealed { secret, .. } = self {
+            secret.clear();
+        }
+    }
+
+    /// When the event was emitted
+    pub fn timestamp(&self) -> u64 {
+        match self {
+            EscrowEvent::EscrowCreated { timestamp, .. } |
+            EscrowEvent::EscrowWithdrawal { timestamp, .. } |
+            EscrowEvent::SecretRevealed { timestamp, .. } |
+            EscrowEvent::EscrowPartiallyFilled { timestamp, .. } |
+            EscrowEvent::EscrowFunded { timestamp, .. } |
+            EscrowEvent::EscrowExpired { timestamp, .. } |
+            EscrowEvent::SwapSessionCreated { timestamp, .. } |
+            EscrowEvent::ProcessingResolved { timestamp, .. } |
+            EscrowEvent::EscrowMigrated { timestamp, .. } |
+            EscrowEvent::TransferQueued { timestamp, .. } |
+            EscrowEvent::QueuedTransferSent { timestamp, .. } |
+            EscrowEvent::OrderAnnounced { timestamp, .. } |
+            EscrowEvent::OrderAccepted { timestamp, .. } |
+            EscrowEvent::OrderCancelled { timestamp, .. } |
+            EscrowEvent::OrderExpired { timestamp, .. } |
+            EscrowEvent::EscrowCancelled { timestamp, .. } |
+            EscrowEvent::RescueRequested { timestamp, .. } |
+            EscrowEvent::FundsRescued { timestamp, .. } |
+            EscrowEvent::ICPTxRecorded { timestamp, .. } |
+            EscrowEvent::EVMAddressRecorded { timestamp, .. } |
+            EscrowEvent::EvmEscrowVerified { timestamp, .. } |
+            EscrowEvent::EvmWithdrawalSubmitted { timestamp, .. } |
+            EscrowEvent::TreasuryRotationApproved { timestamp, .. } |
+            EscrowEvent::TreasuryRotated { timestamp, .. } |
+            EscrowEvent::SafetyDepositFunded { timestamp, .. } |
+            EscrowEvent::NoteAttached { timestamp, .. } |
+            EscrowEvent::EscrowFrozen { timestamp, .. } |
+            EscrowEvent::EscrowUnfrozen { timestamp, .. } |
+            EscrowEvent::ResolverRegistered { timestamp, .. } |
+            EscrowEvent::ResolverUnregistered { timestamp, .. } |
+            EscrowEvent::EvmAddressLinked { timestamp, .. } |
+            EscrowEvent::TreasurySwapFundsSwept { timestamp, .. } |
+            EscrowEvent::FeesWithdrawn { timestamp, .. } |
+            EscrowEvent::ReserveLow { timestamp, .. } |
+            EscrowEvent::EventsPruned { timestamp, .. } |
+            EscrowEvent::MetricsReset { timestamp, .. } |
+            EscrowEvent::RoleGranted { timestamp, .. } |
+            EscrowEvent::RoleRevoked { timestamp, .. } |
+            EscrowEvent::CanisterPaused { timestamp, .. } |
+            EscrowEvent::CanisterUnpaused { timestamp, .. } |
+            EscrowEvent::CyclesLow { timestamp, .. } |
+            EscrowEvent::CyclesDeposited { timestamp, .. } => *timestamp,
+        }
+    }
+}
+
+/// Guardian-approved treasury rotation awaiting quorum
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PendingTreasuryRotation {
+    pub new_treasury: Principal,
+    pub approvals: Vec<Principal>,
+    pub proposed_at: u64,
+}
+
+/// EVM address a principal proved control of by signing the link message
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct EvmLink {
+    pub address: String,           // Lowercase 0x-prefixed address recovered from the signature
+    pub linked_at: u64,
+}
+
+/// Length of one volume-cap period (30 days in nanoseconds)
+pub const VOLUME_PERIOD: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+
+/// A principal's creation volume in the current period, against the cap for its tier
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct VolumeUsage {
+    pub period_start: u64,
+    pub used: u128,
+    pub cap: Option<u128>,         // None when no cap applies
+    pub verified: bool,            // Registered resolver or linked EVM address; never capped
+}
+
+/// Cycles held by the canister and how long they last at the recent burn rate
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct CyclesStatus {
+    pub balance: u128,
+    pub liquid_balance: u128,      // Balance above the freezing threshold, what calls can still spend
+    pub burn_per_day: Option<u128>, // Estimated from the hourly samples of the last day; None until two exist
+    pub days_to_freeze: Option<u64>, // Whole days until the liquid balance runs out; None while nothing burns
+    pub min_cycles_for_creation: u64,
+    pub creation_blocked: bool,    // Balance is below min_cycles_for_creation
+}
+
+impl VolumeUsage {
+    /// Whether creating an escrow of `amount` stays within the cap
+    pub fn allows(&self, amount: u128) -> bool {
+        self.verified || self.cap.is_none_or(|cap| self.used.saturating_add(amount) <= cap)
+    }
+}
+
+/// One timelock stage, described for resolvers configuring themselves against a deployment
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct TimelockStageSpec {
+    pub name: String,
+    pub starts_at: String,          // Timelock field or config delay marking the start of the stage
+    pub operations: Vec<String>,    // Canister methods that become callable in this stage
+    pub callers: String,            // Who may call them
+}
+
+/// Chain an escrow's counter-leg may live on, with the address format its parties must use
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChainSpec {
+    pub chain_id: Option<u64>,      // None for EVM, which covers every chain id not listed separately
+    pub name: String,
+    pub address_format: String,
+}
+
+/// Fees charged by this deployment
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct FeeSchedule {
+    pub creation_fee: u64,
+    pub surplus_share_bps: u64,
+    pub ledger_transfer_fee: u64,
+    pub ledger_fee_payer: LedgerFeePayer,
+    pub deposit_forfeiture: Option<ForfeitureSchedule>,
+}
+
+/// Size and amount limits enforced by this deployment
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct ProtocolLimits {
+    pub min_amount: u128,
+    pub max_amount: u128,
+    pub min_safety_deposit: u128,
+    pub max_address_len: u64,
+    pub max_tx_hash_len: u64,
+    pub max_secret_len: u64,
+    pub max_page_size: u64,
+    pub max_note_size: u64,
+    pub max_notes_per_escrow: u64,
+    pub unverified_monthly_cap: Option<u128>,
+    pub volume_period: u64,
+}
+
+/// Machine-readable description of the deployment, so resolvers can auto-configure against it
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct ProtocolSpec {
+    pub config_schema_version: u32,
+    pub dev_mode: bool,             // Built with simulated time control; never true on a production deployment
+    pub hash_algorithms: Vec<String>,
+    pub timelock_stages: Vec<TimelockStageSpec>,
+    pub fees: FeeSchedule,
+    pub chains: Vec<ChainSpec>,
+    pub tokens: Vec<String>,        // Counter-leg tokens with a registered template
+    pub limits: ProtocolLimits,
+}
+
+/// Standard or custom interface the canister implements, as listed by ICRC-10
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct StandardRecord {
+    pub name: String,
+    pub url: String,
+}
+
+/// Cosmetic display profile a principal sets for itself; never used for authorization
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct ParticipantProfile {
+    pub alias: String,
+    pub avatar_url: Option<String>,
+    pub updated_at: u64,
+}
+
+/// Pre-formatted escrow summary for thin clients: amounts with decimals and symbol,
+/// timelock boundaries as ISO-8601 UTC
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct EscrowSummary {
+    pub hashlock: String,          // Hex
+    pub state: String,
+    pub maker: String,
+    pub taker: String,
+    pub amount: String,
+    pub safety_deposit: String,
+    pub token: String,             // Counter-leg token the maker and taker agreed on
+    pub created_at: String,
+    pub withdrawal_start: String,
+    pub public_withdrawal_start: String,
+    pub cancellation_start: String,
+    pub rescue_start: String,
+    pub completed_at: Option<String>,
+}
+
+/// Where an escrow stands in its lifecycle
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum EscrowPhase {
+    AwaitingFunding,    // Announced; timelocks start once the deposit is proven
+    FinalityLock,       // Funded, private withdrawal not open yet
+    PrivateWithdrawal,
+    PublicWithdrawal,
+    Cancellation,
+    PublicCancellation,
+    Rescue,
+    Closed,             // Completed, cancelled, rescued, migrated or expired
+}
+
+/// Absolute timelock boundaries of an escrow (nanoseconds) and the countdown to its next phase
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct EscrowTimeline {
+    pub hashlock: Vec<u8>,
+    pub deployed_at: u64,
+    pub withdrawal_start: u64,
+    pub public_withdrawal_start: u64,
+    pub cancellation_start: u64,
+    pub public_cancellation_start: Option<u64>, // None = never public
+    pub rescue_start: u64,
+    pub phase: EscrowPhase,
+    pub next_phase: Option<EscrowPhase>,        // None once no later phase will open
+    pub next_phase_at: Option<u64>,
+    pub seconds_to_next_phase: Option<u64>,     // Rounded up, so 0 only once the phase is open
+    pub now: u64,
+}
+
+impl EscrowTimeline {
+    /// Timeline of `escrow` at `now`, on the stages of the escrow's own side of the swap
+    pub fn new(escrow: &ICPEscrow, rescue_delay: u64, now: u64) -> Self {
+        let [withdrawal_start, public_withdrawal_start, cancellation_start] = escrow.stage_starts();
+        let public_cancellation_start = escrow.public_cancellation_start();
+        let rescue_start = escrow.immutables.timelocks.rescue_start(rescue_delay);
+
+        let mut boundaries: Vec<(EscrowPhase, u64)> = [
+            (EscrowPhase::PrivateWithdrawal, Some(withdrawal_start)),
+            (EscrowPhase::PublicWithdrawal, Some(public_withdrawal_start)),
+            (EscrowPhase::Cancellation, Some(cancellation_start)),
+            (EscrowPhase::PublicCancellation, public_cancellation_start),
+            (EscrowPhase::Rescue, Some(rescue_start)),
+        ]
+        .into_iter()
+        .filter_map(|(phase, start)| start.map(|start| (phase, start)))
+        .collect();
+        boundaries.sort_by_key(|(_, start)| *start);
+
+        let (phase, next) = match escrow.state {
+            EscrowState::PendingFunding => (EscrowPhase::AwaitingFunding, None),
+            EscrowState::Active | EscrowState::Processing => {
+                let phase = boundaries.iter()
+                    .take_while(|(_, start)| *start <= now)
+                    .last()
+                    .map_or(EscrowPhase::FinalityLock, |(phase, _)| *phase);
+                (phase, boundaries.iter().find(|(_, start)| *start > now).copied())
+            }
+            _ => (EscrowPhase::Closed, None),
+        };
+
+        EscrowTimeline {
+            hashlock: escrow.immutables.hashlock.clone(),
+            deployed_at: escrow.immutables.timelocks.deployed_at,
+            withdrawal_start,
+            public_withdrawal_start,
+            cancellation_start,
+            public_cancellation_start,
+            rescue_start,
+            phase,
+            next_phase: next.map(|(phase, _)| phase),
+            next_phase_at: next.map(|(_, start)| start),
+            seconds_to_next_phase: next.map(|(_, start)| (start - now).div_ceil(1_000_000_000)),
+            now,
+        }
+    }
+}
+
+/// Escrow together with its parties' display profiles, when they have set one
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct EscrowView {
+    pub escrow: ICPEscrow,
+    pub maker_profile: Option<ParticipantProfile>,
+    pub taker_profile: Option<ParticipantProfile>,
+}
+
+/// Restricted read an observer token can be granted
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum ObserverScope {
+    AuthorizedPrincipals, // get_authorized_principals
+    FeeReports,           // get_fee_report, get_cycle_report
+}
+
+/// Treasury-issued read token; grants queries only, never admin capability
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ObserverGrant {
+    pub principal: Principal,
+    pub scopes: Vec<ObserverScope>,
+    pub granted_at: u64,
+    pub expires_at: Option<u64>,
+}
+
+impl ObserverGrant {
+    /// Whether this grant covers `scope` at time `now`
+    pub fn allows(&self, scope: &ObserverScope, now: u64) -> bool {
+        self.expires_at.is_none_or(|expires_at| now < expires_at) && self.scopes.contains(scope)
+    }
+}
+
+/// Administrative duty a principal can be granted; Admin implies every other role
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Role {
+    Admin,      // Config, templates, chains, access grants and migrations
+    Operator,   // Stuck-escrow recovery, transfer retries, EVM relaying and metrics resets
+    Pauser,     // Freezing and unfreezing escrows
+    FeeManager, // Treasury swaps, sweeps and accounting exports
+}
+
+/// Roles explicitly granted to one principal
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct RoleAssignment {
+    pub principal: Principal,
+    pub roles: Vec<Role>,
+}
+
+/// Which metrics counters a reset clears
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum MetricsResetScope {
+    /// Escrow created/completed/cancelled counters
+    Counters,
+    /// Volume, fee and surplus totals
+    Totals,
+    /// Everything except the live active escrow count and the cycles deposited total
+    All,
+}
+
+/// Event together with its position in the append-only log
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct EventRecord {
+    pub seq: u64,
+    pub event: EscrowEvent,
+}
+
+/// Largest page `get_events_since` returns
+pub const MAX_EVENT_PAGE: usize = 500;
+
+/// An event as served to indexers tailing the log
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct LoggedEvent {
+    pub event_id: u64,             // Position in the log: consecutive from 0, never reused
+    pub timestamp: u64,
+    pub event: EscrowEvent,
+}
+
+impl From<EventRecord> for LoggedEvent {
+    fn from(record: EventRecord) -> Self {
+        LoggedEvent { event_id: record.seq, timestamp: record.event.timestamp(), event: record.event }
+    }
+}
+
+/// One page of `get_events_since`, in log order
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct EventLogPage {
+    pub events: Vec<LoggedEvent>,
+    pub next_event_id: u64,        // Pass back to continue; unchanged when there is nothing newer yet
+    pub latest_event_id: Option<u64>, // Newest event in the log, None while it is empty
+}
+
+/// Proof that an event is part of the certified event log
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct EventProof {
+    pub seq: u64,
+    pub event: EscrowEvent,
+    pub event_candid: Vec<u8>,     // Candid-encoded EventRecord; its SHA256 is the certified leaf
+    pub witness: Vec<u8>,          // CBOR hash tree witness for the path ["events", seq (u64 BE)]
+    pub certificate: Vec<u8>,      // IC certificate whose certified data is the tree root
+}
+
+/// Proof that an escrow record is part of the certified state
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct EscrowProof {
+    pub hashlock: Vec<u8>,
+    pub escrow: ICPEscrow,
+    pub escrow_candid: Vec<u8>,    // Candid-encoded ICPEscrow; its SHA256 is the certified leaf
+    pub witness: Vec<u8>,          // CBOR hash tree witness for the path ["escrows", hashlock]
+    pub root_hash: Vec<u8>,        // State root the witness reconstructs (certified data)
+    pub certificate: Vec<u8>,      // IC certificate whose certified data is the tree root
+}
+
+/// Certified answer to an escrow lookup, proving either the record or that none exists
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct CertifiedEscrow {
+    pub hashlock: Vec<u8>,
+    pub escrow: Option<ICPEscrow>,        // None when the witness proves the hashlock absent
+    pub escrow_candid: Option<Vec<u8>>,   // Candid-encoded ICPEscrow; its SHA256 is the certified leaf
+    pub witness: Vec<u8>,                 // CBOR hash tree witness for the path ["escrows", hashlock]
+    pub certificate: Vec<u8>,             // IC certificate whose certified data is the tree root
+}
+
+/// Final outcome of a settled escrow, as committed in the certified tree
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct EscrowReceipt {
+    pub hashlock: Vec<u8>,
+    pub order_hash: Vec<u8>,
+    pub maker: String,
+    pub taker: String,
+    pub token: String,
+    pub amount: u128,
+    pub outcome: EscrowState,
+    pub block_indices: Vec<u64>,
+    pub created_at: u64,
+    pub settled_at: Option<u64>,
+    pub fee_breakdown: FeeBreakdown,
+}
+
+/// Ledger block relevant to an escrow, flattened for evidence export
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct LedgerBlockEvidence {
+    pub index: u64,
+    pub found: bool,               // False if the ledger (and its archives) did not return the block
+    pub operation: String,         // Transfer, Mint, Burn, ...
+    pub memo: u64,
+    pub from: Option<String>,      // Account identifier (hex)
+    pub to: Option<String>,        // Account identifier (hex)
+    pub amount: u128,
+    pub fee: u128,
+    pub timestamp: u64,            // Block timestamp (nanoseconds)
+    pub parent_hash: Option<Vec<u8>>,
+}
+
+/// One treasury-relevant ledger movement, as a row of the accounting export
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct AccountingRow {
+    pub block_index: u64,
+    pub timestamp: u64,            // Block timestamp (nanoseconds)
+    pub kind: String,              // creation_fee, surplus, forfeiture or sweep
+    pub account: String,           // Receiving account identifier (hex)
+    pub amount: u128,
+    pub fee: u128,
+    pub memo: u64,
+    pub hashlock: Option<Vec<u8>>, // Escrow the movement belongs to, if any
+}
+
+impl AccountingRow {
+    /// Column names of `to_csv`, in order
+    pub const CSV_HEADER: &'static str = "block_index,timestamp,kind,account,amount,fee,memo,hashlock";
+
+    /// CSV line with an ISO-8601 timestamp and the amounts in base units
+    pub fn to_csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{}",
+            self.block_index,
+            utils::format_iso8601(self.timestamp),
+            self.kind,
+            self.account,
+            self.amount,
+            self.fee,
+            self.memo,
+            self.hashlock.as_deref().map(utils::bytes_to_hex).unwrap_or_default(),
+        )
+    }
+}
+
+/// Page of the treasury accounting export, in block order
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TreasuryExport {
+    pub rows: Vec<AccountingRow>,
+    pub csv: String,                // Same rows as CSV, header included
+    pub next_after_block: Option<u64>, // Pass as `after_block` for the next page; None when done
+}
+
+/// Creation fees held apart from escrowed funds, awaiting `withdraw_fees`
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct FeeBalance {
+    pub account: String,           // Fee subaccount of this canister (hex account identifier)
+    pub balance: u64,              // Collected and not yet withdrawn (e8s)
+}
+
+/// Everything known about an escrow, bundled for arbitration or legal export
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct EvidenceBundle {
+    pub hashlock: Vec<u8>,
+    pub escrow: ICPEscrow,
+    pub recorded_tx_hash: Option<String>,
+    pub evm_address: Option<String>,
+    pub events: Vec<EscrowEvent>,
+    pub ledger_blocks: Vec<LedgerBlockEvidence>,
+    pub receipt: Option<EscrowReceipt>,
+    pub generated_at: u64,
+    pub generated_by: Principal,
+}
+
+/// One ICP escrow of a traced swap, with what it recorded on the ledger and the counter-chain
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SwapTraceLeg {
+    pub hashlock: Vec<u8>,
+    pub state: EscrowState,
+    pub deposit_blocks: Vec<u64>,
+    pub creation_fee_block: Option<u64>,
+    pub settlement_blocks: Vec<u64>,
+    pub icp_tx_hash: Option<String>,
+    pub evm_address: Option<String>,
+    pub session: Option<SwapSession>,     // Counterpart-chain leg, when linked by a resolver
+    pub processing: Option<ProcessingLock>,
+    pub freeze: Option<EscrowFreeze>,
+}
+
+impl SwapTraceLeg {
+    pub fn new(hashlock: Vec<u8>, escrow: ICPEscrow, session: Option<SwapSession>) -> Self {
+        Self {
+            hashlock,
+            state: escrow.state,
+            deposit_blocks: escrow.deposit_blocks,
+            creation_fee_block: escrow.creation_fee_block,
+            settlement_blocks: escrow.settlement_blocks,
+            icp_tx_hash: escrow.icp_tx_hash,
+            evm_address: escrow.evm_address,
+            session,
+            processing: escrow.processing,
+            freeze: escrow.freeze,
+        }
+    }
+}
+
+/// Event of one leg, placed on the swap's timeline
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TraceEntry {
+    pub timestamp: u64,
+    pub hashlock: Vec<u8>,
+    pub event: EscrowEvent,
+}
+
+/// Everything recorded for one order across its escrows, for support investigations
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SwapTrace {
+    pub order_hash: Vec<u8>,
+    pub legs: Vec<SwapTraceLeg>,
+    pub timeline: Vec<TraceEntry>,         // Events of every leg, oldest first
+    pub secret: Option<Vec<u8>>,           // Secret revealed by the first withdrawal, unless redacted
+    pub secret_revealed_at: Option<u64>,
+    pub generated_at: u64,
+}
+
+impl SwapTrace {
+    /// Merge the legs' events into one timeline and find where the secret was first revealed
+    pub fn new(order_hash: Vec<u8>, legs: Vec<SwapTraceLeg>, mut timeline: Vec<TraceEntry>, now: u64) -> Self {
+        timeline.sort_by_key(|entry| entry.timestamp);
+        let revealed = timeline.iter().find_map(|entry| match &entry.event {
+            EscrowEvent::EscrowWithdrawal { secret, timestamp, .. } => Some((secret.clone(), *timestamp)),
+            _ => None,
+        });
+
+        Self {
+            order_hash,
+            legs,
+            timeline,
+            secret: revealed.as_ref().map(|(secret, _)| secret.clone()).filter(|secret| !secret.is_empty()),
+            secret_revealed_at: revealed.map(|(_, timestamp)| timestamp),
+            generated_at: now,
+        }
+    }
+}
+
+/// Operation whose cost is simulated by `simulate_operation_cost`
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum SimulatedOperation {
+    CreateSrc,
+    CreateDst,
+    FundSafetyDeposit,
+    Withdraw(EscrowType),
+    Cancel(EscrowType),
+    Rescue(u128),
+}
+
+/// Escrow an operation is simulated against: an existing one or one about to be created
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum OperationTarget {
+    Hashlock(Vec<u8>),
+    Immutables(Box<EscrowImmutables>),
+}
+
+/// Exact base-unit breakdown of an operation
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct OperationCost {
+    pub deposit: u128,             // Amount the caller deposits into the escrow
+    pub protocol_fee: u128,        // Creation fee or surplus taken by the treasury
+    pub ledger_fees: u128,         // Ledger fees across every transfer leg
+    pub transfer_legs: u32,        // Number of ledger transfers made
+    pub total_required: u128,      // Balance the caller needs to execute the operation
+    pub net_payouts: Vec<u128>,    // Amounts sent per settlement leg after fee deductions
+}
+
+/// Outcome of a single check in a preflight report
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PreflightCheck {
+    pub check: String,              // state, caller or timing
+    pub error: Option<EscrowError>, // Error the real call would return, if failing
+    pub passes_at: Option<u64>,     // When a failing check starts passing (None if it will not on its own)
+}
+
+impl PreflightCheck {
+    pub fn passed(check: &str) -> Self {
+        Self { check: check.to_string(), error: None, passes_at: None }
+    }
+
+    pub fn failed(check: &str, error: EscrowError, passes_at: Option<u64>) -> Self {
+        Self { check: check.to_string(), error: Some(error), passes_at }
+    }
+}
+
+/// Result of running an operation's checks without executing it
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PreflightReport {
+    pub ready: bool,                // All checks pass right now
+    pub ready_at: Option<u64>,      // When all checks pass, if only waiting is required
+    pub checks: Vec<PreflightCheck>,
+}
+
+impl PreflightReport {
+    pub fn new(checks: Vec<PreflightCheck>) -> Self {
+        let failing: Vec<&PreflightCheck> = checks.iter().filter(|check| check.error.is_some()).collect();
+        let ready_at = failing.iter()
+            .map(|check| check.passes_at)
+            .collect::<Option<Vec<u64>>>()
+            .and_then(|times| times.into_iter().max());
+
+        Self {
+            ready: failing.is_empty(),
+            ready_at: if failing.is_empty() { None } else { ready_at },
+            checks,
+        }
+    }
+}
+
+/// Certified receipt suitable for presenting to third parties
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ReceiptProof {
+    pub receipt: EscrowReceipt,
+    pub receipt_candid: Vec<u8>,   // Candid-encoded EscrowReceipt; its SHA256 is the certified leaf
+    pub witness: Vec<u8>,          // CBOR hash tree witness for the path ["receipts", hashlock]
+    pub certificate: Vec<u8>,      // IC certificate whose certified data is the tree root
+}
+
+// Validation helpers
+impl EscrowImmutables {
+    /// Whether the safety deposit is funded by the taker rather than the creator
+    pub fn is_taker_funded_deposit(&self) -> bool {
+        matches!(self.safety_deposit_funding, Some(SafetyDepositFunding::Taker))
+    }
+
+    /// Deposit the creator locks: the amount, plus the safety deposit unless the taker funds it
+    pub fn required_deposit(&self) -> Result<u128> {
+        if self.is_taker_funded_deposit() {
+            Ok(self.amount)
+        } else {
+            utils::add_amounts(self.amount, self.safety_deposit)
+        }
+    }
+
+    /// Whether the order fills in parts, each unlocked by its own secret from a Merkle tree
+    pub fn is_partially_fillable(&self) -> bool {
+        self.merkle_root.is_some()
+    }
+
+    /// Hash the hashlock was computed with, SHA-256 unless the order says otherwise
+    pub fn hashlock_algorithm(&self) -> HashAlgorithm {
+        self.hash_algorithm.unwrap_or(HashAlgorithm::Sha256)
+    }
+
+    /// Amount the order's Dutch auction requires at `now`: `maker_min_amount` plus the
+    /// premium then in force. None when the order has no auction.
+    pub fn required_amount_at(&self, now: u64) -> Option<u128> {
+        let auction = self.auction.as_ref()?;
+        let base = self.maker_min_amount?;
+        let rate = 10_000 + u128::from(auction.rate_bps_at(now));
+        Some(utils::mul_div(base, rate, 10_000).unwrap_or(u128::MAX))
+    }
+
+    /// The locked amount must meet the auction's price at `now`
+    pub fn check_auction_price(&self, now: u64) -> Result<()> {
+        match self.required_amount_at(now) {
+            Some(required) if self.amount < required => Err(EscrowError::BelowAuctionPrice),
+            _ => Ok(()),
+        }
+    }
+
+    /// Parameters for a repeat swap between the same parties: order hash and hashlock are
+    /// zeroed placeholders, deployment is left to creation, and the amount, deposit and
+    /// payout floors are brought within the current config's limits
+    pub fn repeat_params(&self, config: &EscrowConfig) -> EscrowImmutables {
+        let amount = self.amount.clamp(config.min_amount, config.max_amount);
+        EscrowImmutables {
+            order_hash: vec![0u8; 32],
+            hashlock: vec![0u8; 32],
+            amount,
+            safety_deposit: self.safety_deposit.max(config.min_safety_deposit),
+            maker_min_amount: self.maker_min_amount.map(|min| min.min(amount)),
+            min_received: self.min_received.map(|min| min.min(amount)),
+            timelocks: Timelocks { deployed_at: 0, ..self.timelocks.clone() },
+            ..self.clone()
+        }
+    }
+
+    pub fn validate(&self, config: &EscrowConfig) -> Result<()> {
+        // Validate hashlock length (32 bytes for both SHA-256 and Keccak-256)
+        if self.hashlock.len() != 32 {
+            return Err(EscrowError::InvalidHashlock);
+        }
+
+        // Validate order hash length (should be 32 bytes)
+        if self.order_hash.len() != 32 {
+            return Err(EscrowError::InvalidHashlock);
+        }
+
+        // Validate amounts
+        if self.amount < config.min_amount || self.amount > config.max_amount {
+            return Err(EscrowError::InvalidAmount);
+        }
+
+        if self.safety_deposit < config.min_safety_deposit {
+            return Err(EscrowError::InvalidAmount);
+        }
+
+        // Maker's minimum cannot exceed what is actually locked
+        if let Some(maker_min_amount) = self.maker_min_amount {
+            if maker_min_amount > self.amount {
+                return Err(EscrowError::InvalidAmount);
+            }
+        }
+
+        // A payout floor above the locked amount could never be met
+        if let Some(min_received) = self.min_received {
+            if min_received > self.amount {
+                return Err(EscrowError::InvalidAmount);
+            }
+        }
+
+        utils::check_length("maker", self.maker.len(), utils::MAX_ADDRESS_LEN)?;
+        utils::check_length("taker", self.taker.len(), utils::MAX_ADDRESS_LEN)?;
+        utils::check_length("token", self.token.len(), utils::MAX_ADDRESS_LEN)?;
+
+        // Validate addresses (basic check for non-empty)
+        if self.maker.is_empty() || self.taker.is_empty() {
+            return Err(EscrowError::InvalidAddress);
+        }
+
+        // Validate maker != taker
+        if self.maker == self.taker {
+            return Err(EscrowError::InvalidAddress);
+        }
+
+        // Validate timelock ordering
+        if !self.timelocks.stages_increasing() {
+            return Err(EscrowError::InvalidTime);
+        }
+
+        // Escrows must stay open at least min_timelock and close within max_timelock, on either side
+        let first_cancellation = self.timelocks.cancellation
+            .min(self.timelocks.offset(TimelockStage::DstCancellation).unwrap_or(self.timelocks.cancellation));
+        if first_cancellation < config.min_timelock || self.timelocks.last_offset() > config.max_timelock {
+            return Err(EscrowError::InvalidTime);
+        }
+
+        // Only ledgers the treasury listed may be called on an escrow's behalf
+        if let Some(ledger) = self.icp_token {
+            if !config.icrc_ledgers.contains(&ledger) {
+                return Err(EscrowError::UnsupportedToken);
+            }
+        }
+
+        // Partial fills need both the tree of secrets and a part count the amount can cover
+        match (&self.merkle_root, self.parts_count) {
+            (None, None) => {}
+            (Some(root), Some(parts)) if root.len() == 32 && parts > 0 && u128::from(parts) <= self.amount => {}
+            _ => return Err(EscrowError::InvalidPartialFill),
+        }
+
+        // An auction prices the order relative to the maker's minimum
+        if let Some(auction) = &self.auction {
+            if self.maker_min_amount.is_none() {
+                return Err(EscrowError::InvalidAuction);
+            }
+            auction.validate()?;
+        }
+
+        Ok(())
+    }
+
+    /// Rules for source escrows (ICP→EVM): the maker locks ICP and is refunded on
+    /// cancellation and the taker is paid on withdrawal, so both must be principals
+    pub fn validate_src(&self, config: &EscrowConfig) -> Result<()> {
+        self.validate(config)?;
+        // Auctions price what the resolver locks, which only a destination escrow holds
+        if self.auction.is_some() {
+            return Err(EscrowError::InvalidAuction);
+        }
+        self.require_principal_parties()
+    }
+
+    /// Rules for destination escrows (EVM→ICP): the taker locks ICP and the maker is paid,
+    /// so both must be principals, and the taker always bundles the deposit. When the source
+    /// leg is known, its cancellation must start strictly after ours so the resolver cannot
+    /// reclaim the source funds while the maker's ICP is still claimable.
+    pub fn validate_dst(&self, config: &EscrowConfig, src: Option<&EscrowImmutables>) -> Result<()> {
+        self.validate(config)?;
+        self.require_principal_parties()?;
+
+        if self.is_taker_funded_deposit() {
+            return Err(EscrowError::InvalidDepositMode);
+        }
+
+        // Resolvers fill the source side in parts; the destination side settles at once
+        if self.is_partially_fillable() {
+            return Err(EscrowError::InvalidPartialFill);
+        }
+
+        if let Some(src) = src {
+            SwapSession::cancellation_gap(&src.timelocks, &self.timelocks)?;
+        }
+
+        Ok(())
+    }
+
+    /// Maker and taker must parse as principals, or payouts and refunds could never be sent
+    fn require_principal_parties(&self) -> Result<()> {
+        utils::validate_principal(self.maker.trim())?;
+        utils::validate_principal(self.taker.trim())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_config_is_valid() {
+        assert!(EscrowConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_config_rejects_inverted_amounts() {
+        let defaults = EscrowConfig::default();
+        let config = EscrowConfig { min_amount: defaults.max_amount + 1, ..defaults };
+        assert!(matches!(config.validate(), Err(EscrowError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_config_rejects_short_rescue_delay() {
+        let config = EscrowConfig { rescue_delay: 0, ..EscrowConfig::default() };
+        assert!(matches!(config.validate(), Err(EscrowError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_config_rejects_excessive_fee() {
+        let config = EscrowConfig { creation_fee: MAX_CREATION_FEE + 1, ..EscrowConfig::default() };
+        assert!(matches!(config.validate(), Err(EscrowError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_config_rejects_anonymous_treasury() {
+        let config = EscrowConfig { treasury: Principal::anonymous(), ..EscrowConfig::default() };
+        assert!(matches!(config.validate(), Err(EscrowError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_config_ledger_defaults_to_mainnet() {
+        let config = EscrowConfig::default();
+        assert_eq!(config.icp_ledger.to_text(), "ryjl3-tyaaa-aaaaa-aaaba-cai");
+
+        let local = EscrowConfig { icp_ledger: Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 9, 1, 1]), ..config.clone() };
+        assert!(local.validate().is_ok());
+        let unset = EscrowConfig { icp_ledger: Principal::anonymous(), ..config };
+        assert!(matches!(unset.validate(), Err(EscrowError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_init_args_override_only_set_fields() {
+        let treasury = Principal::from_slice(&[3; 29]);
+        let args = InitArgs { treasury: Some(treasury), min_timelock: Some(600), ..InitArgs::default() };
+        let config = args.apply(EscrowConfig::default()).unwrap();
+        assert_eq!(config.treasury, treasury);
+        assert_eq!(config.min_timelock, 600);
+        assert_eq!(config.rescue_delay, EscrowConfig::default().rescue_delay);
+
+        let inverted = InitArgs { min_timelock: Some(600), max_timelock: Some(60), ..InitArgs::default() };
+        assert!(matches!(inverted.apply(EscrowConfig::default()), Err(EscrowError::InvalidConfig(_))));
+        let anonymous = InitArgs { treasury: Some(Principal::anonymous()), ..InitArgs::default() };
+        assert!(matches!(anonymous.apply(EscrowConfig::default()), Err(EscrowError::InvalidConfig(_))));
+    }
+
+    fn escrow_with_funding(funding: Option<SafetyDepositFunding>) -> ICPEscrow {
+        ICPEscrow {
+            immutables: EscrowImmutables {
+                order_hash: vec![0; 32],
+                hashlock: vec![1; 32],
+                maker: "maker".to_string(),
+                taker: "taker".to_string(),
+                token: "0x0".to_string(),
+                amount: 1_000_000,
+                safety_deposit: 100_000,
+                timelocks: Timelocks {
+                    deployed_at: 0,
+                    withdrawal: 10,
+                    public_withdrawal: 20,
+                    cancellation: 30,
+                    public_cancellation: None,
+                    dst: None,
+                },
+                maker_min_amount: None,
+                min_received: None,
+                safety_deposit_funding: funding,
+                chain_id: None,
+                icp_token: None,
+                hash_algorithm: None,
+                merkle_root: None,
+                parts_count: None,
+                auction: None,
+                metadata: None,
+            },
+            state: EscrowState::Active,
+            icp_tx_hash: None,
+            evm_address: None,
+            created_at: 0,
+            completed_at: None,
+            revealed_secret: None,
+            surplus_captured: 0,
+            safety_deposit_held: 100_000,
+            fee_breakdown: FeeBreakdown::default(),
+            settlement_blocks: Vec::new(),
+            deposit_blocks: Vec::new(),
+            notes: Vec::new(),
+            freeze: None,
+            rescue_requested_at: None,
+            processing: None,
+            creation_fee_block: None,
+            migration: None,
+            cycles: None,
+            token_fee: None,
+            subaccount: None,
+            partial_fill: None,
+            funding: None,
+            evm_verification: None,
+            evm_immutables: None,
+            evm_withdrawal: None,
+            rescue_balance: None,
+            transfer_legs: None,
+            escrow_type: None,
+        }
+    }
+
+    #[test]
+    fn test_secret_revealed_only_on_completion() {
+        let mut escrow = escrow_with_funding(None);
+        escrow.revealed_secret = Some(vec![7; 32]);
+        assert_eq!(escrow.revealed_secret(), None);
+
+        escrow.state = EscrowState::Completed;
+        assert_eq!(escrow.revealed_secret(), Some(&[7u8; 32][..]));
+        assert_eq!(escrow.clone().without_secret().revealed_secret(), None);
+
+        let mut event = EscrowEvent::SecretRevealed { hashlock: vec![1; 32], order_hash: vec![0; 32], secret: vec![7; 32], timestamp: 3 };
+        assert_eq!(event.hashlock(), Some(&[1u8; 32][..]));
+        event.redact_secret();
+        assert!(matches!(&event, EscrowEvent::SecretRevealed { secret, .. } if secret.is_empty()));
+    }
+
+    #[test]
+    fn test_safety_deposit_refund_routing() {
+        let bundled = escrow_with_funding(None);
+        assert_eq!(bundled.safety_deposit_owner(&EscrowType::Source), "maker");
+        assert_eq!(bundled.safety_deposit_owner(&EscrowType::Destination), "taker");
+
+        let taker_funded = escrow_with_funding(Some(SafetyDepositFunding::Taker));
+        assert_eq!(taker_funded.safety_deposit_owner(&EscrowType::Source), "taker");
+    }
+
+    #[test]
+    fn test_required_deposit_is_checked() {
+        let mut bundled = escrow_with_funding(None).immutables;
+        assert_eq!(bundled.required_deposit().unwrap(), 1_100_000);
+        bundled.amount = u128::MAX;
+        assert!(matches!(bundled.required_deposit(), Err(EscrowError::InvalidAmount)));
+
+        // A taker-funded deposit arrives later, so only the amount is locked at creation
+        let taker_funded = escrow_with_funding(Some(SafetyDepositFunding::Taker)).immutables;
+        assert_eq!(taker_funded.required_deposit().unwrap(), 1_000_000);
+    }
+
+    #[test]
+    fn test_ledger_rejection_names_error_variant() {
+        #[derive(Debug)]
+        #[allow(dead_code)]
+        enum LedgerError {
+            InsufficientFunds { balance: u64 },
+            TxTooOld,
+        }
+
+        let EscrowError::LedgerRejected { method, code, message } =
+            EscrowError::ledger_rejected("icrc1_transfer", &LedgerError::InsufficientFunds { balance: 5 })
+        else {
+            panic!("expected LedgerRejected");
+        };
+        assert_eq!(method, "icrc1_transfer");
+        assert_eq!(code, "InsufficientFunds");
+        assert_eq!(message, "InsufficientFunds { balance: 5 }");
+        assert!(matches!(
+            EscrowError::ledger_rejected("transfer", &LedgerError::TxTooOld),
+            EscrowError::LedgerRejected { code, .. } if code == "TxTooOld"
+        ));
+    }
+
+    #[test]
+    fn test_rescue_capped_at_escrow_balance() {
+        let mut escrow = escrow_with_funding(None);
+        assert_eq!(escrow.rescuable(), 1_100_000);
+
+        escrow.rescue_balance = Some(400_000);
+        assert_eq!(escrow.rescuable(), 400_000);
+
+        escrow.state = EscrowState::Completed;
+        assert_eq!(escrow.rescuable(), 0);
+    }
+
+    #[test]
+    fn test_retried_transfer_reuses_its_stamp() {
+        let mut escrow = escrow_with_funding(None);
+        let taker = Principal::from_slice(&[2]);
+        let first = escrow.open_transfer_leg(7, taker, 1_000, 100);
+        assert_eq!(escrow.open_transfer_leg(7, taker, 1_000, 200), first);
+
+        // A different transfer, or the same one once paid, starts a new leg
+        assert_eq!(escrow.open_transfer_leg(7, taker, 2_000, 300), 300);
+        escrow.settle_transfer_leg(7, &taker, 1_000, 42);
+        assert_eq!(escrow.open_transfer_leg(7, taker, 1_000, 400), 400);
+        assert_eq!(escrow.transfer_legs.as_ref().unwrap()[0].block, Some(42));
+
+        // Stamps the ledger would reject as too old are renewed
+        let later = 400 + crate::ledger::TRANSFER_STAMP_TTL + 1;
+        assert_eq!(escrow.open_transfer_leg(7, taker, 1_000, later), later);
+
+        escrow.close_transfer_legs(7, None);
+        assert_eq!(escrow.transfer_legs.as_ref().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_public_cancellation_follows_cancellation() {
+        let mut immutables = escrow_with_funding(None).immutables;
+        assert_eq!(immutables.timelocks.public_cancellation_start(), None);
+
+        immutables.timelocks.deployed_at = 5;
+        immutables.timelocks.public_cancellation = Some(40);
+        assert_eq!(immutables.timelocks.public_cancellation_start(), Some(5 + 40_000_000_000));
+        assert!(immutables.validate(&EscrowConfig::default()).is_ok());
+
+        immutables.timelocks.public_cancellation = Some(30);
+        assert!(matches!(immutables.validate(&EscrowConfig::default()), Err(EscrowError::InvalidTime)));
+    }
+
+    #[test]
+    fn test_escrow_timeline_phases_and_countdown() {
+        let mut escrow = escrow_with_funding(None);
+        let second = 1_000_000_000;
+        let rescue_delay = 100 * second;
+
+        let locked = EscrowTimeline::new(&escrow, rescue_delay, 5 * second);
+        assert_eq!(locked.phase, EscrowPhase::FinalityLock);
+        assert_eq!((locked.next_phase, locked.seconds_to_next_phase), (Some(EscrowPhase::PrivateWithdrawal), Some(5)));
+
+        // Partial seconds round up, so the countdown only reaches 0 when the phase opens
+        let public = EscrowTimeline::new(&escrow, rescue_delay, 20 * second - 1);
+        assert_eq!(public.seconds_to_next_phase, Some(1));
+        assert_eq!(EscrowTimeline::new(&escrow, rescue_delay, 20 * second).phase, EscrowPhase::PublicWithdrawal);
+
+        escrow.immutables.timelocks.public_cancellation = Some(40);
+        let cancellable = EscrowTimeline::new(&escrow, rescue_delay, 35 * second);
+        assert_eq!(cancellable.phase, EscrowPhase::Cancellation);
+        assert_eq!(cancellable.next_phase_at, Some(40 * second));
+
+        let rescue = EscrowTimeline::new(&escrow, rescue_delay, 100 * second);
+        assert_eq!((rescue.phase, rescue.next_phase), (EscrowPhase::Rescue, None));
+
+        escrow.state = EscrowState::Completed;
+        let closed = EscrowTimeline::new(&escrow, rescue_delay, 5 * second);
+        assert_eq!((closed.phase, closed.seconds_to_next_phase), (EscrowPhase::Closed, None));
+        assert_eq!(closed.cancellation_start, 30 * second);
+    }
+
+    #[test]
+    fn test_dst_stages_default_to_src_stages() {
+        let mut immutables = escrow_with_funding(None).immutables;
+        let timelocks = &immutables.timelocks;
+        assert_eq!(timelocks.offset(TimelockStage::DstWithdrawal), timelocks.offset(TimelockStage::SrcWithdrawal));
+        assert_eq!(timelocks.offset(TimelockStage::DstCancellation), timelocks.offset(TimelockStage::SrcCancellation));
+
+        immutables.timelocks.dst = Some(DstTimelocks { withdrawal: 5, public_withdrawal: 8, cancellation: 15 });
+        assert_eq!(immutables.timelocks.offset(TimelockStage::DstCancellation), Some(15));
+        assert!(immutables.validate(&EscrowConfig::default()).is_ok());
+
+        immutables.timelocks.dst = Some(DstTimelocks { withdrawal: 8, public_withdrawal: 5, cancellation: 15 });
+        assert!(matches!(immutables.validate(&EscrowConfig::default()), Err(EscrowError::InvalidTime)));
+    }
+
+    #[test]
+    fn test_packed_timelocks_match_timelocks_lib_layout() {
+        let timelocks = Timelocks {
+            withdrawal: 1,
+            public_withdrawal: 2,
+            cancellation: 3,
+            public_cancellation: Some(4),
+            deployed_at: 9_000_000_000,
+            dst: Some(DstTimelocks { withdrawal: 5, public_withdrawal: 6, cancellation: 7 }),
+        };
+        let word = timelocks.to_packed().unwrap();
+        // Stage i sits in bits 32*i, counted from the low end of the big-endian word
+        assert_eq!(
+            hex::encode(word),
+            "0000000900000007000000060000000500000004000000030000000200000001"
+        );
+
+        let decoded = Timelocks::from_packed(&word).unwrap();
+        assert_eq!(decoded.deployed_at, 9_000_000_000);
+        assert_eq!(decoded.public_cancellation, Some(4));
+        assert_eq!(decoded.dst, timelocks.dst);
+        assert_eq!(decoded.to_packed().unwrap(), word);
+
+        // A public cancellation that never opens survives the round trip
+        let never = Timelocks { public_cancellation: None, ..timelocks };
+        assert_eq!(Timelocks::from_packed(&never.to_packed().unwrap()).unwrap().public_cancellation, None);
+        assert!(Timelocks::from_packed(&word[1..]).is_err());
+    }
+
+    #[test]
+    fn test_fee_breakdown_splits_deducted_and_covered() {
+        let fee = u128::from(crate::ledger::TRANSFER_FEE);
+
+        // Recipient pays: both fees deducted
+        let mut recipient = FeeBreakdown::default();
+        recipient.record_transfers(&[1_000_000, 200_000], &[1_000_000 - fee, 200_000 - fee], fee);
+        assert_eq!(recipient.ledger_fees_deducted, 2 * fee);
+        assert_eq!(recipient.ledger_fees_covered, 0);
+
+        // Canister pays: nothing deducted
+        let mut canister = FeeBreakdown::default();
+        canister.record_transfers(&[1_000_000, 0], &[1_000_000, 0], fee);
+        assert_eq!(canister.ledger_fees_deducted, 0);
+        assert_eq!(canister.ledger_fees_covered, fee);
+    }
+
+    #[test]
+    fn test_cycle_costs_accumulate_by_phase() {
+        let mut escrow = escrow_with_funding(None);
+        assert!(escrow.cycles.is_none());
+        escrow.cycles_mut().creation += 10;
+        escrow.cycles_mut().timers += 5;
+
+        let mut totals = CycleCosts::default();
+        totals.accumulate(&escrow.cycles.clone().unwrap());
+        totals.accumulate(&CycleCosts { settlement: 20, outcalls: 1, ..CycleCosts::default() });
+        assert_eq!(totals, CycleCosts { creation: 10, settlement: 20, timers: 5, outcalls: 1 });
+        assert_eq!(totals.total(), 36);
+    }
+
+    #[test]
+    fn test_preflight_report_ready_at() {
+        let ready = PreflightReport::new(vec![PreflightCheck::passed("state")]);
+        assert!(ready.ready);
+        assert_eq!(ready.ready_at, None);
+
+        // Only waiting is required: ready once the latest check passes
+        let waiting = PreflightReport::new(vec![
+            PreflightCheck::passed("caller"),
+            PreflightCheck::failed("timing", EscrowError::InvalidTime, Some(50)),
+            PreflightCheck::failed("timing", EscrowError::InvalidTime, Some(80)),
+        ]);
+        assert!(!waiting.ready);
+        assert_eq!(waiting.ready_at, Some(80));
+
+        // A check that will not pass on its own means no ready time
+        let blocked = PreflightReport::new(vec![
+            PreflightCheck::failed("caller", EscrowError::InvalidCaller, None),
+            PreflightCheck::failed("timing", EscrowError::InvalidTime, Some(50)),
+        ]);
+        assert_eq!(blocked.ready_at, None);
+    }
+
+    #[test]
+    fn test_observer_grant_scope_and_expiry() {
+        let grant = ObserverGrant {
+            principal: Principal::from_slice(&[9]),
+            scopes: vec![ObserverScope::FeeReports],
+            granted_at: 100,
+            expires_at: Some(200),
+        };
+        assert!(grant.allows(&ObserverScope::FeeReports, 150));
+        assert!(!grant.allows(&ObserverScope::AuthorizedPrincipals, 150));
+        assert!(!grant.allows(&ObserverScope::FeeReports, 200));
+
+        let open_ended = ObserverGrant { expires_at: None, ..grant };
+        assert!(open_ended.allows(&ObserverScope::FeeReports, u64::MAX));
+    }
+
+    #[test]
+    fn test_is_party_matches_parsed_principals() {
+        let maker = Principal::from_slice(&[1, 2, 3]);
+        let mut escrow = escrow_with_funding(None);
+        escrow.immutables.maker = format!(" {} ", maker.to_text());
+        escrow.immutables.taker = "0x742d35Cc6E5A69e6d89B134b1234567890123456".to_string();
+
+        assert!(escrow.is_party(&maker));
+        assert!(!escrow.is_party(&Principal::from_slice(&[4])));
+    }
+
+    #[test]
+    fn test_public_withdraw_modes() {
+        use PublicWithdrawMode::*;
+        assert!(AuthorizedPrincipals.allows(true, false, false));
+        assert!(!AuthorizedPrincipals.allows(false, true, true));
+
+        assert!(RegisteredResolvers.allows(false, true, false));
+        assert!(!RegisteredResolvers.allows(false, false, true));
+
+        assert!(Permissionless.allows(false, true, false));
+        assert!(Permissionless.allows(false, false, true));
+        assert!(!Permissionless.allows(false, false, false));
+    }
+
+    #[test]
+    fn test_template_expands_into_immutables() {
+        let template = EscrowTemplate {
+            id: "icp-eth-small".to_string(),
+            token: "0x0000000000000000000000000000000000000000".to_string(),
+            min_amount: 500_000,
+            max_amount: 2_000_000,
+            safety_deposit: 200_000,
+            timelocks: TimelockPreset { withdrawal: 60, public_withdrawal: 600, cancellation: 3600 },
+            creation_fee: Some(0),
+        };
+        assert!(template.validate(&EscrowConfig::default()).is_ok());
+
+        let mut immutables = escrow_with_funding(None).immutables;
+        immutables.timelocks.deployed_at = 7;
+        template.apply(&mut immutables).unwrap();
+        assert_eq!(immutables.token, template.token);
+        assert_eq!(immutables.safety_deposit, 200_000);
+        assert_eq!(immutables.timelocks.cancellation, 3600);
+        assert_eq!(immutables.timelocks.deployed_at, 7);
+
+        immutables.amount = 2_000_001;
+        assert!(matches!(template.apply(&mut immutables), Err(EscrowError::InvalidAmount)));
+
+        let unordered = EscrowTemplate {
+            timelocks: TimelockPreset { withdrawal: 600, public_withdrawal: 60, cancellation: 3600 },
+            ..template
+        };
+        assert!(unordered.validate(&EscrowConfig::default()).is_err());
+    }
+
+    #[test]
+    fn test_reminder_only_within_horizon() {
+        // Timelocks from the fixture: withdrawal at 10s, cancellation at 30s
+        let escrow = escrow_with_funding(None);
+        let closes_at = 30 * 1_000_000_000;
+        let horizon = 5 * 1_000_000_000;
+
+        assert!(escrow.reminder(closes_at - horizon - 1, horizon).is_none());
+        let reminder = escrow.reminder(closes_at - horizon, horizon).unwrap();
+        assert_eq!(reminder.remaining, horizon);
+        assert!(reminder.window_open);
+        assert!(escrow.reminder(closes_at, horizon).is_none());
+
+        let settled = ICPEscrow { state: EscrowState::Completed, ..escrow };
+        assert!(settled.reminder(closes_at - 1, horizon).is_none());
+    }
+
+    #[test]
+    fn test_swap_session_legs_must_match() {
+        let dst = escrow_with_funding(None).immutables;
+        let mut src = dst.clone();
+        src.chain_id = Some(1);
+        src.maker = "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed".to_string();
+        src.taker = "0xfb6916095ca1df60bb79ce92ce3ea74c37c5d359".to_string();
+        assert!(SwapSession::validate_legs(&src, &dst).is_ok());
+
+        let other_order = EscrowImmutables { order_hash: vec![9u8; 32], ..src.clone() };
+        assert!(matches!(SwapSession::validate_legs(&other_order, &dst), Err(EscrowError::InvalidSession)));
+
+        let no_chain = EscrowImmutables { chain_id: None, ..src.clone() };
+        assert!(matches!(SwapSession::validate_legs(&no_chain, &dst), Err(EscrowError::InvalidSession)));
+
+        let icp_maker = EscrowImmutables { maker: dst.maker.clone(), ..src };
+        assert!(matches!(SwapSession::validate_legs(&icp_maker, &dst), Err(EscrowError::InvalidAddress)));
+    }
+
+    #[test]
+    fn test_processing_lock_stuck_after_timeout() {
+        let lock = ProcessingLock {
+            operation: ProcessingOperation::Cancellation {
+                escrow_type: EscrowType::Source,
+                canceller: Principal::anonymous(),
+            },
+            started_at: 100,
+        };
+        assert!(!lock.is_stuck(150, 50));
+        assert!(lock.is_stuck(151, 50));
+        assert!(!lock.is_stuck(50, 50)); // Clock behind the lock never counts as stuck
+    }
+
+    #[test]
+    fn test_funding_expires_after_deadline() {
+        let mut escrow = escrow_with_funding(None);
+        escrow.state = EscrowState::PendingFunding;
+        escrow.funding = Some(FundingWindow { escrow_type: EscrowType::Source, required: 1_100_000, deadline: 100 });
+        assert!(!escrow.funding_expired(100));
+        assert!(escrow.funding_expired(101));
+
+        // Once funded the deadline no longer applies
+        escrow.state = EscrowState::Active;
+        assert!(!escrow.funding_expired(101));
+    }
+
+    #[test]
+    fn test_evm_verification_must_match_recorded_address() {
+        let mut escrow = escrow_with_funding(None);
+        assert!(!escrow.evm_verified());
+
+        let status = EvmEscrowStatus {
+            chain_id: 1,
+            address: "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed".to_string(),
+            deployed: true,
+            block_number: 100,
+            providers: 2,
+            checked_at: 1,
+        };
+        escrow.evm_address = Some(status.address.clone());
+        escrow.evm_verification = Some(status.clone());
+        assert!(escrow.evm_verified());
+
+        // Recording another address invalidates the check
+        escrow.evm_address = Some("0x0000000000000000000000000000000000000001".to_string());
+        assert!(!escrow.evm_verified());
+
+        escrow.evm_address = Some(status.address.clone());
+        escrow.evm_verification = Some(EvmEscrowStatus { deployed: false, ..status });
+        assert!(!escrow.evm_verified());
+    }
+
+    #[test]
+    fn test_volume_cap_applies_to_unverified_only() {
+        let usage = VolumeUsage { period_start: 0, used: 900, cap: Some(1_000), verified: false };
+        assert!(usage.allows(100));
+        assert!(!usage.allows(101));
+        assert!(VolumeUsage { verified: true, ..usage.clone() }.allows(u128::MAX));
+        assert!(VolumeUsage { cap: None, ..usage }.allows(u128::MAX));
+    }
+
+    #[test]
+    fn test_forfeiture_split_gives_dust_to_treasury() {
+        let schedule = ForfeitureSchedule { executor_bps: 5_000, counterparty_bps: 3_333, treasury_bps: 1_667 };
+        assert_eq!(schedule.split(10_000), [5_000, 3_333, 1_667]);
+        assert_eq!(schedule.split(7), [3, 2, 2]);
+
+        let config = EscrowConfig {
+            deposit_forfeiture: Some(ForfeitureSchedule { treasury_bps: 1_000, ..schedule }),
+            ..EscrowConfig::default()
+        };
+        assert!(matches!(config.validate(), Err(EscrowError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_deposit_counterparty_is_the_other_party() {
+        let escrow = escrow_with_funding(None);
+        assert_eq!(escrow.deposit_counterparty(&EscrowType::Source), escrow.immutables.taker);
+        assert_eq!(escrow.deposit_counterparty(&EscrowType::Destination), escrow.immutables.maker);
+    }
+
+    #[test]
+    fn test_direction_specific_validation() {
+        let config = EscrowConfig::default();
+        let mut immutables = escrow_with_funding(None).immutables;
+        immutables.maker = Principal::from_slice(&[1]).to_text();
+        immutables.taker = Principal::from_slice(&[2]).to_text();
+        assert!(immutables.validate_src(&config).is_ok());
+        assert!(immutables.validate_dst(&config, None).is_ok());
+
+        let evm_taker = EscrowImmutables {
+            taker: "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed".to_string(),
+            ..immutables.clone()
+        };
+        assert!(evm_taker.validate(&config).is_ok());
+        assert!(matches!(evm_taker.validate_dst(&config, None), Err(EscrowError::InvalidAddress)));
+
+        let taker_funded = EscrowImmutables {
+            safety_deposit_funding: Some(SafetyDepositFunding::Taker),
+            ..immutables.clone()
+        };
+        assert!(taker_funded.validate_src(&config).is_ok());
+        assert!(matches!(taker_funded.validate_dst(&config, None), Err(EscrowError::InvalidDepositMode)));
+
+        // Destination cancellation (30s) must precede the source leg's
+        let mut src = immutables.clone();
+        src.timelocks.cancellation = 60;
+        assert!(immutables.validate_dst(&config, Some(&src)).is_ok());
+        src.timelocks.cancellation = 30;
+        assert!(matches!(immutables.validate_dst(&config, Some(&src)), Err(EscrowError::InvalidTime)));
+
+        // ICRC-1 tokens must be on a listed ledger
+        let ckbtc = Principal::from_text("mxzaz-hqaaa-aaaar-qaada-cai").unwrap();
+        let icrc = EscrowImmutables { icp_token: Some(ckbtc), ..immutables.clone() };
+        assert!(matches!(icrc.validate_src(&config), Err(EscrowError::UnsupportedToken)));
+        let listed = EscrowConfig { icrc_ledgers: vec![ckbtc], ..config.clone() };
+        assert!(icrc.validate_src(&listed).is_ok());
+
+        // Partial fills need a root and part count together, and only on the source side
+        let partial = EscrowImmutables { merkle_root: Some(vec![5; 32]), parts_count: Some(4), ..immutables.clone() };
+        assert!(partial.validate_src(&config).is_ok());
+        assert!(matches!(partial.validate_dst(&config, None), Err(EscrowError::InvalidPartialFill)));
+        let rootless = EscrowImmutables { merkle_root: None, ..partial.clone() };
+        assert!(matches!(rootless.validate_src(&config), Err(EscrowError::InvalidPartialFill)));
+    }
+
+    #[test]
+    fn test_fill_secret_index_advances_through_parts() {
+        // 1_000_000 locked in 4 parts of 250_000, unlocked by secrets 0..=4
+        let mut escrow = escrow_with_funding(None);
+        escrow.immutables.merkle_root = Some(vec![5; 32]);
+        escrow.immutables.parts_count = Some(4);
+
+        assert!(matches!(escrow.fill_secret_index(250_000), Ok(0)));
+        assert!(matches!(escrow.fill_secret_index(250_001), Ok(1)));
+        assert!(matches!(escrow.fill_secret_index(1_000_000), Ok(4)));
+        assert!(matches!(escrow.fill_secret_index(0), Err(EscrowError::InvalidPartialFill)));
+        assert!(matches!(escrow.fill_secret_index(1_000_001), Err(EscrowError::InvalidPartialFill)));
+
+        escrow.record_partial_fill(300_000, 1);
+        assert_eq!(escrow.remaining_amount(), 700_000);
+        // Staying within part 1 would reuse its secret
+        assert!(matches!(escrow.fill_secret_index(100_000), Err(EscrowError::InvalidPartialFill)));
+        assert!(matches!(escrow.fill_secret_index(300_000), Ok(2)));
+
+        escrow.record_final_fill();
+        assert_eq!(escrow.remaining_amount(), 0);
+        assert_eq!(escrow.partial_fill, Some(PartialFill { filled_amount: 1_000_000, last_secret_index: 4, fills: 2 }));
+    }
+
+    #[test]
+    fn test_amounts_beyond_u64_keep_precision() {
+        // 100 ETH in wei overflows u64 and its pro-rata products overflow u128
+        let eth = 10u128.pow(18);
+        let mut escrow = escrow_with_funding(None);
+        escrow.immutables.amount = 100 * eth;
+        escrow.immutables.min_received = Some(99 * eth);
+        escrow.immutables.merkle_root = Some(vec![5; 32]);
+        escrow.immutables.parts_count = Some(4);
+
+        assert!(matches!(escrow.fill_secret_index(25 * eth), Ok(0)));
+        assert!(matches!(escrow.fill_secret_index(25 * eth + 1), Ok(1)));
+        assert_eq!(escrow.min_received_for(50 * eth), Some(49_500_000_000_000_000_000));
+        assert_eq!(escrow.rescuable(), 100 * eth + escrow.safety_deposit_held);
+
+        escrow.immutables.maker_min_amount = Some(90 * eth);
+        escrow.immutables.auction = Some(DutchAuction {
+            start_time: 0, duration: 1, start_rate_bps: 1_000, end_rate_bps: 1_000, curve: AuctionCurve::Linear,
+        });
+        assert_eq!(escrow.immutables.required_amount_at(0), Some(99 * eth));
+    }
+
+    #[test]
+    fn test_cancellation_gap_uses_deployment_times() {
+        let at = |deployed_at, cancellation| Timelocks { withdrawal: 1, public_withdrawal: 2, cancellation, public_cancellation: None, deployed_at, dst: None };
+        let second = 1_000_000_000;
+
+        assert_eq!(SwapSession::cancellation_gap(&at(0, 60), &at(0, 30)).unwrap(), 30 * second);
+
+        // A source leg deployed 40s earlier cancels before a destination leg deployed now
+        assert!(matches!(
+            SwapSession::cancellation_gap(&at(0, 60), &at(40 * second, 30)),
+            Err(EscrowError::InvalidTime)
+        ));
+        assert!(matches!(
+            SwapSession::cancellation_gap(&at(0, 30), &at(0, 30)),
+            Err(EscrowError::InvalidTime)
+        ));
+    }
+
+    #[test]
+    fn test_chain_config_validation() {
+        let config = ChainConfig {
+            chain_id: 1,
+            confirmations: 12,
+            rpc_providers: vec!["https://eth.example.org".to_string()],
+            max_gas_price: 200_000_000_000,
+            finality_lag: 60,
+        };
+        assert!(config.validate().is_ok());
+
+        let non_evm = ChainConfig { chain_id: utils::CHAIN_ID_SOLANA, ..config.clone() };
+        assert!(non_evm.validate().is_err());
+        let plain_http = ChainConfig { rpc_providers: vec!["http://eth.example.org".to_string()], ..config.clone() };
+        assert!(plain_http.validate().is_err());
+        let unconfirmed = ChainConfig { confirmations: 0, ..config };
+        assert!(unconfirmed.validate().is_err());
+    }
+
+    #[test]
+    fn test_repeat_params_fit_current_config() {
+        let mut past = escrow_with_funding(None).immutables;
+        past.timelocks.deployed_at = 7;
+        past.min_received = Some(past.amount);
+        let config = EscrowConfig {
+            max_amount: past.amount / 2,
+            min_safety_deposit: past.safety_deposit * 2,
+            ..EscrowConfig::default()
+        };
+
+        let repeat = past.repeat_params(&config);
+        assert_eq!(repeat.hashlock, vec![0u8; 32]);
+        assert_eq!(repeat.order_hash, vec![0u8; 32]);
+        assert_eq!(repeat.timelocks.deployed_at, 0);
+        assert_eq!((repeat.maker, repeat.taker), (past.maker, past.taker));
+        assert_eq!(repeat.amount, config.max_amount);
+        assert_eq!(repeat.min_received, Some(config.max_amount));
+        assert_eq!(repeat.safety_deposit, config.min_safety_deposit);
+    }
+
+    #[test]
+    fn test_swap_trace_orders_events_and_finds_secret() {
+        let entry = |timestamp, hashlock: u8, event| TraceEntry { timestamp, hashlock: vec![hashlock; 32], event };
+        let withdrawal = |timestamp, secret: &[u8]| EscrowEvent::EscrowWithdrawal {
+            hashlock: vec![2; 32],
+            withdrawer: Principal::anonymous(),
+            secret: secret.to_vec(),
+            order_hash: None,
+            timestamp,
+        };
+        let recorded = EscrowEvent::ICPTxRecorded { hashlock: vec![1; 32], tx_hash: "0xabc".to_string(), timestamp: 5 };
+
+        let trace = SwapTrace::new(
+            vec![0; 32],
+            Vec::new(),
+            vec![entry(30, 2, withdrawal(30, b"late")), entry(5, 1, recorded), entry(20, 2, withdrawal(20, b"first"))],
+            99,
+        );
+        let timestamps: Vec<u64> = trace.timeline.iter().map(|entry| entry.timestamp).collect();
+        assert_eq!(timestamps, vec![5, 20, 30]);
+        assert_eq!(trace.secret, Some(b"first".to_vec()));
+        assert_eq!(trace.secret_revealed_at, Some(20));
+
+        let pending = SwapTrace::new(vec![0; 32], Vec::new(), Vec::new(), 99);
+        assert_eq!(pending.secret, None);
+    }
+
+    #[test]
+    fn test_redact_secret_only_touches_withdrawals() {
+        let mut withdrawal = EscrowEvent::EscrowWithdrawal {
+            hashlock: vec![1; 32],
+            withdrawer: Principal::anonymous(),
+            secret: b"secret".to_vec(),
+            order_hash: None,
+            timestamp: 7,
+        };
+        withdrawal.redact_secret();
+        assert!(matches!(&withdrawal, EscrowEvent::EscrowWithdrawal { secret, .. } if secret.is_empty()));
+        assert_eq!(withdrawal.hashlock(), Some(&[1u8; 32][..]));
+
+        let mut fill = EscrowEvent::EscrowPartiallyFilled {
+            hashlock: vec![1; 32],
+            withdrawer: Principal::anonymous(),
+            secret: b"secret".to_vec(),
+            secret_index: 0,
+            fill_amount: 10,
+            remaining: 90,
+            timestamp: 7,
+        };
+        fill.redact_secret();
+        assert!(matches!(&fill, EscrowEvent::EscrowPartiallyFilled { secret, .. } if secret.is_empty()));
+
+        let mut recorded = EscrowEvent::ICPTxRecorded { hashlock: vec![1; 32], tx_hash: "0xabc".to_string(), timestamp: 7 };
+        recorded.redact_secret();
+        assert!(matches!(recorded, EscrowEvent::ICPTxRecorded { tx_hash, .. } if tx_hash == "0xabc"));
+    }
+
+    #[test]
+    fn test_accounting_row_csv() {
+        let row = AccountingRow {
+            block_index: 42,
+            timestamp: 1_000_000_000,
+            kind: "surplus".to_string(),
+            account: "abcd".to_string(),
+            amount: 5_000,
+            fee: 10_000,
+            memo: 7,
+            hashlock: Some(vec![0xab, 0x01]),
+        };
+        assert_eq!(row.to_csv(), "42,1970-01-01T00:00:01Z,surplus,abcd,5000,10000,7,ab01");
+        assert_eq!(AccountingRow::CSV_HEADER.split(',').count(), row.to_csv().split(',').count());
+    }
+
+    #[test]
+    fn test_escrow_filter_matches() {
+        let maker = Principal::from_slice(&[1]);
+        let mut escrow = escrow_with_funding(None);
+        escrow.immutables.maker = maker.to_text();
+        escrow.created_at = 100;
+
+        assert!(EscrowFilter::default().matches(&escrow));
+        assert!(EscrowFilter { state: Some(EscrowState::Active), party: Some(maker), ..Default::default() }.matches(&escrow));
+        assert!(!EscrowFilter { state: Some(EscrowState::Completed), ..Default::default() }.matches(&escrow));
+        assert!(!EscrowFilter { party: Some(Principal::from_slice(&[2])), ..Default::default() }.matches(&escrow));
+        assert!(!EscrowFilter { icp_token: Some(Principal::from_slice(&[3])), ..Default::default() }.matches(&escrow));
+        assert!(EscrowFilter { created_after: Some(100), created_before: Some(101), ..Default::default() }.matches(&escrow));
+        assert!(!EscrowFilter { created_before: Some(100), ..Default::default() }.matches(&escrow));
+    }
+
+    #[test]
+    fn test_dutch_auction_decays_along_curve() {
+        const SEC: u64 = 1_000_000_000;
+        let linear = DutchAuction {
+            start_time: 100 * SEC,
+            duration: 100,
+            start_rate_bps: 1_000,
+            end_rate_bps: 0,
+            curve: AuctionCurve::Linear,
+        };
+        assert!(linear.validate().is_ok());
+        assert_eq!(linear.rate_bps_at(0), 1_000);
+        assert_eq!(linear.rate_bps_at(150 * SEC), 500);
+        assert_eq!(linear.rate_bps_at(200 * SEC), 0);
+
+        // Steep drop over the first 10 seconds, then a slow tail
+        let points = DutchAuction {
+            curve: AuctionCurve::Points(vec![AuctionPoint { delay: 10, rate_bps: 200 }]),
+            ..linear.clone()
+        };
+        assert!(points.validate().is_ok());
+        assert_eq!(points.rate_bps_at(105 * SEC), 600);
+        assert_eq!(points.rate_bps_at(155 * SEC), 100);
+
+        let rising = AuctionCurve::Points(vec![AuctionPoint { delay: 10, rate_bps: 2_000 }]);
+        assert!(DutchAuction { curve: rising, ..linear.clone() }.validate().is_err());
+        assert!(DutchAuction { duration: 0, ..linear.clone() }.validate().is_err());
+        assert!(DutchAuction { end_rate_bps: 2_000, ..linear.clone() }.validate().is_err());
+
+        let mut immutables = escrow_with_funding(None).immutables;
+        immutables.maker_min_amount = Some(900_000);
+        immutables.auction = Some(linear);
+        assert!(immutables.validate(&EscrowConfig::default()).is_ok());
+        assert_eq!(immutables.required_amount_at(0), Some(990_000));
+        assert!(immutables.check_auction_price(0).is_ok());
+        immutables.amount = 950_000;
+        assert!(matches!(immutables.check_auction_price(0), Err(EscrowError::BelowAuctionPrice)));
+        assert!(immutables.check_auction_price(160 * SEC).is_ok());
+
+        immutables.maker_min_amount = None;
+        assert!(matches!(immutables.validate(&EscrowConfig::default()), Err(EscrowError::InvalidAuction)));
     }
 }
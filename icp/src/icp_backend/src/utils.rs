@@ -1,8 +1,12 @@
-use candid::Principal;
+use std::cell::Cell;
+
+use candid::{Nat, Principal};
 use ic_cdk::api::time;
 use sha2::{Digest, Sha256};
+use num_traits::ToPrimitive;
+use sha3::Keccak256;
 
-use crate::types::{EscrowError, Result};
+use crate::types::{EscrowError, EscrowImmutables, HashAlgorithm, Result};
 
 /// Compute SHA256 hash of input data
 pub fn sha256(data: &[u8]) -> Vec<u8> {
@@ -11,19 +15,80 @@ pub fn sha256(data: &[u8]) -> Vec<u8> {
     hasher.finalize().to_vec()
 }
 
-/// Validate that the secret matches the hashlock
-pub fn validate_secret(secret: &[u8], hashlock: &[u8]) -> bool {
+/// Compute Keccak-256 hash of input data, the hash EVM contracts use
+pub fn keccak256(data: &[u8]) -> Vec<u8> {
+    Keccak256::digest(data).to_vec()
+}
+
+/// Hash a secret with the algorithm its hashlock was computed with
+pub fn hash_secret(secret: &[u8], algorithm: HashAlgorithm) -> Vec<u8> {
+    match algorithm {
+        HashAlgorithm::Sha256 => sha256(secret),
+        HashAlgorithm::Keccak256 => keccak256(secret),
+    }
+}
+
+/// Validate that the secret matches the hashlock under the given hash algorithm
+pub fn validate_secret(secret: &[u8], hashlock: &[u8], algorithm: HashAlgorithm) -> bool {
     if secret.is_empty() || hashlock.len() != 32 {
         return false;
     }
     
-    let computed_hash = sha256(secret);
-    computed_hash == hashlock
+    hash_secret(secret, algorithm) == hashlock
+}
+
+/// Leaf of a partial-fill secret tree, built like the 1inch SDK's:
+/// keccak256 of the secret index as a big-endian uint64 followed by the secret's hash
+pub fn merkle_leaf(index: u32, secret_hash: &[u8]) -> Vec<u8> {
+    keccak256(&[&u64::from(index).to_be_bytes()[..], secret_hash].concat())
+}
+
+/// Whether `proof` links `leaf` to `root`, hashing each pair of siblings in sorted order
+/// as OpenZeppelin's `MerkleProof` does
+pub fn verify_merkle_proof(leaf: &[u8], proof: &[Vec<u8>], root: &[u8]) -> bool {
+    let computed = proof.iter().fold(leaf.to_vec(), |node, sibling| {
+        let (left, right) = if node <= *sibling { (&node, sibling) } else { (sibling, &node) };
+        keccak256(&[left.as_slice(), right.as_slice()].concat())
+    });
+    computed == root
+}
+
+/// Validate the secret unlocking fill part `index` of a partially fillable escrow against its Merkle root
+pub fn validate_fill_secret(secret: &[u8], index: u32, proof: &[Vec<u8>], immutables: &EscrowImmutables) -> bool {
+    let Some(root) = &immutables.merkle_root else { return false };
+    if secret.is_empty() {
+        return false;
+    }
+    let leaf = merkle_leaf(index, &hash_secret(secret, immutables.hashlock_algorithm()));
+    verify_merkle_proof(&leaf, proof, root)
+}
+
+/// Validate a secret for the order `(order_hash, hashlock)` of an escrow; `claimed_order_hash`,
+/// when given, must name the escrow's own order
+pub fn validate_order_secret(secret: &[u8], immutables: &EscrowImmutables, claimed_order_hash: Option<&[u8]>) -> bool {
+    validate_secret(secret, &immutables.hashlock, immutables.hashlock_algorithm())
+        && claimed_order_hash.is_none_or(|order_hash| order_hash == immutables.order_hash.as_slice())
+}
+
+thread_local! {
+    /// Simulated time added by `advance_time`; only dev-mode builds can make it non-zero
+    static TIME_OFFSET: Cell<u64> = const { Cell::new(0) };
 }
 
 /// Get current time in nanoseconds
 pub fn current_time() -> u64 {
-    time()
+    simulated_time(time())
+}
+
+/// `now` shifted by the simulated time `advance_time` has added
+pub fn simulated_time(now: u64) -> u64 {
+    now.saturating_add(TIME_OFFSET.get())
+}
+
+/// Move the simulated clock forward by `delta_ns`
+#[cfg(feature = "dev-mode")]
+pub fn advance_time(delta_ns: u64) {
+    TIME_OFFSET.set(TIME_OFFSET.get().saturating_add(delta_ns));
 }
 
 /// Convert nanoseconds to seconds
@@ -42,6 +107,217 @@ pub fn validate_evm_address(address: &str) -> bool {
     address.len() == 42 && address.starts_with("0x") && address[2..].chars().all(|c| c.is_ascii_hexdigit())
 }
 
+/// Longest maker, taker, token or counterpart-chain address accepted (bech32 allows up to 90)
+pub const MAX_ADDRESS_LEN: usize = 128;
+
+/// Longest transaction hash accepted by `record_icp_tx_hash`
+pub const MAX_TX_HASH_LEN: usize = 128;
+
+/// Longest secret accepted on withdrawal (1inch secrets are 32 bytes)
+pub const MAX_SECRET_LEN: usize = 64;
+
+/// Largest integrator metadata blob accepted on an escrow
+pub const MAX_METADATA_LEN: usize = 256;
+
+/// Reject a string or blob longer than `max`, naming the offending field
+pub fn check_length(field: &str, len: usize, max: usize) -> Result<()> {
+    if len > max {
+        return Err(EscrowError::FieldTooLong(format!("{} exceeds {} bytes", field, max)));
+    }
+    Ok(())
+}
+
+/// Chain id of the Internet Computer ("ICP" in ASCII; outside the EVM chain-id range in use)
+pub const CHAIN_ID_ICP: u64 = 0x49_43_50;
+
+/// Chain id of Bitcoin ("BTC" in ASCII)
+pub const CHAIN_ID_BITCOIN: u64 = 0x42_54_43;
+
+/// Chain id of Solana, as used by 1inch Fusion+
+pub const CHAIN_ID_SOLANA: u64 = 501;
+
+/// Address validator for one chain
+pub type AddressValidator = fn(&str) -> bool;
+
+/// Validators of the non-EVM chains, with their names and address formats; any other chain id is treated as EVM
+const CHAIN_VALIDATORS: &[(u64, &str, &str, AddressValidator)] = &[
+    (CHAIN_ID_ICP, "icp", "principal text", validate_icp_address),
+    (CHAIN_ID_BITCOIN, "bitcoin", "bech32/bech32m segwit (bc1, tb1)", validate_bitcoin_address),
+    (CHAIN_ID_SOLANA, "solana", "base58 32-byte public key", validate_solana_address),
+];
+
+/// Every supported chain as (chain id, name, address format); EVM comes first with no chain id
+pub fn supported_chains() -> Vec<(Option<u64>, &'static str, &'static str)> {
+    std::iter::once((None, "evm", "0x-prefixed hex, EIP-55 checksum when mixed-case"))
+        .chain(CHAIN_VALIDATORS.iter().map(|(id, name, format, _)| (Some(*id), *name, *format)))
+        .collect()
+}
+
+/// Whether `chain_id` is an EVM chain, i.e. not one of the chains with their own validator
+pub fn is_evm_chain(chain_id: u64) -> bool {
+    !CHAIN_VALIDATORS.iter().any(|(chain, ..)| *chain == chain_id)
+}
+
+/// Address validator for `chain_id`; escrows without a chain id are EVM
+pub fn address_validator(chain_id: Option<u64>) -> AddressValidator {
+    chain_id
+        .and_then(|id| CHAIN_VALIDATORS.iter().find(|(chain, ..)| *chain == id))
+        .map(|(.., validator)| *validator)
+        .unwrap_or(validate_evm_address_checksummed)
+}
+
+/// Validate an address on the chain identified by `chain_id`
+pub fn validate_chain_address(chain_id: Option<u64>, address: &str) -> bool {
+    address_validator(chain_id)(address)
+}
+
+/// EVM address whose mixed-case form, if any, matches its EIP-55 checksum
+pub fn validate_evm_address_checksummed(address: &str) -> bool {
+    if !validate_evm_address(address) {
+        return false;
+    }
+    let body = &address[2..];
+    let has_lower = body.chars().any(|c| c.is_ascii_lowercase());
+    let has_upper = body.chars().any(|c| c.is_ascii_uppercase());
+    if !(has_lower && has_upper) {
+        return true; // Single-case addresses carry no checksum
+    }
+
+    let hash = Keccak256::digest(body.to_ascii_lowercase().as_bytes());
+    body.chars().enumerate().all(|(i, c)| {
+        let nibble = (hash[i / 2] >> if i % 2 == 0 { 4 } else { 0 }) & 0x0f;
+        !c.is_ascii_alphabetic() || c.is_ascii_uppercase() == (nibble >= 8)
+    })
+}
+
+/// Message an EVM account signs (EIP-191 personal_sign) to link itself to `principal`
+pub fn evm_link_message(principal: &Principal) -> String {
+    format!("Link ICP principal {} to this address on avginch", principal.to_text())
+}
+
+/// Recover the EVM address that produced a 65-byte `r || s || v` personal_sign signature over `message`
+pub fn recover_evm_address(message: &str, signature: &[u8]) -> Option<String> {
+    if signature.len() != 65 {
+        return None;
+    }
+    let mut prefixed = format!("\x19Ethereum Signed Message:\n{}", message.len()).into_bytes();
+    prefixed.extend_from_slice(message.as_bytes());
+    let digest = Keccak256::digest(&prefixed);
+
+    let recovery_byte = match signature[64] {
+        v @ 27..=28 => v - 27,
+        v @ 0..=1 => v,
+        _ => return None,
+    };
+    let recovery_id = k256::ecdsa::RecoveryId::from_byte(recovery_byte)?;
+    let signature = k256::ecdsa::Signature::from_slice(&signature[..64]).ok()?;
+    let key = k256::ecdsa::VerifyingKey::recover_from_prehash(&digest, &signature, recovery_id).ok()?;
+
+    let public_key = key.to_encoded_point(false);
+    let hash = Keccak256::digest(&public_key.as_bytes()[1..]);
+    Some(format!("0x{}", hex::encode(&hash[12..])))
+}
+
+/// ICP principal in textual form
+pub fn validate_icp_address(address: &str) -> bool {
+    Principal::from_text(address).is_ok()
+}
+
+/// Solana address: base58 encoding of a 32-byte public key
+pub fn validate_solana_address(address: &str) -> bool {
+    (32..=44).contains(&address.len()) && base58_decode(address).is_some_and(|bytes| bytes.len() == 32)
+}
+
+/// Bitcoin segwit address (bech32 for witness v0, bech32m for v1+) on mainnet or testnet
+pub fn validate_bitcoin_address(address: &str) -> bool {
+    if address.len() < 14 || address.len() > 90 {
+        return false;
+    }
+    if address.chars().any(|c| c.is_ascii_lowercase()) && address.chars().any(|c| c.is_ascii_uppercase()) {
+        return false;
+    }
+    let address = address.to_ascii_lowercase();
+    let Some((hrp, data)) = address.rsplit_once('1') else {
+        return false;
+    };
+    if !matches!(hrp, "bc" | "tb") || data.len() < 7 {
+        return false;
+    }
+
+    const CHARSET: &str = "qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+    let Some(values) = data.chars().map(|c| CHARSET.find(c).map(|v| v as u8)).collect::<Option<Vec<u8>>>() else {
+        return false;
+    };
+    let witness_version = values[0];
+    if witness_version > 16 {
+        return false;
+    }
+    let expected = if witness_version == 0 { 1 } else { 0x2bc8_30a3 };
+    bech32_polymod(hrp, &values) == expected
+}
+
+/// BIP-173 checksum over the expanded human-readable part and data values
+fn bech32_polymod(hrp: &str, values: &[u8]) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a_57b2, 0x2650_8e6d, 0x1ea1_19fa, 0x3d42_33dd, 0x2a14_62b3];
+    let expanded = hrp.bytes().map(|b| b >> 5)
+        .chain(std::iter::once(0))
+        .chain(hrp.bytes().map(|b| b & 0x1f))
+        .chain(values.iter().copied());
+
+    expanded.fold(1u32, |chk, value| {
+        let top = chk >> 25;
+        let chk = ((chk & 0x01ff_ffff) << 5) ^ value as u32;
+        GENERATOR.iter().enumerate()
+            .filter(|(i, _)| (top >> i) & 1 == 1)
+            .fold(chk, |chk, (_, g)| chk ^ g)
+    })
+}
+
+/// Decode a base58 (Bitcoin alphabet) string; `None` on invalid characters
+fn base58_decode(input: &str) -> Option<Vec<u8>> {
+    const ALPHABET: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+    let mut bytes: Vec<u8> = Vec::new();
+    for c in input.chars() {
+        let mut carry = ALPHABET.find(c)? as u32;
+        for byte in bytes.iter_mut().rev() {
+            carry += *byte as u32 * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.insert(0, carry as u8);
+            carry >>= 8;
+        }
+    }
+    let leading_zeros = input.chars().take_while(|c| *c == '1').count();
+    let mut decoded = vec![0u8; leading_zeros];
+    decoded.extend(bytes);
+    Some(decoded)
+}
+
+/// Longest display alias accepted by the profile registry
+pub const MAX_ALIAS_LEN: usize = 32;
+
+/// Longest avatar URL accepted by the profile registry
+pub const MAX_AVATAR_URL_LEN: usize = 256;
+
+/// Validate a display alias: 1-32 characters of letters, digits, space, '-', '_' or '.'
+pub fn validate_alias(alias: &str) -> bool {
+    let trimmed = alias.trim();
+    !trimmed.is_empty()
+        && trimmed.len() == alias.len()
+        && alias.len() <= MAX_ALIAS_LEN
+        && alias.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, ' ' | '-' | '_' | '.'))
+}
+
+/// Validate an avatar URL: https only, bounded, no whitespace or control characters
+pub fn validate_avatar_url(url: &str) -> bool {
+    url.len() <= MAX_AVATAR_URL_LEN
+        && url.len() > "https://".len()
+        && url.starts_with("https://")
+        && url.chars().all(|c| c.is_ascii_graphic())
+}
+
 /// Validate ICP Principal format
 pub fn validate_principal(principal_str: &str) -> Result<Principal> {
     Principal::from_text(principal_str).map_err(|_| EscrowError::InvalidAddress)
@@ -110,9 +386,65 @@ pub fn truncate_string(s: &str, max_len: usize) -> String {
 }
 
 /// Format amount in ICP with proper decimals
-pub fn format_icp_amount(e8s: u64) -> String {
-    let icp = e8s as f64 / 100_000_000.0;
-    format!("{:.8} ICP", icp)
+pub fn format_icp_amount(e8s: u128) -> String {
+    format_token_amount(e8s, 8, "ICP")
+}
+
+/// Format a base-unit amount with `decimals` places and the token symbol, using integer
+/// math so large amounts stay exact and output never depends on locale
+pub fn format_token_amount(amount: u128, decimals: u32, symbol: &str) -> String {
+    let unit = 10u128.pow(decimals);
+    if decimals == 0 {
+        return format!("{} {}", amount, symbol);
+    }
+    format!("{}.{:0width$} {}", amount / unit, amount % unit, symbol, width = decimals as usize)
+}
+
+/// Render a nanosecond timestamp as ISO-8601 UTC with second precision, e.g. `2024-03-01T12:00:00Z`
+pub fn format_iso8601(nanoseconds: u64) -> String {
+    let seconds = nanoseconds / 1_000_000_000;
+    let (days, time_of_day) = (seconds / 86_400, seconds % 86_400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm, shifted to March-based years)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, time_of_day / 3_600, time_of_day % 3_600 / 60, time_of_day % 60
+    )
+}
+
+/// Add two token amounts, failing with `InvalidAmount` instead of wrapping
+pub fn add_amounts(a: u128, b: u128) -> Result<u128> {
+    a.checked_add(b).ok_or(EscrowError::InvalidAmount)
+}
+
+/// Sum token amounts, failing with `InvalidAmount` if the total overflows
+pub fn sum_amounts(amounts: impl IntoIterator<Item = u128>) -> Result<u128> {
+    amounts.into_iter().try_fold(0u128, add_amounts)
+}
+
+/// `value * numerator / denominator` rounded down. The product is taken on `Nat`, so
+/// pro-rata shares of 18-decimal amounts cannot overflow; None for a zero denominator or
+/// a result that does not fit a u128.
+pub fn mul_div(value: u128, numerator: u128, denominator: u128) -> Option<u128> {
+    if denominator == 0 {
+        return None;
+    }
+    (Nat::from(value).0 * Nat::from(numerator).0 / Nat::from(denominator).0).to_u128()
+}
+
+/// Narrow a token amount to the u64 the ICP ledger works in
+pub fn icp_amount(amount: u128) -> Result<u64> {
+    u64::try_from(amount).map_err(|_| EscrowError::InvalidAmount)
 }
 
 /// Parse ICP amount string to e8s
@@ -124,6 +456,83 @@ pub fn parse_icp_amount(amount_str: &str) -> Result<u64> {
     Ok((amount * 100_000_000.0) as u64)
 }
 
+/// Serde for u128 amounts in CBOR-encoded records. serde_cbor stops at u64, so amounts
+/// above it are written as 16 big-endian bytes; smaller ones stay plain integers, which is
+/// also how records from before the widening hold them. Candid encoding is unaffected.
+pub mod wide_amount {
+    use serde::de::{self, Deserializer, Visitor};
+    use serde::{Serialize, Serializer};
+    use std::fmt;
+
+    pub fn serialize<S: Serializer>(amount: &u128, serializer: S) -> Result<S::Ok, S::Error> {
+        match u64::try_from(*amount) {
+            Ok(amount) => serializer.serialize_u64(amount),
+            Err(_) if serializer.is_human_readable() => serializer.serialize_u128(*amount),
+            Err(_) => serializer.serialize_bytes(&amount.to_be_bytes()),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u128, D::Error> {
+        deserializer.deserialize_u128(AmountVisitor)
+    }
+
+    struct AmountVisitor;
+
+    impl<'de> Visitor<'de> for AmountVisitor {
+        type Value = u128;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("an amount as an integer or 16 big-endian bytes")
+        }
+
+        fn visit_u64<E: de::Error>(self, amount: u64) -> Result<u128, E> {
+            Ok(u128::from(amount))
+        }
+
+        fn visit_u128<E: de::Error>(self, amount: u128) -> Result<u128, E> {
+            Ok(amount)
+        }
+
+        fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<u128, E> {
+            <[u8; 16]>::try_from(bytes)
+                .map(u128::from_be_bytes)
+                .map_err(|_| E::invalid_length(bytes.len(), &self))
+        }
+    }
+
+    /// The same encoding for optional amounts. Decoding goes through serde's own `Option`
+    /// visitor, the only one Candid's decoder accepts for `opt` values.
+    pub mod option {
+        use super::*;
+        use serde::Deserialize;
+
+        struct Wide(u128);
+
+        impl Serialize for Wide {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                super::serialize(&self.0, serializer)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for Wide {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                super::deserialize(deserializer).map(Wide)
+            }
+        }
+
+        pub fn serialize<S: Serializer>(amount: &Option<u128>, serializer: S) -> Result<S::Ok, S::Error> {
+            match amount {
+                Some(amount) => serializer.serialize_some(&Wide(*amount)),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u128>, D::Error> {
+            Option::<Wide>::deserialize(deserializer).map(|amount| amount.map(|Wide(amount)| amount))
+        }
+    }
+}
+
 /// Constants for time periods
 pub mod time_constants {
     pub const MINUTE: u64 = 60 * 1_000_000_000;           // 1 minute in nanoseconds
@@ -154,8 +563,68 @@ mod tests {
     fn test_validate_secret() {
         let secret = b"test_secret";
         let hash = sha256(secret);
-        assert!(validate_secret(secret, &hash));
-        assert!(!validate_secret(b"wrong_secret", &hash));
+        assert!(validate_secret(secret, &hash, HashAlgorithm::Sha256));
+        assert!(!validate_secret(b"wrong_secret", &hash, HashAlgorithm::Sha256));
+        assert!(!validate_secret(secret, &hash, HashAlgorithm::Keccak256));
+    }
+
+    #[test]
+    fn test_merkle_fill_secrets() {
+        // Four secrets for three parts: leaves 0..=3, paired as (0, 1) and (2, 3)
+        let secrets: Vec<Vec<u8>> = (0..4u8).map(|i| vec![i + 1; 32]).collect();
+        let leaves: Vec<Vec<u8>> = secrets
+            .iter()
+            .enumerate()
+            .map(|(index, secret)| merkle_leaf(index as u32, &keccak256(secret)))
+            .collect();
+        let pair = |a: &[u8], b: &[u8]| if a <= b { keccak256(&[a, b].concat()) } else { keccak256(&[b, a].concat()) };
+        let (left, right) = (pair(&leaves[0], &leaves[1]), pair(&leaves[2], &leaves[3]));
+        let root = pair(&left, &right);
+
+        assert!(verify_merkle_proof(&leaves[0], &[leaves[1].clone(), right.clone()], &root));
+        assert!(verify_merkle_proof(&leaves[3], &[leaves[2].clone(), left.clone()], &root));
+        assert!(!verify_merkle_proof(&leaves[0], &[leaves[2].clone(), right.clone()], &root));
+
+        let immutables = EscrowImmutables {
+            order_hash: vec![0; 32],
+            hashlock: root.clone(),
+            maker: "maker".to_string(),
+            taker: "taker".to_string(),
+            token: "0x0".to_string(),
+            amount: 300,
+            safety_deposit: 0,
+            timelocks: crate::types::Timelocks { withdrawal: 1, public_withdrawal: 2, cancellation: 3, public_cancellation: None, deployed_at: 0, dst: None },
+            maker_min_amount: None,
+            min_received: None,
+            safety_deposit_funding: None,
+            chain_id: None,
+            icp_token: None,
+            hash_algorithm: Some(HashAlgorithm::Keccak256),
+            merkle_root: Some(root),
+            parts_count: Some(3),
+            auction: None,
+            metadata: None,
+        };
+        let proof = [leaves[3].clone(), left];
+        assert!(validate_fill_secret(&secrets[2], 2, &proof, &immutables));
+        // A secret only unlocks its own part
+        assert!(!validate_fill_secret(&secrets[2], 3, &proof, &immutables));
+        assert!(!validate_fill_secret(&secrets[3], 2, &proof, &immutables));
+    }
+
+    #[test]
+    fn test_validate_keccak_secret() {
+        // keccak256("") is the well-known empty hash EVM tooling prints
+        assert_eq!(
+            hex::encode(keccak256(b"")),
+            "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+        );
+
+        let secret = [7u8; 32];
+        let hash = keccak256(&secret);
+        assert!(validate_secret(&secret, &hash, HashAlgorithm::Keccak256));
+        assert!(!validate_secret(&secret, &hash, HashAlgorithm::Sha256));
+        assert!(!validate_secret(&[8u8; 32], &hash, HashAlgorithm::Keccak256));
     }
 
     #[test]
@@ -181,5 +650,138 @@ mod tests {
         assert_eq!(format_icp_amount(100_000_000), "1.00000000 ICP");
         assert_eq!(format_icp_amount(50_000_000), "0.50000000 ICP");
         assert_eq!(format_icp_amount(1), "0.00000001 ICP");
+        assert_eq!(format_icp_amount(u64::MAX.into()), "184467440737.09551615 ICP");
+        assert_eq!(format_token_amount(1_500_000, 6, "ckUSDC"), "1.500000 ckUSDC");
+        assert_eq!(format_token_amount(250_000_000_000_000_000_000, 18, "ckETH"), "250.000000000000000000 ckETH");
+        assert_eq!(format_token_amount(42, 0, "X"), "42 X");
+    }
+
+    #[test]
+    fn test_checked_amount_math() {
+        let eth = 10u128.pow(18);
+        assert_eq!(mul_div(100 * eth, 250, 10_000), Some(2_500_000_000_000_000_000));
+        assert_eq!(mul_div(u128::MAX, u128::MAX, u128::MAX), Some(u128::MAX));
+        assert_eq!(mul_div(u128::MAX, 2, 1), None);
+        assert_eq!(mul_div(1, 1, 0), None);
+
+        assert_eq!(sum_amounts([eth, eth]).unwrap(), 2 * eth);
+        assert!(matches!(add_amounts(u128::MAX, 1), Err(EscrowError::InvalidAmount)));
+        assert!(matches!(icp_amount(u128::from(u64::MAX) + 1), Err(EscrowError::InvalidAmount)));
+    }
+
+    #[test]
+    fn test_wide_amount_cbor_round_trip() {
+        #[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug)]
+        struct Record {
+            #[serde(with = "wide_amount")]
+            amount: u128,
+            #[serde(with = "wide_amount::option", default)]
+            cap: Option<u128>,
+        }
+
+        for record in [Record { amount: 7, cap: None }, Record { amount: u128::MAX, cap: Some(u128::from(u64::MAX) + 1) }] {
+            let bytes = serde_cbor::to_vec(&record).unwrap();
+            assert_eq!(serde_cbor::from_slice::<Record>(&bytes).unwrap(), record);
+        }
+
+        // Records written while amounts were u64 hold plain integers
+        #[derive(serde::Serialize)]
+        struct Legacy {
+            amount: u64,
+        }
+        let bytes = serde_cbor::to_vec(&Legacy { amount: 42 }).unwrap();
+        assert_eq!(serde_cbor::from_slice::<Record>(&bytes).unwrap(), Record { amount: 42, cap: None });
+    }
+
+    #[test]
+    fn test_wide_amount_candid_round_trip() {
+        #[derive(candid::CandidType, serde::Deserialize, PartialEq, Debug)]
+        struct Record {
+            #[serde(with = "wide_amount::option")]
+            cap: Option<u128>,
+        }
+
+        for record in [Record { cap: None }, Record { cap: Some(u128::from(u64::MAX) + 1) }] {
+            let bytes = candid::encode_one(&record).unwrap();
+            assert_eq!(candid::decode_one::<Record>(&bytes).unwrap(), record);
+        }
+    }
+
+    #[test]
+    fn test_format_iso8601() {
+        assert_eq!(format_iso8601(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_iso8601(951_782_400 * 1_000_000_000), "2000-02-29T00:00:00Z");
+        assert_eq!(format_iso8601(1_709_294_400 * 1_000_000_000 + 999), "2024-03-01T12:00:00Z");
+    }
+
+    #[test]
+    fn test_validate_profile_fields() {
+        assert!(validate_alias("mm-alpha"));
+        assert!(validate_alias("Resolver 7.b"));
+        assert!(!validate_alias(""));
+        assert!(!validate_alias(" padded"));
+        assert!(!validate_alias("<script>"));
+        assert!(!validate_alias(&"a".repeat(MAX_ALIAS_LEN + 1)));
+
+        assert!(validate_avatar_url("https://example.com/a.png"));
+        assert!(!validate_avatar_url("http://example.com/a.png"));
+        assert!(!validate_avatar_url("https://"));
+        assert!(!validate_avatar_url("https://example.com/a b.png"));
+        assert!(!validate_avatar_url(&format!("https://{}", "a".repeat(MAX_AVATAR_URL_LEN))));
+    }
+
+    #[test]
+    fn test_evm_checksum() {
+        assert!(validate_evm_address_checksummed("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"));
+        assert!(validate_evm_address_checksummed("0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed"));
+        assert!(!validate_evm_address_checksummed("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD"));
+    }
+
+    #[test]
+    fn test_chain_address_validators() {
+        assert!(validate_chain_address(Some(1), "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"));
+        assert!(validate_chain_address(None, "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed"));
+        assert!(!validate_chain_address(Some(CHAIN_ID_SOLANA), "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed"));
+
+        assert!(validate_chain_address(Some(CHAIN_ID_ICP), "rrkah-fqaaa-aaaaa-aaaaq-cai"));
+        assert!(!validate_chain_address(Some(CHAIN_ID_ICP), "not-a-principal"));
+
+        assert!(validate_chain_address(Some(CHAIN_ID_SOLANA), "11111111111111111111111111111111"));
+        assert!(validate_chain_address(Some(CHAIN_ID_SOLANA), "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA"));
+        assert!(!validate_chain_address(Some(CHAIN_ID_SOLANA), "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ50A"));
+
+        assert!(validate_chain_address(Some(CHAIN_ID_BITCOIN), "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"));
+        assert!(validate_chain_address(Some(CHAIN_ID_BITCOIN), "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4"));
+        assert!(validate_chain_address(Some(CHAIN_ID_BITCOIN), "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0"));
+        assert!(!validate_chain_address(Some(CHAIN_ID_BITCOIN), "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t5"));
+    }
+
+    #[test]
+    fn test_check_length() {
+        assert!(check_length("tx_hash", MAX_TX_HASH_LEN, MAX_TX_HASH_LEN).is_ok());
+        assert!(matches!(
+            check_length("tx_hash", MAX_TX_HASH_LEN + 1, MAX_TX_HASH_LEN),
+            Err(EscrowError::FieldTooLong(_))
+        ));
+    }
+
+    #[test]
+    fn test_recover_evm_address_from_personal_sign() {
+        let signing_key = k256::ecdsa::SigningKey::from_slice(&[7u8; 32]).unwrap();
+        let public_key = signing_key.verifying_key().to_encoded_point(false);
+        let expected = format!("0x{}", hex::encode(&Keccak256::digest(&public_key.as_bytes()[1..])[12..]));
+
+        let message = evm_link_message(&Principal::anonymous());
+        let mut prefixed = format!("\x19Ethereum Signed Message:\n{}", message.len()).into_bytes();
+        prefixed.extend_from_slice(message.as_bytes());
+        let (signature, recovery_id) = signing_key
+            .sign_prehash_recoverable(&Keccak256::digest(&prefixed))
+            .unwrap();
+        let mut bytes = signature.to_bytes().to_vec();
+        bytes.push(recovery_id.to_byte() + 27);
+
+        assert_eq!(recover_evm_address(&message, &bytes), Some(expected.clone()));
+        assert_ne!(recover_evm_address("another message", &bytes), Some(expected));
+        assert_eq!(recover_evm_address(&message, &bytes[..64]), None);
     }
 }
@@ -0,0 +1,76 @@
+use ic_cdk::management_canister::{
+    vetkd_derive_key, vetkd_public_key, VetKDCurve, VetKDDeriveKeyArgs, VetKDKeyId, VetKDPublicKeyArgs,
+};
+
+use crate::types::{EscrowError, Result};
+
+/// Derivation context separating escrow note keys from any other vetKD use
+const NOTES_CONTEXT: &[u8] = b"avginch/escrow-notes";
+
+/// Largest ciphertext accepted for a single note (bytes)
+pub const MAX_NOTE_SIZE: usize = 1024;
+
+/// Most notes an escrow can carry
+pub const MAX_NOTES_PER_ESCROW: usize = 8;
+
+fn key_id(key_name: &str) -> VetKDKeyId {
+    VetKDKeyId {
+        curve: VetKDCurve::Bls12_381_G2,
+        name: key_name.to_string(),
+    }
+}
+
+/// Derived public key under which escrow notes are encrypted (IBE master key for the notes context)
+pub async fn notes_public_key(key_name: &str) -> Result<Vec<u8>> {
+    let args = VetKDPublicKeyArgs {
+        canister_id: None,
+        context: NOTES_CONTEXT.to_vec(),
+        key_id: key_id(key_name),
+    };
+
+    vetkd_public_key(&args)
+        .await
+        .map(|result| result.public_key)
+        .map_err(|_| EscrowError::CanisterCallError)
+}
+
+/// Notes key of one escrow, encrypted to the caller's transport key.
+/// The input is the hashlock, so maker and taker derive the same key.
+pub async fn encrypted_notes_key(hashlock: &[u8], transport_public_key: Vec<u8>, key_name: &str) -> Result<Vec<u8>> {
+    let args = VetKDDeriveKeyArgs {
+        input: hashlock.to_vec(),
+        context: NOTES_CONTEXT.to_vec(),
+        transport_public_key,
+        key_id: key_id(key_name),
+    };
+
+    vetkd_derive_key(&args)
+        .await
+        .map(|result| result.encrypted_key)
+        .map_err(|_| EscrowError::CanisterCallError)
+}
+
+/// Check a note ciphertext against the size limits before it is stored
+pub fn validate_note(ciphertext: &[u8], existing_notes: usize) -> Result<()> {
+    if ciphertext.is_empty() || ciphertext.len() > MAX_NOTE_SIZE {
+        return Err(EscrowError::InvalidNote);
+    }
+    if existing_notes >= MAX_NOTES_PER_ESCROW {
+        return Err(EscrowError::InvalidNote);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_note_limits() {
+        assert!(validate_note(&[1u8; 64], 0).is_ok());
+        assert!(validate_note(&[1u8; MAX_NOTE_SIZE], MAX_NOTES_PER_ESCROW - 1).is_ok());
+        assert!(matches!(validate_note(&[], 0), Err(EscrowError::InvalidNote)));
+        assert!(matches!(validate_note(&[1u8; MAX_NOTE_SIZE + 1], 0), Err(EscrowError::InvalidNote)));
+        assert!(matches!(validate_note(&[1u8; 64], MAX_NOTES_PER_ESCROW), Err(EscrowError::InvalidNote)));
+    }
+}
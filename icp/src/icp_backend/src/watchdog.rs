@@ -0,0 +1,231 @@
+use crate::cycles;
+use crate::guards::EscrowLock;
+use crate::ledger::{self, TransferOperation};
+use crate::storage;
+use crate::pay_out;
+use crate::types::{EscrowError, EscrowEvent, EscrowState, ICPEscrow, ProcessingOperation, Result};
+use crate::utils::current_time;
+
+/// Resolve every escrow that has been Processing for longer than the configured timeout
+pub async fn run() {
+    let config = storage::get_config();
+    for (hashlock, escrow) in storage::get_stuck_escrows(current_time(), config.processing_timeout) {
+        if let Err(err) = check(&hashlock, &escrow).await {
+            ic_cdk::api::debug_print(format!("Watchdog could not resolve escrow {}: {:?}", hex::encode(&hashlock), err));
+        }
+    }
+}
+
+/// Look for the settlement's first payout on the ledger: finalize the escrow if it went out,
+/// otherwise return it to Active so the operation can be retried
+pub async fn check(hashlock: &[u8], escrow: &ICPEscrow) -> Result<()> {
+    let _lock = EscrowLock::acquire(hashlock)?;
+    let lock = escrow.processing.as_ref().ok_or(EscrowError::InvalidState)?;
+    let memo = ledger::TransferMemo::new(first_transfer(&lock.operation), hashlock);
+
+    let resolution = match ledger::find_recent_token_transfer(escrow.immutables.icp_token, escrow.funds_subaccount(), &memo, lock.started_at).await? {
+        Some(block) => finalize(hashlock, Some(block), false),
+        None => revert(hashlock, false),
+    };
+    storage::update_escrow(hashlock, |escrow| escrow.cycles_mut().timers += cycles::message_cost(1))?;
+    resolution
+}
+
+/// Operation of the transfer a settlement sends first
+fn first_transfer(operation: &ProcessingOperation) -> TransferOperation {
+    match operation {
+        ProcessingOperation::Withdrawal { .. } | ProcessingOperation::PartialFill { .. } => TransferOperation::Withdrawal,
+        ProcessingOperation::Cancellation { .. } => TransferOperation::Cancellation,
+        ProcessingOperation::Migration { .. } => TransferOperation::Migration,
+    }
+}
+
+/// Settle a Processing escrow according to its in-flight operation
+pub fn finalize(hashlock: &[u8], block: Option<u64>, forced: bool) -> Result<()> {
+    let escrow = storage::get_escrow(hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    let lock = escrow.processing.ok_or(EscrowError::InvalidState)?;
+    let now = current_time();
+    let memo = ledger::TransferMemo::new(first_transfer(&lock.operation), hashlock);
+
+    let settled_state = match &lock.operation {
+        ProcessingOperation::Withdrawal { .. } => EscrowState::Completed,
+        ProcessingOperation::Cancellation { .. } => EscrowState::Cancelled,
+        ProcessingOperation::Migration { .. } => EscrowState::Migrated,
+        ProcessingOperation::PartialFill { .. } => return finalize_partial_fill(hashlock, lock.operation, block, forced),
+    };
+    storage::update_escrow(hashlock, |escrow| {
+        escrow.state = settled_state;
+        escrow.completed_at = Some(now);
+        escrow.safety_deposit_held = 0;
+        escrow.processing = None;
+        match &lock.operation {
+            ProcessingOperation::Withdrawal { secret, .. } => {
+                escrow.revealed_secret = Some(secret.clone());
+                escrow.record_final_fill();
+            }
+            ProcessingOperation::Migration { receipt } => escrow.migration = Some((**receipt).clone()),
+            ProcessingOperation::Cancellation { .. } | ProcessingOperation::PartialFill { .. } => {}
+        }
+        escrow.close_transfer_legs(memo.value, block);
+        escrow.settlement_blocks.extend(block);
+    })?;
+
+    let revealed = match &lock.operation {
+        ProcessingOperation::Withdrawal { secret, .. } => Some(secret.clone()),
+        _ => None,
+    };
+    let event = match lock.operation {
+        ProcessingOperation::Withdrawal { withdrawer, secret, .. } => {
+            storage::update_metrics(|metrics| metrics.total_escrows_completed += 1);
+            storage::update_daily_activity(now, |day| day.completed += 1);
+            EscrowEvent::EscrowWithdrawal {
+                hashlock: hashlock.to_vec(),
+                withdrawer,
+                secret,
+                order_hash: Some(escrow.immutables.order_hash.clone()),
+                timestamp: now,
+            }
+        }
+        ProcessingOperation::Cancellation { canceller, .. } => {
+            storage::update_metrics(|metrics| metrics.total_escrows_cancelled += 1);
+            storage::update_daily_activity(now, |day| day.cancelled += 1);
+            EscrowEvent::EscrowCancelled {
+                hashlock: hashlock.to_vec(),
+                canceller,
+                timestamp: now,
+            }
+        }
+        ProcessingOperation::Migration { receipt } => EscrowEvent::EscrowMigrated {
+            hashlock: hashlock.to_vec(),
+            successor: receipt.snapshot.successor,
+            amount: receipt.snapshot.funds_transferred,
+            timestamp: now,
+        },
+        ProcessingOperation::PartialFill { .. } => unreachable!("partial fills are finalized above"),
+    };
+    storage::update_metrics(|metrics| {
+        metrics.active_escrows_count = metrics.active_escrows_count.saturating_sub(1);
+    });
+    storage::add_event(event);
+    if let Some(secret) = revealed {
+        storage::add_event(EscrowEvent::SecretRevealed {
+            hashlock: hashlock.to_vec(),
+            order_hash: escrow.immutables.order_hash.clone(),
+            secret,
+            timestamp: now,
+        });
+    }
+    record_resolution(hashlock, true, block, forced, now);
+    Ok(())
+}
+
+/// Record a partial fill whose payout went out and return the escrow to Active for the rest
+fn finalize_partial_fill(hashlock: &[u8], operation: ProcessingOperation, block: Option<u64>, forced: bool) -> Result<()> {
+    let ProcessingOperation::PartialFill { withdrawer, secret, secret_index, fill_amount } = operation else {
+        return Err(EscrowError::InvalidState);
+    };
+    let now = current_time();
+    let memo = ledger::TransferMemo::new(TransferOperation::Withdrawal, hashlock);
+    let mut remaining = 0;
+    storage::update_escrow(hashlock, |escrow| {
+        escrow.state = EscrowState::Active;
+        escrow.processing = None;
+        escrow.record_partial_fill(fill_amount, secret_index);
+        escrow.close_transfer_legs(memo.value, block);
+        escrow.settlement_blocks.extend(block);
+        remaining = escrow.remaining_amount();
+    })?;
+    storage::add_event(EscrowEvent::EscrowPartiallyFilled {
+        hashlock: hashlock.to_vec(),
+        withdrawer,
+        secret,
+        secret_index,
+        fill_amount,
+        remaining,
+        timestamp: now,
+    });
+    record_resolution(hashlock, true, block, forced, now);
+    Ok(())
+}
+
+/// Expire up to `limit` announced escrows whose funding deadline passed without a deposit
+pub fn expire_unfunded(now: u64, limit: usize) {
+    for (hashlock, _) in storage::get_unfunded_escrows(now).into_iter().take(limit) {
+        let expired = storage::update_escrow(&hashlock, |escrow| {
+            escrow.state = EscrowState::Expired;
+            escrow.completed_at = Some(now);
+        });
+        if expired.is_ok() {
+            storage::update_metrics(|metrics| {
+                metrics.active_escrows_count = metrics.active_escrows_count.saturating_sub(1);
+            });
+            storage::add_event(EscrowEvent::EscrowExpired { hashlock, timestamp: now });
+        }
+    }
+}
+
+/// Retry up to `limit` queued settlement transfers, oldest first. Paid transfers leave the queue
+/// and join their escrow's settlement blocks; failed ones stay queued. Returns how many were paid.
+pub async fn retry_pending_transfers(limit: usize) -> u32 {
+    let mut sent = 0;
+    for transfer in storage::get_pending_transfers(limit) {
+        // Skip escrows busy with another operation; the transfer is retried next time
+        let Ok(_lock) = EscrowLock::acquire(&transfer.hashlock) else { continue };
+        let Some(escrow) = storage::get_escrow(&transfer.hashlock) else { continue };
+        // A frozen escrow's queued transfers wait until the freeze is lifted
+        if escrow.freeze.is_some() {
+            continue;
+        }
+        let now = current_time();
+        let memo = ledger::TransferMemo { value: transfer.memo, hashlock: transfer.hashlock.clone(), created_at_time: None };
+        let block = match pay_out(&escrow, transfer.recipient, transfer.amount, &memo).await {
+            Ok(block) => block,
+            Err(err) => {
+                ic_cdk::api::debug_print(format!("Retry of queued transfer {} failed: {:?}", transfer.id, err));
+                storage::record_transfer_attempt(transfer.id, now);
+                continue;
+            }
+        };
+        storage::remove_pending_transfer(transfer.id);
+        // The escrow was read above and escrows are never removed, so this cannot miss
+        let _ = storage::update_escrow(&transfer.hashlock, |escrow| {
+            escrow.settlement_blocks.push(block);
+            escrow.cycles_mut().timers += cycles::message_cost(1);
+        });
+        storage::add_event(EscrowEvent::QueuedTransferSent {
+            hashlock: transfer.hashlock,
+            transfer_id: transfer.id,
+            recipient: transfer.recipient,
+            amount: transfer.amount,
+            block,
+            attempts: transfer.attempts,
+            timestamp: now,
+        });
+        sent += 1;
+    }
+    sent
+}
+
+/// Return a Processing escrow to Active without settling it
+pub fn revert(hashlock: &[u8], forced: bool) -> Result<()> {
+    let escrow = storage::get_escrow(hashlock).ok_or(EscrowError::EscrowNotFound)?;
+    if escrow.processing.is_none() {
+        return Err(EscrowError::InvalidState);
+    }
+    storage::update_escrow(hashlock, |escrow| {
+        escrow.state = EscrowState::Active;
+        escrow.processing = None;
+    })?;
+    record_resolution(hashlock, false, None, forced, current_time());
+    Ok(())
+}
+
+fn record_resolution(hashlock: &[u8], finalized: bool, block: Option<u64>, forced: bool, timestamp: u64) {
+    storage::add_event(EscrowEvent::ProcessingResolved {
+        hashlock: hashlock.to_vec(),
+        finalized,
+        block,
+        forced,
+        timestamp,
+    });
+}
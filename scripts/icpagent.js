@@ -42,8 +42,20 @@ async function createSourceEscrow(params, agent, actor) {
                 withdrawal: params.timelocks.withdrawal,
                 public_withdrawal: params.timelocks.publicWithdrawal,
                 cancellation: params.timelocks.cancellation,
-                deployed_at: 0n // Will be set by the canister
-            }
+                deployed_at: 0n, // Will be set by the canister
+                public_cancellation: [],
+                dst: []
+            },
+            maker_min_amount: params.makerMinAmount !== undefined ? [params.makerMinAmount] : [],
+            min_received: params.minReceived !== undefined ? [params.minReceived] : [],
+            safety_deposit_funding: [],
+            chain_id: [],
+            icp_token: [],
+            hash_algorithm: [],
+            merkle_root: [],
+            parts_count: [],
+            auction: [],
+            metadata: []
         };
         
         console.log('Creating source escrow with params:', {
@@ -53,7 +65,7 @@ async function createSourceEscrow(params, agent, actor) {
         });
         
         // Call the create_src_escrow function
-        const result = await actor.create_src_escrow(escrowImmutables);
+        const result = await actor.create_src_escrow(escrowImmutables, [], []);
         
         // Handle the Result type response
         if ('Ok' in result) {